@@ -0,0 +1,69 @@
+//! Measures how much faster [`Db::iter_values_only`] is than only keeping the values of
+//! [`Db::iter_by_bucket`].
+//!
+//! The database uses a [`CidPrimary`] with random CIDv1s. The number of entries can be set with
+//! the `STH_BENCH_NUM_KEYS` environment variable, it defaults to 100_000, the size of the values
+//! with `STH_BENCH_VALUE_SIZE`, it defaults to 128 bytes.
+//!
+//! ```text
+//! cargo bench --bench iter_values
+//! ```
+use std::env;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use storethehash::db::Db;
+use storethehash::error::Error;
+use storethehash::testing::random_key;
+use storethehash_primary_cid::CidPrimary;
+
+const BUCKETS_BITS: u8 = 16;
+/// The multicodec prefix of a CIDv1 with the raw codec and a SHA2-256 multihash.
+const CID_V1_PREFIX: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+
+fn env_number(name: &str, default: usize) -> usize {
+    env::var(name)
+        .map(|num| num.parse().expect("Environment variable must be a number"))
+        .unwrap_or(default)
+}
+
+/// Returns how long it took to go through all values and their total size.
+fn measure<I>(values: I) -> (Duration, usize)
+where
+    I: Iterator<Item = Result<Vec<u8>, Error>>,
+{
+    let start = Instant::now();
+    let bytes = values.map(|value| value.unwrap().len()).sum();
+    (start.elapsed(), bytes)
+}
+
+fn main() {
+    let num_keys = env_number("STH_BENCH_NUM_KEYS", 100_000);
+    let value_size = env_number("STH_BENCH_VALUE_SIZE", 128);
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary = CidPrimary::open(temp_dir.path().join("storethehash.db")).unwrap();
+    let db = Db::<_, BUCKETS_BITS>::open(primary, temp_dir.path().join("storethehash.db.index"))
+        .unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut value = vec![0; value_size];
+    for _ in 0..num_keys {
+        let cid = [&CID_V1_PREFIX[..], &random_key(32, &mut rng)].concat();
+        rng.fill(&mut value[..]);
+        db.put(&cid, &value).unwrap();
+    }
+    db.flush().unwrap();
+
+    let (by_bucket, by_bucket_bytes) = measure(
+        db.iter_by_bucket()
+            .map(|entry| entry.map(|(_key, value)| value)),
+    );
+    println!(
+        "Iterating {} entries by bucket took {:?}",
+        num_keys, by_bucket
+    );
+    let (values_only, values_only_bytes) = measure(db.iter_values_only());
+    println!("Iterating {} values only took {:?}", num_keys, values_only);
+    assert_eq!(by_bucket_bytes, values_only_bytes);
+}
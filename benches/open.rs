@@ -0,0 +1,83 @@
+//! Measures how long it takes to open an existing index.
+//!
+//! The index is generated with random keys. Its size in MiB can be set with the
+//! `STH_BENCH_INDEX_SIZE_MB` environment variable, it defaults to 500 MiB. Compare the serial
+//! with the parallel bucket reconstruction by running it with and without the `rayon` feature:
+//!
+//! ```text
+//! cargo bench --bench open
+//! cargo bench --bench open --features rayon
+//! ```
+use std::convert::TryFrom;
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use storethehash::index::{Header, Index};
+use storethehash::recordlist;
+use storethehash_primary_inmemory::InMemory;
+
+const BUCKETS_BITS: u8 = 24;
+/// The number of records within a single record list.
+const RECORDS_PER_RECORD_LIST: usize = 4;
+const KEY_SIZE: usize = 32;
+
+/// Writes an index with random keys until it has at least the given size.
+fn write_index(path: &Path, size: u64) {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut file = BufWriter::new(File::create(path).unwrap());
+
+    let header: Vec<u8> = Header::new(BUCKETS_BITS).into();
+    file.write_all(&u32::try_from(header.len()).unwrap().to_le_bytes())
+        .unwrap();
+    file.write_all(&header).unwrap();
+
+    let mut written = u64::try_from(4 + header.len()).unwrap();
+    let mut file_offset = 0;
+    while written < size {
+        let bucket: u32 = rng.gen_range(0..1 << BUCKETS_BITS);
+        let mut keys: Vec<Vec<u8>> = (0..RECORDS_PER_RECORD_LIST)
+            .map(|_| (0..KEY_SIZE).map(|_| rng.gen()).collect())
+            .collect();
+        keys.sort();
+
+        let mut data = bucket.to_le_bytes().to_vec();
+        data.push(BUCKETS_BITS);
+        for key in keys {
+            data.extend_from_slice(&recordlist::encode_offset_and_key(&key, file_offset));
+            file_offset += 1;
+        }
+        file.write_all(&u32::try_from(data.len()).unwrap().to_le_bytes())
+            .unwrap();
+        file.write_all(&data).unwrap();
+        written += u64::try_from(4 + data.len()).unwrap();
+    }
+    file.flush().unwrap();
+}
+
+fn main() {
+    let size_mb: u64 = env::var("STH_BENCH_INDEX_SIZE_MB")
+        .map(|size| size.parse().expect("Size must be a number"))
+        .unwrap_or(500);
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    write_index(&index_path, size_mb * 1024 * 1024);
+
+    let mode = if cfg!(feature = "rayon") {
+        "parallel"
+    } else {
+        "serial"
+    };
+    let start = Instant::now();
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    let elapsed = start.elapsed();
+    drop(index);
+    println!(
+        "Opening a {} MiB index ({}) took {:?}",
+        size_mb, mode, elapsed
+    );
+}
@@ -0,0 +1,64 @@
+//! Measures how much faster inserting sorted keys is with [`Index::put_sorted_hint`].
+//!
+//! The keys are sorted by bucket first, so that consecutive keys end up in the same record list.
+//! The number of keys can be set with the `STH_BENCH_NUM_KEYS` environment variable, it defaults
+//! to 1 million.
+//!
+//! ```text
+//! cargo bench --bench put_sorted
+//! ```
+use std::env;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use storethehash::index::Index;
+use storethehash::testing::random_key;
+use storethehash_primary_inmemory::InMemory;
+
+/// Few bits, so that the record lists are long enough for the scan to matter.
+const BUCKETS_BITS: u8 = 12;
+const KEY_SIZE: usize = 32;
+
+/// Returns the bucket a key is in, see [`Index::put`].
+fn bucket(key: &[u8]) -> u32 {
+    u32::from_le_bytes([key[0], key[1], key[2], key[3]]) & ((1 << BUCKETS_BITS) - 1)
+}
+
+fn insert(keys: &[Vec<u8>], entries: &[(Vec<u8>, Vec<u8>)], with_hint: bool) -> Duration {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(entries)).unwrap();
+
+    let start = Instant::now();
+    let mut hint = None;
+    for (pos, key) in keys.iter().enumerate() {
+        if with_hint {
+            hint = Some(index.put_sorted_hint(key, pos as u64, hint).unwrap());
+        } else {
+            index.put(key, pos as u64).unwrap();
+        }
+    }
+    index.flush().unwrap();
+    start.elapsed()
+}
+
+fn main() {
+    let num_keys: usize = env::var("STH_BENCH_NUM_KEYS")
+        .map(|num| num.parse().expect("Number of keys must be a number"))
+        .unwrap_or(1_000_000);
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut keys: Vec<Vec<u8>> = (0..num_keys)
+        .map(|_| random_key(KEY_SIZE, &mut rng))
+        .collect();
+    keys.sort_by(|a, b| bucket(a).cmp(&bucket(b)).then_with(|| a.cmp(b)));
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = keys.iter().map(|key| (key.clone(), vec![])).collect();
+
+    let without_hint = insert(&keys, &entries, false);
+    println!("Inserting {} sorted keys took {:?}", num_keys, without_hint);
+    let with_hint = insert(&keys, &entries, true);
+    println!(
+        "Inserting {} sorted keys with hints took {:?}",
+        num_keys, with_hint
+    );
+}
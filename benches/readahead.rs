@@ -0,0 +1,103 @@
+//! Measures how much the readahead hints speed up scans of the primary storage, see
+//! [`storethehash::readahead`].
+//!
+//! A database with a [`CidPrimary`] is verified and exported with the hints disabled and with
+//! them enabled. The number of entries can be set with the `STH_BENCH_NUM_KEYS` environment
+//! variable, it defaults to 100_000, the size of the values with `STH_BENCH_VALUE_SIZE`, it
+//! defaults to 1024 bytes. The hints only have an effect with the `libc` feature on Linux.
+//!
+//! The difference only shows with a cold page cache. Before every measurement the page cache is
+//! dropped by writing to `/proc/sys/vm/drop_caches`, which needs root. Otherwise the numbers are
+//! with a warm page cache and a note is printed.
+//!
+//! ```text
+//! sudo -E cargo bench --bench readahead --features libc
+//! ```
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use storethehash::db::{Db, DbBuilder};
+use storethehash::readahead;
+use storethehash::testing::random_key;
+use storethehash_primary_cid::CidPrimary;
+
+const BUCKETS_BITS: u8 = 24;
+/// The multicodec prefix of a CIDv1 with the raw codec and a SHA2-256 multihash.
+const CID_V1_PREFIX: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+
+fn env_number(name: &str, default: usize) -> usize {
+    env::var(name)
+        .map(|num| num.parse().expect("Environment variable must be a number"))
+        .unwrap_or(default)
+}
+
+/// Drops the page cache, the data was already flushed to disk.
+fn drop_page_cache() -> io::Result<()> {
+    fs::write("/proc/sys/vm/drop_caches", "3")
+}
+
+/// Returns how long verifying and exporting the database took.
+fn measure(dir: &Path, readahead_hints: bool) -> (Duration, Duration) {
+    let primary = CidPrimary::open_read_only(dir.join("storethehash.db")).unwrap();
+    let db = DbBuilder::<_, BUCKETS_BITS>::new(primary, dir.join("storethehash.db.index"))
+        .read_only(true)
+        .readahead_hints(readahead_hints)
+        .open()
+        .unwrap();
+
+    let cold = drop_page_cache().is_ok();
+    let start = Instant::now();
+    db.verify().unwrap();
+    let verify = start.elapsed();
+
+    if cold {
+        drop_page_cache().unwrap();
+    }
+    let start = Instant::now();
+    db.export(io::sink()).unwrap();
+    let export = start.elapsed();
+    (verify, export)
+}
+
+fn main() {
+    let num_keys = env_number("STH_BENCH_NUM_KEYS", 100_000);
+    let value_size = env_number("STH_BENCH_VALUE_SIZE", 1024);
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    {
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.db")).unwrap();
+        let db =
+            Db::<_, BUCKETS_BITS>::open(primary, temp_dir.path().join("storethehash.db.index"))
+                .unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut value = vec![0; value_size];
+        for _ in 0..num_keys {
+            let cid = [&CID_V1_PREFIX[..], &random_key(32, &mut rng)].concat();
+            rng.fill(&mut value[..]);
+            db.put(&cid, &value).unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    if !readahead::is_supported() {
+        println!("Readahead hints are not supported, build with the `libc` feature on Linux.");
+    }
+    if drop_page_cache().is_err() {
+        println!("Cannot drop the page cache (needs root), measuring with a warm one.");
+    }
+    for readahead_hints in &[false, true] {
+        let (verify, export) = measure(temp_dir.path(), *readahead_hints);
+        println!(
+            "Readahead hints {}: verifying {} entries took {:?}, exporting them {:?}",
+            if *readahead_hints { "on" } else { "off" },
+            num_keys,
+            verify,
+            export
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Measures how much faster [`Db::get_many`], which reads the primary storage in ascending order
+//! of the positions, is than calling [`Db::get`] for every key.
+//!
+//! The database uses a [`CidPrimary`] with random CIDv1s, the keys are requested in random order.
+//! The number of entries can be set with the `STH_BENCH_NUM_KEYS` environment variable, it
+//! defaults to 100_000, the size of the values with `STH_BENCH_VALUE_SIZE`, it defaults to 1024
+//! bytes. The difference is the biggest if the primary storage doesn't fit into the page cache,
+//! otherwise both are served from memory.
+//!
+//! ```text
+//! cargo bench --bench sorted_reads
+//! ```
+use std::env;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use storethehash::db::Db;
+use storethehash::testing::random_key;
+use storethehash_primary_cid::CidPrimary;
+
+const BUCKETS_BITS: u8 = 24;
+/// The multicodec prefix of a CIDv1 with the raw codec and a SHA2-256 multihash.
+const CID_V1_PREFIX: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+/// The number of keys that are passed to a single [`Db::get_many`] call.
+const BATCH_SIZE: usize = 10_000;
+
+fn env_number(name: &str, default: usize) -> usize {
+    env::var(name)
+        .map(|num| num.parse().expect("Environment variable must be a number"))
+        .unwrap_or(default)
+}
+
+/// Returns how long it took to get all values and their total size.
+fn measure<F>(get_all: F) -> (Duration, usize)
+where
+    F: FnOnce() -> usize,
+{
+    let start = Instant::now();
+    let bytes = get_all();
+    (start.elapsed(), bytes)
+}
+
+fn main() {
+    let num_keys = env_number("STH_BENCH_NUM_KEYS", 100_000);
+    let value_size = env_number("STH_BENCH_VALUE_SIZE", 1024);
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary = CidPrimary::open(temp_dir.path().join("storethehash.db")).unwrap();
+    let db = Db::<_, BUCKETS_BITS>::open(primary, temp_dir.path().join("storethehash.db.index"))
+        .unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut value = vec![0; value_size];
+    let mut keys = Vec::with_capacity(num_keys);
+    for _ in 0..num_keys {
+        let cid = [&CID_V1_PREFIX[..], &random_key(32, &mut rng)].concat();
+        rng.fill(&mut value[..]);
+        db.put(&cid, &value).unwrap();
+        keys.push(cid);
+    }
+    db.flush().unwrap();
+    keys.shuffle(&mut rng);
+    let keys: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+
+    let (random, random_bytes) = measure(|| {
+        keys.iter()
+            .map(|key| db.get(key).unwrap().unwrap().len())
+            .sum()
+    });
+    println!("Getting {} keys one by one took {:?}", num_keys, random);
+    let (sorted, sorted_bytes) = measure(|| {
+        keys.chunks(BATCH_SIZE)
+            .flat_map(|batch| db.get_many(batch))
+            .map(|value| value.unwrap().unwrap().len())
+            .sum()
+    });
+    println!(
+        "Getting {} keys in batches of {} took {:?}",
+        num_keys, BATCH_SIZE, sorted
+    );
+    assert_eq!(random_bytes, sorted_bytes);
+}
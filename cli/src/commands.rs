@@ -0,0 +1,487 @@
+//! The implementations of the subcommands.
+//!
+//! Every command prints its result and returns the exit code.
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use cid::Cid;
+use serde_json::json;
+use storethehash::codec::{KeyCodec, Sha256Codec};
+use storethehash::db::{Db, DbBuilder};
+use storethehash::fsck::{self, FsckOptions};
+use storethehash::index::{self, Index, IndexIter};
+use storethehash::primary::PrimaryStorage;
+use storethehash::progress::{ProgressReporter, ProgressSink, StdoutProgressSink};
+use storethehash::recordlist::{RecordList, BUCKET_PREFIX_SIZE};
+use storethehash_primary_car::CarIter;
+use storethehash_primary_cid::CidPrimary;
+
+use crate::{Flags, EXIT_CORRUPT, EXIT_NOT_FOUND, EXIT_OK, EXIT_USAGE};
+
+/// The number of bits used for the buckets.
+const BUCKETS_BITS: u8 = 24;
+/// The CIDv1 prefix for raw data hashed with SHA2-256.
+const CID_V1_RAW_SHA2_256_PREFIX: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Returns the path of the index, which is stored next to the primary storage.
+fn index_path(db_path: &Path) -> PathBuf {
+    let mut index_path = db_path.as_os_str().to_os_string();
+    index_path.push(".index");
+    PathBuf::from(index_path)
+}
+
+/// Returns the path with the given suffix added, it's used for temporary files.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn open_read_only(db_path: &Path) -> Result<Db<CidPrimary, BUCKETS_BITS>> {
+    let primary = CidPrimary::open_read_only(db_path)?;
+    Ok(Db::open_read_only(primary, index_path(db_path))?)
+}
+
+fn open(db_path: &Path) -> Result<Db<CidPrimary, BUCKETS_BITS>> {
+    let primary = CidPrimary::open(db_path)?;
+    Ok(Db::open(primary, index_path(db_path))?)
+}
+
+/// Returns where the progress is printed to, if it was requested.
+fn progress(flags: &Flags) -> Option<&'static dyn ProgressSink> {
+    if flags.progress {
+        Some(&StdoutProgressSink)
+    } else {
+        None
+    }
+}
+
+pub fn info(db_path: &Path, flags: &Flags) -> Result<i32> {
+    let mut index_file = File::open(index_path(db_path))?;
+    let (header, _bytes_read) = index::read_header(&mut index_file)?;
+    let index_size = index_file.metadata()?.len();
+    let primary_size = fs::metadata(db_path)?.len();
+
+    if flags.json {
+        let info = json!({
+            "version": header.version,
+            "buckets_bits": header.buckets_bits,
+            "index_size": index_size,
+            "primary_size": primary_size,
+        });
+        println!("{}", info);
+    } else {
+        println!("Index version: {}", header.version);
+        println!("Buckets bits: {}", header.buckets_bits);
+        println!("Index size: {} bytes", index_size);
+        println!("Primary storage size: {} bytes", primary_size);
+    }
+    Ok(EXIT_OK)
+}
+
+pub fn stats(db_path: &Path, flags: &Flags) -> Result<i32> {
+    let stats = open_read_only(db_path)?.stats()?;
+
+    if flags.json {
+        println!("{}", serde_json::to_string(&stats)?);
+    } else {
+        println!("Records: {}", stats.index.records);
+        println!("Non-empty buckets: {}", stats.index.non_empty_buckets);
+        println!("Maximum bucket load: {}", stats.index.max_load);
+        println!("Average bucket load: {}", stats.index.avg_load);
+        println!("Index size: {} bytes", stats.index_size);
+        println!("Live index size: {} bytes", stats.live_index_size);
+        println!(
+            "Stale index size: {} bytes ({:.1}%)",
+            stats.garbage.stale_bytes,
+            stats.garbage.stale_ratio * 100.0
+        );
+        if let Some(primary_size) = stats.primary_size {
+            println!("Primary storage size: {} bytes", primary_size);
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+pub fn verify(db_path: &Path, flags: &Flags) -> Result<i32> {
+    let primary = CidPrimary::open_read_only(db_path)?;
+    let options = FsckOptions {
+        check_primary: !flags.no_primary,
+    };
+    let report = fsck::check(index_path(db_path), &primary, options)?;
+
+    if flags.json {
+        println!("{}", serde_json::to_string(&report)?);
+    } else {
+        for problem in &report.problems {
+            match problem.index_offset {
+                Some(offset) => println!(
+                    "{:?} at index offset {}: {:?}",
+                    problem.severity, offset, problem.kind
+                ),
+                None => println!("{:?}: {:?}", problem.severity, problem.kind),
+            }
+        }
+        println!(
+            "Checked {} record lists with {} records, {} problems found.",
+            report.record_lists_checked,
+            report.records_checked,
+            report.problems.len()
+        );
+    }
+    if report.has_fatal() {
+        Ok(EXIT_CORRUPT)
+    } else {
+        Ok(EXIT_OK)
+    }
+}
+
+/// Copies the index without the record lists that precede the oldest live one.
+///
+/// The buckets are recreated from the file when the index is opened, hence the changed offsets
+/// don't matter.
+pub fn compact(db_path: &Path, flags: &Flags) -> Result<i32> {
+    let index_path = index_path(db_path);
+    let lowest_offset = {
+        let primary = CidPrimary::open_read_only(db_path)?;
+        let index = Index::<_, BUCKETS_BITS>::open_read_only(&index_path, primary)?;
+        index
+            .offsets()
+            .into_iter()
+            // Empty buckets have an offset of 0.
+            .filter(|offset| *offset != 0)
+            .min()
+    };
+
+    let mut index_file = File::open(&index_path)?;
+    let size_before = index_file.metadata()?.len();
+    let (_header, header_size) = index::read_header(&mut index_file)?;
+    let header_size = u64::try_from(header_size)?;
+    // Without any records only the header is kept.
+    let lowest_offset = lowest_offset.unwrap_or(size_before);
+
+    let compacted_path = with_suffix(&index_path, ".compacted");
+    let mut compacted = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&compacted_path)?;
+    index_file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut (&index_file).take(header_size), &mut compacted)?;
+    index_file.seek(SeekFrom::Start(lowest_offset))?;
+    io::copy(&mut index_file, &mut compacted)?;
+    compacted.sync_all()?;
+    let size_after = compacted.metadata()?.len();
+    fs::rename(&compacted_path, &index_path)?;
+
+    if flags.json {
+        println!(
+            "{}",
+            json!({ "size_before": size_before, "size_after": size_after })
+        );
+    } else {
+        println!(
+            "Compacted the index from {} to {} bytes.",
+            size_before, size_after
+        );
+    }
+    Ok(EXIT_OK)
+}
+
+/// Prints the records of the given record lists.
+///
+/// Each item is the bucket, the position of the record list within the index, whether it's the
+/// live one of its bucket and its data.
+fn print_record_lists(record_lists: &[(usize, u64, bool, Vec<u8>)], flags: &Flags) -> Result<()> {
+    if flags.json {
+        let record_lists: Vec<_> = record_lists
+            .iter()
+            .map(|(bucket, pos, live, data)| {
+                let records: Vec<_> = RecordList::new(data)
+                    .into_iter()
+                    .map(|record| {
+                        let key: String = record.key.iter().map(|b| format!("{:02x}", b)).collect();
+                        json!({ "key": key, "file_offset": record.file_offset })
+                    })
+                    .collect();
+                json!({ "bucket": bucket, "pos": pos, "live": live, "records": records })
+            })
+            .collect();
+        println!("{}", json!({ "record_lists": record_lists }));
+    } else {
+        for (bucket, pos, live, data) in record_lists {
+            let superseded = if *live { "" } else { " (superseded)" };
+            println!("Bucket {} at index offset {}{}:", bucket, pos, superseded);
+            for record in &RecordList::new(data) {
+                println!("  {}", record);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the record lists of the index, the records contain the file offsets in the primary
+/// storage.
+///
+/// If a record list doesn't belong to any bucket, the ones before it are printed and it returns
+/// [`EXIT_CORRUPT`].
+pub fn dump_index(db_path: &Path, flags: &Flags) -> Result<i32> {
+    if let Some(bucket) = flags.bucket.filter(|bucket| *bucket >= 1 << BUCKETS_BITS) {
+        eprintln!(
+            "Error: bucket {} doesn't exist, the index has {} buckets",
+            bucket,
+            1u64 << BUCKETS_BITS
+        );
+        return Ok(EXIT_USAGE);
+    }
+    let index_path = index_path(db_path);
+    let primary = CidPrimary::open_read_only(db_path)?;
+    let index = Index::<_, BUCKETS_BITS>::open_read_only(&index_path, primary)?;
+
+    let record_lists = match flags.bucket {
+        Some(bucket) => index
+            .bucket_records(bucket)?
+            .map(|(pos, data)| (bucket, pos, true, data))
+            .into_iter()
+            .collect(),
+        None if flags.live_only => index
+            .live_entries()
+            .map(|entry| entry.map(|(bucket, pos, data)| (bucket, pos, true, data)))
+            .collect::<std::result::Result<_, _>>()?,
+        None => {
+            let offsets = index.offsets();
+            let mut index_file = File::open(&index_path)?;
+            let (_header, header_size) = index::read_header(&mut index_file)?;
+            let mut record_lists = Vec::new();
+            for entry in IndexIter::new(BufReader::new(index_file), header_size) {
+                let (data, pos) = entry?;
+                let bucket_and_offset = data.get(..BUCKET_PREFIX_SIZE).and_then(|prefix| {
+                    let mut bucket_prefix = [0; BUCKET_PREFIX_SIZE];
+                    bucket_prefix.copy_from_slice(prefix);
+                    let bucket = usize::try_from(u32::from_le_bytes(bucket_prefix)).ok()?;
+                    Some((bucket, offsets.get(bucket)?))
+                });
+                match bucket_and_offset {
+                    Some((bucket, offset)) => {
+                        record_lists.push((bucket, pos, *offset == pos, data))
+                    }
+                    None => {
+                        print_record_lists(&record_lists, flags)?;
+                        eprintln!("Error: record list at index offset {} is corrupt", pos);
+                        return Ok(EXIT_CORRUPT);
+                    }
+                }
+            }
+            record_lists
+        }
+    };
+    print_record_lists(&record_lists, flags)?;
+    Ok(EXIT_OK)
+}
+
+/// Writes the live records of the index in a portable text format to stdout, see
+/// [`Index::dump`].
+pub fn dump(db_path: &Path) -> Result<i32> {
+    let primary = CidPrimary::open_read_only(db_path)?;
+    let index = Index::<_, BUCKETS_BITS>::open_read_only(index_path(db_path), primary)?;
+    let stdout = io::stdout();
+    index.dump(BufWriter::new(stdout.lock()))?;
+    Ok(EXIT_OK)
+}
+
+/// Creates a new index from a dump (`-` for stdin), which then replaces the old one.
+pub fn restore(dump_path: &Path, db_path: &Path, flags: &Flags) -> Result<i32> {
+    let reader: Box<dyn BufRead> = if dump_path == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(dump_path)?))
+    };
+    let index_path = index_path(db_path);
+    let restore_path = with_suffix(&index_path, ".restore");
+    if restore_path.exists() {
+        fs::remove_file(&restore_path)?;
+    }
+
+    let primary = CidPrimary::open_read_only(db_path)?;
+    let index = Index::<_, BUCKETS_BITS>::restore(reader, &restore_path, primary)?;
+    let records = index.stats()?.records;
+    drop(index);
+    fs::rename(&restore_path, &index_path)?;
+
+    if flags.json {
+        println!("{}", json!({ "records": records }));
+    } else {
+        println!("Restored the index with {} records.", records);
+    }
+    Ok(EXIT_OK)
+}
+
+/// Writes all entries of the database to stdout, see [`Db::export`].
+pub fn export(db_path: &Path, flags: &Flags) -> Result<i32> {
+    let primary = CidPrimary::open_read_only(db_path)?;
+    let db = DbBuilder::<_, BUCKETS_BITS>::new(primary, index_path(db_path))
+        .read_only(true)
+        .readahead_hints(!flags.no_readahead)
+        .open()?;
+    let stdout = io::stdout();
+    db.export(BufWriter::new(stdout.lock()))?;
+    Ok(EXIT_OK)
+}
+
+/// Stores all entries of an export (`-` for stdin), see [`Db::import`].
+pub fn import(export_path: &Path, db_path: &Path, flags: &Flags) -> Result<i32> {
+    let reader: Box<dyn Read> = if export_path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(export_path)?)
+    };
+    let db = open(db_path)?;
+    let report = db.import(BufReader::new(reader), flags.skip_existing)?;
+
+    if flags.json {
+        println!(
+            "{}",
+            json!({ "imported": report.imported, "skipped": report.skipped })
+        );
+    } else {
+        println!(
+            "Imported {} entries, skipped {} existing ones.",
+            report.imported, report.skipped
+        );
+    }
+    Ok(EXIT_OK)
+}
+
+/// Stores the blocks of a CAR file, which is read from stdin if its path is `-`.
+///
+/// A put always stores the data, hence an interrupted import is resumed by skipping the blocks
+/// that are already stored.
+pub fn import_car(car_path: &Path, db_path: &Path, flags: &Flags) -> Result<i32> {
+    let reader: Box<dyn Read> = if car_path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(car_path)?)
+    };
+    let car_iter = CarIter::new(BufReader::new(reader))?;
+    let roots: Vec<String> = car_iter.roots().iter().map(Cid::to_string).collect();
+    if !flags.json {
+        println!("Roots: {}", roots.join(", "));
+    }
+    let db = open(db_path)?;
+    let mut reporter = ProgressReporter::new(progress(flags), "import-car", None);
+    let mut count: u64 = 0;
+    let mut skipped: u64 = 0;
+    for block in car_iter {
+        let (cid, data, _pos) = block?;
+        reporter.update(count + skipped);
+        if flags.resume && db.get_offset(&cid)?.is_some() {
+            skipped += 1;
+            continue;
+        }
+        db.put(&cid, &data)?;
+        count += 1;
+    }
+    db.close()?;
+    reporter.finish(count + skipped);
+
+    if flags.json {
+        println!(
+            "{}",
+            json!({ "blocks": count, "skipped": skipped, "roots": roots })
+        );
+    } else if flags.resume {
+        println!(
+            "Imported {} blocks, {} were already stored.",
+            count, skipped
+        );
+    } else {
+        println!("Imported {} blocks.", count);
+    }
+    Ok(EXIT_OK)
+}
+
+pub fn get(db_path: &Path, cid: &str) -> Result<i32> {
+    let cid = Cid::try_from(cid)?;
+    match open_read_only(db_path)?.get(&cid.to_bytes())? {
+        Some(data) => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+            Ok(EXIT_OK)
+        }
+        None => {
+            eprintln!("Not found: {}", cid);
+            Ok(EXIT_NOT_FOUND)
+        }
+    }
+}
+
+/// Stores the data under a CIDv1 with the raw codec and a SHA2-256 multihash.
+pub fn put(db_path: &Path, file: &str, flags: &Flags) -> Result<i32> {
+    let mut data = Vec::new();
+    if file == "-" {
+        io::stdin().read_to_end(&mut data)?;
+    } else {
+        File::open(file)?.read_to_end(&mut data)?;
+    }
+    let digest = Sha256Codec::encode(&data)?;
+    let cid = Cid::try_from([&CID_V1_RAW_SHA2_256_PREFIX[..], &digest[..]].concat())?;
+
+    let db = open(db_path)?;
+    db.put(&cid.to_bytes(), &data)?;
+    db.close()?;
+
+    if flags.json {
+        println!("{}", json!({ "cid": cid.to_string() }));
+    } else {
+        println!("{}", cid);
+    }
+    Ok(EXIT_OK)
+}
+
+/// Creates a new index from all entries of the primary storage, which then replaces the old one.
+pub fn rebuild_index(db_path: &Path, flags: &Flags) -> Result<i32> {
+    let index_path = index_path(db_path);
+    let rebuild_path = with_suffix(&index_path, ".rebuild");
+    if rebuild_path.exists() {
+        fs::remove_file(&rebuild_path)?;
+    }
+
+    let primary = CidPrimary::open_read_only(db_path)?;
+    let primary_size = primary
+        .size()?
+        .ok_or("Size of the primary storage is unknown.")?;
+    let index = Index::<_, BUCKETS_BITS>::open(&rebuild_path, primary)?;
+    // The progress is the number of bytes of the primary storage that were indexed.
+    let mut reporter = ProgressReporter::new(progress(flags), "rebuild-index", Some(primary_size));
+    let mut count: u64 = 0;
+    let mut pos = index.primary.first_pos();
+    if !flags.no_readahead {
+        // It's only a hint, the index is the same without it.
+        let _ = index.primary.advise_sequential(pos);
+    }
+    while pos < primary_size {
+        reporter.update(pos);
+        let index_key = index.primary.get_index_key(pos)?;
+        index.put(&index_key, pos)?;
+        count += 1;
+        pos = index.primary.next_pos(pos)?;
+    }
+    index.flush()?;
+    drop(index);
+    fs::rename(&rebuild_path, &index_path)?;
+    reporter.finish(primary_size);
+
+    if flags.json {
+        println!("{}", json!({ "records": count }));
+    } else {
+        println!("Rebuilt the index with {} records.", count);
+    }
+    Ok(EXIT_OK)
+}
@@ -0,0 +1,132 @@
+//! Command line tool to inspect and maintain storethehash databases.
+//!
+//! A database is a primary storage file with CIDs as keys, see
+//! [`storethehash_primary_cid::CidPrimary`], and its index, which is stored next to it with an
+//! `.index` suffix.
+use std::env;
+use std::path::Path;
+use std::process::exit;
+
+mod commands;
+
+/// Everything went fine.
+pub(crate) const EXIT_OK: i32 = 0;
+/// An error occured, e.g. a file couldn't be read.
+pub(crate) const EXIT_ERROR: i32 = 1;
+/// The command line arguments are invalid.
+pub(crate) const EXIT_USAGE: i32 = 2;
+/// The requested CID doesn't exist.
+pub(crate) const EXIT_NOT_FOUND: i32 = 3;
+/// The verification found fatal problems.
+pub(crate) const EXIT_CORRUPT: i32 = 4;
+
+const USAGE: &str = "usage: sth [--json] [--progress] [--no-readahead] <command> <args>
+
+commands:
+    info <db>                   Show the index header and the file sizes.
+    stats <db>                  Show how the records are distributed over the buckets.
+    verify [--no-primary] <db>  Check the index and whether it matches the primary storage.
+    compact <db>                Remove superseded record lists from the index.
+    dump-index [--live-only] [--bucket <n>] <db>
+                                Print the record lists of the index in file order. With
+                                `--live-only` the superseded ones are skipped, `--bucket`
+                                only prints the live record list of that bucket.
+    dump <db>                   Print the live records of the index in a portable format.
+    restore <dump> <db>         Replace the index with the one from a dump (`-` for stdin).
+    export <db>                 Write all entries in a storage independent format to stdout.
+    import [--skip-existing] <export> <db>
+                                Store all entries of an export (`-` for stdin), with
+                                `--skip-existing` the keys that are already stored are skipped.
+    import-car [--resume] <car-file> <db>
+                                Store all blocks of a CAR file (`-` for stdin), with
+                                `--resume` the blocks that are already stored are skipped.
+    get <db> <cid>              Write the data of a CID to stdout.
+    put <db> <file>             Store the contents of a file (`-` for stdin) and print its CID.
+    rebuild-index <db>          Recreate the index from the primary storage.
+
+With `--progress` the progress of `import-car` and `rebuild-index` is printed. `export` and
+`rebuild-index` read the whole primary storage, they hint the operating system to read ahead
+unless `--no-readahead` is given.
+
+The index of a database is stored next to it, with an `.index` suffix.";
+
+/// The flags that are valid for all commands.
+#[derive(Debug, Default)]
+pub(crate) struct Flags {
+    /// Print the output as JSON.
+    pub json: bool,
+    /// Skip the checks against the primary storage when verifying.
+    pub no_primary: bool,
+    /// Skip the blocks that are already stored when importing a CAR file.
+    pub resume: bool,
+    /// Only dump the record lists that are not superseded.
+    pub live_only: bool,
+    /// Only dump the record list of this bucket.
+    pub bucket: Option<usize>,
+    /// Skip the keys that are already stored when importing an export.
+    pub skip_existing: bool,
+    /// Print the progress of long-running commands.
+    pub progress: bool,
+    /// Don't give readahead hints for scans of the primary storage.
+    pub no_readahead: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("Error: {}\n\n{}", message, USAGE);
+    exit(EXIT_USAGE)
+}
+
+fn main() {
+    fil_logger::init();
+
+    let mut flags = Flags::default();
+    let mut args = Vec::new();
+    let mut env_args = env::args().skip(1);
+    while let Some(arg) = env_args.next() {
+        match &arg[..] {
+            "--json" => flags.json = true,
+            "--no-primary" => flags.no_primary = true,
+            "--resume" => flags.resume = true,
+            "--live-only" => flags.live_only = true,
+            "--skip-existing" => flags.skip_existing = true,
+            "--progress" => flags.progress = true,
+            "--no-readahead" => flags.no_readahead = true,
+            "--bucket" => match env_args.next().map(|bucket| bucket.parse()) {
+                Some(Ok(bucket)) => flags.bucket = Some(bucket),
+                _ => usage_error("`--bucket` needs a bucket number"),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(EXIT_OK)
+            }
+            flag if flag.starts_with("--") => usage_error(&format!("unknown flag `{}`", flag)),
+            _ => args.push(arg),
+        }
+    }
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["info", db] => commands::info(Path::new(db), &flags),
+        ["stats", db] => commands::stats(Path::new(db), &flags),
+        ["verify", db] => commands::verify(Path::new(db), &flags),
+        ["compact", db] => commands::compact(Path::new(db), &flags),
+        ["dump-index", db] => commands::dump_index(Path::new(db), &flags),
+        ["dump", db] => commands::dump(Path::new(db)),
+        ["restore", dump, db] => commands::restore(Path::new(dump), Path::new(db), &flags),
+        ["export", db] => commands::export(Path::new(db), &flags),
+        ["import", export, db] => commands::import(Path::new(export), Path::new(db), &flags),
+        ["import-car", car, db] => commands::import_car(Path::new(car), Path::new(db), &flags),
+        ["get", db, cid] => commands::get(Path::new(db), cid),
+        ["put", db, file] => commands::put(Path::new(db), file, &flags),
+        ["rebuild-index", db] => commands::rebuild_index(Path::new(db), &flags),
+        [] => usage_error("no command given"),
+        [command, ..] => usage_error(&format!("invalid arguments for `{}`", command)),
+    };
+    match result {
+        Ok(code) => exit(code),
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            exit(EXIT_ERROR)
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use storethehash::codec::{KeyCodec, Sha256Codec};
+
+fn sth(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sth"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+fn car_fixture_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../primary/car/fixtures/sample.car")
+}
+
+fn json(output: &Output) -> serde_json::Value {
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn put_get() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+    let file = temp_dir.path().join("data");
+    fs::write(&file, b"some data").unwrap();
+
+    let output = sth(&["put", path_str(&db), path_str(&file)]);
+    assert_eq!(output.status.code(), Some(0));
+    let cid = String::from_utf8(output.stdout).unwrap();
+    let cid = cid.trim();
+
+    let output = sth(&["get", path_str(&db), cid]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"some data");
+
+    // The data can also be read from stdin.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sth"))
+        .args(["--json", "put", path_str(&db), "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"other data")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let other_cid = json(&output)["cid"].as_str().unwrap().to_string();
+    assert_ne!(other_cid, cid);
+    assert_eq!(
+        sth(&["get", path_str(&db), &other_cid]).stdout,
+        b"other data"
+    );
+}
+
+#[test]
+fn import_car_and_inspect() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+
+    let output = sth(&[
+        "--json",
+        "import-car",
+        path_str(&car_fixture_path()),
+        path_str(&db),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let blocks = json(&output)["blocks"].as_u64().unwrap();
+    assert!(blocks > 0);
+    assert_eq!(
+        json(&output)["roots"],
+        serde_json::json!(["bafkreibqtm3mr2oekrsiujcpczrhrlt7rqjwt3lpfperiuqp2m2dvsmzky"])
+    );
+
+    let output = sth(&["--json", "info", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["buckets_bits"], 24);
+
+    let output = sth(&["--json", "stats", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["index"]["records"], blocks);
+    assert!(json(&output)["garbage"]["stale_ratio"].is_number());
+
+    let output = sth(&["--json", "verify", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["problems"], serde_json::json!([]));
+    assert_eq!(json(&output)["primary_records_checked"], blocks);
+
+    let output = sth(&["verify", "--no-primary", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn import_car_from_stdin() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+    let from_file = temp_dir.path().join("from_file.db");
+    sth(&[
+        "import-car",
+        path_str(&car_fixture_path()),
+        path_str(&from_file),
+    ]);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sth"))
+        .args(["--json", "import-car", "-", path_str(&db)])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&fs::read(car_fixture_path()).unwrap())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["blocks"], 4);
+    assert_eq!(fs::read(&db).unwrap(), fs::read(&from_file).unwrap());
+}
+
+#[test]
+fn progress() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+
+    let output = sth(&[
+        "--progress",
+        "import-car",
+        path_str(&car_fixture_path()),
+        path_str(&db),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("import-car: 4\n"));
+
+    let output = sth(&["--progress", "rebuild-index", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    let primary_size = fs::metadata(&db).unwrap().len();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!(
+        "rebuild-index: {}/{}\n",
+        primary_size, primary_size
+    )));
+}
+
+#[test]
+fn import_car_resume() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+    let car = car_fixture_path();
+
+    let output = sth(&["--json", "import-car", path_str(&car), path_str(&db)]);
+    let blocks = json(&output)["blocks"].as_u64().unwrap();
+    let size = fs::metadata(&db).unwrap().len();
+
+    let output = sth(&[
+        "--json",
+        "import-car",
+        "--resume",
+        path_str(&car),
+        path_str(&db),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["blocks"], 0);
+    assert_eq!(json(&output)["skipped"], blocks);
+    // Nothing was stored a second time.
+    assert_eq!(fs::metadata(&db).unwrap().len(), size);
+}
+
+#[test]
+fn compact_and_rebuild_index() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+    let mut cids = Vec::new();
+    for ii in 0..5 {
+        let file = temp_dir.path().join(format!("data{}", ii));
+        fs::write(&file, format!("data {}", ii)).unwrap();
+        let output = sth(&["put", path_str(&db), path_str(&file)]);
+        cids.push(String::from_utf8(output.stdout).unwrap().trim().to_string());
+    }
+
+    let output = sth(&["--json", "compact", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    let compact = json(&output);
+    assert!(compact["size_after"].as_u64() <= compact["size_before"].as_u64());
+
+    let output = sth(&["--json", "rebuild-index", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["records"], 5);
+    let output = sth(&["--json", "--no-readahead", "rebuild-index", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["records"], 5);
+
+    for (ii, cid) in cids.iter().enumerate() {
+        let output = sth(&["get", path_str(&db), cid]);
+        assert_eq!(output.stdout, format!("data {}", ii).as_bytes());
+    }
+    assert_eq!(sth(&["verify", path_str(&db)]).status.code(), Some(0));
+}
+
+/// Returns two different contents whose SHA2-256 digests share the first three bytes, so that
+/// they end up in the same bucket.
+fn same_bucket_data() -> (Vec<u8>, Vec<u8>) {
+    let mut seen = HashMap::new();
+    for ii in 0.. {
+        let data = format!("data {}", ii).into_bytes();
+        let digest = Sha256Codec::encode(&data).unwrap();
+        if let Some(other) = seen.insert(digest[..3].to_vec(), data.clone()) {
+            return (other, data);
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn dump_index() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+    let (first, second) = same_bucket_data();
+    // The second put writes the record list of the bucket again.
+    let mut second_offset = 0;
+    for (ii, data) in [first, second].iter().enumerate() {
+        let file = temp_dir.path().join(format!("data{}", ii));
+        fs::write(&file, data).unwrap();
+        if db.exists() {
+            second_offset = fs::metadata(&db).unwrap().len();
+        }
+        sth(&["put", path_str(&db), path_str(&file)]);
+    }
+
+    let output = sth(&["--json", "dump-index", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    let record_lists = json(&output)["record_lists"].as_array().unwrap().clone();
+    assert_eq!(record_lists.len(), 2);
+    assert_eq!(record_lists[0]["live"], false);
+    assert_eq!(record_lists[1]["live"], true);
+    assert_eq!(record_lists[0]["bucket"], record_lists[1]["bucket"]);
+
+    let output = sth(&["--json", "dump-index", "--live-only", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    let live = json(&output)["record_lists"].as_array().unwrap().clone();
+    assert_eq!(live.len(), 1);
+    assert_eq!(live[0]["pos"], record_lists[1]["pos"]);
+    let file_offsets: Vec<u64> = live[0]["records"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|record| record["file_offset"].as_u64().unwrap())
+        .collect();
+    assert!(file_offsets.contains(&0));
+    assert!(file_offsets.contains(&second_offset));
+
+    let bucket = record_lists[1]["bucket"].as_u64().unwrap().to_string();
+    let output = sth(&["--json", "dump-index", "--bucket", &bucket, path_str(&db)]);
+    assert_eq!(json(&output)["record_lists"], serde_json::json!(live));
+
+    let output = sth(&["dump-index", "--live-only", path_str(&db)]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("superseded"), "{}", stdout);
+    assert!(
+        stdout.contains(&format!(" -> {}\n", second_offset)),
+        "{}",
+        stdout
+    );
+
+    // The index has 2^24 buckets.
+    let output = sth(&["dump-index", "--bucket", "16777216", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn dump_and_restore() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+    sth(&["import-car", path_str(&car_fixture_path()), path_str(&db)]);
+    let cids: Vec<String> = (0..3)
+        .map(|ii| {
+            let file = temp_dir.path().join(format!("data{}", ii));
+            fs::write(&file, format!("data {}", ii)).unwrap();
+            let output = sth(&["put", path_str(&db), path_str(&file)]);
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        })
+        .collect();
+
+    let output = sth(&["dump", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    let dump = temp_dir.path().join("index.dump");
+    fs::write(&dump, &output.stdout).unwrap();
+    // The header line is followed by one line per record.
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().lines().count(),
+        1 + 4 + 3
+    );
+
+    fs::remove_file(temp_dir.path().join("storethehash.db.index")).unwrap();
+    let output = sth(&["--json", "restore", path_str(&dump), path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["records"], 7);
+    for (ii, cid) in cids.iter().enumerate() {
+        let output = sth(&["get", path_str(&db), cid]);
+        assert_eq!(output.stdout, format!("data {}", ii).as_bytes());
+    }
+    assert_eq!(sth(&["verify", path_str(&db)]).status.code(), Some(0));
+
+    fs::write(&dump, b"not a dump").unwrap();
+    assert_eq!(
+        sth(&["restore", path_str(&dump), path_str(&db)])
+            .status
+            .code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn export_and_import() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+    sth(&["import-car", path_str(&car_fixture_path()), path_str(&db)]);
+
+    let output = sth(&["export", path_str(&db)]);
+    assert_eq!(output.status.code(), Some(0));
+    let export = temp_dir.path().join("db.export");
+    fs::write(&export, &output.stdout).unwrap();
+    // The readahead hints don't change the export.
+    assert_eq!(
+        sth(&["--no-readahead", "export", path_str(&db)]).stdout,
+        output.stdout
+    );
+
+    let imported = temp_dir.path().join("imported.db");
+    let output = sth(&["--json", "import", path_str(&export), path_str(&imported)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["imported"], 4);
+    assert_eq!(
+        sth(&["export", path_str(&imported)]).stdout,
+        fs::read(&export).unwrap()
+    );
+
+    let output = sth(&[
+        "--json",
+        "import",
+        "--skip-existing",
+        path_str(&export),
+        path_str(&imported),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(json(&output)["imported"], 0);
+    assert_eq!(json(&output)["skipped"], 4);
+}
+
+#[test]
+fn exit_codes() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = temp_dir.path().join("storethehash.db");
+    let file = temp_dir.path().join("data");
+    fs::write(&file, b"some data").unwrap();
+    sth(&["put", path_str(&db), path_str(&file)]);
+
+    // A valid CID that isn't stored.
+    let missing = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+    assert_eq!(sth(&["get", path_str(&db), missing]).status.code(), Some(3));
+    assert_eq!(
+        sth(&["get", path_str(&db), "not a cid"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(sth(&[]).status.code(), Some(2));
+    assert_eq!(sth(&["get", path_str(&db)]).status.code(), Some(2));
+    assert_eq!(sth(&["--unknown", "info"]).status.code(), Some(2));
+    let missing_db = temp_dir.path().join("missing.db");
+    assert_eq!(sth(&["info", path_str(&missing_db)]).status.code(), Some(1));
+
+    // The index points past the end of the primary storage.
+    fs::write(&db, b"").unwrap();
+    assert_eq!(sth(&["verify", path_str(&db)]).status.code(), Some(4));
+}
@@ -0,0 +1,108 @@
+//! An async wrapper around a [`Db`], e.g. for use within tokio services.
+//!
+//! The operations of a [`Db`] are blocking, hence [`AsyncDb`] runs them on the blocking thread
+//! pool of a tokio runtime, see [`tokio::task::spawn_blocking`]. The size of that pool is
+//! configured with [`tokio::runtime::Builder::max_blocking_threads`].
+//!
+//! All operations share the database, hence they run concurrently. This needs a primary storage
+//! that is `Sync`. An [`AsyncDb`] can be cloned cheaply, all clones share the same database.
+//!
+//! # Cancellation
+//!
+//! Once an operation was started, it always runs to completion on the blocking thread pool, even
+//! if its future is dropped. Hence dropping a future never leaves the database in a broken state.
+//! It's only unknown whether a dropped put was stored, a later get tells.
+use std::sync::Arc;
+
+use storethehash::db::{Db, InsertPosition};
+use storethehash::error::Error;
+use storethehash::primary::PrimaryStorage;
+use thiserror::Error;
+use tokio::runtime::Handle;
+use tokio::task::{self, JoinError};
+
+/// An error of an [`AsyncDb`] operation.
+#[derive(Debug, Error)]
+pub enum AsyncError {
+    /// The database returned an error.
+    #[error(transparent)]
+    Db(#[from] Error),
+    /// The blocking task panicked or its runtime was shut down.
+    #[error("Blocking task failed: {0}")]
+    Task(#[from] JoinError),
+}
+
+/// A [`Db`] whose operations run on a blocking thread pool.
+pub struct AsyncDb<P: PrimaryStorage, const N: u8> {
+    db: Arc<Db<P, N>>,
+    /// The runtime whose blocking thread pool is used, else the one of the caller.
+    runtime: Option<Handle>,
+}
+
+impl<P: PrimaryStorage, const N: u8> Clone for AsyncDb<P, N> {
+    fn clone(&self) -> Self {
+        Self {
+            db: Arc::clone(&self.db),
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+impl<P, const N: u8> AsyncDb<P, N>
+where
+    P: PrimaryStorage + Send + Sync + 'static,
+{
+    /// The operations run on the blocking thread pool of the runtime they are called from.
+    pub fn new(db: Db<P, N>) -> Self {
+        Self {
+            db: Arc::new(db),
+            runtime: None,
+        }
+    }
+
+    /// The operations run on the blocking thread pool of the given runtime.
+    pub fn with_runtime(db: Db<P, N>, runtime: Handle) -> Self {
+        Self {
+            db: Arc::new(db),
+            runtime: Some(runtime),
+        }
+    }
+
+    /// Runs an operation on the blocking thread pool.
+    async fn run<F, T>(&self, operation: F) -> Result<T, AsyncError>
+    where
+        F: FnOnce(&Db<P, N>) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = Arc::clone(&self.db);
+        let task = move || operation(&db);
+        let handle = match &self.runtime {
+            Some(runtime) => runtime.spawn_blocking(task),
+            None => task::spawn_blocking(task),
+        };
+        Ok(handle.await??)
+    }
+
+    /// Returns the value of a key, see [`Db::get`].
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, AsyncError> {
+        let key = key.to_vec();
+        self.run(move |db| db.get(&key)).await
+    }
+
+    /// Stores a key-value pair, see [`Db::put`].
+    pub async fn put(&self, key: &[u8], value: &[u8]) -> Result<InsertPosition, AsyncError> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.run(move |db| db.put(&key, &value)).await
+    }
+
+    /// Returns whether a key exists, without reading its value, see [`Db::get_offset`].
+    pub async fn contains_key(&self, key: &[u8]) -> Result<bool, AsyncError> {
+        let key = key.to_vec();
+        self.run(move |db| Ok(db.get_offset(&key)?.is_some())).await
+    }
+
+    /// Flushes the database, see [`Db::flush`].
+    pub async fn flush(&self) -> Result<(), AsyncError> {
+        self.run(Db::flush).await
+    }
+}
@@ -0,0 +1,163 @@
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use storethehash::db::Db;
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+use storethehash_async::AsyncDb;
+use storethehash_primary_file::FilePrimary;
+use tokio::runtime::Builder;
+
+const BUCKETS_BITS: u8 = 8;
+
+fn open(temp_dir: &tempfile::TempDir) -> Db<FilePrimary, BUCKETS_BITS> {
+    let primary = FilePrimary::open(temp_dir.path().join("storethehash.db")).unwrap();
+    Db::open(primary, temp_dir.path().join("storethehash.index")).unwrap()
+}
+
+/// Returns a key whose leading bytes differ for every task and every entry.
+fn key(task: u8, ii: u8) -> Vec<u8> {
+    vec![ii, task, 2, 3, 4, 5, 6, 7]
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_gets_and_puts() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = AsyncDb::new(open(&temp_dir));
+
+    let tasks: Vec<_> = (0..8u8)
+        .map(|task| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                for ii in 0..50u8 {
+                    db.put(&key(task, ii), &[task, ii]).await.unwrap();
+                    assert_eq!(db.get(&key(task, ii)).await.unwrap(), Some(vec![task, ii]));
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    db.flush().await.unwrap();
+
+    for task in 0..8u8 {
+        for ii in 0..50u8 {
+            assert!(db.contains_key(&key(task, ii)).await.unwrap());
+        }
+    }
+    assert!(!db.contains_key(&key(8, 0)).await.unwrap());
+    assert_eq!(db.get(&key(8, 0)).await.unwrap(), None);
+}
+
+/// Counts the gets of a [`SlowPrimary`] that run at the same time.
+#[derive(Debug, Default)]
+struct RunningGets {
+    current: AtomicUsize,
+    max: AtomicUsize,
+}
+
+/// An in-memory primary storage whose gets take a while.
+#[derive(Debug, Default)]
+struct SlowPrimary {
+    data: RwLock<Vec<(Vec<u8>, Vec<u8>)>>,
+    running_gets: Arc<RunningGets>,
+}
+
+impl PrimaryStorage for SlowPrimary {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let running = self.running_gets.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.running_gets.max.fetch_max(running, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        self.running_gets.current.fetch_sub(1, Ordering::SeqCst);
+
+        let data = self.data.read().unwrap();
+        data.get(usize::try_from(pos).unwrap())
+            .cloned()
+            .ok_or(PrimaryError::OutOfBounds {
+                pos,
+                len: data.len() as u64,
+            })
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        let mut data = self.data.write().unwrap();
+        data.push((key.to_vec(), value.to_vec()));
+        Ok(data.len() as u64 - 1)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_gets_overlap() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary = SlowPrimary::default();
+    let running_gets = Arc::clone(&primary.running_gets);
+    let db = AsyncDb::new(
+        Db::<_, BUCKETS_BITS>::open(primary, temp_dir.path().join("storethehash.index")).unwrap(),
+    );
+    for task in 0..4u8 {
+        db.put(&key(task, 0), &[task]).await.unwrap();
+    }
+
+    let tasks: Vec<_> = (0..4u8)
+        .map(|task| {
+            let db = db.clone();
+            tokio::spawn(async move { db.get(&key(task, 0)).await.unwrap() })
+        })
+        .collect();
+    for (task, handle) in (0..4u8).zip(tasks) {
+        assert_eq!(handle.await.unwrap(), Some(vec![task]));
+    }
+    // The gets weren't serialized.
+    assert!(running_gets.max.load(Ordering::SeqCst) > 1);
+}
+
+#[test]
+fn separate_runtime() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let blocking_runtime = Builder::new_multi_thread()
+        .max_blocking_threads(2)
+        .build()
+        .unwrap();
+    let db = AsyncDb::with_runtime(open(&temp_dir), blocking_runtime.handle().clone());
+
+    // The caller's runtime doesn't need a blocking thread pool of its own.
+    let runtime = Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        db.put(&key(0, 0), b"value").await.unwrap();
+        assert_eq!(db.get(&key(0, 0)).await.unwrap(), Some(b"value".to_vec()));
+    });
+}
+
+#[tokio::test]
+async fn dropped_put() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = AsyncDb::new(open(&temp_dir));
+    let dropped_key = key(0, 0);
+
+    // The put is started and then dropped in favour of the other branch.
+    tokio::select! {
+        biased;
+        _ = db.put(&dropped_key, b"dropped") => {}
+        _ = async {} => {}
+    }
+
+    // The put still completes and the database stays usable.
+    let mut stored = false;
+    for _ in 0..1000 {
+        if db.contains_key(&dropped_key).await.unwrap() {
+            stored = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(stored);
+    assert_eq!(
+        db.get(&dropped_key).await.unwrap(),
+        Some(b"dropped".to_vec())
+    );
+    db.put(&key(0, 1), b"value").await.unwrap();
+    assert_eq!(db.get(&key(0, 1)).await.unwrap(), Some(b"value".to_vec()));
+}
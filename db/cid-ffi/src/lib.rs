@@ -1,54 +1,636 @@
-use std::ffi::CStr;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::error::Error;
 use std::mem;
+use std::path::Path;
 use std::ptr;
 use std::slice;
+use std::sync::{Mutex, MutexGuard};
 
-use libc::{c_char, c_long, c_uchar, size_t};
-use storethehash::db::Db;
+use libc::{c_char, c_int, c_long, c_uchar, c_void, size_t};
+use storethehash::db::DbStats;
+use storethehash::error::Error as DbError;
+use storethehash::index::INDEX_VERSION;
+use storethehash::prelude::*;
+use storethehash_db_ffi_common::{
+    self as common, bytes_path, c_path, default_index_path, ffi_call, key_slice, leak_buf,
+    set_last_error, RETURN_ERROR, RETURN_NOT_FOUND, RETURN_OK,
+};
 use storethehash_primary_cid::CidPrimary;
 
-const BUCKETS_BITS: u8 = 24;
+/// The number of bits used for the buckets if no options are given.
+const DEFAULT_BUCKETS_BITS: u8 = 24;
 
-const RETURN_OK: u8 = 0;
-const RETURN_ERROR: u8 = 1;
+// The status codes that are returned by the functions, in addition to the common ones. Since
+// version 0.2.0 a missing key is distinguished from an error.
+const RETURN_READ_ONLY: u8 = 3;
+const RETURN_BUFFER_TOO_SMALL: u8 = 4;
 
+// The status codes of the iterator functions, which return 2 on error.
+const ITER_EXHAUSTED: u8 = 0;
+const ITER_ENTRY: u8 = 1;
+
+/// The error message if a write is attempted on a database that was opened read-only.
+const READ_ONLY_MESSAGE: &str = "Store opened read-only.";
+
+/// When the data is synced to disk.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SthSyncPolicy {
+    /// The data is only synced when the database is closed.
+    OnClose,
+    /// The data is synced after every write.
+    Always,
+}
+
+/// The options a database is opened with.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SthOptions {
+    /// The number of bits used for the buckets. It must match the one of an existing index.
+    /// Supported values are 8, 12, 16, 20 and 24.
+    pub buckets_bits: u8,
+    /// If set, the files are opened without write access, all writes return an error and the
+    /// database is not created if it doesn't exist.
+    pub read_only: bool,
+    pub sync_policy: SthSyncPolicy,
+}
+
+impl Default for SthOptions {
+    fn default() -> Self {
+        Self {
+            buckets_bits: DEFAULT_BUCKETS_BITS,
+            read_only: false,
+            sync_policy: SthSyncPolicy::OnClose,
+        }
+    }
+}
+
+// The functions this build supports, see `sth_features`. Bindings can check them at runtime, as
+// functions are added over time.
+/// `del`
+pub const STH_FEATURE_DELETE: u64 = 1;
+/// `for_each`
+pub const STH_FEATURE_FOR_EACH: u64 = 1 << 1;
+/// `flush`
+pub const STH_FEATURE_FLUSH: u64 = 1 << 2;
+/// `open_db_read_only` and `SthOptions::read_only`
+pub const STH_FEATURE_READ_ONLY: u64 = 1 << 3;
+/// `open_db_with_options`
+pub const STH_FEATURE_OPTIONS: u64 = 1 << 4;
+/// `last_error_length` and `last_error_message`
+pub const STH_FEATURE_LAST_ERROR: u64 = 1 << 5;
+/// `stats`
+pub const STH_FEATURE_STATS: u64 = 1 << 6;
+/// `get_many`
+pub const STH_FEATURE_GET_MANY: u64 = 1 << 7;
+/// `get_into` and `get_len`
+pub const STH_FEATURE_GET_INTO: u64 = 1 << 8;
+/// Storing an already existing key is a no-op without further checks, it's enabled with the
+/// `strict_dedup` feature.
+pub const STH_FEATURE_STRICT_DEDUP: u64 = 1 << 9;
+/// `open_db_bytes` and `open_db_with_options_bytes`
+pub const STH_FEATURE_BYTE_PATHS: u64 = 1 << 10;
+/// `set_with_offset` and `get_offset`
+pub const STH_FEATURE_OFFSETS: u64 = 1 << 11;
+/// `iter`, `iter_next_key`, `iter_next` and `free_iter`
+pub const STH_FEATURE_ITER: u64 = 1 << 12;
+
+/// Set in `SthStats::estimated` if `live_index_bytes` is an estimate.
+pub const STH_STATS_LIVE_INDEX_BYTES: u32 = 1;
+
+/// Statistics about a database, see `stats`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SthStats {
+    /// The number of stored keys.
+    pub records: u64,
+    /// The number of buckets that contain at least one key.
+    pub occupied_buckets: u64,
+    /// The size of the index file in bytes.
+    pub index_bytes: u64,
+    /// The number of bytes of the index file that are still in use. The rest is taken up by
+    /// outdated record lists.
+    pub live_index_bytes: u64,
+    /// The size of the primary storage file in bytes.
+    pub primary_bytes: u64,
+    /// A bitmask of the fields that are estimates instead of exact values, e.g.
+    /// `STH_STATS_LIVE_INDEX_BYTES`. Currently all fields are exact.
+    pub estimated: u32,
+}
+
+// The layout of `SthStats` is part of the C API, it must not change.
+const _: [(); 48] = [(); mem::size_of::<SthStats>()];
+const _: [(); mem::align_of::<u64>()] = [(); mem::align_of::<SthStats>()];
+
+impl SthStats {
+    fn new(stats: DbStats) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            records: u64::try_from(stats.index.records)?,
+            occupied_buckets: u64::try_from(stats.index.non_empty_buckets)?,
+            index_bytes: stats.index_size,
+            live_index_bytes: stats.live_index_size,
+            primary_bytes: stats
+                .primary_size
+                .ok_or("Size of the primary storage is unknown.")?,
+            estimated: 0,
+        })
+    }
+}
+
+/// The number of bits for the buckets is a const generic, hence every supported size needs its
+/// own type.
+#[derive(Debug)]
+enum AnyDb {
+    Bits8(Db<CidPrimary, 8>),
+    Bits12(Db<CidPrimary, 12>),
+    Bits16(Db<CidPrimary, 16>),
+    Bits20(Db<CidPrimary, 20>),
+    Bits24(Db<CidPrimary, 24>),
+}
+
+/// Evaluates the expression with `$db` bound to the database, independent of its bucket bits.
+macro_rules! with_db {
+    ($any_db:expr, $db:ident => $body:expr) => {
+        match $any_db {
+            AnyDb::Bits8($db) => $body,
+            AnyDb::Bits12($db) => $body,
+            AnyDb::Bits16($db) => $body,
+            AnyDb::Bits20($db) => $body,
+            AnyDb::Bits24($db) => $body,
+        }
+    };
+}
+
+impl AnyDb {
+    fn open(
+        primary: CidPrimary,
+        index_path: &Path,
+        buckets_bits: u8,
+        read_only: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        // The type of the database is inferred from the enum variant.
+        macro_rules! open {
+            () => {
+                if read_only {
+                    Db::open_read_only(primary, index_path)?
+                } else {
+                    Db::open(primary, index_path)?
+                }
+            };
+        }
+        let db = match buckets_bits {
+            8 => AnyDb::Bits8(open!()),
+            12 => AnyDb::Bits12(open!()),
+            16 => AnyDb::Bits16(open!()),
+            20 => AnyDb::Bits20(open!()),
+            24 => AnyDb::Bits24(open!()),
+            _ => {
+                return Err(
+                    format!("Unsupported number of bits for buckets: {}.", buckets_bits).into(),
+                )
+            }
+        };
+        Ok(db)
+    }
+
+    fn buckets_bits(&self) -> u8 {
+        match self {
+            AnyDb::Bits8(_) => 8,
+            AnyDb::Bits12(_) => 12,
+            AnyDb::Bits16(_) => 16,
+            AnyDb::Bits20(_) => 20,
+            AnyDb::Bits24(_) => 24,
+        }
+    }
+}
+
+/// A key-value pair.
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// The key-value pairs of a bucket.
+type Entries = VecDeque<Entry>;
+
+/// The result of every key of a `get_many` call.
+type GetManyResults = Vec<Result<Option<Vec<u8>>, DbError>>;
+
+/// A database handle that can be shared between threads.
+///
+/// The database itself is not thread-safe, hence all calls are serialized with a mutex.
+///
 /// cbindgen:ignore
-pub type StoreTheHashCidDb = Db<CidPrimary, BUCKETS_BITS>;
+#[derive(Debug)]
+pub struct StoreTheHashCidDb {
+    db: Mutex<AnyDb>,
+    options: SthOptions,
+}
 
-fn leak_buf(v: Vec<u8>, vallen: *mut size_t) -> *mut c_char {
-    unsafe {
-        *vallen = v.len();
+impl StoreTheHashCidDb {
+    fn lock(&self) -> Result<MutexGuard<'_, AnyDb>, Box<dyn Error>> {
+        // A panic while the lock was held might have left the database in an inconsistent state.
+        self.db
+            .lock()
+            .map_err(|_| "Database is unusable after a previous panic.".into())
     }
-    let mut bsv = v.into_boxed_slice();
-    let val = bsv.as_mut_ptr() as *mut _;
-    mem::forget(bsv);
-    val
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let db = self.lock()?;
+        Ok(with_db!(&*db, db => db.get(key))?)
+    }
+
+    /// The database is locked once for all keys.
+    fn get_many(&self, keys: &[&[u8]]) -> Result<GetManyResults, Box<dyn Error>> {
+        let db = self.lock()?;
+        Ok(with_db!(&*db, db => db.get_many(keys)))
+    }
+
+    fn get_offset(&self, key: &[u8]) -> Result<Option<u64>, Box<dyn Error>> {
+        let db = self.lock()?;
+        Ok(with_db!(&*db, db => db.get_offset(key))?)
+    }
+
+    /// Returns the position of the value in the primary storage.
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, Box<dyn Error>> {
+        let db = self.lock()?;
+        let file_offset = with_db!(&*db, db => db.put_get_offset(key, value))?;
+        self.sync(&db)?;
+        Ok(file_offset)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<bool, Box<dyn Error>> {
+        let db = self.lock()?;
+        let deleted = with_db!(&*db, db => db.delete(key))?;
+        self.sync(&db)?;
+        Ok(deleted)
+    }
+
+    /// Calls `f` for every key-value pair until it returns false.
+    ///
+    /// The database is locked during the whole iteration.
+    fn for_each<F>(&self, mut f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let db = self.lock()?;
+        let mut stopped = false;
+        for bucket in 0..1usize << db.buckets_bits() {
+            with_db!(&*db, db => db.for_each_in_bucket(bucket, |key, value| {
+                // The remaining entries of the current bucket are skipped.
+                if !stopped {
+                    stopped = !f(&key, &value);
+                }
+                Ok(())
+            }))?;
+            if stopped {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the entries of the first non-empty bucket, starting at the given one.
+    ///
+    /// The bucket is advanced past the returned one. An empty list is returned once all buckets
+    /// were read. If reading a bucket fails, the bucket is not advanced.
+    fn next_entries(&self, bucket: &mut usize) -> Result<Entries, Box<dyn Error>> {
+        let db = self.lock()?;
+        let mut entries = VecDeque::new();
+        while entries.is_empty() && *bucket < 1usize << db.buckets_bits() {
+            with_db!(&*db, db => db.for_each_in_bucket(*bucket, |key, value| {
+                entries.push_back((key, value));
+                Ok(())
+            }))?;
+            *bucket += 1;
+        }
+        Ok(entries)
+    }
+
+    fn stats(&self) -> Result<DbStats, Box<dyn Error>> {
+        let db = self.lock()?;
+        Ok(with_db!(&*db, db => db.stats())?)
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let db = self.lock()?;
+        Ok(with_db!(&*db, db => db.flush())?)
+    }
+
+    fn close(self) -> Result<(), Box<dyn Error>> {
+        let db = self
+            .db
+            .into_inner()
+            .map_err(|_| "Database is unusable after a previous panic.")?;
+        Ok(with_db!(db, db => db.close())?)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.options.read_only
+    }
+
+    /// Syncs the data to disk if the sync policy requires it.
+    fn sync(&self, db: &AnyDb) -> Result<(), Box<dyn Error>> {
+        if self.options.sync_policy == SthSyncPolicy::Always {
+            with_db!(db, db => db.flush())?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the database behind the given pointer, or an error if it is a null pointer.
+unsafe fn db_ref<'a>(
+    db: *const StoreTheHashCidDb,
+) -> Result<&'a StoreTheHashCidDb, Box<dyn Error>> {
+    db.as_ref()
+        .ok_or_else(|| "Database is a null pointer.".into())
 }
 
+/// Returns the size of the buffer that is needed for the message of the most recent error.
+///
+/// The size includes the trailing null byte. Returns 0 if the most recent call didn't fail.
 #[no_mangle]
-pub unsafe extern "C" fn open_db(path: *const c_char) -> *mut StoreTheHashCidDb {
-    let db_path = CStr::from_ptr(path).to_str().unwrap();
-    let primary = CidPrimary::open(&db_path).unwrap();
-    let index_path = format!("{}{}", db_path, ".index");
+pub extern "C" fn last_error_length() -> size_t {
+    common::last_error_length()
+}
+
+/// Copies the message of the most recent error into the given buffer.
+///
+/// The message is null terminated. Returns the number of bytes written, without the null byte.
+/// If there is no error 0 is returned, if the buffer is too small (or a null pointer) -1 is
+/// returned and nothing is written.
+///
+/// # Safety
+///
+/// `buf` must be a null pointer or point to `len` bytes that can be written.
+#[no_mangle]
+pub unsafe extern "C" fn last_error_message(buf: *mut c_char, len: size_t) -> c_long {
+    common::last_error_message(buf, len)
+}
 
-    match Db::open(primary, &index_path) {
-        Ok(db) => Box::into_raw(Box::new(db)),
-        Err(_) => ptr::null_mut(),
+/// Returns the version of this library.
+///
+/// The version is packed as `major << 16 | minor << 8 | patch`. It can be called without an open
+/// database.
+#[no_mangle]
+pub extern "C" fn sth_version() -> u32 {
+    let part = |part: &str| part.parse::<u32>().expect("Crate version is valid semver.");
+    part(env!("CARGO_PKG_VERSION_MAJOR")) << 16
+        | part(env!("CARGO_PKG_VERSION_MINOR")) << 8
+        | part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+/// Returns the version of the on-disk index format this library writes.
+///
+/// It can be called without an open database.
+#[no_mangle]
+pub extern "C" fn sth_index_version() -> u8 {
+    INDEX_VERSION
+}
+
+/// Returns a bitmask of the functionality this library supports, see the `STH_FEATURE_*`
+/// constants.
+///
+/// It can be called without an open database.
+#[no_mangle]
+pub extern "C" fn sth_features() -> u64 {
+    let mut features = STH_FEATURE_DELETE
+        | STH_FEATURE_FOR_EACH
+        | STH_FEATURE_FLUSH
+        | STH_FEATURE_READ_ONLY
+        | STH_FEATURE_OPTIONS
+        | STH_FEATURE_LAST_ERROR
+        | STH_FEATURE_STATS
+        | STH_FEATURE_GET_MANY
+        | STH_FEATURE_GET_INTO
+        | STH_FEATURE_BYTE_PATHS
+        | STH_FEATURE_OFFSETS
+        | STH_FEATURE_ITER;
+    if cfg!(feature = "strict_dedup") {
+        features |= STH_FEATURE_STRICT_DEDUP;
     }
+    features
+}
+
+/// Open a database.
+///
+/// The index is stored next to the given path with an `.index` suffix. The default options are
+/// used, see `open_db_with_options`. The database needs to be closed with `close_db`. Returns a
+/// null pointer if the database cannot be opened.
+///
+/// The returned handle can be shared between threads, all functions that take it can be called
+/// concurrently. The calls are serialized internally. The only exception is `close_db`, no other
+/// calls on the same handle must happen during or after it.
+///
+/// The path is converted the same way as the ones of `open_db_bytes`.
+///
+/// # Safety
+///
+/// `path` must be a null pointer or point to a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn open_db(path: *const c_char) -> *mut StoreTheHashCidDb {
+    ffi_call(ptr::null_mut(), || {
+        let db_path = c_path(path)?;
+        let db = open(
+            &db_path,
+            &default_index_path(&db_path),
+            SthOptions::default(),
+        )?;
+        Ok(Box::into_raw(Box::new(db)))
+    })
 }
 
-//#[no_mangle]
-//pub unsafe extern "C" fn close(db: *mut StoreTheHashCidDb) {
-//    drop(Box::from_raw(db))
-//}
+/// Open a database at a path that is given as bytes with a length.
+///
+/// On Unix any bytes that are valid in a path are accepted, they don't need to be UTF-8. On other
+/// platforms (e.g. Windows) the path needs to be valid UTF-8, else an error is returned. Apart from
+/// that it's the same as `open_db`.
+///
+/// # Safety
+///
+/// `path` must be a null pointer or point to `path_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn open_db_bytes(
+    path: *const u8,
+    path_len: size_t,
+) -> *mut StoreTheHashCidDb {
+    ffi_call(ptr::null_mut(), || {
+        let db_path = bytes_path(path, path_len)?;
+        let db = open(
+            &db_path,
+            &default_index_path(&db_path),
+            SthOptions::default(),
+        )?;
+        Ok(Box::into_raw(Box::new(db)))
+    })
+}
+
+/// Open a database with the given options.
+///
+/// The primary storage and the index are stored at the given paths. If `options` is a null
+/// pointer, the default options are used (24 bits for the buckets, read-write, sync on close). The
+/// database needs to be closed with `close_db`. Returns a null pointer if the database cannot be
+/// opened, e.g. if the bits for the buckets don't match the ones of an existing index.
+///
+/// The paths are converted the same way as the ones of `open_db_bytes`.
+///
+/// # Safety
+///
+/// The paths must be null pointers or point to null terminated strings.
+/// `options` must be a null pointer or point to valid options.
+#[no_mangle]
+pub unsafe extern "C" fn open_db_with_options(
+    primary_path: *const c_char,
+    index_path: *const c_char,
+    options: *const SthOptions,
+) -> *mut StoreTheHashCidDb {
+    ffi_call(ptr::null_mut(), || {
+        let primary_path = c_path(primary_path)?;
+        let index_path = c_path(index_path)?;
+        let options = options.as_ref().copied().unwrap_or_default();
+        let db = open(&primary_path, &index_path, options)?;
+        Ok(Box::into_raw(Box::new(db)))
+    })
+}
+
+/// Open a database with the given options at paths that are given as bytes with a length.
+///
+/// The paths are converted the same way as the one of `open_db_bytes`. Apart from that it's the
+/// same as `open_db_with_options`.
+///
+/// # Safety
+///
+/// The paths must be null pointers or point to as many bytes as their lengths. `options` must be
+/// a null pointer or point to valid options.
+#[no_mangle]
+pub unsafe extern "C" fn open_db_with_options_bytes(
+    primary_path: *const u8,
+    primary_path_len: size_t,
+    index_path: *const u8,
+    index_path_len: size_t,
+    options: *const SthOptions,
+) -> *mut StoreTheHashCidDb {
+    ffi_call(ptr::null_mut(), || {
+        let primary_path = bytes_path(primary_path, primary_path_len)?;
+        let index_path = bytes_path(index_path, index_path_len)?;
+        let options = options.as_ref().copied().unwrap_or_default();
+        let db = open(&primary_path, &index_path, options)?;
+        Ok(Box::into_raw(Box::new(db)))
+    })
+}
+
+/// Open an existing database without write access.
+///
+/// Both files may be read-only on disk. `set` and `del` return 3 without touching the files. The
+/// default number of bits for the buckets (24) is used, use `open_db_with_options` for other
+/// sizes. Returns a null pointer if the database cannot be opened.
+///
+/// # Safety
+///
+/// The paths must be null pointers or point to null terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn open_db_read_only(
+    primary_path: *const c_char,
+    index_path: *const c_char,
+) -> *mut StoreTheHashCidDb {
+    let options = SthOptions {
+        read_only: true,
+        ..SthOptions::default()
+    };
+    open_db_with_options(primary_path, index_path, &options)
+}
+
+fn open(
+    primary_path: &Path,
+    index_path: &Path,
+    options: SthOptions,
+) -> Result<StoreTheHashCidDb, Box<dyn Error>> {
+    let db = if options.read_only {
+        let primary = CidPrimary::open_read_only(primary_path)?;
+        AnyDb::open(primary, index_path, options.buckets_bits, true)?
+    } else {
+        let primary = CidPrimary::open(primary_path)?;
+        AnyDb::open(primary, index_path, options.buckets_bits, false)?
+    };
+    Ok(StoreTheHashCidDb {
+        db: Mutex::new(db),
+        options,
+    })
+}
+
+/// Close a database that was opened with `open_db`.
+///
+/// All data is flushed to disk before the database is closed. The database must not be used
+/// afterwards, even if an error is returned. Passing a null pointer returns an error.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+#[no_mangle]
+pub unsafe extern "C" fn close_db(db: *mut StoreTheHashCidDb) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        if db.is_null() {
+            return Err("Database is a null pointer.".into());
+        }
+        let db = Box::from_raw(db);
+        db.close()?;
+        Ok(RETURN_OK)
+    })
+}
+
+/// Flush all data to disk.
+///
+/// Once it returns successfully, all previous writes are durable.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+#[no_mangle]
+pub unsafe extern "C" fn flush(db: *const StoreTheHashCidDb) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        db_ref(db)?.flush()?;
+        Ok(RETURN_OK)
+    })
+}
+
+/// Get statistics about the database.
+///
+/// Returns 0 on success and 2 on error, `out` is only written on success. This reads the whole
+/// index, hence it can be slow on big databases.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `out` must be a null pointer or point to stats that can be written.
+#[no_mangle]
+pub unsafe extern "C" fn stats(db: *const StoreTheHashCidDb, out: *mut SthStats) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        if out.is_null() {
+            return Err("Stats is a null pointer.".into());
+        }
+        *out = SthStats::new(db_ref(db)?.stats()?)?;
+        Ok(RETURN_OK)
+    })
+}
 
 /// Free a buffer originally allocated by rust
+///
+/// # Safety
+///
+/// `buf` and `sz` must be a buffer and its length that were returned by this library. Each
+/// buffer must only be freed once.
 #[no_mangle]
 pub unsafe extern "C" fn f_free_buf(buf: *mut c_char, sz: size_t) {
-    drop(Vec::from_raw_parts(buf, sz, sz));
+    common::free_buf(buf, sz)
 }
 
 /// Set a key to a value.
+///
+/// Returns 0 on success, 2 on error and 3 if the database was opened read-only.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+/// `val` must be a null pointer or point to `vallen` bytes.
 #[no_mangle]
 pub unsafe extern "C" fn set(
     db: *const StoreTheHashCidDb,
@@ -57,29 +639,95 @@ pub unsafe extern "C" fn set(
     val: *const c_uchar,
     vallen: size_t,
 ) -> u8 {
-    let k = slice::from_raw_parts(key, keylen);
-    let v = slice::from_raw_parts(val, vallen);
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key, keylen)?;
+        if val.is_null() {
+            return Err("Value is a null pointer.".into());
+        }
+        let v = slice::from_raw_parts(val, vallen);
+        let db = db_ref(db)?;
+        if db.is_read_only() {
+            set_last_error(READ_ONLY_MESSAGE.to_string());
+            return Ok(RETURN_READ_ONLY);
+        }
+        db.put(k, v)?;
+        Ok(RETURN_OK)
+    })
+}
 
-    match (*db).put(&k, &v) {
-        Ok(_) => RETURN_OK,
-        Err(_) => RETURN_ERROR,
-    }
+/// Set a key to a value and return the position of the value in the primary storage.
+///
+/// Returns 0 on success, 2 on error and 3 if the database was opened read-only. The offset is only
+/// set on success. If the key already existed, the value is stored anyway, but the key still
+/// resolves to the existing value, see `get_offset`.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+/// `val` must be a null pointer or point to `vallen` bytes.
+/// `offset` must be a null pointer or point to a `uint64_t` that can be written.
+#[no_mangle]
+pub unsafe extern "C" fn set_with_offset(
+    db: *const StoreTheHashCidDb,
+    key: *const c_uchar,
+    keylen: size_t,
+    val: *const c_uchar,
+    vallen: size_t,
+    offset: *mut u64,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key, keylen)?;
+        if val.is_null() {
+            return Err("Value is a null pointer.".into());
+        }
+        if offset.is_null() {
+            return Err("Offset is a null pointer.".into());
+        }
+        let v = slice::from_raw_parts(val, vallen);
+        let db = db_ref(db)?;
+        if db.is_read_only() {
+            set_last_error(READ_ONLY_MESSAGE.to_string());
+            return Ok(RETURN_READ_ONLY);
+        }
+        *offset = db.put(k, v)?;
+        Ok(RETURN_OK)
+    })
 }
 
+/// Check whether a key exists.
+///
+/// Returns 1 if the key exists, 0 if it doesn't and -1 on error.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
 #[no_mangle]
 pub unsafe extern "C" fn has(
     db: *const StoreTheHashCidDb,
     key: *const c_char,
     keylen: size_t,
-) -> size_t {
-    let k = slice::from_raw_parts(key as *const u8, keylen);
-    match (*db).get(&k).unwrap() {
-        Some(_) => 1,
-        _ => 0,
-    }
+) -> c_int {
+    ffi_call(-1, || {
+        let k = key_slice(key as *const u8, keylen)?;
+        match db_ref(db)?.get(k)? {
+            Some(_) => Ok(1),
+            None => Ok(0),
+        }
+    })
 }
 
 /// Get the value of a key.
+///
+/// Returns 0 if the key was found, 1 if it doesn't exist and 2 on error. The value is only set if
+/// the key was found, the caller is then responsible for freeing it with `f_free_buf`.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+/// `val` and `vallen` must be valid pointers that can be written.
 #[no_mangle]
 pub unsafe extern "C" fn get(
     db: *const StoreTheHashCidDb,
@@ -88,64 +736,393 @@ pub unsafe extern "C" fn get(
     val: *mut *const c_char,
     vallen: *mut size_t,
 ) -> u8 {
-    let k = slice::from_raw_parts(key as *const u8, keylen);
-    match (*db).get(&k).unwrap() {
-        Some(data) => {
-            *val = leak_buf(data, vallen);
-            RETURN_OK
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key as *const u8, keylen)?;
+        match db_ref(db)?.get(k)? {
+            Some(data) => {
+                *val = leak_buf(data, vallen);
+                Ok(RETURN_OK)
+            }
+            None => Ok(RETURN_NOT_FOUND),
         }
-        _ => RETURN_ERROR,
-    }
+    })
+}
+
+/// Get the position of the value of a key in the primary storage.
+///
+/// Returns 0 if the key was found, 1 if it doesn't exist and 2 on error. The offset is only set if
+/// the key was found. The value itself is not returned.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+/// `offset` must be a null pointer or point to a `uint64_t` that can be written.
+#[no_mangle]
+pub unsafe extern "C" fn get_offset(
+    db: *const StoreTheHashCidDb,
+    key: *const c_char,
+    keylen: size_t,
+    offset: *mut u64,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key as *const u8, keylen)?;
+        if offset.is_null() {
+            return Err("Offset is a null pointer.".into());
+        }
+        match db_ref(db)?.get_offset(k)? {
+            Some(file_offset) => {
+                *offset = file_offset;
+                Ok(RETURN_OK)
+            }
+            None => Ok(RETURN_NOT_FOUND),
+        }
+    })
+}
+
+/// Get the values of several keys at once.
+///
+/// `keys` and `key_lens` are arrays of `count` keys and their lengths. The outputs are arrays with
+/// `count` elements that are allocated by the caller. The status of every key is set to 0 if it
+/// was found, 1 if it doesn't exist and 2 on error. The value and its length are only set if the
+/// key was found, the caller is then responsible for freeing it with `f_free_buf`. For all other
+/// keys the value is set to a null pointer and the length to 0.
+///
+/// Returns 0 if all keys were looked up, even if some of the lookups failed, and 2 on error, e.g.
+/// if one of the arrays is a null pointer. Then none of the outputs is set. If lookups failed,
+/// the message of the last one is available through `last_error_message`.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// The arrays must be null pointers or have `count` elements, the output arrays must be
+/// writable. Every key must point to as many bytes as its length.
+#[no_mangle]
+pub unsafe extern "C" fn get_many(
+    db: *const StoreTheHashCidDb,
+    keys: *const *const c_uchar,
+    key_lens: *const size_t,
+    count: size_t,
+    vals_out: *mut *mut c_char,
+    val_lens_out: *mut size_t,
+    statuses_out: *mut u8,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        if keys.is_null()
+            || key_lens.is_null()
+            || vals_out.is_null()
+            || val_lens_out.is_null()
+            || statuses_out.is_null()
+        {
+            return Err("Array is a null pointer.".into());
+        }
+        let db = db_ref(db)?;
+        let keys = slice::from_raw_parts(keys, count)
+            .iter()
+            .zip(slice::from_raw_parts(key_lens, count))
+            .map(|(key, keylen)| key_slice(*key, *keylen))
+            .collect::<Result<Vec<_>, _>>()?;
+        let results = db.get_many(&keys)?;
+
+        let vals = slice::from_raw_parts_mut(vals_out, count);
+        let val_lens = slice::from_raw_parts_mut(val_lens_out, count);
+        let statuses = slice::from_raw_parts_mut(statuses_out, count);
+        for (ii, result) in results.into_iter().enumerate() {
+            vals[ii] = ptr::null_mut();
+            val_lens[ii] = 0;
+            statuses[ii] = match result {
+                Ok(Some(data)) => {
+                    vals[ii] = leak_buf(data, &mut val_lens[ii]);
+                    RETURN_OK
+                }
+                Ok(None) => RETURN_NOT_FOUND,
+                Err(error) => {
+                    set_last_error(error.to_string());
+                    RETURN_ERROR
+                }
+            };
+        }
+        Ok(RETURN_OK)
+    })
+}
+
+/// Copy the value of a key into a buffer provided by the caller.
+///
+/// Returns 0 if the key was found and the value was copied, 1 if it doesn't exist, 2 on error and
+/// 4 if the buffer is too small. On success `written` is set to the length of the value, if the
+/// buffer is too small it's set to the required length and nothing is copied. Together with
+/// `get_len` this allows to retrieve values without buffers that need to be freed.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+/// `buf` must be a null pointer or point to `buf_len` bytes that can be written. `written` must
+/// be a null pointer or point to a `size_t` that can be written.
+#[no_mangle]
+pub unsafe extern "C" fn get_into(
+    db: *const StoreTheHashCidDb,
+    key: *const c_char,
+    keylen: size_t,
+    buf: *mut u8,
+    buf_len: size_t,
+    written: *mut size_t,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key as *const u8, keylen)?;
+        if written.is_null() {
+            return Err("Written length is a null pointer.".into());
+        }
+        match db_ref(db)?.get(k)? {
+            Some(data) => {
+                *written = data.len();
+                if data.len() > buf_len {
+                    set_last_error("Buffer is too small.".to_string());
+                    return Ok(RETURN_BUFFER_TOO_SMALL);
+                }
+                // An empty value can be copied into any buffer, even a null pointer.
+                if !data.is_empty() {
+                    if buf.is_null() {
+                        return Err("Buffer is a null pointer.".into());
+                    }
+                    ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+                }
+                Ok(RETURN_OK)
+            }
+            None => Ok(RETURN_NOT_FOUND),
+        }
+    })
 }
 
+/// Get the length of the value of a key.
+///
+/// Returns 0 if the key was found, 1 if it doesn't exist and 2 on error. The length is only set if
+/// the key was found.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+/// `vallen` must be a null pointer or point to a `size_t` that can be written.
 #[no_mangle]
 pub unsafe extern "C" fn get_len(
     db: *const StoreTheHashCidDb,
     key: *const c_char,
     keylen: size_t,
-) -> c_long {
-    let k = slice::from_raw_parts(key as *const u8, keylen);
-    match (*db).get(&k).unwrap() {
-        Some(data) => data.len() as c_long,
-        _ => -1,
-    }
+    vallen: *mut size_t,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key as *const u8, keylen)?;
+        if vallen.is_null() {
+            return Err("Value length is a null pointer.".into());
+        }
+        match db_ref(db)?.get(k)? {
+            Some(data) => {
+                *vallen = data.len();
+                Ok(RETURN_OK)
+            }
+            None => Ok(RETURN_NOT_FOUND),
+        }
+    })
 }
 
 /// Delete the value of a key.
+///
+/// Returns 0 if the key was deleted, 1 if it didn't exist, 2 on error and 3 if the database was
+/// opened read-only.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
 #[no_mangle]
 pub unsafe extern "C" fn del(
-    _db: *const StoreTheHashCidDb,
-    _key: *const c_char,
-    _keylen: size_t,
-    _serial_number: size_t,
+    db: *const StoreTheHashCidDb,
+    key: *const c_char,
+    keylen: size_t,
 ) -> u8 {
-    todo!()
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key as *const u8, keylen)?;
+        let db = db_ref(db)?;
+        if db.is_read_only() {
+            set_last_error(READ_ONLY_MESSAGE.to_string());
+            return Ok(RETURN_READ_ONLY);
+        }
+        if db.delete(k)? {
+            Ok(RETURN_OK)
+        } else {
+            Ok(RETURN_NOT_FOUND)
+        }
+    })
 }
 
-pub struct Iter {}
+/// The callback that is called by `for_each` for every key-value pair.
+///
+/// The buffers are only valid during the call. Returning a non-zero value stops the iteration.
+pub type ForEachCallback = extern "C" fn(
+    key: *const u8,
+    key_len: size_t,
+    val: *const u8,
+    val_len: size_t,
+    ctx: *mut c_void,
+) -> u8;
+
+/// Call a function for every key-value pair.
+///
+/// The `ctx` is passed on to every call of the callback. The iteration stops early if the
+/// callback returns a non-zero value. Returns 0 on success (also if it was stopped early) and 2 on
+/// error.
+///
+/// The database is locked during the whole iteration, the callback must not call any functions on
+/// the same database, else it deadlocks.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+#[no_mangle]
+pub unsafe extern "C" fn for_each(
+    db: *const StoreTheHashCidDb,
+    callback: Option<ForEachCallback>,
+    ctx: *mut c_void,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let callback = callback.ok_or("Callback is a null pointer.")?;
+        db_ref(db)?.for_each(|key, value| {
+            callback(key.as_ptr(), key.len(), value.as_ptr(), value.len(), ctx) == 0
+        })?;
+        Ok(RETURN_OK)
+    })
+}
+
+/// An iterator over all key-value pairs of a database, see `iter`.
+///
+/// Only the entries of a single bucket are kept in memory.
+///
+/// cbindgen:ignore
+#[derive(Debug)]
+pub struct Iter {
+    db: *const StoreTheHashCidDb,
+    /// The next bucket whose entries are read.
+    bucket: usize,
+    /// The entries of the current bucket that weren't returned yet.
+    entries: Entries,
+}
+
+impl Iter {
+    /// Returns the next key-value pair, or `None` if all of them were returned.
+    unsafe fn next(&mut self) -> Result<Option<Entry>, Box<dyn Error>> {
+        if self.entries.is_empty() {
+            self.entries = db_ref(self.db)?.next_entries(&mut self.bucket)?;
+        }
+        Ok(self.entries.pop_front())
+    }
+}
 
 /// Free an iterator.
+///
+/// # Safety
+///
+/// `iter` must be an iterator returned by `iter` that wasn't freed yet.
 #[no_mangle]
 pub unsafe extern "C" fn free_iter(iter: *mut Iter) {
-    drop(Box::from_raw(iter));
+    ffi_call((), || {
+        drop(Box::from_raw(iter));
+        Ok(())
+    })
 }
 
 /// Iterate over all tuples.
-/// Caller is responsible for freeing the returned iterator with
-/// `free_iter`.
+///
+/// The entries are ordered by the buckets they are in. The database is not locked between the
+/// calls, entries that are added or deleted during the iteration may or may not be returned. The
+/// iterator must be freed with `free_iter` before the database is closed. Returns a null pointer
+/// on error.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `open_db` that wasn't closed.
+/// The database must not be closed before the returned iterator is freed.
 #[no_mangle]
-pub unsafe extern "C" fn iter(_db: *const StoreTheHashCidDb) -> *mut Iter {
-    todo!()
+pub unsafe extern "C" fn iter(db: *const StoreTheHashCidDb) -> *mut Iter {
+    ffi_call(ptr::null_mut(), || {
+        db_ref(db)?;
+        Ok(Box::into_raw(Box::new(Iter {
+            db,
+            bucket: 0,
+            entries: VecDeque::new(),
+        })))
+    })
 }
 
-/// Get they next key from an iterator.
-/// Caller is responsible for freeing the key with `free_buf`.
-/// Returns 0 when exhausted.
+/// Get the next key from an iterator.
+///
+/// The caller is responsible for freeing the key with `f_free_buf`. Returns 1 if a key was
+/// returned, 0 when the iterator is exhausted and 2 on error. After an error, the iterator
+/// should be freed.
+///
+/// It can be mixed with `iter_next`, every call advances the iterator by one entry.
+///
+/// # Safety
+///
+/// `iter` must be a null pointer or an iterator returned by `iter` that wasn't freed yet.
+/// `key` and `keylen` must be null pointers or valid pointers that can be written.
 #[no_mangle]
 pub unsafe extern "C" fn iter_next_key(
-    _iter: *mut Iter,
-    _key: *mut *const c_char,
-    _keylen: *mut size_t,
+    iter: *mut Iter,
+    key: *mut *const c_char,
+    keylen: *mut size_t,
 ) -> c_uchar {
-    todo!()
+    ffi_call(RETURN_ERROR, || {
+        let iter = iter.as_mut().ok_or("Iterator is a null pointer.")?;
+        if key.is_null() || keylen.is_null() {
+            return Err("Key is a null pointer.".into());
+        }
+        match iter.next()? {
+            Some((k, _v)) => {
+                *key = leak_buf(k, keylen);
+                Ok(ITER_ENTRY)
+            }
+            None => Ok(ITER_EXHAUSTED),
+        }
+    })
+}
+
+/// Get the next key and its value from an iterator.
+///
+/// The caller is responsible for freeing the key and the value with `f_free_buf`. Returns 1 if an
+/// entry was returned, 0 when the iterator is exhausted and 2 on error, e.g. if the value
+/// couldn't be read. After an error, the iterator should be freed.
+///
+/// It can be mixed with `iter_next_key`, every call advances the iterator by one entry.
+///
+/// # Safety
+///
+/// `iter` must be a null pointer or an iterator returned by `iter` that wasn't freed yet.
+/// `key`, `keylen`, `val` and `vallen` must be null pointers or valid pointers that can be
+/// written.
+#[no_mangle]
+pub unsafe extern "C" fn iter_next(
+    iter: *mut Iter,
+    key: *mut *const c_char,
+    keylen: *mut size_t,
+    val: *mut *const c_char,
+    vallen: *mut size_t,
+) -> c_uchar {
+    ffi_call(RETURN_ERROR, || {
+        let iter = iter.as_mut().ok_or("Iterator is a null pointer.")?;
+        if key.is_null() || keylen.is_null() {
+            return Err("Key is a null pointer.".into());
+        }
+        if val.is_null() || vallen.is_null() {
+            return Err("Value is a null pointer.".into());
+        }
+        match iter.next()? {
+            Some((k, v)) => {
+                *key = leak_buf(k, keylen);
+                *val = leak_buf(v, vallen);
+                Ok(ITER_ENTRY)
+            }
+            None => Ok(ITER_EXHAUSTED),
+        }
+    })
 }
@@ -0,0 +1,957 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::ptr;
+use std::thread;
+
+use libc::{c_char, c_void, size_t};
+use storethehash::index::INDEX_VERSION;
+use storethehash::prelude::*;
+use storethehash_db_cid::{
+    close_db, del, f_free_buf, flush, for_each, free_iter, get, get_into, get_len, get_many,
+    get_offset, has, iter, iter_next, iter_next_key, last_error_length, last_error_message,
+    open_db, open_db_bytes, open_db_read_only, open_db_with_options, open_db_with_options_bytes,
+    set, set_with_offset, stats, sth_features, sth_index_version, sth_version, SthOptions,
+    SthStats, SthSyncPolicy, StoreTheHashCidDb, STH_FEATURE_BYTE_PATHS, STH_FEATURE_DELETE,
+    STH_FEATURE_FLUSH, STH_FEATURE_FOR_EACH, STH_FEATURE_GET_INTO, STH_FEATURE_GET_MANY,
+    STH_FEATURE_ITER, STH_FEATURE_LAST_ERROR, STH_FEATURE_OFFSETS, STH_FEATURE_OPTIONS,
+    STH_FEATURE_READ_ONLY, STH_FEATURE_STATS, STH_FEATURE_STRICT_DEDUP,
+};
+use storethehash_primary_cid::CidPrimary;
+
+const RETURN_OK: u8 = 0;
+const RETURN_NOT_FOUND: u8 = 1;
+const RETURN_ERROR: u8 = 2;
+const RETURN_READ_ONLY: u8 = 3;
+const RETURN_BUFFER_TOO_SMALL: u8 = 4;
+
+// A CIDv1 with the raw codec and a SHA2-256 multihash.
+fn cid(digest_byte: u8) -> Vec<u8> {
+    [&[0x01, 0x55, 0x12, 0x20][..], &[digest_byte; 32][..]].concat()
+}
+
+fn open(path: &Path) -> *mut StoreTheHashCidDb {
+    let path = CString::new(path.to_str().unwrap()).unwrap();
+    let db = unsafe { open_db(path.as_ptr()) };
+    assert!(!db.is_null());
+    db
+}
+
+unsafe fn get_value(db: *const StoreTheHashCidDb, key: &[u8]) -> Option<Vec<u8>> {
+    let mut val: *const c_char = ptr::null();
+    let mut vallen: size_t = 0;
+    match get(
+        db,
+        key.as_ptr() as *const c_char,
+        key.len(),
+        &mut val,
+        &mut vallen,
+    ) {
+        RETURN_OK => {
+            let value = std::slice::from_raw_parts(val as *const u8, vallen).to_vec();
+            f_free_buf(val as *mut c_char, vallen);
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+fn last_error() -> Option<String> {
+    let len = last_error_length();
+    if len == 0 {
+        return None;
+    }
+    let mut buf = vec![0u8; len];
+    let written = unsafe { last_error_message(buf.as_mut_ptr() as *mut c_char, buf.len()) };
+    assert_eq!(written, len as i64 - 1);
+    buf.truncate(len - 1);
+    Some(String::from_utf8(buf).unwrap())
+}
+
+#[test]
+fn close_and_reopen() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let key = cid(0xaa);
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(close_db(db), RETURN_OK);
+
+        let db = open(&db_path);
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn close_null() {
+    let result = unsafe { close_db(ptr::null_mut()) };
+    assert_eq!(result, RETURN_ERROR);
+}
+
+#[test]
+fn delete() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let key = cid(0xbb);
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+
+        let result = del(db, key.as_ptr() as *const c_char, key.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(get_value(db, &key), None);
+
+        // Deleting it again doesn't find the key.
+        let result = del(db, key.as_ptr() as *const c_char, key.len());
+        assert_eq!(result, RETURN_NOT_FOUND);
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn error_nonexistent_directory() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("nonexistent").join("storethehash.db");
+    let path = CString::new(db_path.to_str().unwrap()).unwrap();
+
+    let db = unsafe { open_db(path.as_ptr()) };
+    assert!(db.is_null());
+    let message = last_error().unwrap();
+    assert!(!message.is_empty());
+
+    // A buffer that is too small isn't written to.
+    let mut buf = vec![0u8; 1];
+    let written = unsafe { last_error_message(buf.as_mut_ptr() as *mut c_char, buf.len()) };
+    assert_eq!(written, -1);
+    assert_eq!(buf, [0]);
+}
+
+#[test]
+fn error_bad_key() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let key = b"not a cid";
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        assert_eq!(last_error(), None);
+
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_ERROR);
+        assert!(last_error().is_some());
+
+        let result = has(db, key.as_ptr() as *const c_char, key.len());
+        assert_eq!(result, -1);
+        assert!(last_error().is_some());
+
+        // A successful call clears the error.
+        assert_eq!(close_db(db), RETURN_OK);
+        assert_eq!(last_error(), None);
+    }
+}
+
+#[test]
+fn error_null_pointer() {
+    let key = cid(0xcc);
+    let result = unsafe { del(ptr::null(), key.as_ptr() as *const c_char, key.len()) };
+    assert_eq!(result, RETURN_ERROR);
+    assert_eq!(last_error().unwrap(), "Database is a null pointer.");
+}
+
+unsafe fn open_with_options(
+    primary_path: &Path,
+    index_path: &Path,
+    options: *const SthOptions,
+) -> *mut StoreTheHashCidDb {
+    let primary_path = CString::new(primary_path.to_str().unwrap()).unwrap();
+    let index_path = CString::new(index_path.to_str().unwrap()).unwrap();
+    open_db_with_options(primary_path.as_ptr(), index_path.as_ptr(), options)
+}
+
+#[test]
+fn options_buckets_bits() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary_path = temp_dir.path().join("storethehash.db");
+    // The index is in a different directory than the primary storage.
+    fs::create_dir(temp_dir.path().join("index")).unwrap();
+    let index_path = temp_dir.path().join("index").join("storethehash.index");
+    let key = cid(0xdd);
+    let value = b"some value";
+    let options = SthOptions {
+        buckets_bits: 16,
+        read_only: false,
+        sync_policy: SthSyncPolicy::Always,
+    };
+
+    unsafe {
+        let db = open_with_options(&primary_path, &index_path, &options);
+        assert!(!db.is_null());
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(close_db(db), RETURN_OK);
+
+        let db = open_with_options(&primary_path, &index_path, &options);
+        assert!(!db.is_null());
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+        assert_eq!(close_db(db), RETURN_OK);
+
+        // Opening it with a different number of bits fails.
+        let mismatched = SthOptions {
+            buckets_bits: 24,
+            ..options
+        };
+        let db = open_with_options(&primary_path, &index_path, &mismatched);
+        assert!(db.is_null());
+        assert!(last_error().unwrap().contains("`16`, expected `24`"));
+
+        // Unsupported number of bits.
+        let unsupported = SthOptions {
+            buckets_bits: 17,
+            ..options
+        };
+        let db = open_with_options(&primary_path, &index_path, &unsupported);
+        assert!(db.is_null());
+        assert!(last_error().is_some());
+    }
+}
+
+#[test]
+fn options_read_only() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary_path = temp_dir.path().join("storethehash.db");
+    let index_path = temp_dir.path().join("storethehash.index");
+    let key = cid(0xee);
+    let value = b"some value";
+    let read_only = SthOptions {
+        read_only: true,
+        ..SthOptions::default()
+    };
+
+    unsafe {
+        // A read-only database isn't created.
+        let db = open_with_options(&primary_path, &index_path, &read_only);
+        assert!(db.is_null());
+        assert!(!primary_path.exists());
+
+        let db = open_with_options(&primary_path, &index_path, ptr::null());
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(close_db(db), RETURN_OK);
+
+        let db = open_with_options(&primary_path, &index_path, &read_only);
+        assert!(!db.is_null());
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_READ_ONLY);
+        assert_eq!(last_error().unwrap(), "Store opened read-only.");
+        let result = del(db, key.as_ptr() as *const c_char, key.len());
+        assert_eq!(result, RETURN_READ_ONLY);
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn flush_to_disk() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let key = cid(0xff);
+    let value = b"some flushed value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(flush(db), RETURN_OK);
+
+        // The data is on disk while the database is still open.
+        let data = fs::read(&db_path).unwrap();
+        assert!(data.ends_with(&[&key[..], &value[..]].concat()));
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn flush_null() {
+    let result = unsafe { flush(ptr::null()) };
+    assert_eq!(result, RETURN_ERROR);
+    assert_eq!(last_error().unwrap(), "Database is a null pointer.");
+}
+
+// This test doesn't use threads, so that it can also be run under Miri (with
+// `-Zmiri-disable-isolation` for the file access) to check that no value is leaked.
+#[test]
+fn get_many_found_missing_and_error() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let found1 = cid(0x11);
+    let found2 = cid(0x22);
+    let missing = cid(0x33);
+    let bad_key = b"not a cid".to_vec();
+
+    unsafe {
+        let db = open(&db_path);
+        let result = set(db, found1.as_ptr(), found1.len(), b"value 1".as_ptr(), 7);
+        assert_eq!(result, RETURN_OK);
+        let result = set(db, found2.as_ptr(), found2.len(), b"value 22".as_ptr(), 8);
+        assert_eq!(result, RETURN_OK);
+
+        let keys = [&found1, &missing, &bad_key, &found2];
+        let key_ptrs: Vec<*const u8> = keys.iter().map(|key| key.as_ptr()).collect();
+        let key_lens: Vec<size_t> = keys.iter().map(|key| key.len()).collect();
+        let mut vals = vec![ptr::null_mut(); keys.len()];
+        // Non-zero lengths, to make sure they are overwritten.
+        let mut val_lens = vec![99; keys.len()];
+        let mut statuses = vec![99; keys.len()];
+        let result = get_many(
+            db,
+            key_ptrs.as_ptr(),
+            key_lens.as_ptr(),
+            keys.len(),
+            vals.as_mut_ptr(),
+            val_lens.as_mut_ptr(),
+            statuses.as_mut_ptr(),
+        );
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(
+            statuses,
+            [RETURN_OK, RETURN_NOT_FOUND, RETURN_ERROR, RETURN_OK]
+        );
+        assert_eq!(val_lens, [7, 0, 0, 8]);
+        assert!(vals[1].is_null());
+        assert!(vals[2].is_null());
+        // The error of the failed lookup is available.
+        assert!(last_error().is_some());
+
+        let value1 = std::slice::from_raw_parts(vals[0] as *const u8, val_lens[0]).to_vec();
+        let value2 = std::slice::from_raw_parts(vals[3] as *const u8, val_lens[3]).to_vec();
+        assert_eq!(value1, b"value 1");
+        assert_eq!(value2, b"value 22");
+        f_free_buf(vals[0], val_lens[0]);
+        f_free_buf(vals[3], val_lens[3]);
+
+        // Invalid arguments don't touch the outputs.
+        let result = get_many(
+            db,
+            ptr::null(),
+            key_lens.as_ptr(),
+            keys.len(),
+            vals.as_mut_ptr(),
+            val_lens.as_mut_ptr(),
+            statuses.as_mut_ptr(),
+        );
+        assert_eq!(result, RETURN_ERROR);
+        assert_eq!(last_error().unwrap(), "Array is a null pointer.");
+        assert_eq!(val_lens, [7, 0, 0, 8]);
+
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn found_missing_and_error() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let found = cid(0x11);
+    let missing = cid(0x22);
+    let bad_key = b"not a cid";
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = set(db, found.as_ptr(), found.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+
+        // has
+        assert_eq!(has(db, found.as_ptr() as *const c_char, found.len()), 1);
+        assert_eq!(has(db, missing.as_ptr() as *const c_char, missing.len()), 0);
+        assert_eq!(last_error(), None);
+        assert_eq!(
+            has(db, bad_key.as_ptr() as *const c_char, bad_key.len()),
+            -1
+        );
+        assert!(last_error().is_some());
+
+        // get
+        let mut val: *const c_char = ptr::null();
+        let mut vallen: size_t = 0;
+        let result = get(
+            db,
+            found.as_ptr() as *const c_char,
+            found.len(),
+            &mut val,
+            &mut vallen,
+        );
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(std::slice::from_raw_parts(val as *const u8, vallen), value);
+        f_free_buf(val as *mut c_char, vallen);
+        let result = get(
+            db,
+            missing.as_ptr() as *const c_char,
+            missing.len(),
+            &mut val,
+            &mut vallen,
+        );
+        assert_eq!(result, RETURN_NOT_FOUND);
+        assert_eq!(last_error(), None);
+        let result = get(
+            db,
+            bad_key.as_ptr() as *const c_char,
+            bad_key.len(),
+            &mut val,
+            &mut vallen,
+        );
+        assert_eq!(result, RETURN_ERROR);
+        assert!(last_error().is_some());
+
+        // get_len
+        let mut vallen: size_t = 0;
+        let result = get_len(
+            db,
+            found.as_ptr() as *const c_char,
+            found.len(),
+            &mut vallen,
+        );
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(vallen, value.len());
+        let result = get_len(
+            db,
+            missing.as_ptr() as *const c_char,
+            missing.len(),
+            &mut vallen,
+        );
+        assert_eq!(result, RETURN_NOT_FOUND);
+        assert_eq!(last_error(), None);
+        let result = get_len(
+            db,
+            bad_key.as_ptr() as *const c_char,
+            bad_key.len(),
+            &mut vallen,
+        );
+        assert_eq!(result, RETURN_ERROR);
+        assert!(last_error().is_some());
+
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+extern "C" fn collect_entries(
+    key: *const u8,
+    key_len: size_t,
+    val: *const u8,
+    val_len: size_t,
+    ctx: *mut c_void,
+) -> u8 {
+    let entries = unsafe { &mut *(ctx as *mut Vec<(Vec<u8>, Vec<u8>)>) };
+    let key = unsafe { std::slice::from_raw_parts(key, key_len) };
+    let value = unsafe { std::slice::from_raw_parts(val, val_len) };
+    entries.push((key.to_vec(), value.to_vec()));
+    0
+}
+
+extern "C" fn collect_first_entry(
+    key: *const u8,
+    key_len: size_t,
+    val: *const u8,
+    val_len: size_t,
+    ctx: *mut c_void,
+) -> u8 {
+    collect_entries(key, key_len, val, val_len, ctx);
+    1
+}
+
+#[test]
+fn for_each_entry() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let index_path = temp_dir.path().join("storethehash.index");
+    let options = SthOptions {
+        buckets_bits: 8,
+        ..SthOptions::default()
+    };
+    let mut expected: Vec<(Vec<u8>, Vec<u8>)> = (1..=5)
+        .map(|ii| (cid(ii * 17), format!("value {}", ii).into_bytes()))
+        .collect();
+
+    unsafe {
+        let db = open_with_options(&db_path, &index_path, &options);
+        for (key, value) in &expected {
+            let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+            assert_eq!(result, RETURN_OK);
+        }
+
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let ctx = &mut entries as *mut _ as *mut c_void;
+        assert_eq!(for_each(db, Some(collect_entries), ctx), RETURN_OK);
+        entries.sort();
+        expected.sort();
+        assert_eq!(entries, expected);
+
+        // The iteration stops early.
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let ctx = &mut entries as *mut _ as *mut c_void;
+        assert_eq!(for_each(db, Some(collect_first_entry), ctx), RETURN_OK);
+        assert_eq!(entries.len(), 1);
+
+        assert_eq!(for_each(db, None, ptr::null_mut()), RETURN_ERROR);
+        assert_eq!(last_error().unwrap(), "Callback is a null pointer.");
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+unsafe fn take_buf(buf: *const c_char, len: size_t) -> Vec<u8> {
+    let data = std::slice::from_raw_parts(buf as *const u8, len).to_vec();
+    f_free_buf(buf as *mut c_char, len);
+    data
+}
+
+#[test]
+fn iter_entries() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let index_path = temp_dir.path().join("storethehash.index");
+    let options = SthOptions {
+        buckets_bits: 8,
+        ..SthOptions::default()
+    };
+    let mut expected: Vec<(Vec<u8>, Vec<u8>)> = (1..=5)
+        .map(|ii| (cid(ii * 17), format!("value {}", ii).into_bytes()))
+        .collect();
+
+    unsafe {
+        let db = open_with_options(&db_path, &index_path, &options);
+        for (key, value) in &expected {
+            let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+            assert_eq!(result, RETURN_OK);
+        }
+
+        let mut key: *const c_char = ptr::null();
+        let mut keylen: size_t = 0;
+        let mut val: *const c_char = ptr::null();
+        let mut vallen: size_t = 0;
+
+        let it = iter(db);
+        assert!(!it.is_null());
+        let mut entries = Vec::new();
+        while iter_next(it, &mut key, &mut keylen, &mut val, &mut vallen) == 1 {
+            entries.push((take_buf(key, keylen), take_buf(val, vallen)));
+        }
+        assert_eq!(last_error(), None);
+        // An exhausted iterator stays exhausted.
+        assert_eq!(
+            iter_next(it, &mut key, &mut keylen, &mut val, &mut vallen),
+            0
+        );
+        free_iter(it);
+        entries.sort();
+        expected.sort();
+        assert_eq!(entries, expected);
+
+        // Both functions advance the same iterator.
+        let it = iter(db);
+        let mut entries = Vec::new();
+        assert_eq!(iter_next_key(it, &mut key, &mut keylen), 1);
+        entries.push(take_buf(key, keylen));
+        while iter_next(it, &mut key, &mut keylen, &mut val, &mut vallen) == 1 {
+            entries.push(take_buf(key, keylen));
+            take_buf(val, vallen);
+        }
+        free_iter(it);
+        entries.sort();
+        let expected_keys: Vec<Vec<u8>> = expected.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(entries, expected_keys);
+
+        let it = iter(db);
+        assert_eq!(
+            iter_next(it, ptr::null_mut(), &mut keylen, &mut val, &mut vallen),
+            2
+        );
+        assert_eq!(last_error().unwrap(), "Key is a null pointer.");
+        free_iter(it);
+        assert!(iter(ptr::null()).is_null());
+
+        // Reading the values fails once the primary storage is gone.
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&db_path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        let it = iter(db);
+        assert_eq!(
+            iter_next(it, &mut key, &mut keylen, &mut val, &mut vallen),
+            2
+        );
+        assert!(last_error().is_some());
+        free_iter(it);
+        close_db(db);
+    }
+}
+
+#[test]
+fn concurrent_access() {
+    const THREADS: u8 = 8;
+    const KEYS_PER_THREAD: u8 = 25;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<StoreTheHashCidDb>();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let index_path = temp_dir.path().join("storethehash.index");
+    let options = SthOptions {
+        buckets_bits: 8,
+        ..SthOptions::default()
+    };
+    let db = unsafe { open_with_options(&db_path, &index_path, &options) };
+    // Raw pointers cannot be sent to other threads.
+    let db_addr = db as usize;
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            thread::spawn(move || {
+                let db = db_addr as *const StoreTheHashCidDb;
+                for ii in 0..KEYS_PER_THREAD {
+                    let key = [&[0x01, 0x55, 0x12, 0x20, thread, ii][..], &[0xab; 30][..]].concat();
+                    let value = vec![thread; usize::from(ii) + 1];
+                    unsafe {
+                        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+                        assert_eq!(result, RETURN_OK);
+                        assert_eq!(get_value(db, &key), Some(value.clone()));
+                        let mut vallen: size_t = 0;
+                        let result =
+                            get_len(db, key.as_ptr() as *const c_char, key.len(), &mut vallen);
+                        assert_eq!(result, RETURN_OK);
+                        assert_eq!(vallen, value.len());
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    unsafe {
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let ctx = &mut entries as *mut _ as *mut c_void;
+        assert_eq!(for_each(db, Some(collect_entries), ctx), RETURN_OK);
+        assert_eq!(
+            entries.len(),
+            usize::from(THREADS) * usize::from(KEYS_PER_THREAD)
+        );
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn read_only_permissions() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary_path = temp_dir.path().join("storethehash.db");
+    let index_path = temp_dir.path().join("storethehash.index");
+    let key = cid(0x44);
+    let value = b"some value";
+
+    unsafe {
+        let db = open_with_options(&primary_path, &index_path, ptr::null());
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+
+    // The files are owned by a different process which only grants read access.
+    for path in &[&primary_path, &index_path] {
+        let mut permissions = fs::metadata(path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(path, permissions).unwrap();
+    }
+    let primary_size = fs::metadata(&primary_path).unwrap().len();
+
+    let primary_path_c = CString::new(primary_path.to_str().unwrap()).unwrap();
+    let index_path_c = CString::new(index_path.to_str().unwrap()).unwrap();
+    unsafe {
+        let db = open_db_read_only(primary_path_c.as_ptr(), index_path_c.as_ptr());
+        assert!(!db.is_null());
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+
+        let other_key = cid(0x45);
+        let result = set(
+            db,
+            other_key.as_ptr(),
+            other_key.len(),
+            value.as_ptr(),
+            value.len(),
+        );
+        assert_eq!(result, RETURN_READ_ONLY);
+        assert_eq!(last_error().unwrap(), "Store opened read-only.");
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+    assert_eq!(fs::metadata(&primary_path).unwrap().len(), primary_size);
+}
+
+#[test]
+fn stats_match_rust_api() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let index_path = temp_dir.path().join("storethehash.db.index");
+
+    let mut ffi_stats = SthStats::default();
+    unsafe {
+        let db = open(&db_path);
+        for digest_byte in 0..3 {
+            let key = cid(digest_byte);
+            let result = set(db, key.as_ptr(), key.len(), b"value".as_ptr(), 5);
+            assert_eq!(result, RETURN_OK);
+        }
+        // Deleting a key supersedes the record list of its bucket.
+        let key = cid(0);
+        let result = del(db, key.as_ptr() as *const c_char, key.len());
+        assert_eq!(result, RETURN_OK);
+
+        assert_eq!(stats(db, &mut ffi_stats), RETURN_OK);
+        assert_eq!(stats(db, ptr::null_mut()), RETURN_ERROR);
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+
+    let primary = CidPrimary::open_read_only(&db_path).unwrap();
+    let db = Db::<_, 24>::open_read_only(primary, &index_path).unwrap();
+    let rust_stats = db.stats().unwrap();
+    assert_eq!(
+        ffi_stats,
+        SthStats {
+            records: 2,
+            occupied_buckets: 2,
+            index_bytes: rust_stats.index_size,
+            live_index_bytes: rust_stats.live_index_size,
+            primary_bytes: rust_stats.primary_size.unwrap(),
+            estimated: 0,
+        }
+    );
+    assert_eq!(rust_stats.index.records, 2);
+    assert!(ffi_stats.live_index_bytes < ffi_stats.index_bytes);
+    assert_eq!(
+        ffi_stats.primary_bytes,
+        fs::metadata(&db_path).unwrap().len()
+    );
+}
+
+#[test]
+fn get_into_buffer() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let found = cid(0x11);
+    let missing = cid(0x22);
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = set(db, found.as_ptr(), found.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+
+        // Exact fit
+        let mut buf = vec![0u8; value.len()];
+        let mut written: size_t = 0;
+        let result = get_into(
+            db,
+            found.as_ptr() as *const c_char,
+            found.len(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut written,
+        );
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(written, value.len());
+        assert_eq!(buf, value);
+
+        // Too small, the required size is returned
+        let mut buf = vec![0u8; value.len() - 1];
+        let mut written: size_t = 0;
+        let result = get_into(
+            db,
+            found.as_ptr() as *const c_char,
+            found.len(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut written,
+        );
+        assert_eq!(result, RETURN_BUFFER_TOO_SMALL);
+        assert_eq!(written, value.len());
+        assert!(buf.iter().all(|byte| *byte == 0));
+        assert_eq!(last_error().unwrap(), "Buffer is too small.");
+
+        // Missing key
+        let mut buf = vec![0u8; 32];
+        let mut written: size_t = 99;
+        let result = get_into(
+            db,
+            missing.as_ptr() as *const c_char,
+            missing.len(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut written,
+        );
+        assert_eq!(result, RETURN_NOT_FOUND);
+        assert_eq!(written, 99);
+        assert_eq!(last_error(), None);
+
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn version_and_features() {
+    let version = sth_version();
+    assert_eq!(
+        format!(
+            "{}.{}.{}",
+            version >> 16,
+            (version >> 8) & 0xff,
+            version & 0xff
+        ),
+        env!("CARGO_PKG_VERSION")
+    );
+    assert_eq!(sth_index_version(), INDEX_VERSION);
+
+    let features = sth_features();
+    for feature in &[
+        STH_FEATURE_DELETE,
+        STH_FEATURE_FOR_EACH,
+        STH_FEATURE_FLUSH,
+        STH_FEATURE_READ_ONLY,
+        STH_FEATURE_OPTIONS,
+        STH_FEATURE_LAST_ERROR,
+        STH_FEATURE_STATS,
+        STH_FEATURE_GET_MANY,
+        STH_FEATURE_GET_INTO,
+        STH_FEATURE_BYTE_PATHS,
+        STH_FEATURE_OFFSETS,
+        STH_FEATURE_ITER,
+    ] {
+        assert_eq!(features & feature, *feature);
+    }
+    assert_eq!(
+        features & STH_FEATURE_STRICT_DEDUP != 0,
+        cfg!(feature = "strict_dedup")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn open_non_utf8_path() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    // Paths on Unix are bytes, they don't need to be valid UTF-8.
+    let db_path = temp_dir
+        .path()
+        .join(OsStr::from_bytes(b"storethehash-\xff\xfe.db"));
+    let db_path_bytes = db_path.as_os_str().as_bytes();
+    let key = cid(0xaa);
+    let value = b"some value";
+
+    unsafe {
+        let db = open_db_bytes(db_path_bytes.as_ptr(), db_path_bytes.len());
+        assert!(!db.is_null());
+        let result = set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(close_db(db), RETURN_OK);
+
+        // The old entry point accepts the same path as null terminated string.
+        let c_path = CString::new(db_path_bytes).unwrap();
+        let db = open_db(c_path.as_ptr());
+        assert!(!db.is_null());
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+        assert_eq!(close_db(db), RETURN_OK);
+
+        let index_path = temp_dir
+            .path()
+            .join(OsStr::from_bytes(b"storethehash-\xff\xfe.db.index"));
+        assert!(index_path.exists());
+        let index_path_bytes = index_path.as_os_str().as_bytes();
+        let db = open_db_with_options_bytes(
+            db_path_bytes.as_ptr(),
+            db_path_bytes.len(),
+            index_path_bytes.as_ptr(),
+            index_path_bytes.len(),
+            ptr::null(),
+        );
+        assert!(!db.is_null());
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+        assert_eq!(close_db(db), RETURN_OK);
+
+        let db = open_db_bytes(ptr::null(), 0);
+        assert!(db.is_null());
+        assert_eq!(last_error().unwrap(), "Path is a null pointer.");
+    }
+}
+
+#[test]
+fn offsets() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let missing = cid(0x99);
+
+    unsafe {
+        let db = open(&db_path);
+        let mut offsets = Vec::new();
+        for digest_byte in 1..=3 {
+            let key = cid(digest_byte);
+            let mut offset = u64::MAX;
+            let result = set_with_offset(
+                db,
+                key.as_ptr(),
+                key.len(),
+                b"value".as_ptr(),
+                5,
+                &mut offset,
+            );
+            assert_eq!(result, RETURN_OK);
+            offsets.push(offset);
+        }
+        // The values are appended to the primary storage.
+        assert_eq!(offsets[0], 0);
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+
+        for (digest_byte, expected) in (1..=3).zip(&offsets) {
+            let key = cid(digest_byte);
+            let mut offset = u64::MAX;
+            let result = get_offset(db, key.as_ptr() as *const c_char, key.len(), &mut offset);
+            assert_eq!(result, RETURN_OK);
+            assert_eq!(offset, *expected);
+        }
+
+        let mut offset = u64::MAX;
+        let result = get_offset(
+            db,
+            missing.as_ptr() as *const c_char,
+            missing.len(),
+            &mut offset,
+        );
+        assert_eq!(result, RETURN_NOT_FOUND);
+        assert_eq!(offset, u64::MAX);
+
+        let key = cid(1);
+        let result = set_with_offset(
+            db,
+            key.as_ptr(),
+            key.len(),
+            b"value".as_ptr(),
+            5,
+            ptr::null_mut(),
+        );
+        assert_eq!(result, RETURN_ERROR);
+        assert_eq!(last_error().unwrap(), "Offset is a null pointer.");
+
+        assert_eq!(close_db(db), RETURN_OK);
+    }
+}
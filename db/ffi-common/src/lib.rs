@@ -0,0 +1,185 @@
+//! Helpers that are shared by the C-APIs of the databases.
+//!
+//! The functions that are exported to C are not part of this crate. They are defined by each of
+//! the C-API crates, so that their symbols can have different names.
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::CStr;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::slice;
+#[cfg(not(unix))]
+use std::str;
+
+use libc::{c_char, c_long, c_uchar, size_t};
+
+// The status codes that are returned by the functions.
+pub const RETURN_OK: u8 = 0;
+pub const RETURN_NOT_FOUND: u8 = 1;
+pub const RETURN_ERROR: u8 = 2;
+
+thread_local! {
+    /// The message of the most recent error that happened on this thread.
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn set_last_error(message: String) {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+/// Runs the body of an exported function.
+///
+/// Panics must not unwind across the FFI boundary, they are caught and treated like errors. The
+/// error message of the call is stored so that it can be retrieved with `last_error_message`,
+/// the `error_value` is returned instead. A successful call clears the last error.
+pub fn ffi_call<T, F>(error_value: T, f: F) -> T
+where
+    F: FnOnce() -> Result<T, Box<dyn Error>>,
+{
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = None);
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(error)) => {
+            set_last_error(error.to_string());
+            error_value
+        }
+        Err(payload) => {
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message.clone()
+            } else {
+                "Unknown panic.".to_string()
+            };
+            set_last_error(format!("Panic: {}", message));
+            error_value
+        }
+    }
+}
+
+/// Returns the key as slice, or an error if it is a null pointer.
+///
+/// # Safety
+///
+/// If `key` isn't null, it must point to `keylen` bytes that stay valid and unchanged for the
+/// lifetime `'a`.
+pub unsafe fn key_slice<'a>(
+    key: *const c_uchar,
+    keylen: size_t,
+) -> Result<&'a [u8], Box<dyn Error>> {
+    if key.is_null() {
+        return Err("Key is a null pointer.".into());
+    }
+    Ok(slice::from_raw_parts(key, keylen))
+}
+
+/// Hands the buffer over to the caller, who needs to free it with `free_buf`.
+///
+/// The length of the buffer is written to `vallen`.
+///
+/// # Safety
+///
+/// `vallen` must be a valid, aligned pointer to a `size_t` that can be written.
+pub unsafe fn leak_buf(v: Vec<u8>, vallen: *mut size_t) -> *mut c_char {
+    *vallen = v.len();
+    let mut bsv = v.into_boxed_slice();
+    let val = bsv.as_mut_ptr() as *mut _;
+    mem::forget(bsv);
+    val
+}
+
+/// Frees a buffer that was handed over to the caller with [`leak_buf`].
+///
+/// # Safety
+///
+/// `buf` and `sz` must be a pointer and the length that [`leak_buf`] returned, and the buffer
+/// must not have been freed already.
+pub unsafe fn free_buf(buf: *mut c_char, sz: size_t) {
+    ffi_call((), || {
+        drop(Vec::from_raw_parts(buf, sz, sz));
+        Ok(())
+    })
+}
+
+/// Returns the size of the buffer that is needed for the message of the most recent error.
+///
+/// The size includes the trailing null byte. Returns 0 if the most recent call didn't fail.
+pub fn last_error_length() -> size_t {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(message) => message.len() + 1,
+        None => 0,
+    })
+}
+
+/// Copies the message of the most recent error into the given buffer.
+///
+/// The message is null terminated. Returns the number of bytes written, without the null byte.
+/// If there is no error 0 is returned, if the buffer is too small (or a null pointer) -1 is
+/// returned and nothing is written.
+///
+/// # Safety
+///
+/// If `buf` isn't null, it must point to `len` bytes that can be written.
+pub unsafe fn last_error_message(buf: *mut c_char, len: size_t) -> c_long {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(message) => {
+            if buf.is_null() || len < message.len() + 1 {
+                return -1;
+            }
+            let buf = slice::from_raw_parts_mut(buf as *mut u8, len);
+            buf[..message.len()].copy_from_slice(message.as_bytes());
+            buf[message.len()] = 0;
+            message.len() as c_long
+        }
+        None => 0,
+    })
+}
+
+/// Returns the path of the index, which is stored next to the primary storage.
+pub fn default_index_path(primary_path: &Path) -> PathBuf {
+    let mut index_path = primary_path.as_os_str().to_os_string();
+    index_path.push(".index");
+    PathBuf::from(index_path)
+}
+
+/// Converts the raw bytes of a path into a path.
+///
+/// On Unix paths are just bytes, hence any bytes are accepted. On other platforms the path needs
+/// to be valid UTF-8.
+pub fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(PathBuf::from(str::from_utf8(bytes)?))
+    }
+}
+
+/// Returns the path of a null terminated string, or an error if it is a null pointer.
+///
+/// # Safety
+///
+/// If `path` isn't null, it must point to a null terminated string.
+pub unsafe fn c_path(path: *const c_char) -> Result<PathBuf, Box<dyn Error>> {
+    if path.is_null() {
+        return Err("Path is a null pointer.".into());
+    }
+    path_from_bytes(CStr::from_ptr(path).to_bytes())
+}
+
+/// Returns the path of the given bytes, or an error if it is a null pointer.
+///
+/// # Safety
+///
+/// If `path` isn't null, it must point to `path_len` bytes that can be read.
+pub unsafe fn bytes_path(path: *const u8, path_len: size_t) -> Result<PathBuf, Box<dyn Error>> {
+    if path.is_null() {
+        return Err("Path is a null pointer.".into());
+    }
+    path_from_bytes(slice::from_raw_parts(path, path_len))
+}
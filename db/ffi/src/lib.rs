@@ -0,0 +1,397 @@
+//! C-API for a database with arbitrary keys.
+//!
+//! All exported symbols are prefixed with `sth_raw_`, so that this library can be linked into the
+//! same binary as the C-API for CIDs.
+use std::collections::VecDeque;
+use std::error::Error;
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+use libc::{c_char, c_int, c_long, c_uchar, c_void, size_t};
+use storethehash::prelude::*;
+use storethehash_db_ffi_common::{
+    self as common, c_path, default_index_path, ffi_call, key_slice, leak_buf, RETURN_ERROR,
+    RETURN_NOT_FOUND, RETURN_OK,
+};
+use storethehash_primary_file::FilePrimary;
+
+/// The number of bits used for the buckets.
+const BUCKETS_BITS: u8 = 24;
+
+// The status codes of the iterator functions, which return 2 on error.
+const ITER_EXHAUSTED: u8 = 0;
+const ITER_ENTRY: u8 = 1;
+
+/// A database handle that can be shared between threads.
+///
+/// The database itself is not thread-safe, hence all calls are serialized with a mutex.
+///
+/// cbindgen:ignore
+#[derive(Debug)]
+pub struct StoreTheHashDb(Mutex<Db<FilePrimary, BUCKETS_BITS>>);
+
+impl StoreTheHashDb {
+    fn lock(&self) -> Result<MutexGuard<'_, Db<FilePrimary, BUCKETS_BITS>>, Box<dyn Error>> {
+        // A panic while the lock was held might have left the database in an inconsistent state.
+        self.0
+            .lock()
+            .map_err(|_| "Database is unusable after a previous panic.".into())
+    }
+}
+
+/// Returns the database behind the given pointer, or an error if it is a null pointer.
+unsafe fn db_ref<'a>(db: *const StoreTheHashDb) -> Result<&'a StoreTheHashDb, Box<dyn Error>> {
+    db.as_ref()
+        .ok_or_else(|| "Database is a null pointer.".into())
+}
+
+/// Returns the size of the buffer that is needed for the message of the most recent error.
+///
+/// The size includes the trailing null byte. Returns 0 if the most recent call didn't fail.
+#[no_mangle]
+pub extern "C" fn sth_raw_last_error_length() -> size_t {
+    common::last_error_length()
+}
+
+/// Copies the message of the most recent error into the given buffer.
+///
+/// The message is null terminated. Returns the number of bytes written, without the null byte.
+/// If there is no error 0 is returned, if the buffer is too small (or a null pointer) -1 is
+/// returned and nothing is written.
+///
+/// # Safety
+///
+/// `buf` must be a null pointer or point to `len` bytes that can be written.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_last_error_message(buf: *mut c_char, len: size_t) -> c_long {
+    common::last_error_message(buf, len)
+}
+
+/// Open a database.
+///
+/// The index is stored next to the given path with an `.index` suffix. The database needs to be
+/// closed with `sth_raw_close_db`. Returns a null pointer if the database cannot be opened.
+///
+/// The returned handle can be shared between threads, all functions that take it can be called
+/// concurrently. The calls are serialized internally. The only exception is `sth_raw_close_db`, no
+/// other calls on the same handle must happen during or after it.
+///
+/// # Safety
+///
+/// `path` must be a null pointer or point to a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_open_db(path: *const c_char) -> *mut StoreTheHashDb {
+    ffi_call(ptr::null_mut(), || {
+        let db_path = c_path(path)?;
+        let primary = FilePrimary::open(&db_path)?;
+        let db = Db::open(primary, default_index_path(&db_path))?;
+        Ok(Box::into_raw(Box::new(StoreTheHashDb(Mutex::new(db)))))
+    })
+}
+
+/// Close a database that was opened with `sth_raw_open_db`.
+///
+/// All data is flushed to disk before the database is closed. The database must not be used
+/// afterwards, even if an error is returned. Passing a null pointer returns an error.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `sth_raw_open_db` that wasn't closed.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_close_db(db: *mut StoreTheHashDb) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        if db.is_null() {
+            return Err("Database is a null pointer.".into());
+        }
+        let db = Box::from_raw(db)
+            .0
+            .into_inner()
+            .map_err(|_| "Database is unusable after a previous panic.")?;
+        db.close()?;
+        Ok(RETURN_OK)
+    })
+}
+
+/// Flush all data to disk.
+///
+/// Once it returns successfully, all previous writes are durable.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `sth_raw_open_db` that wasn't closed.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_flush(db: *const StoreTheHashDb) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        db_ref(db)?.lock()?.flush()?;
+        Ok(RETURN_OK)
+    })
+}
+
+/// Free a buffer originally allocated by rust
+///
+/// # Safety
+///
+/// `buf` and `sz` must be a value and its length that were returned by `sth_raw_get`, or a key
+/// or value and its length that were returned by `sth_raw_iter_next`. Each buffer must only be
+/// freed once.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_free_buf(buf: *mut c_char, sz: size_t) {
+    common::free_buf(buf, sz)
+}
+
+/// Set a key to a value.
+///
+/// Returns 0 on success and 2 on error.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `sth_raw_open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+/// `val` must be a null pointer or point to `vallen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_set(
+    db: *const StoreTheHashDb,
+    key: *const c_uchar,
+    keylen: size_t,
+    val: *const c_uchar,
+    vallen: size_t,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key, keylen)?;
+        if val.is_null() {
+            return Err("Value is a null pointer.".into());
+        }
+        let v = slice::from_raw_parts(val, vallen);
+        db_ref(db)?.lock()?.put(k, v)?;
+        Ok(RETURN_OK)
+    })
+}
+
+/// Check whether a key exists.
+///
+/// Returns 1 if the key exists, 0 if it doesn't and -1 on error.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `sth_raw_open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_has(
+    db: *const StoreTheHashDb,
+    key: *const c_uchar,
+    keylen: size_t,
+) -> c_int {
+    ffi_call(-1, || {
+        let k = key_slice(key, keylen)?;
+        match db_ref(db)?.lock()?.get(k)? {
+            Some(_) => Ok(1),
+            None => Ok(0),
+        }
+    })
+}
+
+/// Get the value of a key.
+///
+/// Returns 0 if the key was found, 1 if it doesn't exist and 2 on error. The value is only set if
+/// the key was found, the caller is then responsible for freeing it with `sth_raw_free_buf`.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `sth_raw_open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+/// `val` and `vallen` must be valid pointers that can be written.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_get(
+    db: *const StoreTheHashDb,
+    key: *const c_uchar,
+    keylen: size_t,
+    val: *mut *const c_char,
+    vallen: *mut size_t,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key, keylen)?;
+        match db_ref(db)?.lock()?.get(k)? {
+            Some(data) => {
+                *val = leak_buf(data, vallen);
+                Ok(RETURN_OK)
+            }
+            None => Ok(RETURN_NOT_FOUND),
+        }
+    })
+}
+
+/// Delete the value of a key.
+///
+/// Returns 0 if the key was deleted, 1 if it didn't exist and 2 on error.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `sth_raw_open_db` that wasn't closed.
+/// `key` must be a null pointer or point to `keylen` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_del(
+    db: *const StoreTheHashDb,
+    key: *const c_uchar,
+    keylen: size_t,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let k = key_slice(key, keylen)?;
+        if db_ref(db)?.lock()?.delete(k)? {
+            Ok(RETURN_OK)
+        } else {
+            Ok(RETURN_NOT_FOUND)
+        }
+    })
+}
+
+/// The callback that is called by `sth_raw_for_each` for every key-value pair.
+///
+/// The buffers are only valid during the call. Returning a non-zero value stops the iteration.
+pub type SthRawForEachCallback = extern "C" fn(
+    key: *const u8,
+    key_len: size_t,
+    val: *const u8,
+    val_len: size_t,
+    ctx: *mut c_void,
+) -> u8;
+
+/// Call a function for every key-value pair.
+///
+/// The entries are ordered by the buckets they are in. The `ctx` is passed on to every call of the
+/// callback. The iteration stops early if the callback returns a non-zero value. Returns 0 on
+/// success (also if it was stopped early) and 2 on error.
+///
+/// The database is locked during the whole iteration, the callback must not call any functions on
+/// the same database, else it deadlocks.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `sth_raw_open_db` that wasn't closed.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_for_each(
+    db: *const StoreTheHashDb,
+    callback: Option<SthRawForEachCallback>,
+    ctx: *mut c_void,
+) -> u8 {
+    ffi_call(RETURN_ERROR, || {
+        let callback = callback.ok_or("Callback is a null pointer.")?;
+        let db = db_ref(db)?.lock()?;
+        for entry in db.iter_by_bucket() {
+            let (key, value) = entry?;
+            if callback(key.as_ptr(), key.len(), value.as_ptr(), value.len(), ctx) != 0 {
+                break;
+            }
+        }
+        Ok(RETURN_OK)
+    })
+}
+
+/// An iterator over all key-value pairs of a database, see `sth_raw_iter`.
+///
+/// Only the entries of a single bucket are kept in memory.
+///
+/// cbindgen:ignore
+#[derive(Debug)]
+pub struct SthRawIter {
+    db: *const StoreTheHashDb,
+    /// The next bucket whose entries are read.
+    bucket: usize,
+    /// The entries of the current bucket that weren't returned yet.
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl SthRawIter {
+    /// Returns the next key-value pair, or `None` if all of them were returned.
+    ///
+    /// The entries of the first non-empty bucket are read once the ones of the current bucket
+    /// were returned. If reading a bucket fails, the bucket is not advanced.
+    unsafe fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>, Box<dyn Error>> {
+        if self.entries.is_empty() {
+            let db = db_ref(self.db)?.lock()?;
+            while self.entries.is_empty() && self.bucket < 1usize << BUCKETS_BITS {
+                let entries = &mut self.entries;
+                db.for_each_in_bucket(self.bucket, |key, value| {
+                    entries.push_back((key, value));
+                    Ok(())
+                })?;
+                self.bucket += 1;
+            }
+        }
+        Ok(self.entries.pop_front())
+    }
+}
+
+/// Free an iterator.
+///
+/// # Safety
+///
+/// `iter` must be an iterator returned by `sth_raw_iter` that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_free_iter(iter: *mut SthRawIter) {
+    ffi_call((), || {
+        drop(Box::from_raw(iter));
+        Ok(())
+    })
+}
+
+/// Iterate over all key-value pairs.
+///
+/// The entries are ordered by the buckets they are in. The database is not locked between the
+/// calls, entries that are added or deleted during the iteration may or may not be returned. The
+/// iterator must be freed with `sth_raw_free_iter` before the database is closed. Returns a null
+/// pointer on error.
+///
+/// # Safety
+///
+/// `db` must be a null pointer or a handle returned by `sth_raw_open_db` that wasn't closed.
+/// The database must not be closed before the returned iterator is freed.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_iter(db: *const StoreTheHashDb) -> *mut SthRawIter {
+    ffi_call(ptr::null_mut(), || {
+        db_ref(db)?;
+        Ok(Box::into_raw(Box::new(SthRawIter {
+            db,
+            bucket: 0,
+            entries: VecDeque::new(),
+        })))
+    })
+}
+
+/// Get the next key and its value from an iterator.
+///
+/// The caller is responsible for freeing the key and the value with `sth_raw_free_buf`. Returns 1
+/// if an entry was returned, 0 when the iterator is exhausted and 2 on error, e.g. if the value
+/// couldn't be read. After an error, the iterator should be freed.
+///
+/// # Safety
+///
+/// `iter` must be a null pointer or an iterator returned by `sth_raw_iter` that wasn't freed yet.
+/// `key`, `keylen`, `val` and `vallen` must be null pointers or valid pointers that can be
+/// written.
+#[no_mangle]
+pub unsafe extern "C" fn sth_raw_iter_next(
+    iter: *mut SthRawIter,
+    key: *mut *const c_char,
+    keylen: *mut size_t,
+    val: *mut *const c_char,
+    vallen: *mut size_t,
+) -> c_uchar {
+    ffi_call(RETURN_ERROR, || {
+        let iter = iter.as_mut().ok_or("Iterator is a null pointer.")?;
+        if key.is_null() || keylen.is_null() {
+            return Err("Key is a null pointer.".into());
+        }
+        if val.is_null() || vallen.is_null() {
+            return Err("Value is a null pointer.".into());
+        }
+        match iter.next()? {
+            Some((k, v)) => {
+                *key = leak_buf(k, keylen);
+                *val = leak_buf(v, vallen);
+                Ok(ITER_ENTRY)
+            }
+            None => Ok(ITER_EXHAUSTED),
+        }
+    })
+}
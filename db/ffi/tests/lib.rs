@@ -0,0 +1,352 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::ptr;
+use std::thread;
+
+use libc::{c_char, c_void, size_t};
+use storethehash_db::{
+    sth_raw_close_db, sth_raw_del, sth_raw_flush, sth_raw_for_each, sth_raw_free_buf,
+    sth_raw_free_iter, sth_raw_get, sth_raw_has, sth_raw_iter, sth_raw_iter_next,
+    sth_raw_last_error_length, sth_raw_last_error_message, sth_raw_open_db, sth_raw_set,
+    StoreTheHashDb,
+};
+
+const RETURN_OK: u8 = 0;
+const RETURN_NOT_FOUND: u8 = 1;
+const RETURN_ERROR: u8 = 2;
+
+// A key that looks like a hash.
+fn key(byte: u8) -> Vec<u8> {
+    vec![byte; 32]
+}
+
+fn open(path: &Path) -> *mut StoreTheHashDb {
+    let path = CString::new(path.to_str().unwrap()).unwrap();
+    let db = unsafe { sth_raw_open_db(path.as_ptr()) };
+    assert!(!db.is_null());
+    db
+}
+
+unsafe fn get_value(db: *const StoreTheHashDb, key: &[u8]) -> Option<Vec<u8>> {
+    let mut val: *const c_char = ptr::null();
+    let mut vallen: size_t = 0;
+    match sth_raw_get(db, key.as_ptr(), key.len(), &mut val, &mut vallen) {
+        RETURN_OK => {
+            let value = std::slice::from_raw_parts(val as *const u8, vallen).to_vec();
+            sth_raw_free_buf(val as *mut c_char, vallen);
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+fn last_error() -> Option<String> {
+    let len = sth_raw_last_error_length();
+    if len == 0 {
+        return None;
+    }
+    let mut buf = vec![0u8; len];
+    let written = unsafe { sth_raw_last_error_message(buf.as_mut_ptr() as *mut c_char, buf.len()) };
+    assert_eq!(written, len as i64 - 1);
+    buf.truncate(len - 1);
+    Some(String::from_utf8(buf).unwrap())
+}
+
+#[test]
+fn close_and_reopen() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let key = key(0xaa);
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = sth_raw_set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(sth_raw_close_db(db), RETURN_OK);
+
+        let db = open(&db_path);
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+        assert_eq!(sth_raw_close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn close_null() {
+    let result = unsafe { sth_raw_close_db(ptr::null_mut()) };
+    assert_eq!(result, RETURN_ERROR);
+}
+
+#[test]
+fn delete() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let key = key(0xbb);
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = sth_raw_set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+
+        let result = sth_raw_del(db, key.as_ptr(), key.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(get_value(db, &key), None);
+
+        // Deleting it again doesn't find the key.
+        let result = sth_raw_del(db, key.as_ptr(), key.len());
+        assert_eq!(result, RETURN_NOT_FOUND);
+        assert_eq!(sth_raw_close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn error_nonexistent_directory() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("nonexistent").join("storethehash.db");
+    let path = CString::new(db_path.to_str().unwrap()).unwrap();
+
+    let db = unsafe { sth_raw_open_db(path.as_ptr()) };
+    assert!(db.is_null());
+    let message = last_error().unwrap();
+    assert!(!message.is_empty());
+}
+
+#[test]
+fn error_null_pointer() {
+    let key = key(0xcc);
+    let result = unsafe { sth_raw_del(ptr::null(), key.as_ptr(), key.len()) };
+    assert_eq!(result, RETURN_ERROR);
+    assert_eq!(last_error().unwrap(), "Database is a null pointer.");
+}
+
+#[test]
+fn flush_to_disk() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let key = key(0xdd);
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = sth_raw_set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+        assert_eq!(sth_raw_flush(db), RETURN_OK);
+        assert_eq!(sth_raw_flush(ptr::null()), RETURN_ERROR);
+        assert_eq!(sth_raw_close_db(db), RETURN_OK);
+    }
+}
+
+#[test]
+fn found_missing_and_error() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let found = key(0x11);
+    let missing = key(0x22);
+    let value = b"some value";
+
+    unsafe {
+        let db = open(&db_path);
+        let result = sth_raw_set(db, found.as_ptr(), found.len(), value.as_ptr(), value.len());
+        assert_eq!(result, RETURN_OK);
+
+        // has
+        assert_eq!(sth_raw_has(db, found.as_ptr(), found.len()), 1);
+        assert_eq!(sth_raw_has(db, missing.as_ptr(), missing.len()), 0);
+        assert_eq!(last_error(), None);
+        assert_eq!(sth_raw_has(db, ptr::null(), 0), -1);
+        assert_eq!(last_error().unwrap(), "Key is a null pointer.");
+
+        // get
+        assert_eq!(get_value(db, &found), Some(value.to_vec()));
+        let mut val: *const c_char = ptr::null();
+        let mut vallen: size_t = 0;
+        let result = sth_raw_get(db, missing.as_ptr(), missing.len(), &mut val, &mut vallen);
+        assert_eq!(result, RETURN_NOT_FOUND);
+        assert_eq!(last_error(), None);
+        let result = sth_raw_get(db, ptr::null(), 0, &mut val, &mut vallen);
+        assert_eq!(result, RETURN_ERROR);
+        assert!(last_error().is_some());
+
+        assert_eq!(sth_raw_close_db(db), RETURN_OK);
+    }
+}
+
+extern "C" fn collect_entries(
+    key: *const u8,
+    key_len: size_t,
+    val: *const u8,
+    val_len: size_t,
+    ctx: *mut c_void,
+) -> u8 {
+    let entries = unsafe { &mut *(ctx as *mut Vec<(Vec<u8>, Vec<u8>)>) };
+    let key = unsafe { std::slice::from_raw_parts(key, key_len) };
+    let value = unsafe { std::slice::from_raw_parts(val, val_len) };
+    entries.push((key.to_vec(), value.to_vec()));
+    0
+}
+
+extern "C" fn collect_first_entry(
+    key: *const u8,
+    key_len: size_t,
+    val: *const u8,
+    val_len: size_t,
+    ctx: *mut c_void,
+) -> u8 {
+    collect_entries(key, key_len, val, val_len, ctx);
+    1
+}
+
+#[test]
+fn for_each_entry() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = (1..=5)
+        .map(|ii| (key(ii * 17), format!("value {}", ii).into_bytes()))
+        .collect();
+
+    unsafe {
+        let db = open(&db_path);
+        // Insert in reverse order, the entries are returned in bucket order.
+        for (key, value) in expected.iter().rev() {
+            let result = sth_raw_set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+            assert_eq!(result, RETURN_OK);
+        }
+
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let ctx = &mut entries as *mut _ as *mut c_void;
+        assert_eq!(sth_raw_for_each(db, Some(collect_entries), ctx), RETURN_OK);
+        assert_eq!(entries, expected);
+
+        // The iteration stops early.
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let ctx = &mut entries as *mut _ as *mut c_void;
+        assert_eq!(
+            sth_raw_for_each(db, Some(collect_first_entry), ctx),
+            RETURN_OK
+        );
+        assert_eq!(entries.len(), 1);
+
+        assert_eq!(sth_raw_for_each(db, None, ptr::null_mut()), RETURN_ERROR);
+        assert_eq!(last_error().unwrap(), "Callback is a null pointer.");
+        assert_eq!(sth_raw_close_db(db), RETURN_OK);
+    }
+}
+
+unsafe fn take_buf(buf: *const c_char, len: size_t) -> Vec<u8> {
+    let data = std::slice::from_raw_parts(buf as *const u8, len).to_vec();
+    sth_raw_free_buf(buf as *mut c_char, len);
+    data
+}
+
+#[test]
+fn iter_entries() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let mut expected: Vec<(Vec<u8>, Vec<u8>)> = (1..=5)
+        .map(|ii| (key(ii * 17), format!("value {}", ii).into_bytes()))
+        .collect();
+
+    unsafe {
+        let db = open(&db_path);
+        for (key, value) in &expected {
+            let result = sth_raw_set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+            assert_eq!(result, RETURN_OK);
+        }
+
+        let mut key: *const c_char = ptr::null();
+        let mut keylen: size_t = 0;
+        let mut val: *const c_char = ptr::null();
+        let mut vallen: size_t = 0;
+
+        let it = sth_raw_iter(db);
+        assert!(!it.is_null());
+        let mut entries = Vec::new();
+        while sth_raw_iter_next(it, &mut key, &mut keylen, &mut val, &mut vallen) == 1 {
+            entries.push((take_buf(key, keylen), take_buf(val, vallen)));
+        }
+        assert_eq!(last_error(), None);
+        // An exhausted iterator stays exhausted.
+        assert_eq!(
+            sth_raw_iter_next(it, &mut key, &mut keylen, &mut val, &mut vallen),
+            0
+        );
+        sth_raw_free_iter(it);
+        entries.sort();
+        expected.sort();
+        assert_eq!(entries, expected);
+
+        let it = sth_raw_iter(db);
+        assert_eq!(
+            sth_raw_iter_next(it, ptr::null_mut(), &mut keylen, &mut val, &mut vallen),
+            RETURN_ERROR
+        );
+        assert_eq!(last_error().unwrap(), "Key is a null pointer.");
+        sth_raw_free_iter(it);
+        assert!(sth_raw_iter(ptr::null()).is_null());
+
+        // Reading the values fails once the primary storage is gone.
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&db_path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        let it = sth_raw_iter(db);
+        assert_eq!(
+            sth_raw_iter_next(it, &mut key, &mut keylen, &mut val, &mut vallen),
+            RETURN_ERROR
+        );
+        assert!(last_error().is_some());
+        sth_raw_free_iter(it);
+        sth_raw_close_db(db);
+    }
+}
+
+#[test]
+fn concurrent_access() {
+    const THREADS: u8 = 8;
+    const KEYS_PER_THREAD: u8 = 25;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<StoreTheHashDb>();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    // Raw pointers are not `Send`, hence pass the address on.
+    let db = open(&db_path) as usize;
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            thread::spawn(move || {
+                let db = db as *const StoreTheHashDb;
+                for ii in 0..KEYS_PER_THREAD {
+                    let key = key(thread * KEYS_PER_THREAD + ii);
+                    let value = [thread, ii];
+                    unsafe {
+                        let result =
+                            sth_raw_set(db, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+                        assert_eq!(result, RETURN_OK);
+                        assert_eq!(get_value(db, &key), Some(value.to_vec()));
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    unsafe {
+        let db = db as *mut StoreTheHashDb;
+        for thread in 0..THREADS {
+            for ii in 0..KEYS_PER_THREAD {
+                let key = key(thread * KEYS_PER_THREAD + ii);
+                assert_eq!(get_value(db, &key), Some(vec![thread, ii]));
+            }
+        }
+        assert_eq!(sth_raw_close_db(db), RETURN_OK);
+    }
+}
@@ -0,0 +1,221 @@
+//! Measures how fast a database ingests and reads random data.
+//!
+//! Random 32-byte digests are stored as CIDv1 in a [`CidPrimary`] with values of a fixed size. It
+//! reports the throughput of the puts, their latency and how much the index grew. The optional
+//! get phase measures the latency of reading a sample of the inserted keys and of keys that don't
+//! exist. The same seed always produces the same keys and values.
+//!
+//! ```text
+//! cargo run --release --example bench -- --keys 1000000 --value-size 256 --buckets-bits 24
+//! ```
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use storethehash::prelude::*;
+use storethehash::testing::random_key;
+use storethehash_primary_cid::CidPrimary;
+
+const USAGE: &str = "usage: bench [options]
+
+options:
+    --keys <n>            Number of keys that are inserted (default: 100000).
+    --value-size <n>      Size of the values in bytes (default: 128).
+    --buckets-bits <n>    Bits used for the buckets, one of 8, 16, 20 or 24 (default: 24).
+    --sync <policy>       When the data is synced to disk: `close`, `always` or every <n> puts
+                          (default: close).
+    --get <n>             Read <n> inserted and <n> absent keys after the puts.
+    --seed <n>            Seed for the random keys and values (default: 42).";
+
+/// The multicodec prefix of a CIDv1 with the raw codec and a SHA2-256 multihash.
+const CID_V1_PREFIX: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+const DIGEST_SIZE: usize = 32;
+
+/// When the database is flushed and synced to disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SyncPolicy {
+    /// Only once all keys are inserted.
+    OnClose,
+    /// After every put.
+    Always,
+    /// After every n puts.
+    Every(u64),
+}
+
+#[derive(Debug)]
+struct Options {
+    keys: u64,
+    value_size: usize,
+    buckets_bits: u8,
+    sync: SyncPolicy,
+    get: usize,
+    seed: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            keys: 100_000,
+            value_size: 128,
+            buckets_bits: 24,
+            sync: SyncPolicy::OnClose,
+            get: 0,
+            seed: 42,
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("Error: {}\n\n{}", message, USAGE);
+    exit(2)
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: Option<String>) -> T {
+    value
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| usage_error(&format!("`{}` needs a number", name)))
+}
+
+fn parse_args() -> Options {
+    let mut options = Options::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--keys" => options.keys = parse_number(&arg, args.next()),
+            "--value-size" => options.value_size = parse_number(&arg, args.next()),
+            "--buckets-bits" => options.buckets_bits = parse_number(&arg, args.next()),
+            "--get" => options.get = parse_number(&arg, args.next()),
+            "--seed" => options.seed = parse_number(&arg, args.next()),
+            "--sync" => {
+                options.sync = match args.next().as_deref() {
+                    Some("close") => SyncPolicy::OnClose,
+                    Some("always") => SyncPolicy::Always,
+                    Some(every) => match every.parse() {
+                        Ok(0) | Err(_) => usage_error("invalid sync policy"),
+                        Ok(every) => SyncPolicy::Every(every),
+                    },
+                    None => usage_error("`--sync` needs a policy"),
+                }
+            }
+            _ => usage_error(&format!("unknown option `{}`", arg)),
+        }
+    }
+    options
+}
+
+/// Returns a CIDv1 with a random digest.
+fn random_cid(rng: &mut StdRng) -> Vec<u8> {
+    [&CID_V1_PREFIX[..], &random_key(DIGEST_SIZE, rng)].concat()
+}
+
+/// Returns the latency at the given percentile, the latencies need to be sorted.
+fn percentile(sorted: &[Duration], percentile: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    sorted[(sorted.len() - 1) * percentile / 100]
+}
+
+fn print_latencies(name: &str, latencies: &mut [Duration]) {
+    latencies.sort_unstable();
+    println!(
+        "{}: p50 {:?}, p99 {:?}",
+        name,
+        percentile(latencies, 50),
+        percentile(latencies, 99)
+    );
+}
+
+fn run<const N: u8>(options: &Options, dir: &Path) -> Result<(), Error> {
+    let db_path = dir.join("storethehash.db");
+    let index_path = dir.join("storethehash.db.index");
+    let db = Db::<_, N>::open(CidPrimary::open(&db_path)?, &index_path)?;
+    let index_size_before = fs::metadata(&index_path)?.len();
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    // Only the keys that are read later on are kept in memory.
+    let mut sample = Vec::with_capacity(options.get);
+    let mut value = vec![0; options.value_size];
+    let mut latencies = Vec::with_capacity(options.keys as usize);
+    let start = Instant::now();
+    for ii in 0..options.keys {
+        let cid = random_cid(&mut rng);
+        rng.fill(&mut value[..]);
+        let put_start = Instant::now();
+        db.put(&cid, &value)?;
+        let synced = match options.sync {
+            SyncPolicy::OnClose => false,
+            SyncPolicy::Always => true,
+            SyncPolicy::Every(every) => (ii + 1) % every == 0,
+        };
+        if synced {
+            db.flush()?;
+        }
+        latencies.push(put_start.elapsed());
+        if sample.len() < options.get {
+            sample.push(cid);
+        }
+    }
+    db.flush()?;
+    let elapsed = start.elapsed();
+
+    let seconds = elapsed.as_secs_f64();
+    let bytes = options.keys * (CID_V1_PREFIX.len() + DIGEST_SIZE + options.value_size) as u64;
+    let index_size = fs::metadata(&index_path)?.len();
+    println!(
+        "put {} keys with {} byte values in {:?}",
+        options.keys, options.value_size, elapsed
+    );
+    println!("throughput: {:.0} puts/s", options.keys as f64 / seconds);
+    println!("throughput: {:.0} bytes/s", bytes as f64 / seconds);
+    println!(
+        "index grew by {} bytes to {} bytes",
+        index_size - index_size_before,
+        index_size
+    );
+    print_latencies("put latency", &mut latencies);
+
+    if options.get > 0 {
+        sample.shuffle(&mut rng);
+        let mut latencies = Vec::with_capacity(sample.len());
+        for cid in &sample {
+            let get_start = Instant::now();
+            let found = db.get(cid)?;
+            latencies.push(get_start.elapsed());
+            assert!(found.is_some(), "Inserted key must be found");
+        }
+        print_latencies("get latency (inserted keys)", &mut latencies);
+
+        latencies.clear();
+        for _ in 0..options.get {
+            let cid = random_cid(&mut rng);
+            let get_start = Instant::now();
+            db.get(&cid)?;
+            latencies.push(get_start.elapsed());
+        }
+        print_latencies("get latency (absent keys)", &mut latencies);
+    }
+    db.close()
+}
+
+fn main() {
+    fil_logger::init();
+    let options = parse_args();
+    let temp_dir = tempfile::tempdir().expect("Temporary directory must be creatable");
+    let result = match options.buckets_bits {
+        8 => run::<8>(&options, temp_dir.path()),
+        16 => run::<16>(&options, temp_dir.path()),
+        20 => run::<20>(&options, temp_dir.path()),
+        24 => run::<24>(&options, temp_dir.path()),
+        _ => usage_error("`--buckets-bits` must be one of 8, 16, 20 or 24"),
+    };
+    if let Err(error) = result {
+        eprintln!("Error: {}", error);
+        exit(1)
+    }
+}
@@ -1,161 +1,179 @@
-mod cariter;
-
-use std::convert::TryFrom;
 use std::env;
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::io::{self, BufReader, Read};
 use std::process::exit;
 
-use cid::Cid;
-use storethehash::db::Db;
-use storethehash::index::Index;
-use storethehash::primary::{PrimaryError, PrimaryStorage};
+use storethehash::prelude::*;
+use storethehash_primary_car::{
+    import_car, verify_car_against_index, CarError, CarIter, CarPrimary, ImportOptions,
+    VerifyFailureKind,
+};
 use storethehash_primary_cid::CidPrimary;
 
-use cariter::CarIter;
-
 const BUCKETS_BITS: u8 = 24;
 
-/// CAR file storage implementation.
-///
-/// The primary storage is a CAR file.
-#[derive(Debug)]
-struct CarFile(File);
-
-impl CarFile {
-    pub fn new(file: File) -> Self {
-        Self(file)
-    }
-}
-
-impl PrimaryStorage for CarFile {
-    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
-        let mut file = &self.0;
-        let file_size = file.seek(SeekFrom::End(0))?;
-        if pos > file_size {
-            return Err(PrimaryError::OutOfBounds);
-        }
-
-        file.seek(SeekFrom::Start(pos))?;
-        let (block, _bytes_read) = cariter::read_data(&mut file)?;
-        Ok(cariter::read_block(&block))
-    }
-
-    fn put(&self, _key: &[u8], _value: &[u8]) -> Result<u64, PrimaryError> {
-        // It only reads from a CAR file, it cannot store anything.
-        unimplemented!()
+fn insert_into_index(car_path: &str, index_path: &str, resume: bool) -> Result<(), Error> {
+    // Corrupt blocks are skipped, so that they don't abort the whole import.
+    let options = ImportOptions {
+        resume,
+        skip_corrupt: true,
+        ..Default::default()
+    };
+    let car = BufReader::new(File::open(car_path)?);
+    let report =
+        import_car::<_, _, BUCKETS_BITS>(car, CarPrimary::open(car_path)?, index_path, options)?;
+    if let Some(pos) = report.resumed_from {
+        println!("resumed at position {}", pos);
     }
-
-    fn index_key(key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
-        // A CID is stored, but the index only contains the digest (the actual hash) of the CID.
-        let cid = Cid::try_from(&key[..]).map_err(|error| PrimaryError::Other(Box::new(error)))?;
-        let digest = cid.hash().digest();
-        Ok(digest.to_vec())
-    }
-}
-
-fn insert_into_index<R: Read>(car_file: CarFile, car_iter: CarIter<R>, index_path: &str) {
-    let index = Index::<_, BUCKETS_BITS>::open(index_path, car_file).unwrap();
-
-    for (counter, (cid_bytes, _, pos)) in car_iter.enumerate() {
-        if counter % 100000 == 0 {
-            println!("{} keys inserted", counter);
-        }
-        let cid = Cid::try_from(&cid_bytes[..]).unwrap();
-        let digest = cid.hash().digest();
-        index.put(&digest, pos).unwrap();
+    for pos in report.corrupt_blocks {
+        println!("skipped corrupt block at position {}", pos);
     }
+    println!(
+        "{} keys inserted, {} keys were already indexed",
+        report.blocks_indexed, report.blocks_skipped
+    );
+    Ok(())
 }
 
-fn insert_into_db<R: Read>(car_iter: CarIter<R>, db_path: &str) {
-    let primary = CidPrimary::open(&db_path).unwrap();
+fn insert_into_db<R: Read>(car_iter: CarIter<R>, db_path: &str) -> Result<(), Error> {
+    let primary = CidPrimary::open(&db_path)?;
     let index_path = format!("{}{}", &db_path, ".index");
-    let db = Db::<_, BUCKETS_BITS>::open(primary, &index_path).unwrap();
+    let db = Db::<_, BUCKETS_BITS>::open(primary, &index_path)?;
 
-    for (counter, (cid, data, _pos)) in car_iter.enumerate() {
+    for (counter, block) in car_iter.enumerate() {
         if counter % 100000 == 0 {
             println!("{} keys inserted", counter);
         }
-        db.put(&cid, &data).unwrap();
+        // Corrupt blocks are skipped, anything else aborts the import.
+        let (cid, data, _pos) = match block {
+            Ok(block) => block,
+            Err(error @ CarError::Corrupt { .. }) => {
+                println!("{}, skipping it", error);
+                continue;
+            }
+            Err(error) => return Err(PrimaryError::from(error).into()),
+        };
+        db.put(&cid, &data)?;
     }
+    Ok(())
 }
 
 // Walk through the car file file and compare it with the data in the index.
+//
+// With `verify_data` the blocks are also re-hashed, to find data that changed in the car file.
+// All failures are printed, returns whether the index is valid.
 fn validate_index<R: Read>(
-    car_file: CarFile,
+    car_primary: CarPrimary,
     car_iter: CarIter<R>,
     index_path: &str,
-) -> Result<(), (u64, Option<u64>)> {
-    let index = Index::<_, BUCKETS_BITS>::open(index_path, car_file).unwrap();
-
-    for (counter, (cid_bytes, _, pos)) in car_iter.enumerate() {
-        if counter % 100000 == 0 {
-            println!("{} keys validated", counter);
-        }
-        let cid = Cid::try_from(&cid_bytes[..]).unwrap();
-        let digest = cid.hash().digest();
-
-        // Do nothing in case the positions match.
-        match index.get(&digest).unwrap() {
-            Some(pos_from_index) if pos_from_index != pos => {
-                return Err((pos, Some(pos_from_index)));
+    verify_data: bool,
+) -> Result<bool, Error> {
+    let index = Index::<_, BUCKETS_BITS>::open(index_path, car_primary)?;
+    let report = verify_car_against_index(car_iter, &index, verify_data)?;
+
+    for failure in &report.failures {
+        match failure.kind {
+            VerifyFailureKind::PositionMismatch { index_pos } => println!(
+                "Invalid index: the index position `{}` \
+                did not match the primary index position `{}`",
+                index_pos, failure.pos
+            ),
+            VerifyFailureKind::NotIndexed => println!(
+                "Invalid index: key not found, primary index position is `{}`",
+                failure.pos
+            ),
+            VerifyFailureKind::DigestMismatch => println!(
+                "Invalid data: the digest doesn't match the block at position `{}`",
+                failure.pos
+            ),
+            VerifyFailureKind::UnsupportedHash { code } => println!(
+                "Cannot verify the block at position `{}`: unsupported hash function 0x{:x}",
+                failure.pos, code
+            ),
+            VerifyFailureKind::Corrupt => {
+                println!("Invalid data: corrupt block at position `{}`", failure.pos)
             }
-            None => return Err((pos, None)),
-            _ => (),
         }
     }
+    println!(
+        "{} blocks validated, {} failures",
+        report.blocks_checked,
+        report.failures.len()
+    );
+    Ok(report.is_valid())
+}
 
-    Ok(())
+fn exit_with_error(error: Error) -> ! {
+    println!("Error: {}", error);
+    exit(1)
 }
 
 fn main() {
     fil_logger::init();
-    let mut args = env::args().skip(1);
+    // A resumed import continues where a previous `generate-index` was interrupted.
+    let resume = env::args().any(|arg| arg == "--resume");
+    // Validating the data also checks the digests of the blocks.
+    let verify_data = env::args().any(|arg| arg == "--verify-data");
+    let mut args = env::args()
+        .skip(1)
+        .filter(|arg| arg != "--resume" && arg != "--verify-data");
     let command_arg = args.next();
     let car_path_arg = args.next();
     let index_path_arg = args.next();
     if let Some(command) = command_arg {
         if let (Some(car_path), Some(index_path)) = (car_path_arg, index_path_arg) {
-            let car_file_for_iter = File::open(&car_path).unwrap();
-            let car_file_for_iter_reader = BufReader::new(car_file_for_iter);
-            let car_iter = CarIter::new(car_file_for_iter_reader);
-
-            let car_file_for_index = File::open(&car_path).unwrap();
-            let car_storage = CarFile::new(car_file_for_index);
+            // Only `generate-db` can read the car data from stdin, the other commands use the car
+            // file as primary storage.
+            if car_path == "-" && command != "generate-db" {
+                println!(
+                    "Error: `{}` needs a car file, it cannot read stdin",
+                    command
+                );
+                exit(1)
+            }
+            let reader: Box<dyn Read> = if car_path == "-" {
+                Box::new(io::stdin())
+            } else {
+                match File::open(&car_path) {
+                    Ok(file) => Box::new(file),
+                    Err(error) => exit_with_error(error.into()),
+                }
+            };
+            let car_iter = match CarIter::new(BufReader::new(reader)) {
+                Ok(car_iter) => car_iter,
+                Err(error) => exit_with_error(error.into()),
+            };
+            for root in car_iter.roots() {
+                println!("root: {}", root);
+            }
 
             match &command[..] {
-                "generate-index" => {
-                    insert_into_index(car_storage, car_iter, &index_path);
-                    exit(0)
-                }
-                "generate-db" => {
-                    insert_into_db(car_iter, &index_path);
-                    exit(0)
-                }
-                "validate" => match validate_index(car_storage, car_iter, &index_path) {
-                    Ok(_) => {
-                        println!("Index is valid.");
-                        exit(0)
-                    }
-                    Err((primary_pos, Some(index_pos))) => {
-                        println!(
-                            "Invalid index: the index position `{}` \
-                            did not match the primary index position `{}`",
-                            index_pos, primary_pos
-                        );
-                        exit(1)
-                    }
-                    Err((primary_pos, None)) => {
-                        println!(
-                            "Invalid index: key not found, primary index position is `{}`",
-                            primary_pos
-                        );
-                        exit(1)
-                    }
+                "generate-index" => match insert_into_index(&car_path, &index_path, resume) {
+                    Ok(_) => exit(0),
+                    Err(error) => exit_with_error(error),
+                },
+                "generate-db" => match insert_into_db(car_iter, &index_path) {
+                    Ok(_) => exit(0),
+                    Err(error) => exit_with_error(error),
                 },
+                "validate" => {
+                    match CarPrimary::open(&car_path)
+                        .map_err(Error::from)
+                        .and_then(|car_primary| {
+                            validate_index(car_primary, car_iter, &index_path, verify_data)
+                        }) {
+                        Ok(true) => {
+                            println!("Index is valid.");
+                            exit(0)
+                        }
+                        Ok(false) => exit(1),
+                        Err(error) => exit_with_error(error),
+                    }
+                }
                 _ => (),
             }
         }
     }
-    println!("usage: fromcarfile [generate-index|generate-db|validate] <path-to-car-file> <index-or-db-file>");
+    println!("usage: fromcarfile [generate-index [--resume]|generate-db|validate [--verify-data]] <path-to-car-file> <index-or-db-file>");
+    println!("`generate-db` reads the car file from stdin if its path is `-`.");
 }
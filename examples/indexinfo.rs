@@ -3,8 +3,9 @@ use std::env;
 use std::fs::File;
 use std::io::BufReader;
 
-use storethehash::index::{self, IndexIter};
-use storethehash::recordlist::{RecordList, BUCKET_PREFIX_SIZE};
+use storethehash::index;
+use storethehash::prelude::*;
+use storethehash::recordlist::BUCKET_PREFIX_SIZE;
 
 fn index_info(index_path: &str) {
     let mut index_file = File::open(&index_path).unwrap();
@@ -15,23 +16,16 @@ fn index_info(index_path: &str) {
     let mut buffered = BufReader::new(index_file);
     for entry in IndexIter::new(&mut buffered, bytes_read) {
         match entry {
-            Ok((data, _pos)) => {
+            Ok((data, pos)) => {
                 let bucket = u32::from_le_bytes(data[..BUCKET_PREFIX_SIZE].try_into().unwrap());
 
+                // Superseded record lists are printed as well, use `sth dump-index --live-only`
+                // to skip them.
+                println!("{} at {}:", bucket, pos);
                 let recordlist = RecordList::new(&data);
-                let keys: Vec<String> = recordlist
-                    .into_iter()
-                    .map(|record| {
-                        // Create a hex string out of the bytes
-                        record
-                            .key
-                            .iter()
-                            .map(|byte| format!("{:02x}", byte))
-                            .collect::<String>()
-                    })
-                    .collect();
-
-                println!("{}: {}", bucket, keys.join(" "));
+                for record in &recordlist {
+                    println!("  {}", record);
+                }
             }
             Err(error) => panic!(error),
         }
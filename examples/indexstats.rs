@@ -4,8 +4,9 @@ use std::env;
 use std::fs::File;
 use std::io::BufReader;
 
-use storethehash::index::{self, IndexIter};
-use storethehash::recordlist::{RecordList, BUCKET_PREFIX_SIZE};
+use storethehash::index;
+use storethehash::prelude::*;
+use storethehash::recordlist::BUCKET_PREFIX_SIZE;
 
 fn index_stats(index_path: &str) -> BTreeMap<u32, Vec<usize>> {
     let mut stats = BTreeMap::new();
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use storethehash::fuzz;
+use storethehash::primary::PrimaryStorage;
+use storethehash_primary_cid::CidPrimary;
+
+fuzz_target!(|data: &[u8]| {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("storethehash.data");
+    std::fs::write(&path, fuzz::size_prefixed(data)).unwrap();
+    let primary = CidPrimary::open_read_only(&path).unwrap();
+    let _ = primary.get(0);
+});
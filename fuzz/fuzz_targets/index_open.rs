@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use storethehash::fuzz;
+use storethehash_primary_inmemory::InMemory;
+
+const BUCKETS_BITS: u8 = 8;
+
+fuzz_target!(|data: &[u8]| {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("storethehash.index");
+    fuzz::index_open::<_, BUCKETS_BITS>(&path, data, InMemory::new(&[]));
+});
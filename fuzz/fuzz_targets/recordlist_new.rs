@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use storethehash::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::record_list_new(data);
+});
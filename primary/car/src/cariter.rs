@@ -0,0 +1,323 @@
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Read, Seek, SeekFrom};
+
+use cid::Cid;
+use log::debug;
+use storethehash::primary::PrimaryError;
+
+use crate::error::CarError;
+use crate::header::CarHeader;
+
+/// Read and unsigen varint (LEB128) from a reader.
+///
+/// A varint that doesn't fit into 64 bits returns an `InvalidData` error.
+///
+/// Code is based on the Rust compiler:
+/// https://github.com/rust-lang/rust/blob/0beba9333754ead8febc5101fc5c35f7dcdfaadf/compiler/rustc_serialize/src/leb128.rs
+pub fn read_u64_leb128<R: Read>(reader: &mut R) -> Result<(u64, usize), io::Error> {
+    let mut result = 0;
+    let mut shift = 0;
+    let mut position = 0;
+    let mut buf = [0];
+
+    loop {
+        if shift >= 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Varint is longer than 64 bits.",
+            ));
+        }
+        reader.read_exact(&mut buf)?;
+        let byte = buf[0];
+        position += 1;
+        if (byte & 0x80) == 0 {
+            result |= (byte as u64) << shift;
+            return Ok((result, position));
+        } else {
+            result |= ((byte & 0x7F) as u64) << shift;
+        }
+        shift += 7;
+    }
+}
+
+/// A CARv2 starts with this pragma, it's a varint prefixed CBOR map that looks like a CARv1
+/// header with version 2.
+const CAR_V2_PRAGMA: [u8; 11] = [
+    0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+];
+/// The size of the CARv2 header that follows the pragma.
+const CAR_V2_HEADER_SIZE: usize = 40;
+/// The only characteristic that is defined, it's the left-most bit. The index section is ignored
+/// anyway, hence it doesn't matter whether it's set.
+const CAR_V2_FULLY_INDEXED: u128 = 1 << 127;
+
+/// Reads the CARv2 header that follows the pragma.
+///
+/// Returns the offset and the size of the CARv1 data payload.
+fn read_car_v2_header<R: Read>(reader: &mut R) -> Result<(u64, u64), PrimaryError> {
+    let mut header = [0; CAR_V2_HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let characteristics = u128::from_be_bytes(header[..16].try_into().unwrap());
+    if characteristics & !CAR_V2_FULLY_INDEXED != 0 {
+        return Err(PrimaryError::Other(
+            format!(
+                "Unsupported CARv2 characteristics: 0x{:032x}.",
+                characteristics
+            )
+            .into(),
+        ));
+    }
+    let data_offset = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    let data_size = u64::from_le_bytes(header[24..32].try_into().unwrap());
+    // The index offset isn't needed, the index is ignored.
+    Ok((data_offset, data_size))
+}
+
+/// An iterator over a car file.
+///
+/// On each iteration it returns the CID, the data and the position of the block within the car
+/// file. Both CARv1 and CARv2 files are supported. For a CARv2 the blocks of the inner CARv1
+/// payload are returned, the positions are still relative to the start of the file.
+#[derive(Debug)]
+pub struct CarIter<R: Read> {
+    /// The data we are iterating over
+    reader: R,
+    /// Position within the reader
+    pos: u64,
+    /// The position the blocks end at, if they are followed by other data, e.g. the index of a
+    /// CARv2.
+    end: Option<u64>,
+    /// The version of the car file, 1 or 2.
+    version: u64,
+    /// The roots from the header.
+    roots: Vec<Cid>,
+}
+
+impl<R: Read> CarIter<R> {
+    pub fn new(mut reader: R) -> Result<Self, PrimaryError> {
+        let header_missing = || {
+            PrimaryError::from(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Car file doesn't contain a header.",
+            ))
+        };
+        let (header, bytes_read) = read_data(&mut reader)?.ok_or_else(header_missing)?;
+        if header != CAR_V2_PRAGMA[1..] {
+            debug!("header size is {} bytes", bytes_read);
+            let header = CarHeader::try_from(&header[..])?;
+            return Ok(CarIter {
+                reader,
+                pos: bytes_read,
+                end: None,
+                version: header.version,
+                roots: header.roots,
+            });
+        }
+
+        let (data_offset, data_size) = read_car_v2_header(&mut reader)?;
+        let header_end = bytes_read + u64::try_from(CAR_V2_HEADER_SIZE).unwrap();
+        if data_offset < header_end {
+            return Err(PrimaryError::OutOfBounds {
+                pos: data_offset,
+                len: header_end,
+            });
+        }
+        debug!(
+            "CARv2 data payload starts at {} and is {} bytes",
+            data_offset, data_size
+        );
+        // Skip the padding between the header and the data payload.
+        let padding = data_offset - header_end;
+        if io::copy(&mut (&mut reader).take(padding), &mut io::sink())? != padding {
+            return Err(header_missing());
+        }
+        // The payload is a CARv1 with its own header.
+        let (header, bytes_read) = read_data(&mut reader)?.ok_or_else(header_missing)?;
+        debug!("header size is {} bytes", bytes_read);
+        let header = CarHeader::try_from(&header[..])?;
+        Ok(CarIter {
+            reader,
+            pos: data_offset + bytes_read,
+            end: Some(data_offset + data_size),
+            version: 2,
+            roots: header.roots,
+        })
+    }
+
+    /// Continues iterating over a CARv1 file from a position other than its start.
+    ///
+    /// The reader must already be at `pos`, which needs to be the start of a block, e.g. the
+    /// position of the block that follows the last one returned by a previous iteration. For a
+    /// CARv2 use [`CarIter::seek`] instead, so that the iteration stops at the end of the data
+    /// payload. As the header isn't read, there are no [`CarIter::roots`].
+    pub fn from_position(reader: R, pos: u64) -> Self {
+        CarIter {
+            reader,
+            pos,
+            end: None,
+            version: 1,
+            roots: Vec::new(),
+        }
+    }
+
+    /// The version of the car file, it's 2 for a CARv2, even if the blocks are read from the
+    /// CARv1 it contains.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The roots the header of the car file lists.
+    pub fn roots(&self) -> &[Cid] {
+        &self.roots
+    }
+
+    /// The position of the block that is returned on the next iteration.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Continues the iteration at the given position, which needs to be the start of a block.
+    ///
+    /// Other than [`CarIter::seek`] it also works with readers that cannot seek, e.g. stdin, as
+    /// the data up to that position is read and discarded. Hence it can only move forward.
+    pub fn skip_to(&mut self, pos: u64) -> Result<(), PrimaryError> {
+        if pos < self.pos {
+            return Err(PrimaryError::Other(
+                format!(
+                    "Cannot skip backwards from position {} to {}.",
+                    self.pos, pos
+                )
+                .into(),
+            ));
+        }
+        if let Some(end) = self.end.filter(|end| pos > *end) {
+            return Err(PrimaryError::OutOfBounds { pos, len: end });
+        }
+        let len = pos - self.pos;
+        let skipped = io::copy(&mut (&mut self.reader).take(len), &mut io::sink())?;
+        self.pos += skipped;
+        if skipped != len {
+            return Err(PrimaryError::OutOfBounds { pos, len: self.pos });
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> CarIter<R> {
+    /// Continues the iteration at the given position, which needs to be the start of a block.
+    pub fn seek(&mut self, pos: u64) -> Result<(), PrimaryError> {
+        self.reader.seek(SeekFrom::Start(pos))?;
+        self.pos = pos;
+        Ok(())
+    }
+}
+
+/// Read some data prefixed with a varint.
+///
+/// Returns `None` if the reader is already at its end. If the reader ends within the varint or
+/// the data, it's a truncated frame, which results in an `UnexpectedEof` error. The size isn't
+/// trusted, memory is only allocated for the data that is actually read.
+pub fn read_data<R: Read>(reader: &mut R) -> Result<Option<(Vec<u8>, u64)>, io::Error> {
+    // Read the first byte separately, so that a clean end of the data can be distinguished from
+    // a frame that was cut off.
+    let mut first_byte = [0];
+    loop {
+        match reader.read(&mut first_byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+
+    let (size, bytes_read): (u64, usize) =
+        read_u64_leb128(&mut (&first_byte[..]).chain(&mut *reader))?;
+    let mut data = Vec::new();
+    reader.take(size).read_to_end(&mut data)?;
+    if u64::try_from(data.len()).expect("64-bit platform needed") != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "Frame is truncated, expected {} bytes, got {}.",
+                size,
+                data.len()
+            ),
+        ));
+    }
+    Ok(Some((
+        data,
+        u64::try_from(bytes_read).expect("64-bit platform needed") + size,
+    )))
+}
+
+/// Read a CID together with some data.
+pub fn read_block(block: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+    // A block is a CID together with some data.
+    let (_version, version_offset) = read_u64_leb128(&mut &block[..])?;
+    let (_codec, codec_offset) = read_u64_leb128(&mut &block[version_offset..])?;
+    let (_multihash_code, multihash_code_offset) =
+        read_u64_leb128(&mut &block[version_offset + codec_offset..])?;
+    let (multihash_size, multihash_size_offset) =
+        read_u64_leb128(&mut &block[version_offset + codec_offset + multihash_code_offset..])?;
+    let cid_size = usize::try_from(multihash_size)
+        .ok()
+        .and_then(|multihash_size| {
+            (version_offset + codec_offset + multihash_code_offset + multihash_size_offset)
+                .checked_add(multihash_size)
+        });
+    let cid_size = match cid_size {
+        Some(cid_size) if cid_size <= block.len() => cid_size,
+        _ => {
+            return Err(PrimaryError::from(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Block is smaller than its CID.",
+            )))
+        }
+    };
+    let (cid, data) = block.split_at(cid_size);
+    Ok((cid.to_vec(), data.to_vec()))
+}
+
+impl<R: Read> Iterator for CarIter<R> {
+    type Item = Result<(Vec<u8>, Vec<u8>, u64), CarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The data payload of a CARv2 might be followed by an index.
+        if let Some(end) = self.end {
+            if self.pos >= end {
+                return None;
+            }
+        }
+
+        // Get the current position in order to return it and update it for the next iteration.
+        let pos = self.pos;
+        match read_data(&mut self.reader) {
+            Ok(Some((block, bytes_read))) => {
+                // The whole block was read, hence a corrupt one can be skipped.
+                self.pos += bytes_read;
+                match read_block(&block) {
+                    Ok((cid, data)) => Some(Ok((cid, data, pos))),
+                    Err(source) => Some(Err(CarError::Corrupt { pos, source })),
+                }
+            }
+            // We hit the end of the file => stop iterating
+            Ok(None) => None,
+            // Nothing sensible can be read after a failure, hence stop iterating.
+            Err(source) if source.kind() == io::ErrorKind::UnexpectedEof => {
+                self.end = Some(pos);
+                Some(Err(CarError::Truncated { pos, source }))
+            }
+            Err(source) if source.kind() == io::ErrorKind::InvalidData => {
+                self.end = Some(pos);
+                Some(Err(CarError::Corrupt {
+                    pos,
+                    source: PrimaryError::from(source),
+                }))
+            }
+            Err(source) => {
+                self.end = Some(pos);
+                Some(Err(CarError::Io { pos, source }))
+            }
+        }
+    }
+}
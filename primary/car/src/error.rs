@@ -0,0 +1,50 @@
+use std::io;
+
+use storethehash::primary::PrimaryError;
+use thiserror::Error;
+
+/// An error while iterating over the blocks of a car file, see [`crate::CarIter`].
+///
+/// All variants contain the position of the block within the car file. The end of the file is
+/// not an error, the iteration just stops.
+#[derive(Error, Debug)]
+pub enum CarError {
+    /// The file ends within the block, the iteration stops.
+    #[error("Block at position {pos} is truncated: {source}")]
+    Truncated {
+        pos: u64,
+        #[source]
+        source: io::Error,
+    },
+    /// The block cannot be parsed. If it was read completely, the iteration continues with the
+    /// next block, hence it can be skipped. If already its size cannot be parsed, the iteration
+    /// stops.
+    #[error("Block at position {pos} is corrupt: {source}")]
+    Corrupt {
+        pos: u64,
+        #[source]
+        source: PrimaryError,
+    },
+    /// Reading the block failed, the iteration stops.
+    #[error("IO error at position {pos}: {source}")]
+    Io {
+        pos: u64,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl CarError {
+    /// Returns the position of the block the error is about.
+    pub fn pos(&self) -> u64 {
+        match self {
+            Self::Truncated { pos, .. } | Self::Corrupt { pos, .. } | Self::Io { pos, .. } => *pos,
+        }
+    }
+}
+
+impl From<CarError> for PrimaryError {
+    fn from(error: CarError) -> Self {
+        Self::Other(Box::new(error))
+    }
+}
@@ -0,0 +1,242 @@
+//! Decoding of the CARv1 header.
+//!
+//! The header is a DAG-CBOR map with the version and the roots. Only the subset of CBOR that is
+//! needed for it is supported, which is enough for any valid header.
+use std::convert::TryFrom;
+
+use cid::Cid;
+use storethehash::primary::PrimaryError;
+
+/// The CBOR major types that are used by the header.
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+/// The CBOR tag that is used for CIDs in DAG-CBOR.
+const CID_TAG: u64 = 42;
+/// The maximum nesting of values that are skipped, deeper ones are considered malformed.
+const MAX_DEPTH: usize = 16;
+
+/// The decoded header of a CARv1.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CarHeader {
+    pub version: u64,
+    pub roots: Vec<Cid>,
+}
+
+impl TryFrom<&[u8]> for CarHeader {
+    type Error = PrimaryError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        decode_header(data).map_err(|reason| {
+            PrimaryError::Other(format!("Invalid CAR header: {}.", reason).into())
+        })
+    }
+}
+
+/// A cursor over CBOR encoded data.
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| "it is truncated".to_string())?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    /// Reads the initial byte and the argument of a data item, it returns the major type and the
+    /// argument.
+    fn read_head(&mut self) -> Result<(u8, u64), String> {
+        let initial = self.read_slice(1)?[0];
+        let major = initial >> 5;
+        let argument = match initial & 0x1f {
+            info @ 0..=23 => u64::from(info),
+            24 => u64::from(self.read_slice(1)?[0]),
+            25 => {
+                let mut bytes = [0; 2];
+                bytes.copy_from_slice(self.read_slice(2)?);
+                u64::from(u16::from_be_bytes(bytes))
+            }
+            26 => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(self.read_slice(4)?);
+                u64::from(u32::from_be_bytes(bytes))
+            }
+            27 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.read_slice(8)?);
+                u64::from_be_bytes(bytes)
+            }
+            _ => return Err("indefinite lengths are not supported".to_string()),
+        };
+        Ok((major, argument))
+    }
+
+    /// Reads the head and makes sure it's of the expected major type.
+    fn read_expected(&mut self, expected: u8, what: &str) -> Result<u64, String> {
+        match self.read_head()? {
+            (major, argument) if major == expected => Ok(argument),
+            (major, _) => Err(format!("expected {}, got major type {}", what, major)),
+        }
+    }
+
+    fn read_length(&mut self, expected: u8, what: &str) -> Result<usize, String> {
+        let length = self.read_expected(expected, what)?;
+        usize::try_from(length).map_err(|_| "it is too big".to_string())
+    }
+
+    fn read_text(&mut self) -> Result<&'a str, String> {
+        let length = self.read_length(MAJOR_TEXT, "a string")?;
+        std::str::from_utf8(self.read_slice(length)?).map_err(|_| "invalid UTF-8".to_string())
+    }
+
+    fn read_cid(&mut self) -> Result<Cid, String> {
+        let tag = self.read_expected(MAJOR_TAG, "a CID")?;
+        if tag != CID_TAG {
+            return Err(format!("expected a CID, got tag {}", tag));
+        }
+        let length = self.read_length(MAJOR_BYTES, "the bytes of a CID")?;
+        // DAG-CBOR prefixes the binary CID with the identity multibase.
+        match self.read_slice(length)? {
+            [0x00, cid @ ..] => Cid::try_from(cid).map_err(|error| error.to_string()),
+            _ => Err("CID without identity multibase prefix".to_string()),
+        }
+    }
+
+    /// Skips a whole data item, including nested ones.
+    fn skip(&mut self, depth: usize) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("it is nested too deeply".to_string());
+        }
+        let (major, argument) = self.read_head()?;
+        let argument = usize::try_from(argument).map_err(|_| "it is too big".to_string())?;
+        match major {
+            MAJOR_BYTES | MAJOR_TEXT => {
+                self.read_slice(argument)?;
+            }
+            MAJOR_ARRAY => {
+                for _ in 0..argument {
+                    self.skip(depth + 1)?;
+                }
+            }
+            MAJOR_MAP => {
+                for _ in 0..argument {
+                    self.skip(depth + 1)?;
+                    self.skip(depth + 1)?;
+                }
+            }
+            MAJOR_TAG => self.skip(depth + 1)?,
+            // Integers and simple values (incl. floats) don't have any content.
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+fn decode_header(data: &[u8]) -> Result<CarHeader, String> {
+    let mut decoder = Decoder { data, pos: 0 };
+    let mut version = None;
+    let mut roots = None;
+
+    let num_entries = decoder.read_length(MAJOR_MAP, "a map")?;
+    for _ in 0..num_entries {
+        match decoder.read_text()? {
+            "version" => version = Some(decoder.read_expected(MAJOR_UNSIGNED, "a version")?),
+            "roots" => {
+                let num_roots = decoder.read_length(MAJOR_ARRAY, "a list of roots")?;
+                roots = Some(
+                    (0..num_roots)
+                        .map(|_| decoder.read_cid())
+                        .collect::<Result<_, _>>()?,
+                );
+            }
+            // Additional fields are allowed.
+            _ => decoder.skip(0)?,
+        }
+    }
+    if decoder.pos != data.len() {
+        return Err("there is data after the header".to_string());
+    }
+
+    match (version, roots) {
+        (Some(1), Some(roots)) => Ok(CarHeader { version: 1, roots }),
+        (Some(1), None) => Err("the roots are missing".to_string()),
+        (Some(version), _) => Err(format!("unsupported version {}", version)),
+        (None, _) => Err("the version is missing".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CarHeader;
+
+    use std::convert::TryFrom;
+
+    use storethehash::primary::PrimaryError;
+
+    fn error_message(data: &[u8]) -> String {
+        match CarHeader::try_from(data) {
+            Err(PrimaryError::Other(error)) => error.to_string(),
+            result => panic!("expected an error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn decode_empty_roots() {
+        // `{"roots": [], "version": 1}`
+        let data = b"\xa2\x65roots\x80\x67version\x01";
+        let header = CarHeader::try_from(&data[..]).unwrap();
+        assert_eq!(header.version, 1);
+        assert!(header.roots.is_empty());
+    }
+
+    #[test]
+    fn decode_skips_unknown_fields() {
+        // `{"version": 1, "extra": [{"a": -1}], "roots": []}`
+        let data = b"\xa3\x67version\x01\x65extra\x81\xa1\x61a\x20\x65roots\x80";
+        let header = CarHeader::try_from(&data[..]).unwrap();
+        assert_eq!(header.version, 1);
+    }
+
+    #[test]
+    fn decode_invalid() {
+        assert_eq!(
+            error_message(b"\xa2\x65roots\x80"),
+            "Invalid CAR header: it is truncated."
+        );
+        assert_eq!(
+            error_message(b"\xa1\x67version\x02"),
+            "Invalid CAR header: unsupported version 2."
+        );
+        assert_eq!(
+            error_message(b"\xa1\x67version\x01"),
+            "Invalid CAR header: the roots are missing."
+        );
+        assert_eq!(
+            error_message(b"\xa1\x65roots\x80"),
+            "Invalid CAR header: the version is missing."
+        );
+        assert_eq!(
+            error_message(b"\x82\x01\x02"),
+            "Invalid CAR header: expected a map, got major type 4."
+        );
+        assert_eq!(
+            error_message(b"\xa2\x65roots\x81\x01\x67version\x01"),
+            "Invalid CAR header: expected a CID, got major type 0."
+        );
+        assert_eq!(
+            error_message(b"\xa2\x65roots\x80\x67version\x01\x00"),
+            "Invalid CAR header: there is data after the header."
+        );
+    }
+}
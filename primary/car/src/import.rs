@@ -0,0 +1,188 @@
+//! Creates an index for a CAR file, with the possibility to resume an interrupted run.
+//!
+//! The position of the next block that needs to be indexed is stored in a progress file next to
+//! the index (the index path with a `.progress` suffix). It's updated every
+//! [`PROGRESS_INTERVAL`] blocks, after the index was flushed, and once all blocks are indexed.
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use storethehash::error::Error;
+use storethehash::index::Index;
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+use storethehash::progress::{ProgressReporter, ProgressSink};
+
+use crate::{CarError, CarIter, CarPrimary};
+
+/// The number of blocks after which the progress is stored.
+pub const PROGRESS_INTERVAL: u64 = 10_000;
+
+/// Controls how [`import_car`] indexes a CAR file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportOptions {
+    /// Continue at the position stored in the progress file, instead of starting with the first
+    /// block. Blocks that are already in the index at the same position are skipped.
+    pub resume: bool,
+    /// Stop after this many blocks were read, the progress is stored so that the import can be
+    /// resumed.
+    pub max_blocks: Option<u64>,
+    /// Skip blocks that cannot be parsed instead of returning an error, see [`CarError::Corrupt`].
+    pub skip_corrupt: bool,
+}
+
+/// The result of [`import_car`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// The number of blocks that were added to the index.
+    pub blocks_indexed: u64,
+    /// The number of blocks that were already in the index.
+    pub blocks_skipped: u64,
+    /// The position within the CAR file the import started at, if it was resumed.
+    pub resumed_from: Option<u64>,
+    /// Whether the end of the CAR file was reached.
+    pub finished: bool,
+    /// The positions of the blocks that were skipped as they are corrupt.
+    pub corrupt_blocks: Vec<u64>,
+}
+
+/// Returns the path of the progress file that belongs to the index.
+pub fn progress_path<T: AsRef<Path>>(index_path: T) -> PathBuf {
+    let mut path = index_path.as_ref().as_os_str().to_os_string();
+    path.push(".progress");
+    PathBuf::from(path)
+}
+
+/// Reads the position of the next block that needs to be indexed, if there is a progress file.
+fn read_progress(path: &Path) -> Result<Option<u64>, Error> {
+    match fs::read(path) {
+        Ok(data) => {
+            let bytes: [u8; 8] = data.as_slice().try_into().map_err(|_| {
+                Error::io("reading progress", path, None)(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Progress file is corrupt.",
+                ))
+            })?;
+            Ok(Some(u64::from_le_bytes(bytes)))
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(Error::io("reading progress", path, None)(error)),
+    }
+}
+
+/// Stores the position of the next block that needs to be indexed.
+///
+/// The file is replaced atomically, so that a crash never leaves a partially written one behind.
+fn write_progress(path: &Path, pos: u64) -> Result<(), Error> {
+    let mut tmp_path = path.as_os_str().to_os_string();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, pos.to_le_bytes())
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(Error::io("writing progress", path, None))
+}
+
+/// Indexes all blocks of a CAR file, the CAR file itself is the primary storage.
+///
+/// The blocks are read from `car`, which needs to contain the same data as the `primary`. It
+/// doesn't need to be seekable, so that the data can e.g. be streamed while it's written to disk.
+/// Wrap it in a [`std::io::BufReader`] if it isn't buffered already.
+///
+/// With [`ImportOptions::resume`] the import continues where a previous one stopped, else it
+/// starts with the first block. In both cases the resulting index is the same as the one of a
+/// single uninterrupted import.
+pub fn import_car<R, U, const N: u8>(
+    car: R,
+    primary: CarPrimary,
+    index_path: U,
+    options: ImportOptions,
+) -> Result<ImportReport, Error>
+where
+    R: Read,
+    U: AsRef<Path>,
+{
+    import_car_with_progress::<_, _, N>(car, primary, index_path, options, None)
+}
+
+/// Same as [`import_car`], the number of blocks that were read is reported to the progress sink.
+///
+/// The total isn't known, as the CAR file is streamed.
+pub fn import_car_with_progress<R, U, const N: u8>(
+    car: R,
+    primary: CarPrimary,
+    index_path: U,
+    options: ImportOptions,
+    progress: Option<&dyn ProgressSink>,
+) -> Result<ImportReport, Error>
+where
+    R: Read,
+    U: AsRef<Path>,
+{
+    let mut reporter = ProgressReporter::new(progress, "import-car", None);
+    let progress_path = progress_path(&index_path);
+    let index = Index::<_, N>::open(index_path.as_ref(), primary)?;
+
+    let mut car_iter = CarIter::new(car)?;
+    let resumed_from = if options.resume {
+        read_progress(&progress_path)?
+    } else {
+        None
+    };
+    if let Some(pos) = resumed_from {
+        debug!(
+            "resuming import into {:?} at position {}",
+            index_path.as_ref(),
+            pos
+        );
+        car_iter.skip_to(pos)?;
+    }
+
+    let mut report = ImportReport {
+        resumed_from,
+        ..Default::default()
+    };
+    let mut blocks_read: u64 = 0;
+    loop {
+        if options.max_blocks == Some(blocks_read) {
+            break;
+        }
+        let block = match car_iter.next() {
+            Some(block) => block,
+            None => {
+                report.finished = true;
+                break;
+            }
+        };
+        blocks_read += 1;
+        reporter.update(blocks_read);
+
+        match block {
+            Ok((cid, _data, pos)) => {
+                let digest = CarPrimary::index_key(&cid)?;
+                // Blocks after the last stored progress might already be indexed.
+                if options.resume && index.get(&digest)? == Some(pos) {
+                    report.blocks_skipped += 1;
+                } else {
+                    index.put(&digest, pos)?;
+                    report.blocks_indexed += 1;
+                }
+            }
+            Err(CarError::Corrupt { pos, source }) if options.skip_corrupt => {
+                warn!("skipping corrupt block at position {}: {}", pos, source);
+                report.corrupt_blocks.push(pos);
+            }
+            Err(error) => return Err(PrimaryError::from(error).into()),
+        }
+
+        if blocks_read % PROGRESS_INTERVAL == 0 {
+            debug!("{} blocks read", blocks_read);
+            index.flush()?;
+            write_progress(&progress_path, car_iter.position())?;
+        }
+    }
+
+    index.flush()?;
+    write_progress(&progress_path, car_iter.position())?;
+    reporter.finish(blocks_read);
+    Ok(report)
+}
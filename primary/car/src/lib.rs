@@ -0,0 +1,673 @@
+//! A read-only primary storage that is backed by a [CAR file], either a CARv1 or a CARv2.
+//!
+//! The CAR file is only read, nothing can be stored. This makes it possible to create an index
+//! for an existing CAR file without copying any of its data. Such an index is created with
+//! [`import_car`], which can also resume an interrupted import. [`verify_car_against_index`]
+//! checks such an index and optionally the data of the CAR file itself.
+//!
+//! [CAR file]: https://github.com/ipld/specs/blob/d8ae7e9d78e4efe7e21ec2bae427d79b5af95bcd/block-layer/content-addressable-archives.md#format-description
+mod cariter;
+mod error;
+mod header;
+mod import;
+mod verify;
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+
+use cid::Cid;
+use log::debug;
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+pub use cariter::{read_block, read_data, read_u64_leb128, CarIter};
+pub use error::CarError;
+pub use import::{
+    import_car, import_car_with_progress, progress_path, ImportOptions, ImportReport,
+    PROGRESS_INTERVAL,
+};
+pub use verify::{verify_car_against_index, VerifyFailure, VerifyFailureKind, VerifyReport};
+
+/// CAR file storage implementation.
+///
+/// The primary storage is a CAR file.
+#[derive(Debug)]
+pub struct CarPrimary(File);
+
+impl CarPrimary {
+    pub fn open<P>(path: P) -> Result<Self, PrimaryError>
+    where
+        P: AsRef<Path>,
+    {
+        debug!("Opening car file: {:?}", &path.as_ref());
+        let file = File::open(path)?;
+        Ok(Self(file))
+    }
+}
+
+impl PrimaryStorage for CarPrimary {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let mut file = &self.0;
+        let file_size = file.seek(SeekFrom::End(0))?;
+        if pos > file_size {
+            return Err(PrimaryError::OutOfBounds {
+                pos,
+                len: file_size,
+            });
+        }
+
+        file.seek(SeekFrom::Start(pos))?;
+        let (block, _bytes_read) = read_data(&mut file)?.ok_or(PrimaryError::OutOfBounds {
+            pos,
+            len: file_size,
+        })?;
+        read_block(&block)
+    }
+
+    fn put(&self, _key: &[u8], _value: &[u8]) -> Result<u64, PrimaryError> {
+        // It only reads from a CAR file, it cannot store anything.
+        Err(PrimaryError::ReadOnly)
+    }
+
+    fn index_key(key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
+        // A CID is stored, but the index only contains the digest (the actual hash) of the CID.
+        let cid = Cid::try_from(key).map_err(|error| PrimaryError::Other(Box::new(error)))?;
+        let digest = cid.hash().digest();
+        Ok(digest.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        import_car, import_car_with_progress, progress_path, read_block, verify_car_against_index,
+        CarError, CarIter, CarPrimary, ImportOptions, ImportReport, VerifyFailure,
+        VerifyFailureKind,
+    };
+
+    use std::convert::TryFrom;
+    use std::fs::{self, File};
+    use std::io::{self, BufReader, Cursor, Read};
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use cid::Cid;
+    use storethehash::error::Error;
+    use storethehash::index::Index;
+    use storethehash::primary::{PrimaryError, PrimaryStorage};
+    use storethehash::progress::ProgressSink;
+
+    const BUCKETS_BITS: u8 = 8;
+
+    // The fixture contains four blocks, the first one is also the root.
+    const BLOCK_POSITIONS: [u64; 4] = [59, 114, 163, 204];
+    // The CARv2 fixture contains the CARv1 fixture as data payload at this offset. It's followed
+    // by an index.
+    const V2_DATA_OFFSET: u64 = 64;
+
+    fn fixture_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+    }
+
+    fn fixture_path() -> PathBuf {
+        fixture_dir().join("sample.car")
+    }
+
+    fn v2_fixture_path() -> PathBuf {
+        fixture_dir().join("sample-v2.car")
+    }
+
+    /// A reader that cannot seek, like stdin.
+    struct NoSeek<R>(R);
+
+    impl<R: Read> Read for NoSeek<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    /// Imports the CAR file at the given path, which is also the primary storage.
+    fn import_from_path<P: AsRef<Path>>(
+        car_path: P,
+        index_path: &Path,
+        options: ImportOptions,
+    ) -> Result<ImportReport, Error> {
+        let car = BufReader::new(File::open(&car_path).unwrap());
+        let primary = CarPrimary::open(&car_path).unwrap();
+        import_car::<_, _, BUCKETS_BITS>(car, primary, index_path, options)
+    }
+
+    #[test]
+    fn iter() {
+        let file = BufReader::new(File::open(fixture_path()).unwrap());
+        let blocks: Vec<(Vec<u8>, Vec<u8>, u64)> = CarIter::new(file)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let positions: Vec<u64> = blocks.iter().map(|(_, _, pos)| *pos).collect();
+        assert_eq!(positions, BLOCK_POSITIONS);
+        assert_eq!(blocks[0].1, b"hello storethehash");
+        assert_eq!(blocks[1].1, b"second block");
+        assert_eq!(blocks[3].1, vec![b'x'; 100]);
+    }
+
+    #[test]
+    fn iter_v2() {
+        let v1_file = BufReader::new(File::open(fixture_path()).unwrap());
+        let v1_blocks: Vec<(Vec<u8>, Vec<u8>, u64)> = CarIter::new(v1_file)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let v2_file = BufReader::new(File::open(v2_fixture_path()).unwrap());
+        let v2_blocks: Vec<(Vec<u8>, Vec<u8>, u64)> = CarIter::new(v2_file)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(v2_blocks.len(), v1_blocks.len());
+        for ((v1_cid, v1_data, v1_pos), (v2_cid, v2_data, v2_pos)) in
+            v1_blocks.iter().zip(v2_blocks.iter())
+        {
+            assert_eq!(v2_cid, v1_cid);
+            assert_eq!(v2_data, v1_data);
+            assert_eq!(*v2_pos, V2_DATA_OFFSET + v1_pos);
+        }
+
+        // The positions are valid within the whole file.
+        let primary = CarPrimary::open(v2_fixture_path()).unwrap();
+        for (cid, data, pos) in v2_blocks {
+            assert_eq!(primary.get(pos).unwrap(), (cid, data));
+        }
+    }
+
+    #[test]
+    fn roots() {
+        let file = BufReader::new(File::open(fixture_path()).unwrap());
+        let mut car_iter = CarIter::new(file).unwrap();
+        assert_eq!(car_iter.version(), 1);
+        // The first block is the only root.
+        let (cid, _data, _pos) = car_iter.next().unwrap().unwrap();
+        assert_eq!(car_iter.roots(), [Cid::try_from(cid).unwrap()]);
+
+        let v2_file = BufReader::new(File::open(v2_fixture_path()).unwrap());
+        let v2_car_iter = CarIter::new(v2_file).unwrap();
+        assert_eq!(v2_car_iter.version(), 2);
+        assert_eq!(v2_car_iter.roots(), car_iter.roots());
+    }
+
+    #[test]
+    fn iter_corrupt_header() {
+        let file = BufReader::new(File::open(fixture_dir().join("corrupt-header.car")).unwrap());
+        match CarIter::new(file) {
+            Err(PrimaryError::Other(error)) => {
+                assert_eq!(error.to_string(), "Invalid CAR header: it is truncated.")
+            }
+            _ => panic!("expected an error about the header"),
+        }
+    }
+
+    #[test]
+    fn iter_v2_unsupported_characteristics() {
+        let mut data = fs::read(v2_fixture_path()).unwrap();
+        // The characteristics directly follow the 11 bytes of the pragma.
+        data[11] |= 0x01;
+        let result = CarIter::new(Cursor::new(data));
+        match result {
+            Err(PrimaryError::Other(error)) => assert_eq!(
+                error.to_string(),
+                "Unsupported CARv2 characteristics: 0x81000000000000000000000000000000."
+            ),
+            _ => panic!("expected an error about the characteristics"),
+        }
+    }
+
+    #[test]
+    fn iter_truncated() {
+        // The last block is cut in half.
+        let file = BufReader::new(File::open(fixture_dir().join("truncated.car")).unwrap());
+        let blocks: Vec<_> = CarIter::new(file).unwrap().collect();
+
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[..3].iter().all(|block| block.is_ok()));
+        match &blocks[3] {
+            Err(CarError::Truncated { pos, source }) => {
+                assert_eq!(*pos, BLOCK_POSITIONS[3]);
+                assert_eq!(source.kind(), std::io::ErrorKind::UnexpectedEof);
+            }
+            _ => panic!("expected a truncated block"),
+        }
+    }
+
+    #[test]
+    fn iter_corrupt_block() {
+        // The CID of the second block is corrupt, the other blocks can still be read.
+        let file = BufReader::new(File::open(fixture_dir().join("corrupt-block.car")).unwrap());
+        let blocks: Vec<_> = CarIter::new(file).unwrap().collect();
+
+        assert_eq!(blocks.len(), 4);
+        match &blocks[1] {
+            Err(error @ CarError::Corrupt { .. }) => {
+                assert_eq!(error.pos(), BLOCK_POSITIONS[1]);
+                assert_eq!(
+                    error.to_string(),
+                    "Block at position 114 is corrupt: IO error: Block is smaller than its CID."
+                );
+            }
+            _ => panic!("expected a corrupt block"),
+        }
+        let positions: Vec<u64> = blocks
+            .iter()
+            .filter_map(|block| block.as_ref().ok())
+            .map(|(_, _, pos)| *pos)
+            .collect();
+        assert_eq!(
+            positions,
+            [BLOCK_POSITIONS[0], BLOCK_POSITIONS[2], BLOCK_POSITIONS[3]]
+        );
+    }
+
+    #[test]
+    fn iter_block_size_too_big() {
+        // The size prefix claims a block of 2^59 bytes, only the bytes that are there are read.
+        let mut data = vec![0x80; 8];
+        data.extend_from_slice(&[0x20, 0x01, 0x55]);
+        let blocks: Vec<_> = CarIter::from_position(Cursor::new(data), 0).collect();
+        assert_eq!(blocks.len(), 1);
+        assert!(matches!(
+            &blocks[0],
+            Err(CarError::Truncated { pos: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn iter_block_size_too_long() {
+        // The varint of the size prefix doesn't fit into 64 bits.
+        let data = vec![0xff; 12];
+        let blocks: Vec<_> = CarIter::from_position(Cursor::new(data), 0).collect();
+        assert_eq!(blocks.len(), 1);
+        match &blocks[0] {
+            Err(CarError::Corrupt { pos: 0, source }) => assert_eq!(
+                source.to_string(),
+                "IO error: Varint is longer than 64 bits."
+            ),
+            _ => panic!("expected a corrupt block"),
+        }
+    }
+
+    #[test]
+    fn read_block_cid_size_overflow() {
+        // The multihash size of the CID is the maximum 64-bit value.
+        let mut block = vec![0x01, 0x55, 0x12];
+        block.extend_from_slice(&[0xff; 9]);
+        block.push(0x01);
+        assert!(matches!(read_block(&block), Err(PrimaryError::Io { .. })));
+    }
+
+    #[test]
+    fn import_corrupt_block() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let car_path = fixture_dir().join("corrupt-block.car");
+        let index_path = temp_dir.path().join("storethehash.index");
+        let result = import_from_path(&car_path, &index_path, ImportOptions::default());
+        assert!(result.is_err());
+
+        let index_path = temp_dir.path().join("skipped.index");
+        let options = ImportOptions {
+            skip_corrupt: true,
+            ..Default::default()
+        };
+        let report = import_from_path(&car_path, &index_path, options).unwrap();
+        assert_eq!(report.blocks_indexed, 3);
+        assert_eq!(report.corrupt_blocks, [BLOCK_POSITIONS[1]]);
+        assert!(report.finished);
+    }
+
+    #[test]
+    fn import_with_progress() {
+        #[derive(Default)]
+        struct RecordingProgress(Mutex<Vec<(String, u64, Option<u64>)>>);
+
+        impl ProgressSink for RecordingProgress {
+            fn report_phase(&self, phase: &str, done: u64, total: Option<u64>) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((phase.to_string(), done, total));
+            }
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index_path = temp_dir.path().join("storethehash.index");
+        let car = BufReader::new(File::open(fixture_path()).unwrap());
+        let primary = CarPrimary::open(fixture_path()).unwrap();
+        let progress = RecordingProgress::default();
+        import_car_with_progress::<_, _, BUCKETS_BITS>(
+            car,
+            primary,
+            &index_path,
+            ImportOptions::default(),
+            Some(&progress),
+        )
+        .unwrap();
+
+        let reports = progress.0.into_inner().unwrap();
+        assert!(reports.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        assert_eq!(
+            reports.last().unwrap(),
+            &("import-car".to_string(), BLOCK_POSITIONS.len() as u64, None)
+        );
+    }
+
+    #[test]
+    fn get() {
+        let primary = CarPrimary::open(fixture_path()).unwrap();
+        let file = BufReader::new(File::open(fixture_path()).unwrap());
+        for block in CarIter::new(file).unwrap() {
+            let (cid, data, pos) = block.unwrap();
+            let (primary_cid, primary_data) = primary.get(pos).unwrap();
+            assert_eq!(primary_cid, cid);
+            assert_eq!(primary_data, data);
+        }
+    }
+
+    #[test]
+    fn get_out_of_bounds() {
+        let primary = CarPrimary::open(fixture_path()).unwrap();
+        let result = primary.get(10_000);
+        let file_size = fs::metadata(fixture_path()).unwrap().len();
+        match result {
+            Err(PrimaryError::OutOfBounds { pos, len }) => {
+                assert_eq!(pos, 10_000);
+                assert_eq!(len, file_size);
+            }
+            _ => panic!("expected an out of bounds error"),
+        }
+    }
+
+    #[test]
+    fn put_is_read_only() {
+        let primary = CarPrimary::open(fixture_path()).unwrap();
+        let result = primary.put(b"key", b"value");
+        assert!(matches!(result, Err(PrimaryError::ReadOnly)));
+    }
+
+    #[test]
+    fn index_key() {
+        let primary = CarPrimary::open(fixture_path()).unwrap();
+        let (cid, _data) = primary.get(BLOCK_POSITIONS[0]).unwrap();
+        let index_key = CarPrimary::index_key(&cid).unwrap();
+        // The CID is a CIDv1 with a SHA2-256 multihash, the digest are the last 32 bytes.
+        assert_eq!(index_key, &cid[cid.len() - 32..]);
+    }
+
+    #[test]
+    fn iter_from_position() {
+        let file = BufReader::new(File::open(fixture_path()).unwrap());
+        let mut car_iter = CarIter::new(file).unwrap();
+        car_iter.next().unwrap().unwrap();
+        assert_eq!(car_iter.position(), BLOCK_POSITIONS[1]);
+
+        let data = fs::read(fixture_path()).unwrap();
+        let reader = Cursor::new(&data[BLOCK_POSITIONS[2] as usize..]);
+        let positions: Vec<u64> = CarIter::from_position(reader, BLOCK_POSITIONS[2])
+            .map(|block| block.unwrap().2)
+            .collect();
+        assert_eq!(positions, &BLOCK_POSITIONS[2..]);
+    }
+
+    #[test]
+    fn iter_not_seekable() {
+        for path in &[fixture_path(), v2_fixture_path()] {
+            let data = fs::read(path).unwrap();
+            let reader = BufReader::new(NoSeek(Cursor::new(data)));
+            let positions: Vec<u64> = CarIter::new(reader)
+                .unwrap()
+                .map(|block| block.unwrap().2)
+                .collect();
+            let expected: Vec<u64> = CarIter::new(BufReader::new(File::open(path).unwrap()))
+                .unwrap()
+                .map(|block| block.unwrap().2)
+                .collect();
+            assert_eq!(positions, expected);
+        }
+    }
+
+    #[test]
+    fn iter_skip_to() {
+        let data = fs::read(fixture_path()).unwrap();
+        let mut car_iter = CarIter::new(BufReader::new(NoSeek(Cursor::new(data)))).unwrap();
+        car_iter.skip_to(BLOCK_POSITIONS[2]).unwrap();
+        let positions: Vec<u64> = (&mut car_iter).map(|block| block.unwrap().2).collect();
+        assert_eq!(positions, &BLOCK_POSITIONS[2..]);
+        assert!(matches!(
+            car_iter.skip_to(BLOCK_POSITIONS[1]),
+            Err(PrimaryError::Other(_))
+        ));
+    }
+
+    #[test]
+    fn import_resume_not_seekable() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let one_shot_path = temp_dir.path().join("one_shot.index");
+        import_from_path(fixture_path(), &one_shot_path, ImportOptions::default()).unwrap();
+
+        let index_path = temp_dir.path().join("resumed.index");
+        let data = fs::read(fixture_path()).unwrap();
+        for &max_blocks in &[Some(1), None] {
+            let options = ImportOptions {
+                resume: true,
+                max_blocks,
+                ..Default::default()
+            };
+            let car = BufReader::new(NoSeek(Cursor::new(&data)));
+            let primary = CarPrimary::open(fixture_path()).unwrap();
+            import_car::<_, _, BUCKETS_BITS>(car, primary, &index_path, options).unwrap();
+        }
+        assert_eq!(
+            fs::read(progress_path(&index_path)).unwrap(),
+            fs::read(progress_path(&one_shot_path)).unwrap()
+        );
+        assert_eq!(
+            fs::read(&index_path).unwrap(),
+            fs::read(&one_shot_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn import() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index_path = temp_dir.path().join("storethehash.index");
+        let report =
+            import_from_path(fixture_path(), &index_path, ImportOptions::default()).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                blocks_indexed: 4,
+                blocks_skipped: 0,
+                resumed_from: None,
+                finished: true,
+                corrupt_blocks: Vec::new(),
+            }
+        );
+
+        let primary = CarPrimary::open(fixture_path()).unwrap();
+        let index = Index::<_, BUCKETS_BITS>::open(&index_path, primary).unwrap();
+        for &pos in &BLOCK_POSITIONS {
+            let (cid, _data) = index.primary.get(pos).unwrap();
+            let digest = CarPrimary::index_key(&cid).unwrap();
+            assert_eq!(index.get(&digest).unwrap(), Some(pos));
+        }
+    }
+
+    #[test]
+    fn import_resume() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let one_shot_path = temp_dir.path().join("one_shot.index");
+        import_from_path(fixture_path(), &one_shot_path, ImportOptions::default()).unwrap();
+
+        // Interrupt the import after two blocks.
+        let index_path = temp_dir.path().join("resumed.index");
+        let options = ImportOptions {
+            resume: true,
+            max_blocks: Some(2),
+            ..Default::default()
+        };
+        let report = import_from_path(fixture_path(), &index_path, options).unwrap();
+        assert_eq!(report.blocks_indexed, 2);
+        assert!(!report.finished);
+        assert_eq!(
+            fs::read(progress_path(&index_path)).unwrap(),
+            BLOCK_POSITIONS[2].to_le_bytes()
+        );
+
+        let options = ImportOptions {
+            resume: true,
+            max_blocks: None,
+            ..Default::default()
+        };
+        let report = import_from_path(fixture_path(), &index_path, options).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                blocks_indexed: 2,
+                blocks_skipped: 0,
+                resumed_from: Some(BLOCK_POSITIONS[2]),
+                finished: true,
+                corrupt_blocks: Vec::new(),
+            }
+        );
+        assert_eq!(
+            fs::read(&index_path).unwrap(),
+            fs::read(&one_shot_path).unwrap()
+        );
+
+        // Resuming a finished import doesn't change anything.
+        let report = import_from_path(fixture_path(), &index_path, options).unwrap();
+        assert_eq!(report.blocks_indexed, 0);
+        assert!(report.finished);
+        assert_eq!(
+            fs::read(&index_path).unwrap(),
+            fs::read(&one_shot_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn import_resume_v2() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let one_shot_path = temp_dir.path().join("one_shot.index");
+        import_from_path(v2_fixture_path(), &one_shot_path, ImportOptions::default()).unwrap();
+
+        let index_path = temp_dir.path().join("resumed.index");
+        let options = ImportOptions {
+            resume: true,
+            max_blocks: Some(3),
+            ..Default::default()
+        };
+        import_from_path(v2_fixture_path(), &index_path, options).unwrap();
+        let options = ImportOptions {
+            resume: true,
+            max_blocks: None,
+            ..Default::default()
+        };
+        let report = import_from_path(v2_fixture_path(), &index_path, options).unwrap();
+        // The index that follows the data payload isn't read as blocks.
+        assert_eq!(report.blocks_indexed, 1);
+        assert_eq!(
+            report.resumed_from,
+            Some(V2_DATA_OFFSET + BLOCK_POSITIONS[3])
+        );
+        assert!(report.finished);
+        assert_eq!(
+            fs::read(&index_path).unwrap(),
+            fs::read(&one_shot_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn import_resume_skips_indexed_blocks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let one_shot_path = temp_dir.path().join("one_shot.index");
+        import_from_path(fixture_path(), &one_shot_path, ImportOptions::default()).unwrap();
+
+        // Simulate a crash after the third block was indexed, but before the progress was stored.
+        let index_path = temp_dir.path().join("resumed.index");
+        let options = ImportOptions {
+            resume: true,
+            max_blocks: Some(3),
+            ..Default::default()
+        };
+        import_from_path(fixture_path(), &index_path, options).unwrap();
+        fs::write(progress_path(&index_path), BLOCK_POSITIONS[1].to_le_bytes()).unwrap();
+
+        let options = ImportOptions {
+            resume: true,
+            max_blocks: None,
+            ..Default::default()
+        };
+        let report = import_from_path(fixture_path(), &index_path, options).unwrap();
+        assert_eq!(report.blocks_indexed, 1);
+        assert_eq!(report.blocks_skipped, 2);
+        assert_eq!(
+            fs::read(&index_path).unwrap(),
+            fs::read(&one_shot_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn verify_data() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index_path = temp_dir.path().join("storethehash.index");
+        import_from_path(fixture_path(), &index_path, ImportOptions::default()).unwrap();
+
+        // Change the last byte of the first block's data, the block can still be parsed.
+        let car_path = temp_dir.path().join("bit-rot.car");
+        let mut data = fs::read(fixture_path()).unwrap();
+        data[BLOCK_POSITIONS[1] as usize - 1] ^= 0x01;
+        fs::write(&car_path, &data).unwrap();
+
+        let primary = CarPrimary::open(&car_path).unwrap();
+        let index = Index::<_, BUCKETS_BITS>::open(&index_path, primary).unwrap();
+        let car_iter = || CarIter::new(BufReader::new(File::open(&car_path).unwrap())).unwrap();
+
+        // The positions are still the same.
+        let report = verify_car_against_index(car_iter(), &index, false).unwrap();
+        assert_eq!(report.blocks_checked, 4);
+        assert!(report.is_valid());
+
+        let report = verify_car_against_index(car_iter(), &index, true).unwrap();
+        assert_eq!(report.blocks_checked, 4);
+        assert_eq!(
+            report.failures,
+            [VerifyFailure {
+                pos: BLOCK_POSITIONS[0],
+                kind: VerifyFailureKind::DigestMismatch,
+            }]
+        );
+    }
+
+    #[test]
+    fn verify_positions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index_path = temp_dir.path().join("storethehash.index");
+        let options = ImportOptions {
+            max_blocks: Some(2),
+            ..Default::default()
+        };
+        import_from_path(fixture_path(), &index_path, options).unwrap();
+
+        let primary = CarPrimary::open(fixture_path()).unwrap();
+        let index = Index::<_, BUCKETS_BITS>::open(&index_path, primary).unwrap();
+        let car_iter = CarIter::new(BufReader::new(File::open(fixture_path()).unwrap())).unwrap();
+        let report = verify_car_against_index(car_iter, &index, true).unwrap();
+        let failures: Vec<_> = BLOCK_POSITIONS[2..]
+            .iter()
+            .map(|&pos| VerifyFailure {
+                pos,
+                kind: VerifyFailureKind::NotIndexed,
+            })
+            .collect();
+        assert_eq!(report.failures, failures);
+    }
+}
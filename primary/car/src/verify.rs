@@ -0,0 +1,119 @@
+//! Verifies an index that was created for a CAR file.
+//!
+//! Comparing the positions only shows that the index matches the CAR file. With digest checks
+//! each block is also re-hashed, which detects data that changed after the index was created.
+use std::convert::TryFrom;
+use std::io::Read;
+
+use cid::Cid;
+use log::debug;
+use storethehash::codec::{IdentityCodec, KeyCodec, Sha256Codec, Sha512Codec};
+use storethehash::error::Error;
+use storethehash::index::Index;
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+use crate::{CarError, CarIter, CarPrimary};
+
+// The multihash codes of the hash functions that can be verified.
+const MULTIHASH_IDENTITY: u64 = 0x00;
+const MULTIHASH_SHA2_256: u64 = 0x12;
+const MULTIHASH_SHA2_512: u64 = 0x13;
+
+/// What is wrong with a block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyFailureKind {
+    /// The index returns a different position for the block.
+    PositionMismatch { index_pos: u64 },
+    /// The block isn't in the index.
+    NotIndexed,
+    /// The digest of the CID doesn't match the data of the block.
+    DigestMismatch,
+    /// The CID uses a hash function that cannot be verified.
+    UnsupportedHash { code: u64 },
+    /// The block cannot be parsed, see [`CarError::Corrupt`].
+    Corrupt,
+}
+
+/// A block that failed the verification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifyFailure {
+    /// The position of the block within the CAR file.
+    pub pos: u64,
+    pub kind: VerifyFailureKind,
+}
+
+/// The result of [`verify_car_against_index`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    /// The number of blocks that were read from the CAR file.
+    pub blocks_checked: u64,
+    /// The failures in the order of the blocks.
+    pub failures: Vec<VerifyFailure>,
+}
+
+impl VerifyReport {
+    /// Returns whether all blocks passed the verification.
+    pub fn is_valid(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Checks that every block of the CAR file is in the index at the position it is stored at.
+///
+/// If `check_digests` is set, the data of each block is additionally hashed with the hash
+/// function of its CID and compared to the digest. Only SHA2-256, SHA2-512 and identity hashes
+/// are supported. The verification continues after a failure, only errors while reading the CAR
+/// file or the index abort it.
+pub fn verify_car_against_index<R: Read, const N: u8>(
+    car: CarIter<R>,
+    index: &Index<CarPrimary, N>,
+    check_digests: bool,
+) -> Result<VerifyReport, Error> {
+    let mut report = VerifyReport::default();
+    for block in car {
+        let (cid, data, pos) = match block {
+            Ok(block) => block,
+            Err(CarError::Corrupt { pos, source }) => {
+                debug!("corrupt block at position {}: {}", pos, source);
+                report.blocks_checked += 1;
+                report.failures.push(VerifyFailure {
+                    pos,
+                    kind: VerifyFailureKind::Corrupt,
+                });
+                continue;
+            }
+            Err(error) => return Err(PrimaryError::from(error).into()),
+        };
+        report.blocks_checked += 1;
+
+        let digest = CarPrimary::index_key(&cid)?;
+        let kind = match index.get(&digest)? {
+            Some(index_pos) if index_pos != pos => {
+                Some(VerifyFailureKind::PositionMismatch { index_pos })
+            }
+            None => Some(VerifyFailureKind::NotIndexed),
+            _ if check_digests => check_digest(&cid, &data)?,
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            report.failures.push(VerifyFailure { pos, kind });
+        }
+    }
+    Ok(report)
+}
+
+/// Returns the failure if the digest of the CID doesn't match the data.
+fn check_digest(cid: &[u8], data: &[u8]) -> Result<Option<VerifyFailureKind>, PrimaryError> {
+    let cid = Cid::try_from(cid).map_err(|error| PrimaryError::Other(Box::new(error)))?;
+    let digest = match cid.hash().code() {
+        MULTIHASH_IDENTITY => IdentityCodec::encode(data)?,
+        MULTIHASH_SHA2_256 => Sha256Codec::encode(data)?,
+        MULTIHASH_SHA2_512 => Sha512Codec::encode(data)?,
+        code => return Ok(Some(VerifyFailureKind::UnsupportedHash { code })),
+    };
+    if digest == cid.hash().digest() {
+        Ok(None)
+    } else {
+        Ok(Some(VerifyFailureKind::DigestMismatch))
+    }
+}
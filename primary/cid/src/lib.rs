@@ -4,93 +4,1027 @@
 //! doesn't contain a header. It is only a sequence of `varint | CID | data`, where the `varint`
 //! is the byte length of `CID | data`. The `varint` is an unsigned [LEB128].
 //!
+//! In the version 2 format, see [`CidPrimary::open_v2`], the file starts with the
+//! [`FORMAT_V2`] byte and every frame is followed by an 8 byte checksum of `CID | data`. It's a
+//! little-endian CRC-64/XZ (the reflected ECMA-182 polynomial).
+//!
 //! [Car files]: https://github.com/ipld/specs/blob/d8ae7e9d78e4efe7e21ec2bae427d79b5af95bcd/block-layer/content-addressable-archives.md#format-description
 //! [LEB128]: https://en.wikipedia.org/wiki/LEB128
 
 use std::cell::RefCell;
+use std::cmp;
 use std::convert::TryFrom;
-use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
 
 use cid::Cid;
 use log::debug;
+use storethehash::bufferpool::BufferPool;
+use storethehash::codec::{IdentityCodec, KeyCodec, Sha256Codec, Sha512Codec};
+use storethehash::db::Db;
+use storethehash::error::Error;
 use storethehash::primary::{PrimaryError, PrimaryStorage};
+use storethehash::readahead;
+use storethehash::syncer::{BackgroundSyncer, SyncPolicy};
 use wasabi_leb128::{ParseLeb128Error, ReadLeb128, WriteLeb128};
 
+/// A CIDv0 starts with the multihash code of SHA2-256 and a 32 byte digest size.
+const CID_V0_PREFIX: [u8; 2] = [0x12, 0x20];
+/// The byte size of a CIDv0, the multihash prefix and the digest.
+const CID_V0_SIZE: usize = 34;
+// The multihash codes of the hash functions that can be verified.
+const MULTIHASH_IDENTITY: u64 = 0x00;
+const MULTIHASH_SHA2_256: u64 = 0x12;
+const MULTIHASH_SHA2_512: u64 = 0x13;
+/// The number of bytes of a block that are read to check whether it starts with a CID, it's
+/// bigger than the CIDs of all supported hash functions.
+const CID_CHECK_SIZE: u64 = 128;
+/// The first byte of a file in the version 2 format.
+///
+/// Files in the original format (version 1) don't have a version byte, they start with the size
+/// prefix of the first frame. That is never 2, as a block is always longer than the shortest
+/// possible CID.
+pub const FORMAT_V2: u8 = 2;
+/// The byte size of the checksum that follows every block in the version 2 format.
+const CHECKSUM_SIZE: u64 = 8;
+/// The reflected ECMA-182 polynomial, as used by CRC-64/XZ.
+const CRC64_POLYNOMIAL: u64 = 0xc96c_5795_d787_0f42;
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+/// Uses the digest of a CID as index key.
+#[derive(Debug)]
+pub struct CidDigestCodec;
+
+impl KeyCodec for CidDigestCodec {
+    fn encode(raw_key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
+        let cid = Cid::try_from(raw_key).map_err(|error| PrimaryError::Other(Box::new(error)))?;
+        let digest = cid.hash().digest();
+        Ok(digest.to_vec())
+    }
+}
+
 /// A primary storage that is CID aware.
 #[derive(Debug)]
 pub struct CidPrimary {
     reader: File,
     writer: RefCell<BufWriter<File>>,
+    /// If set, storing data returns an error.
+    read_only: bool,
+    /// The path of the file, it's used for error messages.
+    path: PathBuf,
+    /// The format version, see [`CidPrimary::version`].
+    version: u8,
+    /// If set, the written blocks are reported to it, see [`CidPrimary::with_background_sync`].
+    syncer: Option<BackgroundSyncer>,
+    /// The buffers the frames are read into by [`PrimaryStorage::get`].
+    buffers: BufferPool,
 }
 
 impl CidPrimary {
+    /// Opens a file, the format version is detected from its first byte.
+    ///
+    /// A new or empty file uses the original format, see [`CidPrimary::open_v2`] for the version 2
+    /// one.
     pub fn open<P>(path: P) -> Result<Self, PrimaryError>
     where
         P: AsRef<Path>,
     {
-        debug!("Opening db file: {:?}", &path.as_ref());
+        let path = path.as_ref();
+        debug!("Opening db file: {:?}", path);
         let mut file = OpenOptions::new()
             .read(true)
             .create(true)
             .append(true)
-            .open(path)?;
-        file.seek(SeekFrom::End(0))?;
+            .open(path)
+            .map_err(PrimaryError::io("opening primary storage", path, None))?;
+        let version = read_version(&file, path)?;
+        file.seek(SeekFrom::End(0)).map_err(PrimaryError::io(
+            "opening primary storage",
+            path,
+            None,
+        ))?;
         Ok(Self {
             reader: file.try_clone()?,
             writer: RefCell::new(BufWriter::new(file)),
+            read_only: false,
+            path: path.to_path_buf(),
+            version,
+            syncer: None,
+            buffers: BufferPool::new(),
         })
     }
-}
 
-impl PrimaryStorage for CidPrimary {
-    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+    /// Opens a file in the version 2 format, where every block is followed by a checksum.
+    ///
+    /// A new or empty file is initialized with the [`FORMAT_V2`] byte. A file in the original
+    /// format returns an error, it can be converted with [`CidPrimary::migrate_to_v2`]. Every
+    /// [`PrimaryStorage::get`] validates the checksum of the block, a mismatch returns
+    /// [`PrimaryError::ChecksumMismatch`].
+    pub fn open_v2<P>(path: P) -> Result<Self, PrimaryError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut primary = Self::open(path)?;
+        if primary.file_size(None)? == 0 {
+            let mut writer = primary.writer.borrow_mut();
+            writer
+                .write_all(&[FORMAT_V2])
+                .and_then(|_| writer.flush())
+                .and_then(|_| writer.get_ref().sync_data())
+                .map_err(PrimaryError::io("writing format version", path, Some(0)))?;
+            drop(writer);
+            primary.version = 2;
+        } else if primary.version != 2 {
+            return Err(not_v2(path));
+        }
+        Ok(primary)
+    }
+
+    /// Opens an existing file without write access.
+    ///
+    /// This works even if the file is read-only on disk. Storing data returns an error.
+    pub fn open_read_only<P>(path: P) -> Result<Self, PrimaryError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        debug!("Opening db file read-only: {:?}", path);
+        let file =
+            File::open(path).map_err(PrimaryError::io("opening primary storage", path, None))?;
+        let version = read_version(&file, path)?;
+        Ok(Self {
+            reader: file.try_clone()?,
+            writer: RefCell::new(BufWriter::new(file)),
+            read_only: true,
+            path: path.to_path_buf(),
+            version,
+            syncer: None,
+            buffers: BufferPool::new(),
+        })
+    }
+
+    /// Syncs the file from a background thread, so that the blocks a crash can lose are bounded by
+    /// the policy, see [`BackgroundSyncer`].
+    ///
+    /// The thread does a final sync when the storage is dropped. A read-only storage returns
+    /// [`PrimaryError::ReadOnly`].
+    pub fn with_background_sync(mut self, policy: SyncPolicy) -> Result<Self, PrimaryError> {
+        if self.read_only {
+            return Err(PrimaryError::ReadOnly);
+        }
+        self.start_syncer(policy)?;
+        Ok(self)
+    }
+
+    fn start_syncer(&mut self, policy: SyncPolicy) -> Result<(), PrimaryError> {
+        let io_error = || PrimaryError::io("starting background sync", &self.path, None);
+        let file = self
+            .writer
+            .borrow()
+            .get_ref()
+            .try_clone()
+            .map_err(io_error())?;
+        self.syncer = Some(BackgroundSyncer::new(file, policy).map_err(io_error())?);
+        Ok(())
+    }
+
+    /// Returns the format version of the file, it's 1 for the original one and 2 for the one with
+    /// checksums.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the byte size of the checksum that follows every block.
+    fn checksum_size(&self) -> u64 {
+        if self.version == 2 {
+            CHECKSUM_SIZE
+        } else {
+            0
+        }
+    }
+
+    /// Returns the file handle that is used for reading.
+    ///
+    /// It's the same file as the one of the writer, so that wrappers can reuse the handles.
+    pub fn reader(&self) -> &File {
+        &self.reader
+    }
+
+    /// Returns the buffered file handle that is used for writing.
+    ///
+    /// It's borrowed mutably, hence it must be dropped before calling any other method that
+    /// writes, else it panics.
+    pub fn writer(&self) -> impl DerefMut<Target = BufWriter<File>> + '_ {
+        self.writer.borrow_mut()
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the file handles for reading and writing.
+    pub fn into_parts(self) -> (File, BufWriter<File>) {
+        (self.reader, self.writer.into_inner())
+    }
+
+    /// Returns the version (0 or 1) of the CID that is stored at the given position.
+    pub fn cid_version_at(&self, pos: u64) -> Result<u64, PrimaryError> {
+        let block = self.read_block_at(pos)?;
+        let (version, _cid_size) = read_cid_version_and_size(&block)?;
+        Ok(version)
+    }
+
+    /// Rewrites the file, so that the blocks are stored in a new order.
+    ///
+    /// `sorted_offsets` contains `(old_offset, new_offset)` tuples, the blocks are written in the
+    /// order of the new offsets, e.g. sorted by the buckets the keys are in, so that lookups within
+    /// a bucket are close to each other. Blocks that are not listed are dropped. Returns the
+    /// `(old_offset, new_offset)` tuples with the offsets the blocks were actually written to,
+    /// they only differ from the given ones if those don't account for the block sizes. Use
+    /// [`storethehash::index::Index::remap_offsets`] to update the index accordingly.
+    ///
+    /// The new file is written next to the old one and then replaces it.
+    pub fn defragment(
+        &mut self,
+        sorted_offsets: &[(u64, u64)],
+    ) -> Result<Vec<(u64, u64)>, PrimaryError> {
+        if self.read_only {
+            return Err(PrimaryError::ReadOnly);
+        }
+        self.flush()?;
+
+        let mut order = sorted_offsets.to_vec();
+        order.sort_by_key(|(_old_offset, new_offset)| *new_offset);
+
+        let mut defragmented_path = self.path.as_os_str().to_owned();
+        defragmented_path.push(".defragment");
+        let defragmented_path = PathBuf::from(defragmented_path);
+        let io_error = |offset| PrimaryError::io("defragmenting", &defragmented_path, offset);
+        let mut defragmented = BufWriter::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&defragmented_path)
+                .map_err(io_error(None))?,
+        );
+
+        // The format version is kept.
+        if self.version == 2 {
+            defragmented
+                .write_all(&[FORMAT_V2])
+                .map_err(io_error(Some(0)))?;
+        }
+        let mut mapping = Vec::with_capacity(order.len());
+        let mut pos = self.first_pos();
+        for (old_offset, _new_offset) in order {
+            let block = self.read_block_at(old_offset)?;
+            let bytes_written = write_frame(&mut defragmented, &block, self.version)
+                .map_err(io_error(Some(pos)))?;
+            mapping.push((old_offset, pos));
+            pos += bytes_written;
+        }
+        defragmented
+            .flush()
+            .and_then(|_| defragmented.get_ref().sync_all())
+            .map_err(io_error(None))?;
+        drop(defragmented);
+
+        fs::rename(&defragmented_path, &self.path).map_err(io_error(None))?;
+        let reopened = Self::open(&self.path)?;
+        // The syncer of the old file does its final sync when it's dropped.
+        let sync_policy = self.syncer.as_ref().map(BackgroundSyncer::policy);
+        *self = reopened;
+        if let Some(policy) = sync_policy {
+            self.start_syncer(policy)?;
+        }
+        Ok(mapping)
+    }
+
+    /// Writes all blocks into a new file in the version 2 format, where every block is followed
+    /// by its checksum.
+    ///
+    /// The blocks keep their order, but their positions change, hence the index needs to be
+    /// rebuilt for the new file, which is opened with [`CidPrimary::open_v2`]. A file that is
+    /// already in the version 2 format returns an error.
+    pub fn migrate_to_v2(&self, output_path: &Path) -> Result<(), PrimaryError> {
+        if self.version == 2 {
+            return Err(PrimaryError::Other(
+                format!("{:?} is already in the version 2 format.", self.path).into(),
+            ));
+        }
+        if !self.read_only {
+            self.flush()?;
+        }
+        let file_size = self.file_size(None)?;
+        let io_error = |offset| PrimaryError::io("migrating to version 2", output_path, offset);
+        let mut migrated = BufWriter::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output_path)
+                .map_err(io_error(None))?,
+        );
+        migrated
+            .write_all(&[FORMAT_V2])
+            .map_err(io_error(Some(0)))?;
+
+        let mut pos = 0;
+        let mut migrated_pos = 1;
+        while pos < file_size {
+            let (block, frame_size) = self.read_frame_at(pos)?;
+            pos += frame_size;
+            // The last block might be cut off.
+            if pos > file_size {
+                return Err(PrimaryError::OutOfBounds {
+                    pos,
+                    len: file_size,
+                });
+            }
+            migrated_pos += write_frame(&mut migrated, &block, FORMAT_V2)
+                .map_err(io_error(Some(migrated_pos)))?;
+        }
+        migrated
+            .flush()
+            .and_then(|_| migrated.get_ref().sync_all())
+            .map_err(io_error(None))
+    }
+
+    /// Checks the checksums of all blocks of a file in the version 2 format.
+    ///
+    /// Returns the number of blocks whose checksum matches and the number of those whose checksum
+    /// doesn't, a block that is cut off at the end of the file counts as the latter. A file in the
+    /// original format returns an error, it doesn't contain any checksums.
+    pub fn verify_checksum_v2(&self) -> Result<(u64, u64), PrimaryError> {
+        if self.version != 2 {
+            return Err(not_v2(&self.path));
+        }
+        let file_size = self.file_size(None)?;
+        let (mut valid, mut invalid) = (0, 0);
+        let mut pos = self.first_pos();
+        let mut block = Vec::new();
+        while pos < file_size {
+            let frame = self.read_raw_frame_into(pos, &mut block)?;
+            match check_checksum(pos, &block, frame.checksum.as_ref()) {
+                Ok(()) => valid += 1,
+                Err(_) => invalid += 1,
+            }
+            pos += frame.size;
+        }
+        Ok((valid, invalid))
+    }
+
+    /// Returns the number of blocks that are stored.
+    ///
+    /// Only the size prefixes are read, the blocks themselves are skipped. A block that is cut off
+    /// at the end of the file returns an out of bounds error.
+    pub fn count_blocks(&self) -> Result<u64, PrimaryError> {
         let mut file = &self.reader;
-        let file_size = file.seek(SeekFrom::End(0))?;
+        let file_size = file.seek(SeekFrom::End(0)).map_err(PrimaryError::io(
+            "counting blocks",
+            &self.path,
+            None,
+        ))?;
+        let mut pos = self.first_pos();
+        file.seek(SeekFrom::Start(pos)).map_err(PrimaryError::io(
+            "counting blocks",
+            &self.path,
+            Some(pos),
+        ))?;
+        let mut reader = BufReader::new(file);
+
+        let mut count = 0;
+        while pos < file_size {
+            let (size, bytes_read): (u64, usize) = reader.read_leb128().map_err(|error| {
+                leb128_to_primary_error(error).with_io_context(
+                    "counting blocks",
+                    &self.path,
+                    Some(pos),
+                )
+            })?;
+            // The checksum is skipped together with the block.
+            let skip = size + self.checksum_size();
+            pos += u64::try_from(bytes_read).expect("64 bit platform needed") + skip;
+            if pos > file_size {
+                return Err(PrimaryError::OutOfBounds {
+                    pos,
+                    len: file_size,
+                });
+            }
+            reader
+                .seek_relative(i64::try_from(skip).expect("block size fits into 63 bits"))
+                .map_err(PrimaryError::io("counting blocks", &self.path, Some(pos)))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns the values of all blocks in the order they were stored, without their CIDs.
+    ///
+    /// Only the size prefixes of the CIDs are parsed, the CIDs themselves are skipped. The file is
+    /// opened again, so that the iteration is independent of other reads. The checksums of the
+    /// version 2 format are skipped as well, they are not validated.
+    pub fn iter_values(&self) -> CidValueIter<'_> {
+        CidValueIter {
+            path: &self.path,
+            reader: None,
+            pos: self.first_pos(),
+            file_size: 0,
+            checksum_size: self.checksum_size(),
+            done: false,
+        }
+    }
+
+    /// Returns the position of the first frame that starts at or after the given position.
+    ///
+    /// This can be used to recover from a position that points into the middle of a frame, see
+    /// [`PrimaryError::MisalignedRead`]. A frame is found if it ends within the file, its block
+    /// starts with a CIDv0 or CIDv1 and the same holds for the frame after it, unless it's the
+    /// last one. The frame after it may be cut off at the end of the file, e.g. by a crash while
+    /// it was written. If there isn't any frame, the size of the file is returned.
+    pub fn find_nearest_frame(&self, pos: u64) -> Result<u64, PrimaryError> {
+        let file_size = self.file_size(Some(pos))?;
         if pos > file_size {
-            return Err(PrimaryError::OutOfBounds);
+            return Err(PrimaryError::OutOfBounds {
+                pos,
+                len: file_size,
+            });
         }
+        // The version byte is never the start of a frame.
+        for candidate in cmp::max(pos, self.first_pos())..file_size {
+            match self.frame_end(candidate)? {
+                Some(next_pos) if next_pos == file_size => return Ok(candidate),
+                Some(next_pos) if next_pos < file_size && self.frame_end(next_pos)?.is_some() => {
+                    return Ok(candidate)
+                }
+                _ => {}
+            }
+        }
+        Ok(file_size)
+    }
 
-        file.seek(SeekFrom::Start(pos))?;
-        let (block, _bytes_read) = read_data(&mut file)?;
-        read_block(&block)
+    /// Returns the position right after the frame at the given position, if it looks like one.
+    ///
+    /// It's the case if the size prefix can be read and the block starts with a CIDv0 or CIDv1.
+    /// Only the beginning of the block is read, hence the returned position (it includes the
+    /// checksum of the version 2 format) may be past the end of the file.
+    fn frame_end(&self, pos: u64) -> Result<Option<u64>, PrimaryError> {
+        let io_error = || PrimaryError::io("finding frame", &self.path, Some(pos));
+        let mut file = &self.reader;
+        file.seek(SeekFrom::Start(pos)).map_err(io_error())?;
+        let (size, bytes_read): (u64, usize) = match file.read_leb128() {
+            Ok(size_and_bytes_read) => size_and_bytes_read,
+            Err(ParseLeb128Error::UnexpectedEndOfData(_)) | Err(ParseLeb128Error::OverflowU64) => {
+                return Ok(None)
+            }
+            Err(error) => return Err(leb128_to_primary_error(error)),
+        };
+        let end = match (pos + u64::try_from(bytes_read).expect("64 bit platform needed"))
+            .checked_add(size)
+            .and_then(|end| end.checked_add(self.checksum_size()))
+        {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+
+        let mut block = [0; CID_CHECK_SIZE as usize];
+        let block_size = read_up_to(&mut file.take(size), &mut block).map_err(io_error())?;
+        // Only the CID is checked, hence the block may be cut off after it.
+        match read_cid_version_and_size(&block[..block_size]) {
+            Ok((0, _)) | Ok((1, _)) => Ok(Some(end)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the size of the file, the position is used for the error context.
+    fn file_size(&self, pos: Option<u64>) -> Result<u64, PrimaryError> {
+        let mut file = &self.reader;
+        file.seek(SeekFrom::End(0))
+            .map_err(PrimaryError::io("reading block", &self.path, pos))
+    }
+
+    /// Reads the block (CID and data) at the given position.
+    fn read_block_at(&self, pos: u64) -> Result<Vec<u8>, PrimaryError> {
+        self.read_frame_at(pos).map(|(block, _frame_size)| block)
+    }
+
+    /// Reads the block (CID and data) at the given position, together with the byte size of the
+    /// whole frame (size prefix, block and checksum).
+    ///
+    /// In the version 2 format the checksum is validated.
+    fn read_frame_at(&self, pos: u64) -> Result<(Vec<u8>, u64), PrimaryError> {
+        let mut block = Vec::new();
+        let frame = self.read_raw_frame_into(pos, &mut block)?;
+        check_checksum(pos, &block, frame.checksum.as_ref())?;
+        Ok((block, frame.size))
+    }
+
+    /// Same as [`CidPrimary::read_frame_at`], but the block is read into the given buffer and
+    /// the checksum is returned instead of being validated.
+    fn read_raw_frame_into(&self, pos: u64, block: &mut Vec<u8>) -> Result<RawFrame, PrimaryError> {
+        if pos < self.first_pos() {
+            return Err(PrimaryError::MisalignedRead { pos });
+        }
+        let mut file = &self.reader;
+        let file_size = file.seek(SeekFrom::End(0)).map_err(PrimaryError::io(
+            "reading block",
+            &self.path,
+            Some(pos),
+        ))?;
+        if pos > file_size {
+            return Err(PrimaryError::OutOfBounds {
+                pos,
+                len: file_size,
+            });
+        }
+
+        file.seek(SeekFrom::Start(pos)).map_err(PrimaryError::io(
+            "reading block",
+            &self.path,
+            Some(pos),
+        ))?;
+        let io_context =
+            |error: PrimaryError| error.with_io_context("reading block", &self.path, Some(pos));
+        let frame_size =
+            read_data_into(&mut file, block).map_err(io_context)? + self.checksum_size();
+        let checksum = if self.version == 2 {
+            let mut checksum = StoredChecksum::default();
+            checksum.len = read_up_to(&mut file, &mut checksum.bytes)
+                .map_err(|error| io_context(error.into()))?;
+            Some(checksum)
+        } else {
+            None
+        };
+        Ok(RawFrame {
+            size: frame_size,
+            checksum,
+            cut_off: pos.saturating_add(frame_size) > file_size,
+        })
+    }
+}
+
+/// An iterator over the values of a [`CidPrimary`], see [`CidPrimary::iter_values`].
+#[derive(Debug)]
+pub struct CidValueIter<'a> {
+    path: &'a Path,
+    /// The file is opened with the first call to `next`.
+    reader: Option<BufReader<File>>,
+    /// The position of the next frame.
+    pos: u64,
+    /// The size of the file when it was opened, data that is stored later isn't returned.
+    file_size: u64,
+    /// The byte size of the checksum that follows every block.
+    checksum_size: u64,
+    /// Set once the end of the file or an error was reached.
+    done: bool,
+}
+
+impl CidValueIter<'_> {
+    fn open(&mut self) -> Result<(), PrimaryError> {
+        let mut file = File::open(self.path)?;
+        self.file_size = file.metadata()?.len();
+        // Skip the version byte.
+        file.seek(SeekFrom::Start(self.pos))?;
+        self.reader = Some(BufReader::new(file));
+        Ok(())
+    }
+
+    /// Reads the value of the frame at the current position and moves on to the next one.
+    fn read_value(&mut self) -> Result<Vec<u8>, PrimaryError> {
+        let reader = self.reader.as_mut().expect("File was opened");
+        let (size, size_bytes_read): (u64, usize) =
+            reader.read_leb128().map_err(leb128_to_primary_error)?;
+        let frame_end =
+            self.pos + u64::try_from(size_bytes_read).unwrap() + size + self.checksum_size;
+        if frame_end > self.file_size {
+            return Err(PrimaryError::OutOfBounds {
+                pos: frame_end,
+                len: self.file_size,
+            });
+        }
+
+        // A CIDv0 is a multihash, a CIDv1 has the version and the codec in front of it. In both
+        // cases the CID ends with the digest size and the digest.
+        let mut read_varint = || -> Result<(u64, u64), PrimaryError> {
+            let (value, bytes_read): (u64, usize) =
+                reader.read_leb128().map_err(leb128_to_primary_error)?;
+            Ok((value, u64::try_from(bytes_read).unwrap()))
+        };
+        let (first, mut cid_size) = read_varint()?;
+        let varints = if first == u64::from(CID_V0_PREFIX[0]) {
+            1
+        } else {
+            3
+        };
+        let mut digest_size = 0;
+        for _ in 0..varints {
+            let (value, bytes_read) = read_varint()?;
+            digest_size = value;
+            cid_size += bytes_read;
+        }
+        cid_size = cid_size.saturating_add(digest_size);
+        if cid_size > size {
+            return Err(PrimaryError::OutOfBounds {
+                pos: cid_size,
+                len: size,
+            });
+        }
+
+        reader.seek_relative(i64::try_from(digest_size).expect("digest size fits into 63 bits"))?;
+        let mut value = Vec::with_capacity(usize::try_from(size - cid_size).unwrap());
+        reader.take(size - cid_size).read_to_end(&mut value)?;
+        reader.seek_relative(i64::try_from(self.checksum_size).unwrap())?;
+        self.pos = frame_end;
+        Ok(value)
+    }
+}
+
+impl Iterator for CidValueIter<'_> {
+    type Item = Result<Vec<u8>, PrimaryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let (path, pos) = (self.path, self.pos);
+        if self.reader.is_none() {
+            if let Err(error) = self.open() {
+                self.done = true;
+                return Some(Err(error.with_io_context("iterating values", path, None)));
+            }
+        }
+        if self.pos >= self.file_size {
+            self.done = true;
+            return None;
+        }
+        let value = self.read_value();
+        // Stop the iteration after an error.
+        self.done = value.is_err();
+        Some(value.map_err(|error| error.with_io_context("iterating values", path, Some(pos))))
+    }
+}
+
+impl PrimaryStorage for CidPrimary {
+    /// Reads the CID and the data at the given position.
+    ///
+    /// If the position isn't the start of a frame, the data read is garbage. To detect that, the
+    /// frame needs to end within the file and its block needs to start with a CIDv0 or CIDv1,
+    /// else [`PrimaryError::MisalignedRead`] is returned. [`CidPrimary::find_nearest_frame`]
+    /// finds the next valid position. In the version 2 format the checksum is validated
+    /// afterwards.
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        #[cfg(feature = "tracing")]
+        let span = storethehash::tracing::trace_span!(
+            "CidPrimary::get",
+            pos,
+            bytes_read = storethehash::tracing::field::Empty
+        )
+        .entered();
+
+        let mut block = self.buffers.get();
+        let frame = self.read_raw_frame_into(pos, &mut block)?;
+        #[cfg(feature = "tracing")]
+        span.record("bytes_read", block.len());
+        let cid_size = match read_cid_version_and_size(&block) {
+            Ok((0, cid_size)) | Ok((1, cid_size)) if !frame.cut_off => cid_size,
+            _ => return Err(PrimaryError::MisalignedRead { pos }),
+        };
+        check_checksum(pos, &block, frame.checksum.as_ref())?;
+        let (cid, data) = block.split_at(cid_size);
+        Ok((cid.to_vec(), data.to_vec()))
     }
 
     fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        if self.read_only {
+            return Err(PrimaryError::ReadOnly);
+        }
+        #[cfg(feature = "tracing")]
+        let span = storethehash::tracing::trace_span!(
+            "CidPrimary::put",
+            pos = storethehash::tracing::field::Empty,
+            bytes_written = storethehash::tracing::field::Empty
+        )
+        .entered();
+
         let mut file = self.writer.borrow_mut();
-        let file_size = file.seek(SeekFrom::End(0))?;
+        let file_size = file.seek(SeekFrom::End(0)).map_err(PrimaryError::io(
+            "writing block",
+            &self.path,
+            None,
+        ))?;
 
         let size = key.len() + value.len();
-        let _bytes_written = file.write_leb128(size)?;
-        file.write_all(&key)?;
-        file.write_all(&value)?;
+        let checksum = if self.version == 2 {
+            crc64(crc64(0, key), value).to_le_bytes().to_vec()
+        } else {
+            Vec::new()
+        };
+        let frame_size = file
+            .write_leb128(size)
+            .and_then(|varint_size| {
+                file.write_all(key)?;
+                file.write_all(value)?;
+                file.write_all(&checksum)?;
+                // Flush, so that the data is visible to the reader.
+                file.flush()?;
+                Ok(varint_size + size + checksum.len())
+            })
+            .map_err(PrimaryError::io(
+                "writing block",
+                &self.path,
+                Some(file_size),
+            ))?;
+        if let Some(syncer) = &self.syncer {
+            syncer.record_write(frame_size as u64);
+        }
+        #[cfg(feature = "tracing")]
+        {
+            span.record("pos", file_size);
+            span.record("bytes_written", size);
+        }
 
         Ok(file_size)
     }
 
+    fn flush(&self) -> Result<(), PrimaryError> {
+        let mut file = self.writer.borrow_mut();
+        file.flush()
+            .and_then(|_| file.get_ref().sync_data())
+            .map_err(PrimaryError::io(
+                "flushing primary storage",
+                &self.path,
+                None,
+            ))
+    }
+
+    /// Flushes all data to disk and copies the file.
+    fn snapshot(&self, path: &Path) -> Result<u64, PrimaryError> {
+        self.flush()?;
+        let mut file = &self.reader;
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut snapshot = File::create(path)?;
+        // Only the data up to the size determined above is copied.
+        io::copy(&mut file.take(size), &mut snapshot)?;
+        snapshot.sync_all()?;
+        Ok(size)
+    }
+
+    fn size(&self) -> Result<Option<u64>, PrimaryError> {
+        let mut file = &self.reader;
+        Ok(Some(file.seek(SeekFrom::End(0))?))
+    }
+
+    fn iter_values(&self) -> Box<dyn Iterator<Item = Result<Vec<u8>, PrimaryError>> + '_> {
+        Box::new(CidPrimary::iter_values(self))
+    }
+
+    /// The first entry follows the version byte in the version 2 format.
+    fn first_pos(&self) -> u64 {
+        if self.version == 2 {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn advise_sequential(&self, pos: u64) -> Result<(), PrimaryError> {
+        readahead::advise_sequential(&self.reader, pos, 0).map_err(PrimaryError::io(
+            "giving readahead hint",
+            &self.path,
+            Some(pos),
+        ))
+    }
+
+    fn advise_normal(&self) -> Result<(), PrimaryError> {
+        readahead::advise_normal(&self.reader, 0, 0).map_err(PrimaryError::io(
+            "reverting readahead hint",
+            &self.path,
+            None,
+        ))
+    }
+
+    fn next_pos(&self, pos: u64) -> Result<u64, PrimaryError> {
+        let mut file = &self.reader;
+        file.seek(SeekFrom::Start(pos))?;
+        let (size, bytes_read): (u64, usize) =
+            file.read_leb128().map_err(leb128_to_primary_error)?;
+        Ok(pos + u64::try_from(bytes_read).unwrap() + size + self.checksum_size())
+    }
+
+    /// Checks whether the digest of the CID matches the data.
+    ///
+    /// Only the SHA2-256, SHA2-512 and identity hash functions are supported, for any other an
+    /// error is returned. A CID that cannot be parsed counts as corrupt. In the version 2 format
+    /// a block whose checksum doesn't match counts as corrupt as well.
+    fn verify_at(&self, pos: u64) -> Result<bool, PrimaryError> {
+        let block = match self.read_block_at(pos) {
+            Ok(block) => block,
+            Err(PrimaryError::ChecksumMismatch { .. }) => return Ok(false),
+            Err(error) => return Err(error),
+        };
+        let (cid, data) = match read_block(&block) {
+            Ok(cid_and_data) => cid_and_data,
+            Err(PrimaryError::OutOfBounds { .. }) => return Ok(false),
+            Err(error) => return Err(error),
+        };
+        let cid = match Cid::try_from(&cid[..]) {
+            Ok(cid) => cid,
+            Err(_) => return Ok(false),
+        };
+        let digest = match cid.hash().code() {
+            MULTIHASH_IDENTITY => IdentityCodec::encode(&data)?,
+            MULTIHASH_SHA2_256 => Sha256Codec::encode(&data)?,
+            MULTIHASH_SHA2_512 => Sha512Codec::encode(&data)?,
+            code => {
+                return Err(PrimaryError::Other(
+                    format!("Unsupported hash function: 0x{:x}.", code).into(),
+                ))
+            }
+        };
+        Ok(digest == cid.hash().digest())
+    }
+
     fn index_key(key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
         // A CID is stored, but the index only contains the digest (the actual hash) of the CID.
-        let cid = Cid::try_from(&key[..]).map_err(|error| PrimaryError::Other(Box::new(error)))?;
-        let digest = cid.hash().digest();
-        Ok(digest.to_vec())
+        CidDigestCodec::encode(key)
     }
 }
 
-/// Read some data prefixed with a varint.
+/// Lookups on a database with a [`CidPrimary`] by parsed CIDs.
+pub trait CidDb {
+    /// Returns the values of the given CIDs, in the same order as the CIDs.
+    ///
+    /// CIDs that are not found are `None`. Both CIDv0 and CIDv1 are supported, they are looked up
+    /// by their digest. The first failed lookup returns an error.
+    fn get_batch_by_cid(&self, cids: &[Cid]) -> Result<Vec<Option<Vec<u8>>>, Error>;
+
+    /// Returns the number of blocks in the primary storage, see [`CidPrimary::count_blocks`].
+    ///
+    /// Unlike [`Db::count`] it also counts blocks that are superseded or not indexed.
+    fn count_primary_blocks(&self) -> Result<u64, Error>;
+}
+
+impl<const N: u8> CidDb for Db<CidPrimary, N> {
+    fn get_batch_by_cid(&self, cids: &[Cid]) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        // The database derives the index key (the digest) from the binary CID itself.
+        let keys: Vec<Vec<u8>> = cids.iter().map(Cid::to_bytes).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|key| &key[..]).collect();
+        self.get_many(&keys).into_iter().collect()
+    }
+
+    fn count_primary_blocks(&self) -> Result<u64, Error> {
+        Ok(self.primary().count_blocks()?)
+    }
+}
+
+/// Returns the format version of a file, an empty one is in the original format.
+fn read_version(file: &File, path: &Path) -> Result<u8, PrimaryError> {
+    let io_error = || PrimaryError::io("reading format version", path, Some(0));
+    let mut reader = file;
+    let mut first_byte = [0];
+    let bytes_read = reader
+        .seek(SeekFrom::Start(0))
+        .and_then(|_| reader.read(&mut first_byte))
+        .map_err(io_error())?;
+    if bytes_read == 1 && first_byte[0] == FORMAT_V2 {
+        Ok(2)
+    } else {
+        Ok(1)
+    }
+}
+
+/// Returns the error for a file that isn't in the version 2 format.
+fn not_v2(path: &Path) -> PrimaryError {
+    PrimaryError::Other(format!("{:?} is not in the version 2 format.", path).into())
+}
+
+/// Writes a frame in the given format version, returns its byte size.
+fn write_frame<W: Write>(writer: &mut W, block: &[u8], version: u8) -> Result<u64, io::Error> {
+    let varint_size = writer.write_leb128(block.len())?;
+    writer.write_all(block)?;
+    let mut frame_size = u64::try_from(varint_size + block.len()).expect("64 bit platform needed");
+    if version == 2 {
+        writer.write_all(&crc64(0, block).to_le_bytes())?;
+        frame_size += CHECKSUM_SIZE;
+    }
+    Ok(frame_size)
+}
+
+/// A frame as it was read by [`CidPrimary::read_raw_frame_into`].
+#[derive(Debug)]
+struct RawFrame {
+    /// The byte size of the whole frame (size prefix, block and checksum) according to its size
+    /// prefix.
+    size: u64,
+    /// It's `None` in the original format.
+    checksum: Option<StoredChecksum>,
+    /// Whether the frame extends past the end of the file, then less than its size was read.
+    cut_off: bool,
+}
+
+/// The checksum that follows a block in the version 2 format, as it was read from the file.
+#[derive(Debug, Default)]
+struct StoredChecksum {
+    bytes: [u8; CHECKSUM_SIZE as usize],
+    /// It's shorter than [`CHECKSUM_SIZE`] if the frame is cut off at the end of the file.
+    len: usize,
+}
+
+/// Returns [`PrimaryError::ChecksumMismatch`] if there is a checksum and it doesn't match the
+/// block, see [`CidPrimary::read_raw_frame_into`].
+fn check_checksum(
+    pos: u64,
+    block: &[u8],
+    checksum: Option<&StoredChecksum>,
+) -> Result<(), PrimaryError> {
+    match checksum {
+        Some(checksum) if checksum.bytes[..checksum.len] != crc64(0, block).to_le_bytes() => {
+            Err(PrimaryError::ChecksumMismatch { pos })
+        }
+        _ => Ok(()),
+    }
+}
+
+const fn crc64_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+}
+
+/// Continues a CRC-64/XZ checksum with more data, a new checksum starts with 0.
+fn crc64(checksum: u64, data: &[u8]) -> u64 {
+    let mut crc = !checksum;
+    for byte in data {
+        crc = CRC64_TABLE[usize::from(crc as u8 ^ byte)] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Read some data prefixed with a varint into the given buffer, its previous content is replaced.
 ///
-/// Returns the data as well as the total bytes read (varint + data).
-fn read_data<R: Read>(reader: &mut R) -> Result<(Vec<u8>, u64), PrimaryError> {
+/// Returns the total bytes read (varint + data).
+fn read_data_into<R: Read>(reader: &mut R, data: &mut Vec<u8>) -> Result<u64, PrimaryError> {
     let (size, bytes_read): (u64, usize) = reader.read_leb128().map_err(leb128_to_primary_error)?;
-    let mut data = Vec::with_capacity(usize::try_from(size).unwrap());
-    reader.take(size).read_to_end(&mut data)?;
-    Ok((data, u64::try_from(bytes_read).unwrap() + size))
+    data.clear();
+    data.reserve(usize::try_from(size).unwrap());
+    reader.take(size).read_to_end(data)?;
+    Ok(u64::try_from(bytes_read).unwrap() + size)
+}
+
+/// Fills the buffer, unless the end of the reader is reached before. Returns the number of bytes
+/// that were read.
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, io::Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(bytes_read) => filled += bytes_read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
 }
 
 /// Split some data into a CID and the rest.
 fn read_block(block: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+    let (_version, cid_size) = read_cid_version_and_size(block)?;
+    let (cid, data) = block.split_at(cid_size);
+    Ok((cid.to_vec(), data.to_vec()))
+}
+
+/// Returns the version and the byte size of the CID the given block starts with.
+fn read_cid_version_and_size(block: &[u8]) -> Result<(u64, usize), PrimaryError> {
+    // A CIDv0 is just a SHA2-256 multihash, it doesn't have a version or codec prefix.
+    if block.starts_with(&CID_V0_PREFIX) {
+        if block.len() < CID_V0_SIZE {
+            return Err(block_too_short(CID_V0_SIZE, block));
+        }
+        return Ok((0, CID_V0_SIZE));
+    }
+
     // A block is a CID together with some data.
-    let (_version, version_offset): (u64, _) = (&mut &block[..])
+    let (version, version_offset): (u64, _) = (&mut &block[..])
         .read_leb128()
         .map_err(leb128_to_primary_error)?;
     let (_codec, codec_offset): (u64, _) = (&mut &block[version_offset..])
@@ -110,16 +1044,605 @@ fn read_block(block: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
         + multihash_code_offset
         + multihash_size_offset
         + usize::try_from(multihash_size).unwrap();
-    let (cid, data) = block.split_at(cid_size);
-    Ok((cid.to_vec(), data.to_vec()))
+    if block.len() < cid_size {
+        return Err(block_too_short(cid_size, block));
+    }
+    Ok((version, cid_size))
+}
+
+/// Returns the error for a block that is shorter than the CID it starts with.
+///
+/// The position and length are relative to the block, not to the primary storage.
+fn block_too_short(cid_size: usize, block: &[u8]) -> PrimaryError {
+    PrimaryError::OutOfBounds {
+        pos: u64::try_from(cid_size).expect("64 bit platform needed"),
+        len: u64::try_from(block.len()).expect("64 bit platform needed"),
+    }
 }
 
 /// Coverts an error caused by the wasabi-leb128 library into a [`PrimaryError`]
 fn leb128_to_primary_error(parse_error: ParseLeb128Error) -> PrimaryError {
     match parse_error {
         ParseLeb128Error::UnexpectedEndOfData(error) | ParseLeb128Error::Other(error) => {
-            PrimaryError::Io(error)
+            PrimaryError::from(error)
         }
         error => PrimaryError::Other(Box::new(error)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{crc64, BackgroundSyncer, CidDb, CidPrimary, SyncPolicy, FORMAT_V2};
+
+    use std::convert::TryFrom;
+    use std::fs::{self, OpenOptions};
+    use std::io::{Seek, SeekFrom, Write};
+    use std::time::Duration;
+
+    use cid::Cid;
+    use storethehash::codec::{KeyCodec, Sha256Codec};
+    use storethehash::db::Db;
+    use storethehash::index::Index;
+    use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+    // A CIDv0 is only a SHA2-256 multihash.
+    fn cid_v0(digest_byte: u8) -> Vec<u8> {
+        [&[0x12, 0x20][..], &[digest_byte; 32][..]].concat()
+    }
+
+    // A CIDv1 with the raw codec and a SHA2-256 multihash.
+    fn cid_v1(digest_byte: u8) -> Vec<u8> {
+        [&[0x01, 0x55, 0x12, 0x20][..], &[digest_byte; 32][..]].concat()
+    }
+
+    #[test]
+    fn get_cid_v0() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+
+        let cid = cid_v0(0xaa);
+        let pos = primary.put(&cid, b"some data").unwrap();
+        assert_eq!(primary.get(pos).unwrap(), (cid, b"some data".to_vec()));
+        assert_eq!(primary.cid_version_at(pos).unwrap(), 0);
+    }
+
+    #[test]
+    fn get_mixed_cid_versions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+
+        let v1 = cid_v1(0x11);
+        let v0 = cid_v0(0x22);
+        let v1_pos = primary.put(&v1, b"v1 data").unwrap();
+        let v0_pos = primary.put(&v0, b"v0 data").unwrap();
+
+        assert_eq!(primary.get(v1_pos).unwrap(), (v1, b"v1 data".to_vec()));
+        assert_eq!(
+            primary.get(v0_pos).unwrap(),
+            (v0.clone(), b"v0 data".to_vec())
+        );
+        assert_eq!(primary.cid_version_at(v1_pos).unwrap(), 1);
+        assert_eq!(primary.cid_version_at(v0_pos).unwrap(), 0);
+        // Both CID versions result in an index key that is the digest.
+        assert_eq!(primary.get_index_key(v1_pos).unwrap(), [0x11; 32]);
+        assert_eq!(primary.get_index_key(v0_pos).unwrap(), [0x22; 32]);
+    }
+
+    #[test]
+    fn open_read_only() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("storethehash.data");
+        let cid = cid_v1(0x33);
+        let pos = CidPrimary::open(&path).unwrap().put(&cid, b"data").unwrap();
+
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+
+        let primary = CidPrimary::open_read_only(&path).unwrap();
+        assert_eq!(primary.get(pos).unwrap(), (cid.clone(), b"data".to_vec()));
+        assert!(matches!(
+            primary.put(&cid, b"data"),
+            Err(PrimaryError::ReadOnly)
+        ));
+    }
+
+    #[test]
+    fn file_handles() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("storethehash.data");
+        let primary = CidPrimary::open(&path).unwrap();
+        let cid = cid_v1(0x44);
+        primary.put(&cid, b"data").unwrap();
+
+        let file_size = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(primary.reader().metadata().unwrap().len(), file_size);
+        // Data written through the writer is visible to the primary storage.
+        let pos = {
+            let mut writer = primary.writer();
+            let pos = writer.seek(SeekFrom::End(0)).unwrap();
+            writer.write_all(&[4 + 32 + 4]).unwrap();
+            writer.write_all(&cid_v1(0x55)).unwrap();
+            writer.write_all(b"more").unwrap();
+            writer.flush().unwrap();
+            pos
+        };
+        assert_eq!(primary.get(pos).unwrap(), (cid_v1(0x55), b"more".to_vec()));
+
+        let (reader, writer) = primary.into_parts();
+        assert_eq!(
+            reader.metadata().unwrap().len(),
+            writer.get_ref().metadata().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn io_error_context() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("storethehash.data");
+        let primary = CidPrimary::open(&path).unwrap();
+        primary.put(&cid_v1(0x44), b"data").unwrap();
+        let pos = primary.put(&cid_v1(0x55), b"more").unwrap();
+        assert_eq!(primary.path(), path);
+
+        // Cut the file right before the second block.
+        primary.reader().set_len(pos).unwrap();
+        let error = primary.get(pos).unwrap_err();
+        assert!(matches!(
+            &error,
+            PrimaryError::Io { offset: Some(offset), .. } if *offset == pos
+        ));
+        let message = error.to_string();
+        assert!(
+            message.contains(&format!("at offset {}", pos)),
+            "{}",
+            message
+        );
+        assert!(message.contains(&format!("{:?}", path)), "{}", message);
+    }
+
+    #[test]
+    fn get_out_of_bounds() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+        primary.put(&cid_v1(0x66), b"data").unwrap();
+        primary.flush().unwrap();
+        let size = primary.size().unwrap().unwrap();
+
+        let error = primary.get(size + 10).unwrap_err();
+        assert!(matches!(
+            error,
+            PrimaryError::OutOfBounds { pos, len } if pos == size + 10 && len == size
+        ));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Out of bounds error: position {} is past the length {}.",
+                size + 10,
+                size
+            )
+        );
+    }
+
+    #[test]
+    fn get_misaligned() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+        let first = primary.put(&cid_v1(0x11), b"first").unwrap();
+        let second = primary.put(&cid_v1(0x22), b"second").unwrap();
+        primary.flush().unwrap();
+        let size = primary.size().unwrap().unwrap();
+
+        // The CID starts one byte after the size prefix.
+        let error = primary.get(first + 1).unwrap_err();
+        assert!(matches!(error, PrimaryError::MisalignedRead { pos } if pos == first + 1));
+        assert_eq!(
+            primary.get(second).unwrap(),
+            (cid_v1(0x22), b"second".to_vec())
+        );
+
+        assert_eq!(primary.find_nearest_frame(first).unwrap(), first);
+        assert_eq!(primary.find_nearest_frame(first + 1).unwrap(), second);
+        assert_eq!(primary.find_nearest_frame(second + 1).unwrap(), size);
+        assert_eq!(primary.find_nearest_frame(size).unwrap(), size);
+        assert!(matches!(
+            primary.find_nearest_frame(size + 1),
+            Err(PrimaryError::OutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn get_with_truncated_tail() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+        let first = primary.put(&cid_v1(0x11), b"first").unwrap();
+        let second = primary.put(&cid_v1(0x22), b"second").unwrap();
+        primary.flush().unwrap();
+        let size = primary.size().unwrap().unwrap();
+
+        // The last frame was only partially written, e.g. due to a crash.
+        primary.reader().set_len(size - 5).unwrap();
+        assert_eq!(first, 0);
+        assert_eq!(primary.get(0).unwrap(), (cid_v1(0x11), b"first".to_vec()));
+        assert!(matches!(
+            primary.get(second),
+            Err(PrimaryError::MisalignedRead { pos }) if pos == second
+        ));
+        assert_eq!(primary.find_nearest_frame(first).unwrap(), first);
+    }
+
+    #[test]
+    fn get_batch_by_cid() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+        let db = Db::<_, 8>::open(primary, temp_dir.path().join("storethehash.index")).unwrap();
+        db.put(&cid_v0(0x11), b"v0").unwrap();
+        db.put(&cid_v1(0x22), b"v1").unwrap();
+
+        let cids: Vec<Cid> = [cid_v1(0x33), cid_v1(0x22), cid_v0(0x11), cid_v0(0x44)]
+            .iter()
+            .map(|cid| Cid::try_from(&cid[..]).unwrap())
+            .collect();
+        assert_eq!(
+            db.get_batch_by_cid(&cids).unwrap(),
+            vec![None, Some(b"v1".to_vec()), Some(b"v0".to_vec()), None]
+        );
+        assert_eq!(
+            db.get_batch_by_cid(&[]).unwrap(),
+            Vec::<Option<Vec<u8>>>::new()
+        );
+
+        // A CIDv1 with the same digest as a stored CIDv0 is a different key.
+        let v1_of_v0 = Cid::try_from(&[&[0x01, 0x70][..], &cid_v0(0x11)[..]].concat()[..]).unwrap();
+        assert_eq!(db.get_batch_by_cid(&[v1_of_v0]).unwrap(), vec![None]);
+    }
+
+    #[test]
+    fn count_blocks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary_path = temp_dir.path().join("storethehash.data");
+        let primary = CidPrimary::open(&primary_path).unwrap();
+        assert_eq!(primary.count_blocks().unwrap(), 0);
+
+        for byte in 0..100u8 {
+            primary
+                .put(&cid_v1(byte), &vec![byte; usize::from(byte) * 3])
+                .unwrap();
+        }
+        assert_eq!(primary.count_blocks().unwrap(), 100);
+
+        // A block that is cut off is an error.
+        drop(primary);
+        let size = fs::metadata(&primary_path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&primary_path)
+            .unwrap()
+            .set_len(size - 1)
+            .unwrap();
+        let primary = CidPrimary::open_read_only(&primary_path).unwrap();
+        assert!(matches!(
+            primary.count_blocks(),
+            Err(PrimaryError::OutOfBounds { pos, len }) if pos == size && len == size - 1
+        ));
+    }
+
+    #[test]
+    fn iter_values() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary_path = temp_dir.path().join("storethehash.data");
+        let primary = CidPrimary::open(&primary_path).unwrap();
+        assert_eq!(primary.iter_values().count(), 0);
+
+        let mut expected = Vec::new();
+        for byte in 0..20u8 {
+            let cid = if byte < 10 {
+                cid_v0(byte)
+            } else {
+                cid_v1(byte)
+            };
+            let value = vec![byte; usize::from(byte) * 3];
+            primary.put(&cid, &value).unwrap();
+            expected.push(value);
+        }
+        let values: Vec<Vec<u8>> = primary.iter_values().map(Result::unwrap).collect();
+        assert_eq!(values, expected);
+        // It's the same as the values of all entries.
+        let mut pos = 0;
+        for value in &values {
+            assert_eq!(&primary.get(pos).unwrap().1, value);
+            pos = primary.next_pos(pos).unwrap();
+        }
+
+        // A block that is cut off ends the iteration with an error.
+        drop(primary);
+        let size = fs::metadata(&primary_path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&primary_path)
+            .unwrap()
+            .set_len(size - 1)
+            .unwrap();
+        let primary = CidPrimary::open_read_only(&primary_path).unwrap();
+        let mut values = primary.iter_values();
+        for value in &expected[..expected.len() - 1] {
+            assert_eq!(&values.next().unwrap().unwrap(), value);
+        }
+        assert!(matches!(
+            values.next(),
+            Some(Err(PrimaryError::OutOfBounds { pos, len })) if pos == size && len == size - 1
+        ));
+        assert!(values.next().is_none());
+    }
+
+    #[test]
+    fn count_primary_blocks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+        let db = Db::<_, 8>::open(primary, temp_dir.path().join("storethehash.index")).unwrap();
+        db.put(&cid_v1(0x11), b"first").unwrap();
+        db.put(&cid_v0(0x22), b"second").unwrap();
+        // The key already exists, the data is stored nonetheless.
+        db.put(&cid_v1(0x11), b"first").unwrap();
+
+        assert_eq!(db.count_primary_blocks().unwrap(), 3);
+        assert_eq!(db.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn defragment() {
+        const BUCKETS_BITS: u8 = 8;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary_path = temp_dir.path().join("storethehash.data");
+        let primary = CidPrimary::open(&primary_path).unwrap();
+        let mut index =
+            Index::<_, BUCKETS_BITS>::open(temp_dir.path().join("storethehash.index"), primary)
+                .unwrap();
+
+        // The first byte of the digest is the bucket, insert them in reverse bucket order.
+        let entries: Vec<_> = (1..=5u8)
+            .rev()
+            .map(|byte| (cid_v1(byte), vec![byte; usize::from(byte)]))
+            .collect();
+        for (cid, data) in &entries {
+            let pos = index.primary.put(cid, data).unwrap();
+            index
+                .put(&CidPrimary::index_key(cid).unwrap(), pos)
+                .unwrap();
+        }
+        // A block that isn't referenced by the index is dropped.
+        index.primary.put(&cid_v1(0x99), b"unreferenced").unwrap();
+        let size_before = index.primary.size().unwrap().unwrap();
+
+        let mut sorted_offsets = Vec::new();
+        let mut new_offset = 0;
+        for bucket in 0..1 << BUCKETS_BITS {
+            for old_offset in index.file_offsets_in_bucket(bucket).unwrap() {
+                sorted_offsets.push((old_offset, new_offset));
+                new_offset += index.primary.next_pos(old_offset).unwrap() - old_offset;
+            }
+        }
+        let mapping = index.primary.defragment(&sorted_offsets).unwrap();
+        assert_eq!(mapping, sorted_offsets);
+        index.remap_offsets(&mapping).unwrap();
+
+        assert_eq!(index.primary.size().unwrap(), Some(new_offset));
+        assert!(new_offset < size_before);
+        let offsets: Vec<u64> = (0..1 << BUCKETS_BITS)
+            .flat_map(|bucket| index.file_offsets_in_bucket(bucket).unwrap())
+            .collect();
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+        for (cid, data) in &entries {
+            let pos = index
+                .get(&CidPrimary::index_key(cid).unwrap())
+                .unwrap()
+                .unwrap();
+            assert_eq!(index.primary.get(pos).unwrap(), (cid.clone(), data.clone()));
+        }
+
+        // The defragmented storage can still be written to.
+        let pos = index.primary.put(&cid_v1(0x66), b"new").unwrap();
+        assert_eq!(pos, new_offset);
+        assert_eq!(
+            index.primary.get(pos).unwrap(),
+            (cid_v1(0x66), b"new".to_vec())
+        );
+    }
+
+    #[test]
+    fn verify_at() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+
+        let data = b"some data";
+        let digest = Sha256Codec::encode(data).unwrap();
+        let intact = [&[0x01, 0x55, 0x12, 0x20][..], &digest[..]].concat();
+        let intact_pos = primary.put(&intact, data).unwrap();
+        // The digest doesn't match the data.
+        let corrupt_pos = primary.put(&cid_v1(0x66), data).unwrap();
+        // The CID is truncated.
+        let truncated_pos = primary.put(&[0x01, 0x55, 0x12, 0x20, 0x00], b"").unwrap();
+
+        assert!(primary.verify_at(intact_pos).unwrap());
+        assert!(!primary.verify_at(corrupt_pos).unwrap());
+        assert!(!primary.verify_at(truncated_pos).unwrap());
+
+        assert_eq!(primary.next_pos(intact_pos).unwrap(), corrupt_pos);
+        assert_eq!(primary.next_pos(corrupt_pos).unwrap(), truncated_pos);
+        assert_eq!(
+            primary.next_pos(truncated_pos).unwrap(),
+            primary.size().unwrap().unwrap()
+        );
+    }
+
+    #[test]
+    fn crc64_check_value() {
+        assert_eq!(crc64(0, b"123456789"), 0x995d_c9bb_df19_39fa);
+        // The checksum can be continued.
+        assert_eq!(crc64(crc64(0, b"1234"), b"56789"), crc64(0, b"123456789"));
+    }
+
+    #[test]
+    fn open_v2() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("storethehash.data");
+        let primary = CidPrimary::open_v2(&path).unwrap();
+        assert_eq!(primary.version(), 2);
+        assert_eq!(primary.first_pos(), 1);
+
+        let first_pos = primary.put(&cid_v1(0x11), b"first").unwrap();
+        let second_pos = primary.put(&cid_v0(0x22), b"second").unwrap();
+        assert_eq!(first_pos, 1);
+        assert_eq!(primary.next_pos(first_pos).unwrap(), second_pos);
+        assert_eq!(
+            primary.next_pos(second_pos).unwrap(),
+            primary.size().unwrap().unwrap()
+        );
+        assert_eq!(
+            primary.get(first_pos).unwrap(),
+            (cid_v1(0x11), b"first".to_vec())
+        );
+        assert_eq!(
+            primary.get(second_pos).unwrap(),
+            (cid_v0(0x22), b"second".to_vec())
+        );
+        assert_eq!(primary.count_blocks().unwrap(), 2);
+        let values: Vec<_> = primary.iter_values().map(Result::unwrap).collect();
+        assert_eq!(values, vec![b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(primary.verify_checksum_v2().unwrap(), (2, 0));
+        assert_eq!(primary.find_nearest_frame(0).unwrap(), first_pos);
+        drop(primary);
+        assert_eq!(fs::read(&path).unwrap()[0], FORMAT_V2);
+
+        // The version is detected when opening.
+        let reopened = CidPrimary::open_read_only(&path).unwrap();
+        assert_eq!(reopened.version(), 2);
+        assert_eq!(
+            reopened.get(second_pos).unwrap(),
+            (cid_v0(0x22), b"second".to_vec())
+        );
+
+        // A file in the original format isn't opened as version 2.
+        let v1_path = temp_dir.path().join("storethehash.v1.data");
+        let v1 = CidPrimary::open(&v1_path).unwrap();
+        v1.put(&cid_v1(0x11), b"first").unwrap();
+        assert_eq!(v1.version(), 1);
+        assert!(matches!(
+            v1.verify_checksum_v2(),
+            Err(PrimaryError::Other(_))
+        ));
+        drop(v1);
+        assert!(matches!(
+            CidPrimary::open_v2(&v1_path),
+            Err(PrimaryError::Other(_))
+        ));
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("storethehash.data");
+        let primary = CidPrimary::open_v2(&path).unwrap();
+        let intact_pos = primary.put(&cid_v1(0x11), b"intact").unwrap();
+        let corrupt_pos = primary.put(&cid_v1(0x22), b"corrupt").unwrap();
+
+        // Flip the last byte of the data, right before the checksum.
+        let mut bytes = fs::read(&path).unwrap();
+        let data_end = bytes.len() - 9;
+        bytes[data_end] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        assert_eq!(
+            primary.get(intact_pos).unwrap(),
+            (cid_v1(0x11), b"intact".to_vec())
+        );
+        assert!(matches!(
+            primary.get(corrupt_pos),
+            Err(PrimaryError::ChecksumMismatch { pos }) if pos == corrupt_pos
+        ));
+        assert!(!primary.verify_at(corrupt_pos).unwrap());
+        assert_eq!(primary.verify_checksum_v2().unwrap(), (1, 1));
+    }
+
+    #[test]
+    fn migrate_to_v2() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+        for ii in 0..10u8 {
+            primary.put(&cid_v1(ii), &[ii; 3]).unwrap();
+        }
+
+        let migrated_path = temp_dir.path().join("storethehash.v2.data");
+        primary.migrate_to_v2(&migrated_path).unwrap();
+        let migrated = CidPrimary::open_v2(&migrated_path).unwrap();
+        assert_eq!(migrated.count_blocks().unwrap(), 10);
+        assert_eq!(migrated.verify_checksum_v2().unwrap(), (10, 0));
+        // Every block is 8 bytes bigger, hence the index needs to be rebuilt.
+        assert_eq!(
+            migrated.size().unwrap().unwrap(),
+            primary.size().unwrap().unwrap() + 1 + 10 * 8
+        );
+
+        let mut pos = migrated.first_pos();
+        for ii in 0..10u8 {
+            assert_eq!(migrated.get(pos).unwrap(), (cid_v1(ii), vec![ii; 3]));
+            pos = migrated.next_pos(pos).unwrap();
+        }
+
+        // Migrating a second time isn't possible.
+        assert!(matches!(
+            migrated.migrate_to_v2(&temp_dir.path().join("again.data")),
+            Err(PrimaryError::Other(_))
+        ));
+    }
+
+    #[test]
+    fn defragment_v2() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("storethehash.data");
+        let mut primary = CidPrimary::open_v2(&path).unwrap();
+        let first_pos = primary.put(&cid_v1(0x11), b"first").unwrap();
+        let second_pos = primary.put(&cid_v1(0x22), b"second").unwrap();
+
+        let mapping = primary
+            .defragment(&[(second_pos, 0), (first_pos, 1)])
+            .unwrap();
+        assert_eq!(primary.version(), 2);
+        assert_eq!(mapping[0], (second_pos, 1));
+        assert_eq!(primary.get(1).unwrap(), (cid_v1(0x22), b"second".to_vec()));
+        assert_eq!(primary.verify_checksum_v2().unwrap(), (2, 0));
+    }
+
+    #[test]
+    fn background_sync() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("storethehash.data");
+        let policy = SyncPolicy {
+            max_dirty_bytes: 64,
+            max_interval: Duration::from_millis(10),
+        };
+        let mut primary = CidPrimary::open(&path)
+            .unwrap()
+            .with_background_sync(policy)
+            .unwrap();
+        let first_pos = primary.put(&cid_v1(0x11), b"first").unwrap();
+        let second_pos = primary.put(&cid_v1(0x22), b"second").unwrap();
+
+        // The file is replaced, the new one is synced with the same policy.
+        primary
+            .defragment(&[(second_pos, 0), (first_pos, 1)])
+            .unwrap();
+        assert_eq!(
+            primary.syncer.as_ref().map(BackgroundSyncer::policy),
+            Some(policy)
+        );
+        assert_eq!(primary.get(0).unwrap(), (cid_v1(0x22), b"second".to_vec()));
+        drop(primary);
+
+        let read_only = CidPrimary::open_read_only(&path).unwrap();
+        assert!(matches!(
+            read_only.with_background_sync(policy),
+            Err(PrimaryError::ReadOnly)
+        ));
+    }
+}
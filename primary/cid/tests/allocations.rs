@@ -0,0 +1,82 @@
+//! Counts the heap allocations of lookups, so that the hot path doesn't start allocating again.
+//!
+//! A counting global allocator is used. The count is per thread, as the tests run in parallel.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use storethehash::primary::PrimaryStorage;
+use storethehash_primary_cid::CidPrimary;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // The thread local might already be gone when a thread shuts down.
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations the given function did on the current thread.
+fn count_allocations<F: FnOnce()>(function: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    function();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+// A CIDv1 with the raw codec and a SHA2-256 multihash.
+fn cid_v1(digest_byte: u8) -> Vec<u8> {
+    [&[0x01, 0x55, 0x12, 0x20][..], &[digest_byte; 32][..]].concat()
+}
+
+/// Only the returned CID and data are allocated, the frame is read into a reused buffer.
+fn assert_get_allocations(primary: &CidPrimary) {
+    const GETS: usize = 1000;
+
+    let positions: Vec<_> = (0..100)
+        .map(|ii| primary.put(&cid_v1(ii), &[ii; 100]).unwrap())
+        .collect();
+    primary.flush().unwrap();
+    // The first get may allocate the buffer that is reused later.
+    primary.get(positions[0]).unwrap();
+
+    let mut results = Vec::with_capacity(GETS);
+    let allocations = count_allocations(|| {
+        for ii in 0..GETS {
+            results.push(primary.get(positions[ii % 100]).unwrap());
+        }
+    });
+    println!("allocations per get: {}", allocations as f64 / GETS as f64);
+    assert_eq!(allocations, 2 * GETS);
+    assert_eq!(results[GETS - 1], (cid_v1(99), vec![99; 100]));
+}
+
+#[test]
+fn get_allocations() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+    assert_get_allocations(&primary);
+}
+
+#[test]
+fn get_allocations_v2() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary = CidPrimary::open_v2(temp_dir.path().join("storethehash.data")).unwrap();
+    assert_get_allocations(&primary);
+}
@@ -0,0 +1,192 @@
+//! A primary storage for arbitrary keys and values that is backed by a single file.
+//!
+//! The file is a sequence of `key size | value size | key | value`, where the sizes are 32-bit
+//! unsigned little-endian integers. The index keys are the keys itself, hence the keys should
+//! already be hashes.
+//!
+//! The storage is `Sync`. Reads don't use the cursor of the file, hence they can run concurrently
+//! with each other and with the writes, which are serialized.
+
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use log::debug;
+use storethehash::primary::{read_exact_at, PrimaryError, PrimaryStorage};
+use storethehash::readahead;
+
+/// Number of bytes used for each of the size prefixes of the key and the value.
+const SIZE_PREFIX_SIZE: usize = 4;
+
+/// A primary storage for arbitrary key-value pairs.
+#[derive(Debug)]
+pub struct FilePrimary {
+    reader: File,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl FilePrimary {
+    pub fn open<P>(path: P) -> Result<Self, PrimaryError>
+    where
+        P: AsRef<Path>,
+    {
+        debug!("Opening db file: {:?}", &path.as_ref());
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(path)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            reader: file.try_clone()?,
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Returns the writer of the file.
+    ///
+    /// Its buffer is always flushed before the lock is released, hence a panic of another thread
+    /// at most leaves a partial entry at the end of the file, which no index points to. Therefore
+    /// the poisoning is ignored.
+    fn writer(&self) -> MutexGuard<'_, BufWriter<File>> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl PrimaryStorage for FilePrimary {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let file_size = self.reader.metadata()?.len();
+        if pos > file_size {
+            return Err(PrimaryError::OutOfBounds {
+                pos,
+                len: file_size,
+            });
+        }
+
+        let mut size_prefixes = [0; 2 * SIZE_PREFIX_SIZE];
+        read_exact_at(&self.reader, &mut size_prefixes, pos)?;
+        let (key_size, value_size) = {
+            let mut reader = &size_prefixes[..];
+            (
+                read_size_prefix(&mut reader)?,
+                read_size_prefix(&mut reader)?,
+            )
+        };
+        // The key and the value are read at once, then split.
+        let mut key = vec![0u8; key_size + value_size];
+        read_exact_at(&self.reader, &mut key, pos + size_prefixes.len() as u64)?;
+        let value = key.split_off(key_size);
+        Ok((key, value))
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        let mut file = self.writer();
+        let file_size = file.seek(SeekFrom::End(0))?;
+
+        file.write_all(&size_prefix(key)?)?;
+        file.write_all(&size_prefix(value)?)?;
+        file.write_all(key)?;
+        file.write_all(value)?;
+        // Flush, so that the data is visible to the reader.
+        file.flush()?;
+
+        Ok(file_size)
+    }
+
+    fn flush(&self) -> Result<(), PrimaryError> {
+        let mut file = self.writer();
+        file.flush()?;
+        file.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    /// Flushes all data to disk and copies the file.
+    ///
+    /// The writes wait until the copy is done, as it uses the cursor of the file.
+    fn snapshot(&self, path: &Path) -> Result<u64, PrimaryError> {
+        let mut writer = self.writer();
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+        let mut file = &self.reader;
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut snapshot = File::create(path)?;
+        // Only the data up to the size determined above is copied.
+        io::copy(&mut file.take(size), &mut snapshot)?;
+        snapshot.sync_all()?;
+        Ok(size)
+    }
+
+    fn size(&self) -> Result<Option<u64>, PrimaryError> {
+        Ok(Some(self.reader.metadata()?.len()))
+    }
+
+    fn advise_sequential(&self, pos: u64) -> Result<(), PrimaryError> {
+        Ok(readahead::advise_sequential(&self.reader, pos, 0)?)
+    }
+
+    fn advise_normal(&self) -> Result<(), PrimaryError> {
+        Ok(readahead::advise_normal(&self.reader, 0, 0)?)
+    }
+}
+
+/// Returns the size prefix of the given data.
+fn size_prefix(data: &[u8]) -> Result<[u8; SIZE_PREFIX_SIZE], PrimaryError> {
+    let size = u32::try_from(data.len()).map_err(|error| PrimaryError::Other(Box::new(error)))?;
+    Ok(size.to_le_bytes())
+}
+
+/// Reads a size prefix.
+fn read_size_prefix<R: Read>(reader: &mut R) -> Result<usize, PrimaryError> {
+    let mut size_buffer = [0; SIZE_PREFIX_SIZE];
+    reader.read_exact(&mut size_buffer)?;
+    Ok(usize::try_from(u32::from_le_bytes(size_buffer)).expect(">=32-bit platform needed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FilePrimary;
+
+    use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+    #[test]
+    fn put_and_get() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = FilePrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+
+        let pos1 = primary.put(b"key 1", b"value 1").unwrap();
+        let pos2 = primary.put(b"key 2", b"").unwrap();
+        assert_eq!(pos1, 0);
+        assert_eq!(
+            primary.get(pos1).unwrap(),
+            (b"key 1".to_vec(), b"value 1".to_vec())
+        );
+        assert_eq!(primary.get(pos2).unwrap(), (b"key 2".to_vec(), Vec::new()));
+        assert_eq!(primary.size().unwrap(), Some(4 + 4 + 5 + 7 + 4 + 4 + 5));
+        assert!(matches!(
+            primary.get(1000),
+            Err(PrimaryError::OutOfBounds { pos: 1000, len: 33 })
+        ));
+    }
+
+    #[test]
+    fn reopen() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("storethehash.data");
+        let pos = {
+            let primary = FilePrimary::open(&path).unwrap();
+            primary.put(b"some key", b"some value").unwrap()
+        };
+
+        let primary = FilePrimary::open(&path).unwrap();
+        assert_eq!(
+            primary.get(pos).unwrap(),
+            (b"some key".to_vec(), b"some value".to_vec())
+        );
+        // New data is appended.
+        let new_pos = primary.put(b"other key", b"other value").unwrap();
+        assert!(new_pos > pos);
+    }
+}
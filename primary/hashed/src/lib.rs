@@ -0,0 +1,188 @@
+//! A primary storage wrapper for keys that are not random.
+//!
+//! The index needs keys that are cryptographically secure hashes and at least 4 bytes long. If
+//! your natural keys are short or structured (like paths or ids), wrap your primary storage in a
+//! [`HashedKeyPrimary`]. The keys and values are stored unchanged in the inner primary storage,
+//! only the key that is used for the index is the hash of the original key.
+//!
+//! Two different keys might end up with the same hash (e.g. if a hash function with a small
+//! output size is used). [`storethehash::db::Db::get`] compares the requested key with the key
+//! stored in the primary storage, hence it never returns the value of a different key. Though
+//! only the first of the colliding keys can be retrieved.
+
+use std::marker::PhantomData;
+use std::path::Path;
+
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+/// The hash function that is used to create the index keys.
+pub trait KeyHasher {
+    /// Returns the hash of the given key.
+    ///
+    /// The hash needs to be at least 4 bytes long.
+    fn hash(key: &[u8]) -> Vec<u8>;
+}
+
+/// Hashes the keys with [BLAKE3].
+///
+/// [BLAKE3]: https://github.com/BLAKE3-team/BLAKE3
+#[derive(Debug)]
+pub struct Blake3;
+
+impl KeyHasher for Blake3 {
+    fn hash(key: &[u8]) -> Vec<u8> {
+        blake3::hash(key).as_bytes().to_vec()
+    }
+}
+
+/// A primary storage that uses the hash of the key for the index.
+#[derive(Debug)]
+pub struct HashedKeyPrimary<P: PrimaryStorage, H: KeyHasher = Blake3> {
+    inner: P,
+    hasher: PhantomData<H>,
+}
+
+impl<P: PrimaryStorage, H: KeyHasher> HashedKeyPrimary<P, H> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped primary storage.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+impl<P: PrimaryStorage, H: KeyHasher> PrimaryStorage for HashedKeyPrimary<P, H> {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        self.inner.get(pos)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        self.inner.put(key, value)
+    }
+
+    fn snapshot(&self, path: &Path) -> Result<u64, PrimaryError> {
+        self.inner.snapshot(path)
+    }
+
+    fn size(&self) -> Result<Option<u64>, PrimaryError> {
+        self.inner.size()
+    }
+
+    fn first_pos(&self) -> u64 {
+        self.inner.first_pos()
+    }
+
+    fn next_pos(&self, pos: u64) -> Result<u64, PrimaryError> {
+        self.inner.next_pos(pos)
+    }
+
+    fn iter_values(&self) -> Box<dyn Iterator<Item = Result<Vec<u8>, PrimaryError>> + '_> {
+        self.inner.iter_values()
+    }
+
+    fn advise_sequential(&self, pos: u64) -> Result<(), PrimaryError> {
+        self.inner.advise_sequential(pos)
+    }
+
+    fn advise_normal(&self) -> Result<(), PrimaryError> {
+        self.inner.advise_normal()
+    }
+
+    fn verify_at(&self, pos: u64) -> Result<bool, PrimaryError> {
+        self.inner.verify_at(pos)
+    }
+
+    fn index_key(key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
+        Ok(H::hash(key))
+    }
+
+    /// Returns the hash of the key that is stored at the given position.
+    ///
+    /// This must not be delegated to the inner primary storage, as its index key would be
+    /// different from the hashed one.
+    fn get_index_key(&self, pos: u64) -> Result<Vec<u8>, PrimaryError> {
+        let (key, _value) = self.inner.get(pos)?;
+        Self::index_key(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Blake3, HashedKeyPrimary, KeyHasher};
+
+    use storethehash::db::Db;
+    use storethehash::primary::PrimaryStorage;
+    use storethehash_primary_inmemory::InMemory;
+
+    const BUCKETS_BITS: u8 = 8;
+
+    // A hasher that truncates the hash to the minimum size, so that collisions can be forced.
+    struct Truncated;
+
+    impl KeyHasher for Truncated {
+        fn hash(key: &[u8]) -> Vec<u8> {
+            Blake3::hash(&key[..1])[..4].to_vec()
+        }
+    }
+
+    #[test]
+    fn index_key() {
+        let index_key = HashedKeyPrimary::<InMemory>::index_key(b"a").unwrap();
+        assert_eq!(index_key, blake3::hash(b"a").as_bytes());
+    }
+
+    #[test]
+    fn get_index_key() {
+        let primary = HashedKeyPrimary::<_, Blake3>::new(InMemory::new(&[]));
+        let pos = primary.put(b"short", b"value").unwrap();
+        assert_eq!(
+            primary.get_index_key(pos).unwrap(),
+            blake3::hash(b"short").as_bytes()
+        );
+        // The original key is stored.
+        assert_eq!(
+            primary.get(pos).unwrap(),
+            (b"short".to_vec(), b"value".to_vec())
+        );
+    }
+
+    #[test]
+    fn db_short_keys() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index_path = temp_dir.path().join("storethehash.index");
+        let primary = HashedKeyPrimary::<_, Blake3>::new(InMemory::new(&[]));
+        let db = Db::<_, BUCKETS_BITS>::open(primary, index_path).unwrap();
+
+        db.put(b"a", b"value a").unwrap();
+        db.put(b"/some/path", b"value path").unwrap();
+
+        assert_eq!(db.get(b"a").unwrap(), Some(b"value a".to_vec()));
+        assert_eq!(db.get(b"/some/path").unwrap(), Some(b"value path".to_vec()));
+        assert_eq!(db.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn db_hash_collision() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index_path = temp_dir.path().join("storethehash.index");
+        let primary = HashedKeyPrimary::<_, Truncated>::new(InMemory::new(&[]));
+        let db = Db::<_, BUCKETS_BITS>::open(primary, index_path).unwrap();
+
+        // Both keys have the same index key.
+        assert_eq!(
+            HashedKeyPrimary::<InMemory, Truncated>::index_key(b"key1").unwrap(),
+            HashedKeyPrimary::<InMemory, Truncated>::index_key(b"key2").unwrap()
+        );
+        db.put(b"key1", b"value1").unwrap();
+        db.put(b"key2", b"value2").unwrap();
+
+        assert_eq!(db.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        // The colliding key must never return the value of the other key.
+        assert_ne!(db.get(b"key2").unwrap(), Some(b"value1".to_vec()));
+    }
+}
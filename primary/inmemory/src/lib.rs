@@ -1,40 +1,184 @@
 //! In-memory primary storage implementation.
 //!
-//! It's using a vector of tuples containing the key-value pairs.
+//! It's using a vector of tuples containing the key-value pairs. [`RecordingInMemory`] additionally
+//! logs all accesses, so that a test run can be replayed.
 
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
 use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
 
 use storethehash::primary::{PrimaryError, PrimaryStorage};
 
-#[derive(Debug, Default)]
-pub struct InMemory(RefCell<Vec<(Vec<u8>, Vec<u8>)>>);
+/// A function that transforms a stored key into the key that is used for the index.
+///
+/// It's reference counted, so that clones of the storage share it.
+type IndexKeyTransform = Rc<dyn Fn(&[u8]) -> Vec<u8>>;
+
+/// Cloning the storage copies the data, the clone can be modified independently.
+#[derive(Clone, Default)]
+pub struct InMemory {
+    data: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+    index_key_transform: Option<IndexKeyTransform>,
+}
+
+impl fmt::Debug for InMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InMemory")
+            .field("data", &self.data)
+            .field("index_key_transform", &self.index_key_transform.is_some())
+            .finish()
+    }
+}
 
 impl InMemory {
     /// It can be initialized with some key value pairs.
     pub fn new(data: &[(Vec<u8>, Vec<u8>)]) -> Self {
-        InMemory(RefCell::new(data.to_vec()))
+        Self {
+            data: RefCell::new(data.to_vec()),
+            index_key_transform: None,
+        }
+    }
+
+    /// Creates an empty storage where [`PrimaryStorage::get_index_key`] returns the transformed
+    /// stored key.
+    ///
+    /// This way it can stand in for a storage like the CID one in tests, where the index key
+    /// differs from the stored key. [`PrimaryStorage::index_key`] cannot know about the
+    /// transform, hence the callers need to transform the keys for the index themselves.
+    pub fn with_index_key_transform<F>(transform: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + 'static,
+    {
+        Self {
+            data: RefCell::new(Vec::new()),
+            index_key_transform: Some(Rc::new(transform)),
+        }
     }
 }
 
 impl PrimaryStorage for InMemory {
     fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let data = self.data.borrow();
         let usize_pos = usize::try_from(pos).expect(">=64 bit platform needed");
-        Ok(self.0.borrow()[usize_pos].clone())
+        data.get(usize_pos)
+            .cloned()
+            .ok_or_else(|| PrimaryError::OutOfBounds {
+                pos,
+                len: u64::try_from(data.len()).expect("64 bit platform needed"),
+            })
     }
 
     fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
-        let pos = self.0.borrow().len();
-        self.0.borrow_mut().push((key.to_vec(), value.to_vec()));
+        let pos = self.data.borrow().len();
+        self.data.borrow_mut().push((key.to_vec(), value.to_vec()));
         Ok(u64::try_from(pos).expect("64 bit platform needed"))
     }
+
+    fn get_index_key(&self, pos: u64) -> Result<Vec<u8>, PrimaryError> {
+        let (key, _value) = self.get(pos)?;
+        match &self.index_key_transform {
+            Some(transform) => Ok(transform(&key)),
+            None => Self::index_key(&key),
+        }
+    }
+}
+
+/// An access to a [`RecordingInMemory`] storage.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrimaryEvent {
+    Put {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        returned_pos: u64,
+    },
+    /// `returned` is `None` if the position was out of bounds.
+    Get {
+        pos: u64,
+        returned: Option<(Vec<u8>, Vec<u8>)>,
+    },
+}
+
+/// An in-memory storage that logs every [`PrimaryStorage::put`] and [`PrimaryStorage::get`].
+///
+/// The log can be used to rebuild the state with [`RecordingInMemory::replay`], e.g. to debug a
+/// flaky test deterministically.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingInMemory {
+    inner: InMemory,
+    events: RefCell<Vec<PrimaryEvent>>,
+}
+
+impl RecordingInMemory {
+    /// It can be initialized with some key value pairs, which are not logged.
+    pub fn new(data: &[(Vec<u8>, Vec<u8>)]) -> Self {
+        Self {
+            inner: InMemory::new(data),
+            events: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// All events in the order they happened.
+    pub fn events(&self) -> Ref<'_, [PrimaryEvent]> {
+        Ref::map(self.events.borrow(), Vec::as_slice)
+    }
+
+    /// Rebuilds a storage from the events of another one, new events are appended to them.
+    ///
+    /// Only the puts change the state. It panics if a put doesn't return the logged position, as
+    /// the replay then diverged from the original run.
+    pub fn replay(events: &[PrimaryEvent]) -> Self {
+        let inner = InMemory::new(&[]);
+        for event in events {
+            if let PrimaryEvent::Put {
+                key,
+                value,
+                returned_pos,
+            } = event
+            {
+                let pos = inner.put(key, value).expect("in-memory put cannot fail");
+                assert_eq!(pos, *returned_pos, "replayed put returned another position");
+            }
+        }
+        Self {
+            inner,
+            events: RefCell::new(events.to_vec()),
+        }
+    }
+}
+
+impl PrimaryStorage for RecordingInMemory {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let result = self.inner.get(pos);
+        self.events.borrow_mut().push(PrimaryEvent::Get {
+            pos,
+            returned: result.as_ref().ok().cloned(),
+        });
+        result
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        let pos = self.inner.put(key, value)?;
+        self.events.borrow_mut().push(PrimaryEvent::Put {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            returned_pos: pos,
+        });
+        Ok(pos)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::InMemory;
+    use super::{InMemory, PrimaryEvent, RecordingInMemory};
 
-    use storethehash::primary::PrimaryStorage;
+    use sha2::{Digest, Sha256};
+    use storethehash::index::Index;
+    use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+    fn sha256(key: &[u8]) -> Vec<u8> {
+        Sha256::digest(key).to_vec()
+    }
 
     #[test]
     fn get() {
@@ -51,6 +195,15 @@ mod tests {
         assert_eq!(result_yy, yy);
     }
 
+    #[test]
+    fn get_out_of_bounds() {
+        let storage = InMemory::new(&[(b"aa".to_vec(), vec![0x10])]);
+        assert!(matches!(
+            storage.get(3),
+            Err(PrimaryError::OutOfBounds { pos: 3, len: 1 })
+        ));
+    }
+
     #[test]
     fn put() {
         let aa = (b"aa".to_vec(), vec![0x10]);
@@ -72,4 +225,113 @@ mod tests {
         let result_yy = storage.get(1).unwrap();
         assert_eq!(result_yy, yy);
     }
+
+    #[test]
+    fn clone() {
+        let aa = (b"aa".to_vec(), vec![0x10]);
+        let yy = (b"yy".to_vec(), vec![0x11]);
+        let storage = InMemory::new(&[aa.clone()]);
+
+        let cloned = storage.clone();
+        let put_yy = cloned.put(&yy.0, &yy.1).unwrap();
+        assert_eq!(put_yy, 1);
+        assert_eq!(cloned.get(1).unwrap(), yy);
+
+        // The original is unaffected.
+        assert_eq!(storage.get(0).unwrap(), aa);
+        let put_aa = storage.put(&aa.0, &aa.1).unwrap();
+        assert_eq!(put_aa, 1);
+        assert_eq!(storage.get(1).unwrap(), aa);
+        assert_eq!(cloned.get(1).unwrap(), yy);
+
+        // The transform is kept.
+        let storage = InMemory::with_index_key_transform(sha256);
+        let cloned = storage.clone();
+        let pos = cloned.put(b"some key", b"some value").unwrap();
+        assert_eq!(cloned.get_index_key(pos).unwrap(), sha256(b"some key"));
+    }
+
+    #[test]
+    fn get_index_key_transform() {
+        let storage = InMemory::with_index_key_transform(sha256);
+        let pos = storage.put(b"some key", b"some value").unwrap();
+        assert_eq!(storage.get_index_key(pos).unwrap(), sha256(b"some key"));
+        // The stored key is not transformed.
+        assert_eq!(
+            storage.get(pos).unwrap(),
+            (b"some key".to_vec(), b"some value".to_vec())
+        );
+
+        let storage = InMemory::new(&[]);
+        let pos = storage.put(b"some key", b"some value").unwrap();
+        assert_eq!(storage.get_index_key(pos).unwrap(), b"some key");
+    }
+
+    #[test]
+    fn index_with_index_key_transform() {
+        // Find two keys whose hashes are in the same bucket and share the next byte, so that the
+        // index needs to get the full index key of the first one from the primary storage.
+        let key1 = b"key 0".to_vec();
+        let key2 = (1..)
+            .map(|ii| format!("key {}", ii).into_bytes())
+            .find(|key| sha256(key)[..2] == sha256(&key1)[..2])
+            .unwrap();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage = InMemory::with_index_key_transform(sha256);
+        let index =
+            Index::<_, 8>::open(temp_dir.path().join("storethehash.index"), storage).unwrap();
+        for key in &[&key1, &key2] {
+            let pos = index.primary.put(key, b"value").unwrap();
+            index.put(&sha256(key), pos).unwrap();
+        }
+        assert_eq!(index.get(&sha256(&key1)).unwrap(), Some(0));
+        assert_eq!(index.get(&sha256(&key2)).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn recording_replay() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage = RecordingInMemory::new(&[]);
+        let index =
+            Index::<_, 8>::open(temp_dir.path().join("storethehash.index"), storage).unwrap();
+        // The keys share the first two bytes, hence the index reads the first one back.
+        let keys: Vec<[u8; 4]> = (0..3).map(|ii| [1, 2, 3, ii]).collect();
+        for key in &keys {
+            let pos = index.primary.put(key, b"value").unwrap();
+            index.put(key, pos).unwrap();
+        }
+        assert!(index.primary.get(5).is_err());
+
+        let events = index.primary.events().to_vec();
+        assert_eq!(
+            events[0],
+            PrimaryEvent::Put {
+                key: keys[0].to_vec(),
+                value: b"value".to_vec(),
+                returned_pos: 0
+            }
+        );
+        assert!(events.contains(&PrimaryEvent::Get {
+            pos: 0,
+            returned: Some((keys[0].to_vec(), b"value".to_vec()))
+        }));
+        assert_eq!(
+            events.last(),
+            Some(&PrimaryEvent::Get {
+                pos: 5,
+                returned: None
+            })
+        );
+
+        let replayed = RecordingInMemory::replay(&events);
+        assert_eq!(&*replayed.events(), &events[..]);
+        for (pos, key) in keys.iter().enumerate() {
+            assert_eq!(
+                replayed.inner.get(pos as u64).unwrap(),
+                (key.to_vec(), b"value".to_vec())
+            );
+        }
+        assert_eq!(replayed.put(b"key", b"value").unwrap(), 3);
+    }
 }
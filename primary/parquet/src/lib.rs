@@ -0,0 +1,89 @@
+//! A primary storage that stores the data as Parquet files, so that it can be analyzed directly.
+//!
+//! The key-value pairs are stored within a directory as a sequence of Parquet files, the
+//! segments, with the schema [`SCHEMA`]. The position of an entry is its row number, counted
+//! over all segments. Every segment is named after the position of its first row (e.g.
+//! `00000000000000000042.parquet`), so that they sort in the order they were written. Query
+//! engines can read the whole directory as a single table, e.g. with DuckDB:
+//!
+//! ```sql
+//! SELECT count(*), sum(length(value)) FROM 'data/*.parquet';
+//! ```
+//!
+//! The Parquet storage is only available with the `parquet` feature enabled.
+//!
+//! # Writes
+//!
+//! A Parquet file cannot be read before it's complete, hence [`ParquetPrimary::put`] buffers the
+//! rows in memory. They are written as new segment once there are as many as the row group size,
+//! or when the storage is flushed. Flushing often results in many small segments, which are
+//! slower to query.
+#[cfg(feature = "parquet")]
+mod parquetprimary;
+
+#[cfg(feature = "parquet")]
+pub use parquetprimary::ParquetPrimary;
+
+/// The schema of the segments, in the Parquet message type format.
+pub const SCHEMA: &str = "
+    message storethehash {
+        REQUIRED INT64 position;
+        REQUIRED BINARY key;
+        REQUIRED BINARY value;
+    }
+";
+
+/// The default number of rows of a row group, it's also the maximum size of a segment.
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// The file extension of the segments.
+const SEGMENT_EXTENSION: &str = ".parquet";
+
+/// Returns the file name of the segment that starts at the given position.
+pub fn segment_file_name(first_pos: u64) -> String {
+    format!("{:020}{}", first_pos, SEGMENT_EXTENSION)
+}
+
+/// Returns the position of the first row of a segment with the given file name.
+///
+/// `None` is returned if it isn't the name of a segment.
+pub fn parse_segment_file_name(file_name: &str) -> Option<u64> {
+    let first_pos = file_name.strip_suffix(SEGMENT_EXTENSION)?;
+    if first_pos.len() != 20 || !first_pos.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    first_pos.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_segment_file_name, segment_file_name};
+
+    #[test]
+    fn segment_file_names() {
+        assert_eq!(segment_file_name(0), "00000000000000000000.parquet");
+        assert_eq!(segment_file_name(42), "00000000000000000042.parquet");
+        assert_eq!(segment_file_name(u64::MAX), "18446744073709551615.parquet");
+        for first_pos in &[0, 42, u64::MAX] {
+            assert_eq!(
+                parse_segment_file_name(&segment_file_name(*first_pos)),
+                Some(*first_pos)
+            );
+        }
+
+        assert_eq!(parse_segment_file_name("00000000000000000042"), None);
+        assert_eq!(parse_segment_file_name("42.parquet"), None);
+        assert_eq!(
+            parse_segment_file_name("0000000000000000004x.parquet"),
+            None
+        );
+        assert_eq!(
+            parse_segment_file_name("00000000000000000042.parquet.tmp"),
+            None
+        );
+        assert_eq!(
+            parse_segment_file_name("99999999999999999999.parquet"),
+            None
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{debug, warn};
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::FileReader;
+use parquet::file::serialized_reader::SerializedFileReader;
+use parquet::file::statistics::Statistics;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::record::RowAccessor;
+use parquet::schema::parser::parse_message_type;
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+use crate::{parse_segment_file_name, segment_file_name, DEFAULT_ROW_GROUP_SIZE, SCHEMA};
+
+/// The indices of the columns of [`SCHEMA`].
+const POSITION_COLUMN: usize = 0;
+const KEY_COLUMN: usize = 1;
+const VALUE_COLUMN: usize = 2;
+
+/// Converts any error of the Parquet library into a primary storage error.
+fn other_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> PrimaryError {
+    PrimaryError::Other(Box::new(error))
+}
+
+/// A Parquet file that was completely written.
+struct Segment {
+    /// The position of the first row.
+    first_pos: u64,
+    rows: u64,
+    reader: SerializedFileReader<File>,
+}
+
+impl Segment {
+    fn open(dir: &Path, first_pos: u64) -> Result<Self, PrimaryError> {
+        let path = dir.join(segment_file_name(first_pos));
+        let file = File::open(&path).map_err(PrimaryError::io("opening segment", &path, None))?;
+        let reader = SerializedFileReader::new(file).map_err(other_error)?;
+        let rows =
+            u64::try_from(reader.metadata().file_metadata().num_rows()).map_err(other_error)?;
+        Ok(Self {
+            first_pos,
+            rows,
+            reader,
+        })
+    }
+
+    /// Returns the key-value pair at the given position, which must be within the segment.
+    ///
+    /// The position is pushed down to the row groups, only the one whose statistics of the
+    /// position column contain it is read.
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let wanted = i64::try_from(pos).map_err(other_error)?;
+        for (ii, row_group) in self.reader.metadata().row_groups().iter().enumerate() {
+            let first_pos = match row_group.column(POSITION_COLUMN).statistics() {
+                Some(Statistics::Int64(statistics)) => {
+                    match (statistics.min_opt(), statistics.max_opt()) {
+                        (Some(min), Some(max)) if (*min..=*max).contains(&wanted) => *min,
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+            // The positions within a row group are consecutive.
+            let row_group_reader = self.reader.get_row_group(ii).map_err(other_error)?;
+            let row = row_group_reader
+                .get_row_iter(None)
+                .map_err(other_error)?
+                .nth(usize::try_from(wanted - first_pos).map_err(other_error)?)
+                .ok_or_else(|| missing_position(pos))?
+                .map_err(other_error)?;
+            if row.get_long(POSITION_COLUMN).map_err(other_error)? != wanted {
+                return Err(missing_position(pos));
+            }
+            let key = row.get_bytes(KEY_COLUMN).map_err(other_error)?;
+            let value = row.get_bytes(VALUE_COLUMN).map_err(other_error)?;
+            return Ok((key.data().to_vec(), value.data().to_vec()));
+        }
+        Err(missing_position(pos))
+    }
+}
+
+fn missing_position(pos: u64) -> PrimaryError {
+    PrimaryError::Other(format!("Position {} is missing in its Parquet segment.", pos).into())
+}
+
+/// Parquet storage implementation.
+///
+/// The rows that are buffered are written when it's dropped. Errors are only logged then, call
+/// [`PrimaryStorage::flush`] to get them.
+pub struct ParquetPrimary {
+    dir: PathBuf,
+    row_group_size: usize,
+    /// The segments in the order of their positions.
+    segments: RefCell<Vec<Segment>>,
+    /// The key-value pairs that are not written to a segment yet.
+    buffer: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl ParquetPrimary {
+    /// Opens the segments within the given directory, which is created if needed.
+    ///
+    /// Files that are not segments are ignored, e.g. a segment that was not completely written
+    /// before a crash. The row groups have [`DEFAULT_ROW_GROUP_SIZE`] rows.
+    pub fn open<P>(dir: P) -> Result<Self, PrimaryError>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        debug!("Opening Parquet directory: {:?}", dir);
+        let io_error = || PrimaryError::io("opening primary storage", dir, None);
+        fs::create_dir_all(dir).map_err(io_error())?;
+        let mut first_positions = Vec::new();
+        for entry in fs::read_dir(dir).map_err(io_error())? {
+            let entry = entry.map_err(io_error())?;
+            if let Some(first_pos) = entry.file_name().to_str().and_then(parse_segment_file_name) {
+                first_positions.push(first_pos);
+            }
+        }
+        first_positions.sort_unstable();
+
+        let mut segments = Vec::with_capacity(first_positions.len());
+        let mut next_pos = 0;
+        for first_pos in first_positions {
+            if first_pos != next_pos {
+                return Err(PrimaryError::Other(
+                    format!(
+                        "The Parquet segment starting at {} is missing in {:?}.",
+                        next_pos, dir
+                    )
+                    .into(),
+                ));
+            }
+            let segment = Segment::open(dir, first_pos)?;
+            next_pos += segment.rows;
+            segments.push(segment);
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            segments: RefCell::new(segments),
+            buffer: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Sets the number of rows of a row group.
+    ///
+    /// The rows are buffered until there are that many, a smaller size means less memory, but
+    /// more segments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is 0.
+    pub fn with_row_group_size(mut self, rows: usize) -> Self {
+        assert!(rows > 0, "a row group needs at least one row");
+        self.row_group_size = rows;
+        self
+    }
+
+    /// Returns the number of stored key-value pairs, including the buffered ones.
+    pub fn len(&self) -> u64 {
+        self.written_rows() + self.buffer.borrow().len() as u64
+    }
+
+    /// Returns true if no key-value pairs are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of segments.
+    pub fn segment_count(&self) -> usize {
+        self.segments.borrow().len()
+    }
+
+    /// Returns the number of rows that were written to segments.
+    fn written_rows(&self) -> u64 {
+        self.segments
+            .borrow()
+            .last()
+            .map(|segment| segment.first_pos + segment.rows)
+            .unwrap_or(0)
+    }
+
+    /// Writes the buffered rows to a new segment.
+    ///
+    /// It's written to a temporary file first, which is renamed once it's complete.
+    fn write_segment(&self) -> Result<(), PrimaryError> {
+        let mut buffer = self.buffer.borrow_mut();
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let first_pos = self.written_rows();
+        let path = self.dir.join(segment_file_name(first_pos));
+        let tmp_path = path.with_extension("parquet.tmp");
+        let io_error = |path| PrimaryError::io("writing segment", path, None);
+
+        let file = File::create(&tmp_path).map_err(io_error(&tmp_path))?;
+        write_rows(&file, first_pos, &buffer, self.row_group_size).map_err(other_error)?;
+        file.sync_all().map_err(io_error(&tmp_path))?;
+        fs::rename(&tmp_path, &path).map_err(io_error(&path))?;
+
+        let segment = Segment::open(&self.dir, first_pos)?;
+        self.segments.borrow_mut().push(segment);
+        buffer.clear();
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ParquetPrimary {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("ParquetPrimary")
+            .field("dir", &self.dir)
+            .field("row_group_size", &self.row_group_size)
+            .field("segments", &self.segments.borrow().len())
+            .field("buffered", &self.buffer.borrow().len())
+            .finish()
+    }
+}
+
+impl Drop for ParquetPrimary {
+    fn drop(&mut self) {
+        if let Err(error) = self.write_segment() {
+            warn!("Writing the buffered Parquet rows failed: {}", error);
+        }
+    }
+}
+
+impl PrimaryStorage for ParquetPrimary {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let written_rows = self.written_rows();
+        if pos >= written_rows {
+            let buffer = self.buffer.borrow();
+            return usize::try_from(pos - written_rows)
+                .ok()
+                .and_then(|index| buffer.get(index))
+                .cloned()
+                .ok_or(PrimaryError::OutOfBounds {
+                    pos,
+                    len: written_rows + buffer.len() as u64,
+                });
+        }
+        let segments = self.segments.borrow();
+        // The first segment starts at 0, hence there is always one.
+        let index = segments.partition_point(|segment| segment.first_pos <= pos) - 1;
+        segments[index].get(pos)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        let pos = self.len();
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.push((key.to_vec(), value.to_vec()));
+        if buffer.len() >= self.row_group_size {
+            drop(buffer);
+            if let Err(error) = self.write_segment() {
+                self.buffer.borrow_mut().pop();
+                return Err(error);
+            }
+        }
+        Ok(pos)
+    }
+
+    /// Writes the buffered rows to a new segment.
+    fn flush(&self) -> Result<(), PrimaryError> {
+        self.write_segment()
+    }
+}
+
+/// Writes the rows as Parquet file, the first one has the given position.
+fn write_rows<W: Write + Send>(
+    writer: W,
+    first_pos: u64,
+    rows: &[(Vec<u8>, Vec<u8>)],
+    row_group_size: usize,
+) -> Result<(), ParquetError> {
+    let schema = Arc::new(parse_message_type(SCHEMA)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_max_row_group_size(row_group_size)
+            .build(),
+    );
+    let mut writer = SerializedFileWriter::new(writer, schema, properties)?;
+    let mut pos = first_pos;
+    for chunk in rows.chunks(row_group_size) {
+        let positions = (pos..pos + chunk.len() as u64)
+            .map(|pos| {
+                i64::try_from(pos)
+                    .map_err(|_| ParquetError::General(format!("Position {} is too big.", pos)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let keys: Vec<ByteArray> = chunk
+            .iter()
+            .map(|(key, _value)| key.clone().into())
+            .collect();
+        let values: Vec<ByteArray> = chunk
+            .iter()
+            .map(|(_key, value)| value.clone().into())
+            .collect();
+
+        let mut row_group = writer.next_row_group()?;
+        write_column::<Int64Type, _>(&mut row_group, &positions)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &keys)?;
+        write_column::<ByteArrayType, _>(&mut row_group, &values)?;
+        row_group.close()?;
+        pos += chunk.len() as u64;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// Writes the values of the next column of a row group.
+fn write_column<T: DataType, W: Write + Send>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    values: &[T::T],
+) -> Result<(), ParquetError> {
+    let mut column = row_group
+        .next_column()?
+        .ok_or_else(|| ParquetError::General("The schema has too few columns.".to_string()))?;
+    column.typed::<T>().write_batch(values, None, None)?;
+    column.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParquetPrimary;
+
+    use std::fs;
+
+    use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+    use crate::segment_file_name;
+
+    fn entry(ii: u8) -> (Vec<u8>, Vec<u8>) {
+        (vec![ii; 8], vec![ii; usize::from(ii)])
+    }
+
+    #[test]
+    fn put_and_get() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = ParquetPrimary::open(temp_dir.path())
+            .unwrap()
+            .with_row_group_size(4);
+        assert!(primary.is_empty());
+
+        for ii in 0..10 {
+            let (key, value) = entry(ii);
+            assert_eq!(primary.put(&key, &value).unwrap(), u64::from(ii));
+        }
+        // Two segments are full, the rest is buffered.
+        assert_eq!(primary.segment_count(), 2);
+        assert_eq!(primary.len(), 10);
+        for ii in 0..10 {
+            assert_eq!(primary.get(u64::from(ii)).unwrap(), entry(ii));
+        }
+        assert!(matches!(
+            primary.get(10),
+            Err(PrimaryError::OutOfBounds { pos: 10, len: 10 })
+        ));
+
+        primary.flush().unwrap();
+        assert_eq!(primary.segment_count(), 3);
+        assert!(temp_dir.path().join(segment_file_name(8)).exists());
+        assert_eq!(primary.get(9).unwrap(), entry(9));
+        // Flushing without buffered rows doesn't create a segment.
+        primary.flush().unwrap();
+        assert_eq!(primary.segment_count(), 3);
+    }
+
+    #[test]
+    fn flush_with_several_row_groups() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let primary = ParquetPrimary::open(temp_dir.path())
+            .unwrap()
+            .with_row_group_size(3);
+        primary.put(b"first key", b"first value").unwrap();
+        primary.flush().unwrap();
+
+        // A segment can have several row groups, if a bigger size was used before.
+        let primary = primary.with_row_group_size(100);
+        for ii in 0..7 {
+            let (key, value) = entry(ii);
+            primary.put(&key, &value).unwrap();
+        }
+        let primary = primary.with_row_group_size(3);
+        primary.flush().unwrap();
+        assert_eq!(primary.segment_count(), 2);
+        for ii in 0..7 {
+            assert_eq!(primary.get(u64::from(ii) + 1).unwrap(), entry(ii));
+        }
+    }
+
+    #[test]
+    fn reopen() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let primary = ParquetPrimary::open(temp_dir.path())
+                .unwrap()
+                .with_row_group_size(2);
+            for ii in 0..5 {
+                let (key, value) = entry(ii);
+                primary.put(&key, &value).unwrap();
+            }
+            // The buffered row is written when the storage is dropped.
+        }
+        // Incomplete segments are ignored.
+        fs::write(
+            temp_dir
+                .path()
+                .join(format!("{}.tmp", segment_file_name(5))),
+            b"PAR1",
+        )
+        .unwrap();
+
+        let primary = ParquetPrimary::open(temp_dir.path()).unwrap();
+        assert_eq!(primary.segment_count(), 3);
+        assert_eq!(primary.len(), 5);
+        assert_eq!(primary.put(b"next", b"value").unwrap(), 5);
+        assert_eq!(primary.get(4).unwrap(), entry(4));
+        drop(primary);
+
+        // A missing segment is an error, the positions would be wrong otherwise.
+        fs::remove_file(temp_dir.path().join(segment_file_name(2))).unwrap();
+        assert!(matches!(
+            ParquetPrimary::open(temp_dir.path()),
+            Err(PrimaryError::Other(_))
+        ));
+    }
+}
@@ -0,0 +1,96 @@
+//! Uses the Parquet storage as primary storage of a database, the tests need the `parquet`
+//! feature:
+//!
+//! ```text
+//! cargo test --features parquet
+//! ```
+#![cfg(feature = "parquet")]
+
+use std::fs::File;
+
+use parquet::file::reader::FileReader;
+use parquet::file::serialized_reader::SerializedFileReader;
+use parquet::record::RowAccessor;
+use storethehash::db::Db;
+use storethehash_primary_parquet::{segment_file_name, ParquetPrimary};
+
+const BUCKETS_BITS: u8 = 24;
+
+/// Returns a key whose leading bytes differ for every entry.
+fn key(ii: u32) -> Vec<u8> {
+    [&ii.to_le_bytes()[..], &[0xaa; 28]].concat()
+}
+
+fn value(ii: u32) -> Vec<u8> {
+    format!("value {}", ii).into_bytes()
+}
+
+#[test]
+fn db_put_get() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let data_dir = temp_dir.path().join("data");
+    let index_path = temp_dir.path().join("storethehash.index");
+    let primary = ParquetPrimary::open(&data_dir)
+        .unwrap()
+        .with_row_group_size(100);
+    let db = Db::<_, BUCKETS_BITS>::open(primary, &index_path).unwrap();
+
+    for ii in 0..250 {
+        db.put(&key(ii), &value(ii)).unwrap();
+    }
+    // Both the written and the buffered entries can be read.
+    assert_eq!(db.primary().segment_count(), 2);
+    for ii in 0..250 {
+        assert_eq!(db.get(&key(ii)).unwrap(), Some(value(ii)));
+    }
+    assert_eq!(db.get(&key(250)).unwrap(), None);
+    db.close().unwrap();
+
+    let primary = ParquetPrimary::open(&data_dir).unwrap();
+    assert_eq!(primary.len(), 250);
+    let db = Db::<_, BUCKETS_BITS>::open(primary, &index_path).unwrap();
+    assert_eq!(db.get(&key(42)).unwrap(), Some(value(42)));
+    assert_eq!(db.get(&key(249)).unwrap(), Some(value(249)));
+}
+
+#[test]
+fn segments_are_plain_parquet() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let data_dir = temp_dir.path().join("data");
+    let db = Db::<_, BUCKETS_BITS>::open(
+        ParquetPrimary::open(&data_dir).unwrap(),
+        temp_dir.path().join("storethehash.index"),
+    )
+    .unwrap();
+    for ii in 0..10 {
+        db.put(&key(ii), &value(ii)).unwrap();
+    }
+    db.close().unwrap();
+
+    // Any Parquet reader can read the segments.
+    let file = File::open(data_dir.join(segment_file_name(0))).unwrap();
+    let reader = SerializedFileReader::new(file).unwrap();
+    let columns: Vec<_> = reader
+        .metadata()
+        .file_metadata()
+        .schema_descr()
+        .columns()
+        .iter()
+        .map(|column| column.name().to_string())
+        .collect();
+    assert_eq!(columns, ["position", "key", "value"]);
+    let rows: Vec<_> = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|row| {
+            let row = row.unwrap();
+            (
+                row.get_long(0).unwrap(),
+                row.get_bytes(1).unwrap().data().to_vec(),
+                row.get_bytes(2).unwrap().data().to_vec(),
+            )
+        })
+        .collect();
+    assert_eq!(rows.len(), 10);
+    assert_eq!(rows[3], (3, key(3), value(3)));
+}
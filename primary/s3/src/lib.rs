@@ -0,0 +1,112 @@
+//! A primary storage that stores the data in Amazon S3 or any S3-compatible object storage.
+//!
+//! Every key-value pair is stored as its own object. The object key is the position formatted as
+//! 20 digit number (e.g. `00000000000000000042`), optionally preceded by a prefix, so that several
+//! stores can share a single bucket. Only the primary data is stored remotely, the index stays a
+//! local file.
+//!
+//! The S3 client is only available with the `s3` feature enabled.
+//!
+//! # Consistency
+//!
+//! Amazon S3 provides strong read-after-write consistency, hence a value can be read as soon as
+//! [`S3Primary::put`] returns. Other S3-compatible object storages might only be eventually
+//! consistent, reading a value that was just stored might then fail with an out of bounds error.
+//!
+//! The next position is determined once, when the storage is opened, by counting the objects
+//! with the given prefix. Hence there must only be a single writer per prefix. With an eventually
+//! consistent listing, recently stored objects might not be counted yet and would be overwritten.
+#[cfg(feature = "s3")]
+mod s3primary;
+
+use std::convert::{TryFrom, TryInto};
+
+use storethehash::primary::PrimaryError;
+
+#[cfg(feature = "s3")]
+pub use s3primary::S3Primary;
+
+/// Number of bytes used for the size prefix of the key.
+const KEY_SIZE_PREFIX_SIZE: usize = 4;
+
+/// Returns the object key for a position.
+pub fn object_key(prefix: &str, pos: u64) -> String {
+    format!("{}{:020}", prefix, pos)
+}
+
+/// Encodes a key-value pair into the body of an object.
+///
+/// The format is:
+///
+/// ```text
+///     |     4 bytes     | Variable size | Variable size |
+///     | Size of the key |      Key      |     Value     |
+/// ```
+pub fn encode_object(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let key_size: [u8; KEY_SIZE_PREFIX_SIZE] = u32::try_from(key.len())
+        .expect("A key cannot be bigger than 2^32.")
+        .to_le_bytes();
+    [&key_size[..], key, value].concat()
+}
+
+/// Decodes the body of an object into the key-value pair.
+pub fn decode_object(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+    if data.len() < KEY_SIZE_PREFIX_SIZE {
+        return Err(PrimaryError::Other("Object is too small.".into()));
+    }
+    let (key_size, rest) = data.split_at(KEY_SIZE_PREFIX_SIZE);
+    let key_size = usize::try_from(u32::from_le_bytes(
+        key_size
+            .try_into()
+            .expect("Slice is guaranteed to be exactly 4 bytes"),
+    ))
+    .expect(">=32-bit platform needed");
+    if key_size > rest.len() {
+        return Err(PrimaryError::Other(
+            "Object is smaller than its key.".into(),
+        ));
+    }
+    let (key, value) = rest.split_at(key_size);
+    Ok((key.to_vec(), value.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_object, encode_object, object_key};
+
+    use storethehash::primary::PrimaryError;
+
+    #[test]
+    fn object_keys() {
+        assert_eq!(object_key("", 42), "00000000000000000042");
+        assert_eq!(object_key("tenant/", 0), "tenant/00000000000000000000");
+        assert_eq!(object_key("", u64::MAX), "18446744073709551615");
+    }
+
+    #[test]
+    fn encode_decode() {
+        let data = encode_object(b"key", b"value");
+        assert_eq!(data, b"\x03\x00\x00\x00keyvalue");
+        assert_eq!(
+            decode_object(&data).unwrap(),
+            (b"key".to_vec(), b"value".to_vec())
+        );
+        assert_eq!(
+            decode_object(&encode_object(b"", b"")).unwrap(),
+            (vec![], vec![])
+        );
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let data = encode_object(b"key", b"value");
+        assert!(matches!(
+            decode_object(&data[..2]),
+            Err(PrimaryError::Other(_))
+        ));
+        assert!(matches!(
+            decode_object(&data[..5]),
+            Err(PrimaryError::Other(_))
+        ));
+    }
+}
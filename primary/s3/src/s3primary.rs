@@ -0,0 +1,217 @@
+use std::cell::Cell;
+
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client;
+use log::debug;
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{decode_object, encode_object, object_key};
+
+/// Converts any error of the S3 client into a primary storage error.
+fn other_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> PrimaryError {
+    PrimaryError::Other(Box::new(error))
+}
+
+/// S3 storage implementation.
+///
+/// The S3 client is configured from the environment, e.g. the credentials are read from
+/// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`. To use an S3-compatible object storage like
+/// MinIO, set `AWS_ENDPOINT_URL` to its address.
+pub struct S3Primary {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    /// The S3 client is async, all requests are run to completion on this runtime.
+    runtime: Runtime,
+    /// The position the next key-value pair is stored at.
+    next_pos: Cell<u64>,
+}
+
+impl S3Primary {
+    /// Opens a primary storage that uses the whole bucket.
+    pub fn new(bucket: &str) -> Result<Self, PrimaryError> {
+        Self::with_prefix(bucket, "")
+    }
+
+    /// Opens a primary storage that only uses objects whose keys start with the given prefix.
+    ///
+    /// This way a single bucket can be shared by several stores.
+    pub fn with_prefix(bucket: &str, prefix: &str) -> Result<Self, PrimaryError> {
+        debug!("Opening S3 bucket {:?} with prefix {:?}", bucket, prefix);
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let config = runtime.block_on(aws_config::load_from_env());
+        // Path style addressing is needed for most S3-compatible object storages.
+        let s3_config = aws_sdk_s3::config::Builder::from(&config)
+            .force_path_style(true)
+            .build();
+        let primary = Self {
+            client: Client::from_conf(s3_config),
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            runtime,
+            next_pos: Cell::new(0),
+        };
+        primary.next_pos.set(primary.len()?);
+        Ok(primary)
+    }
+
+    /// Returns the number of stored key-value pairs.
+    pub fn len(&self) -> Result<u64, PrimaryError> {
+        self.runtime.block_on(async {
+            let mut pages = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&self.prefix)
+                .into_paginator()
+                .send();
+            let mut count = 0;
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(other_error)?;
+                count += page.contents().len() as u64;
+            }
+            Ok(count)
+        })
+    }
+
+    /// Returns true if no key-value pairs are stored.
+    pub fn is_empty(&self) -> Result<bool, PrimaryError> {
+        Ok(self.len()? == 0)
+    }
+}
+
+impl std::fmt::Debug for S3Primary {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("S3Primary")
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .field("next_pos", &self.next_pos)
+            .finish()
+    }
+}
+
+impl PrimaryStorage for S3Primary {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let data = self.runtime.block_on(async {
+            let output = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(object_key(&self.prefix, pos))
+                .send()
+                .await
+                .map_err(|error| {
+                    let not_found = error
+                        .as_service_error()
+                        .map(|service_error| service_error.is_no_such_key())
+                        .unwrap_or(false);
+                    if not_found {
+                        PrimaryError::OutOfBounds {
+                            pos,
+                            len: self.next_pos.get(),
+                        }
+                    } else {
+                        other_error(error)
+                    }
+                })?;
+            let body = output.body.collect().await.map_err(other_error)?;
+            Ok::<_, PrimaryError>(body.into_bytes())
+        })?;
+        decode_object(&data)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        let pos = self.next_pos.get();
+        self.runtime.block_on(async {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(object_key(&self.prefix, pos))
+                .body(ByteStream::from(encode_object(key, value)))
+                .send()
+                .await
+                .map_err(other_error)
+        })?;
+        self.next_pos.set(pos + 1);
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests need an S3-compatible object storage, e.g. a local MinIO instance:
+    //!
+    //! ```text
+    //! docker run -p 9000:9000 minio/minio server /data
+    //! AWS_ENDPOINT_URL=http://localhost:9000 AWS_REGION=us-east-1 \
+    //!     AWS_ACCESS_KEY_ID=minioadmin AWS_SECRET_ACCESS_KEY=minioadmin \
+    //!     STORETHEHASH_S3_BUCKET=<existing-bucket> \
+    //!     cargo test --features s3 -- --ignored
+    //! ```
+    use super::S3Primary;
+
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use storethehash::db::Db;
+    use storethehash::primary::{PrimaryError, PrimaryStorage};
+
+    const BUCKETS_BITS: u8 = 8;
+
+    // Every test run uses a unique prefix, so that they don't interfere with each other.
+    fn unique_prefix(name: &str) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("storethehash-test/{}/{}/", nanos, name)
+    }
+
+    fn bucket() -> String {
+        std::env::var("STORETHEHASH_S3_BUCKET").expect("STORETHEHASH_S3_BUCKET needs to be set")
+    }
+
+    #[test]
+    #[ignore]
+    fn put_get() {
+        let prefix = unique_prefix("put_get");
+        let primary = S3Primary::with_prefix(&bucket(), &prefix).unwrap();
+        assert!(primary.is_empty().unwrap());
+
+        let pos_a = primary.put(b"key a", b"value a").unwrap();
+        let pos_b = primary.put(b"key b", b"value b").unwrap();
+        assert_eq!((pos_a, pos_b), (0, 1));
+        assert_eq!(primary.len().unwrap(), 2);
+        assert_eq!(
+            primary.get(pos_b).unwrap(),
+            (b"key b".to_vec(), b"value b".to_vec())
+        );
+        assert!(matches!(
+            primary.get(2),
+            Err(PrimaryError::OutOfBounds { pos: 2, len: 2 })
+        ));
+
+        // Opening it again continues at the next position.
+        let primary = S3Primary::with_prefix(&bucket(), &prefix).unwrap();
+        assert_eq!(primary.put(b"key c", b"value c").unwrap(), 2);
+        assert_eq!(
+            primary.get(pos_a).unwrap(),
+            (b"key a".to_vec(), b"value a".to_vec())
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn db() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index_path = temp_dir.path().join("storethehash.index");
+        let primary = S3Primary::with_prefix(&bucket(), &unique_prefix("db")).unwrap();
+        let db = Db::<_, BUCKETS_BITS>::open(primary, index_path).unwrap();
+
+        let key = [1, 2, 3, 4, 5, 6, 7, 8];
+        db.put(&key, b"value").unwrap();
+        assert_eq!(db.get(&key).unwrap(), Some(b"value".to_vec()));
+        assert_eq!(db.get(&[8, 7, 6, 5, 4, 3, 2, 1]).unwrap(), None);
+    }
+}
@@ -1,3 +1,5 @@
+use std::ops;
+
 use crate::error::Error;
 
 /// Contains pointers to file offsets
@@ -27,6 +29,26 @@ impl<const N: u8> Buckets<N> {
         }
         Ok(self.0[bucket])
     }
+
+    /// Returns the buckets together with their offsets, sorted by bucket.
+    ///
+    /// An offset of 0 means that the bucket is empty.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.0.iter().copied().enumerate()
+    }
+
+    /// Returns the buckets whose offsets differ as `(bucket, old_offset, new_offset)` tuples.
+    ///
+    /// The tuples are sorted by bucket. An offset of 0 means that the bucket is empty.
+    pub fn diff(old: &Buckets<N>, new: &Buckets<N>) -> Vec<(usize, u64, u64)> {
+        old.0
+            .iter()
+            .zip(new.0.iter())
+            .enumerate()
+            .filter(|(_, (old_offset, new_offset))| old_offset != new_offset)
+            .map(|(bucket, (old_offset, new_offset))| (bucket, *old_offset, *new_offset))
+            .collect()
+    }
 }
 
 impl<const N: u8> Default for Buckets<N> {
@@ -35,6 +57,40 @@ impl<const N: u8> Default for Buckets<N> {
     }
 }
 
+/// Returns the offset of a bucket, it panics if the bucket is out of bounds.
+///
+/// Use [`Buckets::get`] if the bucket isn't known to be valid.
+impl<const N: u8> ops::Index<usize> for Buckets<N> {
+    type Output = u64;
+
+    fn index(&self, bucket: usize) -> &Self::Output {
+        match self.0.get(bucket) {
+            Some(offset) => offset,
+            None => out_of_bounds(bucket, self.0.len()),
+        }
+    }
+}
+
+/// Returns the offset of a bucket for modification, it panics if the bucket is out of bounds.
+///
+/// Use [`Buckets::put`] if the bucket isn't known to be valid.
+impl<const N: u8> ops::IndexMut<usize> for Buckets<N> {
+    fn index_mut(&mut self, bucket: usize) -> &mut Self::Output {
+        let len = self.0.len();
+        match self.0.get_mut(bucket) {
+            Some(offset) => offset,
+            None => out_of_bounds(bucket, len),
+        }
+    }
+}
+
+fn out_of_bounds(bucket: usize, len: usize) -> ! {
+    panic!(
+        "bucket index out of bounds: the number of buckets is {} but the index is {}",
+        len, bucket
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Buckets, Error};
@@ -54,6 +110,29 @@ mod tests {
         assert!(matches!(buckets.get(3), Ok(54321)));
     }
 
+    #[test]
+    fn diff() {
+        const BUCKETS_BITS: u8 = 3;
+        let old = Buckets::<BUCKETS_BITS>::new();
+        let mut new = Buckets::<BUCKETS_BITS>::new();
+        assert_eq!(Buckets::diff(&old, &new), vec![]);
+
+        // Added buckets.
+        new.put(0, 10).unwrap();
+        new.put(7, 20).unwrap();
+        assert_eq!(Buckets::diff(&old, &new), vec![(0, 0, 10), (7, 0, 20)]);
+        // Removed buckets.
+        assert_eq!(Buckets::diff(&new, &old), vec![(0, 10, 0), (7, 20, 0)]);
+
+        // Changed and unchanged buckets.
+        let mut newer = Buckets::<BUCKETS_BITS>::new();
+        newer.put(0, 10).unwrap();
+        newer.put(3, 30).unwrap();
+        newer.put(7, 40).unwrap();
+        assert_eq!(Buckets::diff(&new, &newer), vec![(3, 0, 30), (7, 20, 40)]);
+        assert_eq!(Buckets::diff(&newer, &newer), vec![]);
+    }
+
     #[test]
     fn put_error() {
         const BUCKETS_BITS: u8 = 3;
@@ -74,6 +153,35 @@ mod tests {
         assert!(matches!(result, Ok(54321)));
     }
 
+    #[test]
+    fn index() {
+        const BUCKETS_BITS: u8 = 3;
+        let mut buckets = Buckets::<BUCKETS_BITS>::new();
+        assert_eq!(buckets[7], 0);
+
+        buckets[7] = 54321;
+        assert_eq!(buckets[7], 54321);
+        assert!(matches!(buckets.get(7), Ok(54321)));
+        buckets.put(2, 12345).unwrap();
+        assert_eq!(buckets[2], 12345);
+    }
+
+    #[test]
+    #[should_panic(expected = "the number of buckets is 8 but the index is 8")]
+    fn index_out_of_bounds() {
+        const BUCKETS_BITS: u8 = 3;
+        let buckets = Buckets::<BUCKETS_BITS>::new();
+        let _ = buckets[8];
+    }
+
+    #[test]
+    #[should_panic(expected = "the number of buckets is 8 but the index is 333")]
+    fn index_mut_out_of_bounds() {
+        const BUCKETS_BITS: u8 = 3;
+        let mut buckets = Buckets::<BUCKETS_BITS>::new();
+        buckets[333] = 54321;
+    }
+
     #[test]
     fn get_error() {
         const BUCKETS_BITS: u8 = 3;
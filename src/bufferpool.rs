@@ -0,0 +1,132 @@
+//! Reusable byte buffers, so that reads don't allocate every time.
+//!
+//! Every lookup reads some data from disk, e.g. a record list or a block of the primary storage.
+//! A [`BufferPool`] keeps the buffers of earlier reads around, so that a lookup usually gets one
+//! that is already big enough. It can be shared between threads, every thread that reads at the
+//! same time gets its own buffer.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// The number of buffers a pool keeps at most, one per concurrent reader is enough.
+pub const MAX_POOLED_BUFFERS: usize = 16;
+
+/// Buffers with a bigger capacity are dropped instead of being kept, so that a single huge read
+/// doesn't keep its memory alive.
+pub const MAX_POOLED_CAPACITY: usize = 1024 * 1024;
+
+/// A pool of byte buffers, see the [module documentation](self).
+#[derive(Default)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffers", &self.lock().len())
+            .finish()
+    }
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an empty buffer, it's returned to the pool when it's dropped.
+    ///
+    /// It has the capacity of an earlier buffer, if there is one. Otherwise a new one is created,
+    /// which only allocates once data is added.
+    pub fn get(&self) -> PooledBuffer<'_> {
+        let buffer = self.lock().pop().unwrap_or_default();
+        PooledBuffer { pool: self, buffer }
+    }
+
+    /// Pooled buffers are always empty, hence the poisoning is ignored.
+    fn lock(&self) -> MutexGuard<'_, Vec<Vec<u8>>> {
+        self.buffers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn put_back(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.lock();
+        if buffers.len() < MAX_POOLED_BUFFERS {
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// A buffer of a [`BufferPool`], it dereferences to a `Vec<u8>`.
+#[derive(Debug)]
+pub struct PooledBuffer<'a> {
+    pool: &'a BufferPool,
+    buffer: Vec<u8>,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool.put_back(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferPool, MAX_POOLED_BUFFERS, MAX_POOLED_CAPACITY};
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = BufferPool::new();
+        let mut buffer = pool.get();
+        buffer.extend_from_slice(&[1, 2, 3]);
+        let ptr = buffer.as_ptr();
+        drop(buffer);
+
+        // The same allocation comes back, but without its content.
+        let buffer = pool.get();
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 3);
+        assert_eq!(buffer.as_ptr(), ptr);
+
+        // A concurrent reader gets a different buffer.
+        let other = pool.get();
+        assert_eq!(other.capacity(), 0);
+    }
+
+    #[test]
+    fn pool_is_bounded() {
+        let pool = BufferPool::new();
+        let buffers: Vec<_> = (0..MAX_POOLED_BUFFERS + 2)
+            .map(|_| {
+                let mut buffer = pool.get();
+                buffer.push(1);
+                buffer
+            })
+            .collect();
+        drop(buffers);
+        assert_eq!(pool.lock().len(), MAX_POOLED_BUFFERS);
+
+        // Huge buffers aren't kept.
+        let pool = BufferPool::new();
+        pool.get().reserve(MAX_POOLED_CAPACITY + 1);
+        assert!(pool.lock().is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+//! Encoders that turn the keys that are stored into keys for the index.
+//!
+//! The index needs keys that are cryptographically secure hashes. A [`KeyCodec`] can be used to
+//! implement [`PrimaryStorage::index_key`] without writing the same code for every primary
+//! storage. The codecs that hash the keys need their corresponding feature (`sha2`, `sha3` or
+//! `blake3`) enabled.
+//!
+//! [`PrimaryStorage::index_key`]: crate::primary::PrimaryStorage::index_key
+
+use crate::primary::PrimaryError;
+
+/// Encodes a key into a key that is used for the index.
+pub trait KeyCodec {
+    /// Returns the index key of the given raw key.
+    fn encode(raw_key: &[u8]) -> Result<Vec<u8>, PrimaryError>;
+}
+
+/// Uses the keys unchanged, for keys that already are hashes.
+#[derive(Debug)]
+pub struct IdentityCodec;
+
+impl KeyCodec for IdentityCodec {
+    fn encode(raw_key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
+        Ok(raw_key.to_vec())
+    }
+}
+
+/// Hashes the keys with SHA2-256.
+#[cfg(feature = "sha2")]
+#[derive(Debug)]
+pub struct Sha256Codec;
+
+#[cfg(feature = "sha2")]
+impl KeyCodec for Sha256Codec {
+    fn encode(raw_key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
+        use sha2::Digest;
+        Ok(sha2::Sha256::digest(raw_key).to_vec())
+    }
+}
+
+/// Hashes the keys with SHA2-512.
+#[cfg(feature = "sha2")]
+#[derive(Debug)]
+pub struct Sha512Codec;
+
+#[cfg(feature = "sha2")]
+impl KeyCodec for Sha512Codec {
+    fn encode(raw_key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
+        use sha2::Digest;
+        Ok(sha2::Sha512::digest(raw_key).to_vec())
+    }
+}
+
+/// Hashes the keys with SHA3-256.
+#[cfg(feature = "sha3")]
+#[derive(Debug)]
+pub struct Sha3_256Codec;
+
+#[cfg(feature = "sha3")]
+impl KeyCodec for Sha3_256Codec {
+    fn encode(raw_key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
+        use sha3::Digest;
+        Ok(sha3::Sha3_256::digest(raw_key).to_vec())
+    }
+}
+
+/// Hashes the keys with [BLAKE3].
+///
+/// [BLAKE3]: https://github.com/BLAKE3-team/BLAKE3
+#[cfg(feature = "blake3")]
+#[derive(Debug)]
+pub struct Blake3Codec;
+
+#[cfg(feature = "blake3")]
+impl KeyCodec for Blake3Codec {
+    fn encode(raw_key: &[u8]) -> Result<Vec<u8>, PrimaryError> {
+        Ok(blake3::hash(raw_key).as_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdentityCodec, KeyCodec};
+
+    #[test]
+    fn identity() {
+        assert_eq!(IdentityCodec::encode(b"some key").unwrap(), b"some key");
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn sha2() {
+        use super::{Sha256Codec, Sha512Codec};
+
+        // The hashes of an empty input are well known.
+        let sha256 = Sha256Codec::encode(b"").unwrap();
+        assert_eq!(sha256.len(), 32);
+        assert_eq!(sha256[..4], [0xe3, 0xb0, 0xc4, 0x42]);
+        let sha512 = Sha512Codec::encode(b"").unwrap();
+        assert_eq!(sha512.len(), 64);
+        assert_eq!(sha512[..4], [0xcf, 0x83, 0xe1, 0x35]);
+    }
+
+    #[cfg(feature = "sha3")]
+    #[test]
+    fn sha3() {
+        use super::Sha3_256Codec;
+
+        let sha3_256 = Sha3_256Codec::encode(b"").unwrap();
+        assert_eq!(sha3_256.len(), 32);
+        assert_eq!(sha3_256[..4], [0xa7, 0xff, 0xc6, 0xf8]);
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn blake3() {
+        use super::Blake3Codec;
+
+        let blake3 = Blake3Codec::encode(b"").unwrap();
+        assert_eq!(blake3.len(), 32);
+        assert_eq!(blake3[..4], [0xaf, 0x13, 0x49, 0xb9]);
+    }
+}
@@ -3,16 +3,281 @@
 //! You can store and retrieve keys. The data is stored in a primary storage, the index is updated
 //! automatically.
 
-use std::path::Path;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use std::vec;
 
-use crate::error::Error;
-use crate::index::Index;
-use crate::primary::PrimaryStorage;
+use log::debug;
+
+use crate::buckets::Buckets;
+use crate::error::{ChecksumError, Error};
+use crate::index::{GarbageStats, Index, IndexStats};
+use crate::metrics::{Metrics, MetricsSnapshot};
+use crate::primary::{PrimaryError, PrimaryStorage};
+use crate::progress::{ProgressReporter, ProgressSink, DEFAULT_PROGRESS_INTERVAL};
+use crate::ratelimit::RateLimiter;
+use crate::readscheduler::ReadScheduler;
 
 /// A database to store and retrive key-value pairs.
-#[derive(Debug)]
 pub struct Db<P: PrimaryStorage, const N: u8> {
     index: Index<P, N>,
+    /// If set, all writes return an error.
+    read_only: bool,
+    /// If set, it is called before every write.
+    rate_limiter: Option<Box<dyn RateLimiter>>,
+    /// If set, it is called every `progress_interval` writes.
+    progress_sink: Option<Box<dyn ProgressSink>>,
+    /// The number of keys after which the progress sink is called, it's never 0.
+    progress_interval: u64,
+    /// The number of keys inserted since the database was opened.
+    keys_inserted: AtomicU64,
+    /// The number of bytes (keys and values) written since the database was opened.
+    bytes_written: AtomicU64,
+    /// If set, the gets and the primary storage reads are reported to it.
+    metrics: Option<Arc<dyn Metrics>>,
+    /// Whether scans give readahead hints to the primary storage, see
+    /// [`DbBuilder::readahead_hints`].
+    readahead_hints: bool,
+}
+
+impl<P: PrimaryStorage + fmt::Debug, const N: u8> fmt::Debug for Db<P, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Db")
+            .field("index", &self.index)
+            .field("read_only", &self.read_only)
+            .field("rate_limiter", &self.rate_limiter.is_some())
+            .field("progress_sink", &self.progress_sink.is_some())
+            .field("progress_interval", &self.progress_interval)
+            .field("keys_inserted", &self.keys_inserted.load(Ordering::Relaxed))
+            .field("bytes_written", &self.bytes_written.load(Ordering::Relaxed))
+            .field("metrics", &self.metrics.is_some())
+            .field("readahead_hints", &self.readahead_hints)
+            .finish()
+    }
+}
+
+/// Opens a [`Db`] with settings that go beyond [`Db::open`] and [`Db::open_read_only`].
+pub struct DbBuilder<P: PrimaryStorage, const N: u8> {
+    primary: P,
+    index_path: PathBuf,
+    read_only: bool,
+    rate_limiter: Option<Box<dyn RateLimiter>>,
+    progress_sink: Option<Box<dyn ProgressSink>>,
+    progress_interval: u64,
+    metrics: Option<Arc<dyn Metrics>>,
+    readahead_hints: bool,
+}
+
+impl<P: PrimaryStorage + fmt::Debug, const N: u8> fmt::Debug for DbBuilder<P, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DbBuilder")
+            .field("primary", &self.primary)
+            .field("index_path", &self.index_path)
+            .field("read_only", &self.read_only)
+            .field("rate_limiter", &self.rate_limiter.is_some())
+            .field("progress_sink", &self.progress_sink.is_some())
+            .field("progress_interval", &self.progress_interval)
+            .field("metrics", &self.metrics.is_some())
+            .field("readahead_hints", &self.readahead_hints)
+            .finish()
+    }
+}
+
+impl<P: PrimaryStorage, const N: u8> DbBuilder<P, N> {
+    pub fn new<T>(primary: P, index_path: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        Self {
+            primary,
+            index_path: index_path.as_ref().to_path_buf(),
+            read_only: false,
+            rate_limiter: None,
+            progress_sink: None,
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            metrics: None,
+            readahead_hints: true,
+        }
+    }
+
+    /// Opens the database read-only, see [`Db::open_read_only`].
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Limits the rate of the writes, see [`RateLimiter`].
+    pub fn with_rate_limiter(mut self, limiter: impl RateLimiter + 'static) -> Self {
+        self.rate_limiter = Some(Box::new(limiter));
+        self
+    }
+
+    /// Reports the progress of the writes, e.g. of a long-running import, see [`ProgressSink`].
+    ///
+    /// The sink is called every [`DbBuilder::progress_interval`] keys.
+    pub fn with_progress_sink(mut self, sink: impl ProgressSink + 'static) -> Self {
+        self.progress_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// The number of keys after which the progress sink is called, it defaults to
+    /// [`DEFAULT_PROGRESS_INTERVAL`]. An interval of 0 is treated like 1.
+    ///
+    /// The sink is called by the put that reaches a multiple of the interval, also with concurrent
+    /// puts every multiple is reported exactly once.
+    pub fn progress_interval(mut self, keys: u64) -> Self {
+        self.progress_interval = keys.max(1);
+        self
+    }
+
+    /// Reports measurements of the operations, e.g. for monitoring, see [`Metrics`].
+    ///
+    /// The database reports its gets and the reads from the primary storage, the index its puts
+    /// and writes.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Whether scans that read the whole primary storage give readahead hints, it's enabled by
+    /// default.
+    ///
+    /// [`Db::verify`], [`Db::export`] and [`Db::repair_primary`] hint the primary storage that it
+    /// is about to be read sequentially, see [`PrimaryStorage::advise_sequential`]. It makes them
+    /// faster with a cold page cache, but it might not be wanted if the database shares the disk
+    /// with latency sensitive reads.
+    pub fn readahead_hints(mut self, enabled: bool) -> Self {
+        self.readahead_hints = enabled;
+        self
+    }
+
+    pub fn open(self) -> Result<Db<P, N>, Error> {
+        let mut db = if self.read_only {
+            Db::open_read_only(self.primary, self.index_path)?
+        } else {
+            Db::open(self.primary, self.index_path)?
+        };
+        db.rate_limiter = self.rate_limiter;
+        db.progress_sink = self.progress_sink;
+        db.progress_interval = self.progress_interval;
+        db.readahead_hints = self.readahead_hints;
+        if let Some(metrics) = self.metrics {
+            db.index.set_metrics(metrics.clone());
+            db.metrics = Some(metrics);
+        }
+        Ok(db)
+    }
+}
+
+/// The number of entries after which [`Db::import`] flushes the database.
+pub const IMPORT_BATCH_SIZE: u64 = 10_000;
+
+/// The number of bytes of the checksum that [`Db::put_checked_crc32`] appends to a value.
+pub const CRC32_SIZE: usize = 4;
+
+/// The number of primary storage reads that [`Db::verify`] sorts by their positions at once, it
+/// bounds the memory that is needed.
+pub const VERIFY_BATCH_SIZE: usize = 64 * 1024;
+
+/// The result of [`Db::import`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// The number of entries that were stored.
+    pub imported: u64,
+    /// The number of entries that were skipped as their key already exists.
+    pub skipped: u64,
+}
+
+/// Where the data of a [`Db::put`] ended up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InsertPosition {
+    /// The position of the entry within the primary storage.
+    pub primary_offset: u64,
+    /// The bucket of the key.
+    pub index_bucket: usize,
+    /// The position of the bucket's record list within the index file, it points to its size
+    /// prefix.
+    ///
+    /// It's the record list that was appended by the put. If the key already existed, nothing is
+    /// appended and it's the position of the record list that contains the existing key.
+    pub index_record_list_offset: u64,
+}
+
+/// The result of [`Db::repair_primary`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepairPrimaryReport {
+    /// The number of entries in the primary storage that were checked.
+    pub blocks_checked: u64,
+    /// The number of entries that are corrupt.
+    pub blocks_corrupt: u64,
+    /// The positions of the corrupt entries in the primary storage.
+    pub corrupt_offsets: Vec<u64>,
+}
+
+/// The result of [`Db::verify`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    /// The number of records in the index that were checked.
+    pub records_checked: u64,
+    /// The records that point past the end of the primary storage.
+    pub dangling: Vec<DanglingReference>,
+}
+
+/// A record of the index that points past the end of the primary storage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DanglingReference {
+    /// The bucket the record is in.
+    pub bucket: usize,
+    /// The position in the primary storage the record points to.
+    pub file_offset: u64,
+    /// How far the position is past the end of the primary storage, 0 means it points right at
+    /// the end.
+    pub past_eof: u64,
+}
+
+/// A consistent copy of a database, see [`Db::snapshot`].
+#[derive(Debug)]
+pub struct DbSnapshot {
+    /// The path to the copy of the index.
+    pub index_path: PathBuf,
+    /// The path to the copy of the primary storage.
+    pub primary_path: PathBuf,
+    /// The size of the primary storage at the time the snapshot was taken.
+    pub primary_size: u64,
+}
+
+/// Statistics about a database, see [`Db::stats`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DbStats {
+    /// How the records are distributed over the buckets.
+    pub index: IndexStats,
+    /// The size of the index file in bytes.
+    pub index_size: u64,
+    /// The number of bytes of the index file that are still in use, see [`Index::live_size`].
+    pub live_index_size: u64,
+    /// How many bytes of the index file are taken by superseded record lists.
+    pub garbage: GarbageStats,
+    /// The size of the primary storage in bytes, if the primary storage can tell.
+    pub primary_size: Option<u64>,
+    /// The measurements since the database was opened, if its metrics keep them, see
+    /// [`DbBuilder::with_metrics`].
+    pub metrics: Option<MetricsSnapshot>,
+}
+
+impl DbSnapshot {
+    /// Opens the snapshot read-only.
+    ///
+    /// The primary storage needs to be opened by the caller from [`DbSnapshot::primary_path`].
+    pub fn open<P: PrimaryStorage, const N: u8>(&self, primary: P) -> Result<Db<P, N>, Error> {
+        Db::open_read_only(primary, &self.index_path)
+    }
 }
 
 impl<P: PrimaryStorage, const N: u8> Db<P, N> {
@@ -21,31 +286,723 @@ impl<P: PrimaryStorage, const N: u8> Db<P, N> {
         T: AsRef<Path>,
     {
         let index = Index::<_, N>::open(index_path, primary)?;
-        Ok(Self { index })
+        Ok(Self {
+            index,
+            read_only: false,
+            rate_limiter: None,
+            progress_sink: None,
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            keys_inserted: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            metrics: None,
+            readahead_hints: true,
+        })
+    }
+
+    /// Opens an existing database where all writes return an error.
+    ///
+    /// The index is opened without write access, see [`Index::open_read_only`].
+    pub fn open_read_only<T>(primary: P, index_path: T) -> Result<Self, Error>
+    where
+        T: AsRef<Path>,
+    {
+        let index = Index::<_, N>::open_read_only(index_path, primary)?;
+        Ok(Self {
+            index,
+            read_only: true,
+            rate_limiter: None,
+            progress_sink: None,
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            keys_inserted: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            metrics: None,
+            readahead_hints: true,
+        })
     }
 
     /// Returns the value of the given key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "Db::get",
+            level = "trace",
+            skip(self, key),
+            fields(file_offset = tracing::field::Empty, bytes_read = tracing::field::Empty)
+        )
+    )]
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        // Time is only measured if it's reported.
+        let start = self.metrics.as_ref().map(|_| Instant::now());
         let index_key = P::index_key(&key)?;
-        match self.index.get(&index_key)? {
+        let (value, false_positive) = match self.index.get(&index_key)? {
             Some(file_offset) => {
                 let (primary_key, value) = self.index.primary.get(file_offset)?;
+                record_span!(
+                    file_offset = file_offset,
+                    bytes_read = primary_key.len() + value.len()
+                );
+                self.match_primary_entry(key, primary_key, value)
+            }
+            None => (None, false),
+        };
+        if let (Some(metrics), Some(start)) = (&self.metrics, start) {
+            metrics.record_get(start.elapsed(), value.is_some(), false_positive);
+        }
+        Ok(value)
+    }
+
+    /// Returns the value of an entry that was read from the primary storage for the given key,
+    /// together with whether it was a false positive of the index.
+    fn match_primary_entry(
+        &self,
+        key: &[u8],
+        primary_key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> (Option<Vec<u8>>, bool) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_primary_read(primary_key.len() + value.len());
+        }
+        // The index stores only prefixes, hence check if the given key fully matches the key that
+        // is stored in the primary storage before returning the actual value.
+        if key == primary_key {
+            (Some(value), false)
+        } else {
+            (None, true)
+        }
+    }
+
+    /// Returns the values of the given keys.
+    ///
+    /// The results are in the same order as the keys. A failed lookup doesn't affect the others.
+    /// All keys are looked up in the index first, then the primary storage is read in ascending
+    /// order of the positions, which is much faster than reading them in random order.
+    pub fn get_many(&self, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>, Error>> {
+        // The time is only measured if it's reported.
+        let timer = || self.metrics.as_ref().map(|_| Instant::now());
+        let elapsed = |start: Option<Instant>| start.map(|start| start.elapsed());
+
+        let mut results = Vec::with_capacity(keys.len());
+        let mut reads = ReadScheduler::with_capacity(keys.len());
+        // The keys that need a read, together with the time of their index lookup.
+        let mut pending = Vec::with_capacity(keys.len());
+        for (ii, key) in keys.iter().enumerate() {
+            let start = timer();
+            let file_offset = P::index_key(key)
+                .map_err(Error::from)
+                .and_then(|index_key| self.index.get(&index_key));
+            match file_offset {
+                Ok(Some(file_offset)) => {
+                    reads.push(file_offset);
+                    pending.push((ii, elapsed(start)));
+                    results.push(Ok(None));
+                }
+                Ok(None) => {
+                    if let (Some(metrics), Some(start)) = (&self.metrics, start) {
+                        metrics.record_get(start.elapsed(), false, false);
+                    }
+                    results.push(Ok(None));
+                }
+                Err(error) => results.push(Err(error)),
+            }
+        }
+
+        let entries = reads.run(|file_offset| {
+            let start = timer();
+            (self.index.primary.get(file_offset), elapsed(start))
+        });
+        for ((ii, lookup_time), (entry, read_time)) in pending.into_iter().zip(entries) {
+            results[ii] = entry.map_err(Error::from).map(|(primary_key, value)| {
+                let (value, false_positive) =
+                    self.match_primary_entry(keys[ii], primary_key, value);
+                if let (Some(metrics), Some(lookup_time), Some(read_time)) =
+                    (&self.metrics, lookup_time, read_time)
+                {
+                    metrics.record_get(lookup_time + read_time, value.is_some(), false_positive);
+                }
+                value
+            });
+        }
+        results
+    }
+
+    /// Stores a key-value pair together with a CRC32 checksum of the value.
+    ///
+    /// The checksum is appended to the value as [`CRC32_SIZE`] bytes in little-endian, it's
+    /// verified by [`Db::get_checked_crc32`]. [`Db::get`] returns the value with the checksum.
+    pub fn put_checked_crc32(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let checksum = crc32fast::hash(value);
+        self.put(key, &[value, &checksum.to_le_bytes()].concat())?;
+        Ok(())
+    }
+
+    /// Returns the value of a key that was stored with [`Db::put_checked_crc32`], without the
+    /// checksum.
+    ///
+    /// If the value doesn't match its checksum, e.g. because it was corrupted on disk or in
+    /// memory, a [`ChecksumError`] is returned instead. A stored value that is too short to
+    /// contain a checksum returns [`Error::MissingChecksum`].
+    pub fn get_checked_crc32(
+        &self,
+        key: &[u8],
+    ) -> Result<Option<Result<Vec<u8>, ChecksumError>>, Error> {
+        let mut value = match self.get(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let value_len = value
+            .len()
+            .checked_sub(CRC32_SIZE)
+            .ok_or(Error::MissingChecksum { len: value.len() })?;
+        let expected = u32::from_le_bytes(
+            value[value_len..]
+                .try_into()
+                .expect("the slice has the size of the checksum"),
+        );
+        value.truncate(value_len);
+        let found = crc32fast::hash(&value);
+        if found == expected {
+            Ok(Some(Ok(value)))
+        } else {
+            Ok(Some(Err(ChecksumError { expected, found })))
+        }
+    }
+
+    /// Returns the position of the given key in the primary storage.
+    ///
+    /// The value isn't returned. To make sure it's not a different key that only shares a prefix,
+    /// the index key of the entry at that position is compared with the index key of the given
+    /// key.
+    pub fn get_offset(&self, key: &[u8]) -> Result<Option<u64>, Error> {
+        let index_key = P::index_key(key)?;
+        match self.index.get(&index_key)? {
+            Some(file_offset) if self.index.primary.get_index_key(file_offset)? == index_key => {
+                Ok(Some(file_offset))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Stores a key-value pair and returns where it was written to, see [`InsertPosition`].
+    ///
+    /// The value is always stored. Though if the key already exists, the index keeps pointing to
+    /// the existing entry. If a rate limiter is set, it is called before anything is written. If a
+    /// progress sink is set, it is called after the key was stored, every
+    /// [`DbBuilder::progress_interval`] keys.
+    ///
+    /// The returned positions are only durable after the next [`Db::flush`]. Together they
+    /// describe everything a put changed: the entry that was appended to the primary storage and
+    /// the record list that was appended to the index, which the bucket now points to.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "Db::put",
+            level = "trace",
+            skip(self, key, value),
+            fields(file_offset = tracing::field::Empty, bytes_written = key.len() + value.len())
+        )
+    )]
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<InsertPosition, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire(key.len() + value.len())?;
+        }
+        let file_offset = self.index.primary.put(key, value)?;
+        record_span!(file_offset = file_offset);
+        let index_key = P::index_key(key)?;
+        let index_record_list_offset = self.index.put(&index_key, file_offset)?;
+        let index_bucket = self.index.key_bucket(&index_key);
+
+        let bytes = (key.len() + value.len()) as u64;
+        let keys_inserted = self.keys_inserted.fetch_add(1, Ordering::Relaxed) + 1;
+        let bytes_written = self.bytes_written.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(sink) = &self.progress_sink {
+            // `u64::is_multiple_of()` isn't available on the supported toolchains.
+            #[allow(clippy::manual_is_multiple_of)]
+            let is_due = keys_inserted % self.progress_interval == 0;
+            // With concurrent puts the bytes may already include some of the other puts.
+            if is_due {
+                sink.report(keys_inserted, bytes_written);
+            }
+        }
+        Ok(InsertPosition {
+            primary_offset: file_offset,
+            index_bucket,
+            index_record_list_offset,
+        })
+    }
+
+    /// Same as [`Db::put`], but only returns the position of the entry in the primary storage.
+    pub fn put_get_offset(&self, key: &[u8], value: &[u8]) -> Result<u64, Error> {
+        Ok(self.put(key, value)?.primary_offset)
+    }
+
+    /// Deletes a key.
+    ///
+    /// Only the index is updated, the data stays in the primary storage. Returns whether the key
+    /// existed.
+    pub fn delete(&self, key: &[u8]) -> Result<bool, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let index_key = P::index_key(key)?;
+        match self.index.get(&index_key)? {
+            Some(file_offset) => {
                 // The index stores only prefixes, hence check if the given key fully matches the
-                // key that is stored in the primary storage before returning the actual value.
+                // key that is stored in the primary storage before deleting it.
+                let (primary_key, _value) = self.index.primary.get(file_offset)?;
                 if key == primary_key {
-                    Ok(Some(value))
+                    self.index.delete(&index_key)
                 } else {
-                    Ok(None)
+                    Ok(false)
                 }
             }
-            None => Ok(None),
+            None => Ok(false),
         }
     }
 
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
-        let file_offset = self.index.primary.put(&key, &value)?;
-        let index_key = P::index_key(&key)?;
-        self.index.put(&index_key, file_offset)?;
+    /// Copies all changes to another database, so that it contains the same key-value pairs.
+    ///
+    /// Only buckets whose index offsets differ from the ones of `dest` are compared entry by
+    /// entry, see [`Buckets::diff`]. Hence `dest` should be empty or a copy of this database, e.g.
+    /// opened from a [`Db::snapshot`]. Within a changed bucket, missing or different entries are
+    /// put into `dest` and entries that don't exist in this database are deleted from it.
+    ///
+    /// The offsets of `dest` diverge from the ones of this database once it was written to, such
+    /// buckets are compared again on the next sync. Returns the number of compared buckets.
+    pub fn sync_to(&self, dest: &mut Db<P, N>) -> Result<usize, Error> {
+        let changed = Buckets::<N>::diff(
+            &Buckets(dest.index.offsets()),
+            &Buckets(self.index.offsets()),
+        );
+        for (bucket, _dest_offset, _offset) in &changed {
+            let mut entries = Vec::new();
+            self.for_each_in_bucket(*bucket, |key, value| {
+                entries.push((key, value));
+                Ok(())
+            })?;
+            let mut dest_keys = Vec::new();
+            dest.for_each_in_bucket(*bucket, |key, _value| {
+                dest_keys.push(key);
+                Ok(())
+            })?;
+
+            for key in dest_keys {
+                if !entries.iter().any(|(entry_key, _)| *entry_key == key) {
+                    dest.delete(&key)?;
+                }
+            }
+            for (key, value) in entries {
+                match dest.get(&key)? {
+                    Some(dest_value) if dest_value == value => {}
+                    Some(_) => {
+                        // A put never replaces an existing key.
+                        dest.delete(&key)?;
+                        dest.put(&key, &value)?;
+                    }
+                    None => {
+                        dest.put(&key, &value)?;
+                    }
+                }
+            }
+        }
+        Ok(changed.len())
+    }
+
+    /// Checks every entry of the primary storage and removes the corrupt ones from the index.
+    ///
+    /// The primary storage needs to support [`PrimaryStorage::next_pos`] and
+    /// [`PrimaryStorage::verify_at`]. The corrupt data itself stays in the primary storage. A
+    /// corrupt entry is only removed from the index if the index points to it and its key can
+    /// still be read.
+    pub fn repair_primary(&self) -> Result<RepairPrimaryReport, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let primary = &self.index.primary;
+        let primary_size = primary
+            .size()?
+            .ok_or_else(|| PrimaryError::Other("Size of the primary storage is unknown.".into()))?;
+
+        self.sequential_scan(|| {
+            let mut report = RepairPrimaryReport::default();
+            let mut pos = primary.first_pos();
+            while pos < primary_size {
+                report.blocks_checked += 1;
+                if !primary.verify_at(pos)? {
+                    report.blocks_corrupt += 1;
+                    report.corrupt_offsets.push(pos);
+                    if let Ok(index_key) = primary.get_index_key(pos) {
+                        if self.index.get(&index_key)? == Some(pos) {
+                            self.index.delete(&index_key)?;
+                        }
+                    }
+                }
+                pos = primary.next_pos(pos)?;
+            }
+            Ok(report)
+        })
+    }
+
+    /// Checks that every record of the index points to data within the primary storage.
+    ///
+    /// Records that point past the end are reported, together with how far they are off, any
+    /// other error is returned. Nothing is modified. This reads the whole index and looks up every
+    /// entry in the primary storage, hence it can be slow.
+    pub fn verify(&self) -> Result<VerifyReport, Error> {
+        self.verify_with_progress(None)
+    }
+
+    /// Same as [`Db::verify`], the number of buckets that were checked is reported to the
+    /// progress sink, see [`ProgressSink::report_phase`].
+    ///
+    /// The records of several buckets are collected, so that the primary storage is read in
+    /// ascending order of the positions, see [`VERIFY_BATCH_SIZE`].
+    pub fn verify_with_progress(
+        &self,
+        progress: Option<&dyn ProgressSink>,
+    ) -> Result<VerifyReport, Error> {
+        let mut reporter = ProgressReporter::new(progress, "verify", Some(1 << N));
+        let report = self.sequential_scan(|| {
+            let mut report = VerifyReport::default();
+            let mut records = Vec::new();
+            for bucket in 0..1 << N {
+                reporter.update(bucket as u64);
+                for file_offset in self.index.file_offsets_in_bucket(bucket)? {
+                    records.push((bucket, file_offset));
+                }
+                if records.len() >= VERIFY_BATCH_SIZE {
+                    self.verify_records(&records, &mut report)?;
+                    records.clear();
+                }
+            }
+            self.verify_records(&records, &mut report)?;
+            Ok(report)
+        })?;
+        reporter.finish(1 << N);
+        Ok(report)
+    }
+
+    /// Runs a scan over the primary storage, with readahead hints around it unless they are
+    /// disabled, see [`DbBuilder::readahead_hints`].
+    ///
+    /// The hints don't change the result, hence the ones that fail are only logged.
+    fn sequential_scan<T, F>(&self, scan: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        if !self.readahead_hints {
+            return scan();
+        }
+        let primary = &self.index.primary;
+        if let Err(error) = primary.advise_sequential(primary.first_pos()) {
+            debug!("Readahead hint failed: {}", error);
+        }
+        let result = scan();
+        if let Err(error) = primary.advise_normal() {
+            debug!("Reverting the readahead hint failed: {}", error);
+        }
+        result
+    }
+
+    /// Checks the given `(bucket, file_offset)` records for [`Db::verify_with_progress`].
+    fn verify_records(
+        &self,
+        records: &[(usize, u64)],
+        report: &mut VerifyReport,
+    ) -> Result<(), Error> {
+        let reads = ReadScheduler::from(
+            records
+                .iter()
+                .map(|(_bucket, file_offset)| *file_offset)
+                .collect::<Vec<_>>(),
+        );
+        let results = reads.run(|file_offset| self.index.primary.get(file_offset).map(|_| ()));
+        for ((bucket, file_offset), result) in records.iter().zip(results) {
+            report.records_checked += 1;
+            match result {
+                Ok(()) => {}
+                Err(PrimaryError::OutOfBounds { pos, len }) => {
+                    report.dangling.push(DanglingReference {
+                        bucket: *bucket,
+                        file_offset: *file_offset,
+                        past_eof: pos.saturating_sub(len),
+                    })
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with the key and value of every entry within a single bucket.
+    ///
+    /// Only the record list of that bucket is read, which is much cheaper than going through the
+    /// whole database. The entries of the bucket are read from the primary storage in ascending
+    /// order of their positions before `f` is called in the order of the keys. The iteration
+    /// stops at the first error, which is then returned.
+    pub fn for_each_in_bucket<F>(&self, bucket: usize, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(Vec<u8>, Vec<u8>) -> Result<(), Error>,
+    {
+        let reads = ReadScheduler::from(self.index.file_offsets_in_bucket(bucket)?);
+        for entry in reads.run(|file_offset| self.index.primary.get(file_offset)) {
+            let (key, value) = entry?;
+            f(key, value)?;
+        }
         Ok(())
     }
+
+    /// Returns an iterator over all key-value pairs, ordered by the buckets they are in.
+    ///
+    /// The order is deterministic: it starts with bucket 0 and within a bucket the entries are
+    /// sorted by their keys. Only the record list of the current bucket is kept in memory.
+    pub fn iter_by_bucket(&self) -> BucketIter<'_, P, N> {
+        BucketIter {
+            db: self,
+            bucket: 0,
+            file_offsets: Vec::new().into_iter(),
+        }
+    }
+
+    /// Returns the values of all entries of the primary storage, see
+    /// [`PrimaryStorage::iter_values`].
+    ///
+    /// Unlike [`Db::iter_by_bucket`] the index isn't read and the values are in the order they
+    /// were stored. Also the values the index doesn't point to are returned, e.g. the ones of
+    /// deleted keys.
+    pub fn iter_values_only(&self) -> impl Iterator<Item = Result<Vec<u8>, Error>> + '_ {
+        self.index
+            .primary
+            .iter_values()
+            .map(|value| value.map_err(Error::from))
+    }
+
+    /// Returns the primary storage, e.g. for functionality that is specific to it.
+    pub fn primary(&self) -> &P {
+        &self.index.primary
+    }
+
+    /// Returns the number of keys.
+    ///
+    /// This reads the whole index, hence it can be slow.
+    pub fn count(&self) -> Result<usize, Error> {
+        Ok(self.index.stats()?.records)
+    }
+
+    /// Returns the number of keys of every bucket, see [`Index::count_by_bucket`].
+    pub fn count_by_bucket(&self) -> Result<Vec<u64>, Error> {
+        self.index.count_by_bucket()
+    }
+
+    /// Returns the number of keys, summed up from [`Db::count_by_bucket`].
+    pub fn total_count(&self) -> Result<u64, Error> {
+        Ok(self.count_by_bucket()?.iter().sum())
+    }
+
+    /// Returns statistics about the database.
+    ///
+    /// This reads the whole index, hence it can be slow.
+    pub fn stats(&self) -> Result<DbStats, Error> {
+        let garbage = self.index.garbage_stats()?;
+        Ok(DbStats {
+            index: self.index.stats()?,
+            index_size: garbage.total_bytes,
+            live_index_size: garbage.live_bytes,
+            garbage,
+            primary_size: self.index.primary.size()?,
+            metrics: self.metrics.as_ref().and_then(|metrics| metrics.snapshot()),
+        })
+    }
+
+    /// Writes all key-value pairs in a format that is independent of the storage, see
+    /// [`Db::import`].
+    ///
+    /// Every entry is the size of the key as unsigned LEB128 varint, the key, the size of the
+    /// value as varint and the value. Only the entries the index points to are exported, in the
+    /// order of [`Db::iter_by_bucket`]. Returns the number of entries.
+    pub fn export<W: Write>(&self, writer: W) -> Result<u64, Error> {
+        self.export_with_progress(writer, None)
+    }
+
+    /// Same as [`Db::export`], the number of entries that were written is reported to the
+    /// progress sink, see [`ProgressSink::report_phase`].
+    pub fn export_with_progress<W: Write>(
+        &self,
+        mut writer: W,
+        progress: Option<&dyn ProgressSink>,
+    ) -> Result<u64, Error> {
+        let mut reporter = ProgressReporter::new(progress, "export", None);
+        let count = self.sequential_scan(|| {
+            let mut count = 0;
+            for entry in self.iter_by_bucket() {
+                reporter.update(count);
+                let (key, value) = entry?;
+                write_varint(&mut writer, key.len() as u64)?;
+                writer.write_all(&key)?;
+                write_varint(&mut writer, value.len() as u64)?;
+                writer.write_all(&value)?;
+                count += 1;
+            }
+            writer.flush()?;
+            Ok(count)
+        })?;
+        reporter.finish(count);
+        Ok(count)
+    }
+
+    /// Stores all key-value pairs of an export created by [`Db::export`].
+    ///
+    /// The database is flushed every [`IMPORT_BATCH_SIZE`] entries and at the end. With
+    /// `skip_existing` the entries whose key already exists aren't stored again, so that an
+    /// interrupted import can be restarted without storing the values twice. Without it, the
+    /// values are always stored, though existing keys keep their value, see
+    /// [`Db::put_get_offset`].
+    pub fn import<R: Read>(
+        &self,
+        mut reader: R,
+        skip_existing: bool,
+    ) -> Result<ImportReport, Error> {
+        let mut report = ImportReport::default();
+        // The number of entries since the last flush.
+        let mut batch = 0;
+        while let Some(key_size) = read_varint(&mut reader)? {
+            let key = read_exact_vec(&mut reader, key_size)?;
+            let value_size = read_varint(&mut reader)?.ok_or_else(unexpected_eof)?;
+            let value = read_exact_vec(&mut reader, value_size)?;
+
+            if skip_existing && self.get_offset(&key)?.is_some() {
+                report.skipped += 1;
+            } else {
+                self.put(&key, &value)?;
+                report.imported += 1;
+            }
+            batch += 1;
+            if batch == IMPORT_BATCH_SIZE {
+                self.flush()?;
+                batch = 0;
+            }
+        }
+        self.flush()?;
+        Ok(report)
+    }
+
+    /// Creates a consistent copy of the database within the given directory.
+    ///
+    /// The index is copied first. As the primary storage is append-only, its copy, which is made
+    /// afterwards, contains all the data the copied index points to.
+    pub fn snapshot(&self, snapshot_dir: &Path) -> Result<DbSnapshot, Error> {
+        fs::create_dir_all(snapshot_dir)?;
+        let index_path = snapshot_dir.join("index");
+        let primary_path = snapshot_dir.join("primary");
+        self.index.snapshot(&index_path)?;
+        let primary_size = self.index.primary.snapshot(&primary_path)?;
+        Ok(DbSnapshot {
+            index_path,
+            primary_path,
+            primary_size,
+        })
+    }
+
+    /// Flushes the primary storage and the index and syncs them to disk.
+    ///
+    /// The primary storage is flushed first, so that the index never points to data that isn't
+    /// persisted yet.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.index.primary.flush()?;
+        self.index.flush()
+    }
+
+    /// Flushes all data to disk and closes the database.
+    pub fn close(self) -> Result<(), Error> {
+        self.flush()
+    }
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "Export ends within an entry.")
+}
+
+/// Writes an unsigned LEB128 varint.
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<(), io::Error> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// Reads an unsigned LEB128 varint, it's `None` if the reader is at its end.
+fn read_varint<R: Read>(reader: &mut R) -> Result<Option<u64>, io::Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err(unexpected_eof())
+            };
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Varint is too long.",
+    ))
+}
+
+/// Reads exactly the given number of bytes.
+///
+/// The memory isn't allocated upfront, so that a corrupt size doesn't exhaust it.
+fn read_exact_vec<R: Read>(reader: &mut R, size: u64) -> Result<Vec<u8>, io::Error> {
+    let mut data = Vec::new();
+    reader.take(size).read_to_end(&mut data)?;
+    if (data.len() as u64) < size {
+        return Err(unexpected_eof());
+    }
+    Ok(data)
+}
+
+/// An iterator over all key-value pairs of a database, see [`Db::iter_by_bucket`].
+#[derive(Debug)]
+pub struct BucketIter<'a, P: PrimaryStorage, const N: u8> {
+    db: &'a Db<P, N>,
+    /// The next bucket whose record list is read.
+    bucket: usize,
+    /// The file offsets of the current bucket that weren't returned yet.
+    file_offsets: vec::IntoIter<u64>,
+}
+
+impl<'a, P: PrimaryStorage, const N: u8> Iterator for BucketIter<'a, P, N> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(file_offset) = self.file_offsets.next() {
+                return Some(self.db.index.primary.get(file_offset).map_err(Error::from));
+            }
+            if self.bucket >= 1 << N {
+                return None;
+            }
+            match self.db.index.file_offsets_in_bucket(self.bucket) {
+                Ok(file_offsets) => {
+                    self.file_offsets = file_offsets.into_iter();
+                    self.bucket += 1;
+                }
+                Err(error) => {
+                    // Stop the iteration after an error.
+                    self.bucket = 1 << N;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
 }
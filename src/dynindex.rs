@@ -0,0 +1,93 @@
+//! Open an index without knowing the number of bits used for the buckets at compile time.
+//!
+//! [`Index`] needs the number of bits as const generic. [`DynIndex::open_detect`] reads them from
+//! the header of an existing index and opens it as the matching [`Index`]. Only the bit sizes in
+//! [`SUPPORTED_BUCKETS_BITS`] are supported, as each of them is a separate type.
+
+use std::fs::File;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::index::{self, Index};
+use crate::primary::PrimaryStorage;
+
+/// The bit sizes for the buckets that [`DynIndex::open_detect`] can open.
+pub const SUPPORTED_BUCKETS_BITS: [u8; 4] = [8, 16, 24, 32];
+
+/// Opens indexes whose number of bits for the buckets is only known at runtime.
+#[derive(Debug)]
+pub struct DynIndex;
+
+impl DynIndex {
+    /// Returns the number of bits used for the buckets of an existing index.
+    pub fn detect_buckets_bits(path: &Path) -> Result<u8, Error> {
+        let mut file = File::open(path).map_err(Error::io("opening index", path, None))?;
+        let (header, _bytes_read) = index::read_index_header(&mut file, path)?;
+        Ok(header.buckets_bits)
+    }
+
+    /// Opens an existing index with the number of bits for the buckets that is stored in it.
+    ///
+    /// Unlike [`Index::open`] it doesn't create a new index. An index with a bit size that is not
+    /// in [`SUPPORTED_BUCKETS_BITS`] returns [`Error::UnsupportedBitSize`].
+    pub fn open_detect<P: PrimaryStorage>(
+        path: &Path,
+        primary: P,
+    ) -> Result<DynIndexHandle<P>, Error> {
+        match Self::detect_buckets_bits(path)? {
+            8 => Ok(DynIndexHandle::Bits8(Index::open(path, primary)?)),
+            16 => Ok(DynIndexHandle::Bits16(Index::open(path, primary)?)),
+            24 => Ok(DynIndexHandle::Bits24(Index::open(path, primary)?)),
+            32 => Ok(DynIndexHandle::Bits32(Index::open(path, primary)?)),
+            bits => Err(Error::UnsupportedBitSize(bits)),
+        }
+    }
+}
+
+/// An index that was opened with [`DynIndex::open_detect`].
+#[derive(Debug)]
+pub enum DynIndexHandle<P: PrimaryStorage> {
+    Bits8(Index<P, 8>),
+    Bits16(Index<P, 16>),
+    Bits24(Index<P, 24>),
+    Bits32(Index<P, 32>),
+}
+
+/// Calls the same method on the index, independent of its number of bits.
+macro_rules! dispatch {
+    ($handle:expr, $index:ident => $call:expr) => {
+        match $handle {
+            DynIndexHandle::Bits8($index) => $call,
+            DynIndexHandle::Bits16($index) => $call,
+            DynIndexHandle::Bits24($index) => $call,
+            DynIndexHandle::Bits32($index) => $call,
+        }
+    };
+}
+
+impl<P: PrimaryStorage> DynIndexHandle<P> {
+    /// The number of bits that are used for the buckets.
+    pub fn buckets_bits(&self) -> u8 {
+        match self {
+            Self::Bits8(_) => 8,
+            Self::Bits16(_) => 16,
+            Self::Bits24(_) => 24,
+            Self::Bits32(_) => 32,
+        }
+    }
+
+    /// See [`Index::get`].
+    pub fn get(&self, key: &[u8]) -> Result<Option<u64>, Error> {
+        dispatch!(self, index => index.get(key))
+    }
+
+    /// See [`Index::put`].
+    pub fn put(&self, key: &[u8], file_offset: u64) -> Result<u64, Error> {
+        dispatch!(self, index => index.put(key, file_offset))
+    }
+
+    /// See [`Index::flush`].
+    pub fn flush(&self) -> Result<(), Error> {
+        dispatch!(self, index => index.flush())
+    }
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::path::{Path, PathBuf};
 
 use thiserror::Error;
 
@@ -6,14 +7,342 @@ use crate::primary::PrimaryError;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("IO Error: {0}")]
-    Io(#[from] io::Error),
+    /// An I/O error together with the operation that failed and where it happened, if known.
+    #[error("IO error{}: {source}", io_context(*.operation, .path, *.offset))]
+    Io {
+        operation: Option<&'static str>,
+        path: Option<PathBuf>,
+        offset: Option<u64>,
+        #[source]
+        source: io::Error,
+    },
     #[error("Buckets out of bound error.")]
     BucketsOutOfBounds,
     #[error("Index bit size for buckets is `{0}`, expected `{1}`.")]
     IndexWrongBitSize(u8, u8),
+    #[error("Index bit size for buckets `{0}` is not supported.")]
+    UnsupportedBitSize(u8),
     #[error("Index file is corrupt.")]
     IndexCorrupt,
+    #[error("Index header has an invalid {field} `{value}`.")]
+    InvalidHeader { field: &'static str, value: u8 },
+    #[error("Index header is corrupt: {reason}.")]
+    CorruptHeader { reason: &'static str },
+    #[error("Index version `{0}` is not supported.")]
+    UnsupportedVersion(u8),
+    #[error("Record list at index file offset `{offset}` is corrupt.")]
+    CorruptRecordList { offset: u64 },
+    #[error("Arithmetic overflow, a value doesn't fit into its type.")]
+    Arithmetic,
     #[error("Primary storage error: {0}")]
     Primary(#[from] PrimaryError),
+    #[error("Database is read-only.")]
+    ReadOnly,
+    #[error("Write was rejected by the rate limiter.")]
+    RateLimited,
+    #[error("Index dump is invalid at line {line}: {reason}.")]
+    InvalidDump { line: usize, reason: &'static str },
+    #[error("Sharded database has {manifest} shards, but {given} were given.")]
+    ShardCountMismatch { manifest: usize, given: usize },
+    #[error("Shard manifest is corrupt.")]
+    CorruptShardManifest,
+    #[error("Stored value of {len} bytes is too short to contain a checksum.")]
+    MissingChecksum { len: usize },
+}
+
+/// A value doesn't match the checksum it was stored with, see
+/// [`crate::db::Db::get_checked_crc32`].
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+#[error(
+    "Checksum mismatch: stored CRC32 is `{expected:#010x}`, but the value has `{found:#010x}`."
+)]
+pub struct ChecksumError {
+    /// The checksum that was stored together with the value.
+    pub expected: u32,
+    /// The checksum of the value that was read.
+    pub found: u32,
+}
+
+impl Error {
+    /// Returns a function that converts an I/O error into one with the given context.
+    ///
+    /// It's meant to be used with `map_err`, e.g.
+    /// `file.seek(pos).map_err(Error::io("reading record list", path, Some(pos)))?`.
+    pub fn io<'a>(
+        operation: &'static str,
+        path: &'a Path,
+        offset: Option<u64>,
+    ) -> impl FnOnce(io::Error) -> Self + 'a {
+        move |source| Self::Io {
+            operation: Some(operation),
+            path: Some(path.to_path_buf()),
+            offset,
+            source,
+        }
+    }
+
+    /// Adds context to an I/O error that doesn't have any yet, other errors are returned as is.
+    pub fn with_io_context(
+        self,
+        operation: &'static str,
+        path: &Path,
+        offset: Option<u64>,
+    ) -> Self {
+        match self {
+            Self::Io {
+                operation: None,
+                path: None,
+                offset: None,
+                source,
+            } => Self::io(operation, path, offset)(source),
+            error => error,
+        }
+    }
+}
+
+impl Error {
+    /// Returns whether the error is caused by a damaged index.
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            Self::IndexCorrupt
+                | Self::CorruptRecordList { .. }
+                | Self::InvalidHeader { .. }
+                | Self::CorruptHeader { .. }
+        )
+    }
+
+    /// Returns whether the error is caused by a file that doesn't exist.
+    ///
+    /// A key that doesn't exist isn't an error, lookups return `None` for those.
+    pub fn is_not_found(&self) -> bool {
+        self.kind() == io::ErrorKind::NotFound
+    }
+
+    /// Returns the [`io::ErrorKind`] that is used when converting into an [`io::Error`].
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            Self::Io { source, .. } => source.kind(),
+            Self::Primary(PrimaryError::Io { source, .. }) => source.kind(),
+            Self::Primary(PrimaryError::OutOfBounds { .. }) => io::ErrorKind::UnexpectedEof,
+            Self::Primary(PrimaryError::ReadOnly) | Self::ReadOnly => {
+                io::ErrorKind::PermissionDenied
+            }
+            Self::Primary(PrimaryError::MisalignedRead { .. }) => io::ErrorKind::InvalidInput,
+            Self::Primary(PrimaryError::ChecksumMismatch { .. }) => io::ErrorKind::InvalidData,
+            Self::Primary(PrimaryError::Other(_)) => io::ErrorKind::Other,
+            Self::BucketsOutOfBounds
+            | Self::IndexWrongBitSize(..)
+            | Self::UnsupportedBitSize(_)
+            | Self::ShardCountMismatch { .. } => io::ErrorKind::InvalidInput,
+            Self::IndexCorrupt
+            | Self::CorruptRecordList { .. }
+            | Self::InvalidHeader { .. }
+            | Self::CorruptHeader { .. }
+            | Self::UnsupportedVersion(_)
+            | Self::InvalidDump { .. }
+            | Self::CorruptShardManifest
+            | Self::MissingChecksum { .. }
+            | Self::Arithmetic => io::ErrorKind::InvalidData,
+            Self::RateLimited => io::ErrorKind::WouldBlock,
+        }
+    }
+}
+
+/// Maps the errors to the closest [`io::ErrorKind`], the original error is kept as inner error.
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        Self::new(error.kind(), error)
+    }
+}
+
+/// An I/O error without any context.
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Self::Io {
+            operation: None,
+            path: None,
+            offset: None,
+            source,
+        }
+    }
+}
+
+/// Formats the context of an I/O error, e.g. ` reading record list at offset 12 in "x.index"`.
+pub(crate) fn io_context(
+    operation: Option<&str>,
+    path: &Option<PathBuf>,
+    offset: Option<u64>,
+) -> String {
+    let mut context = String::new();
+    if let Some(operation) = operation {
+        context.push(' ');
+        context.push_str(operation);
+    }
+    if let Some(offset) = offset {
+        context.push_str(&format!(" at offset {}", offset));
+    }
+    if let Some(path) = path {
+        context.push_str(&format!(" in {:?}", path));
+    }
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::Path;
+
+    use super::Error;
+    use crate::primary::PrimaryError;
+
+    #[test]
+    fn into_io_error() {
+        let io_error = |kind| io::Error::new(kind, "some error");
+        let cases = vec![
+            (
+                Error::from(io_error(io::ErrorKind::NotFound)),
+                io::ErrorKind::NotFound,
+            ),
+            (
+                Error::io("reading record list", Path::new("x.index"), Some(4))(io_error(
+                    io::ErrorKind::UnexpectedEof,
+                )),
+                io::ErrorKind::UnexpectedEof,
+            ),
+            (Error::BucketsOutOfBounds, io::ErrorKind::InvalidInput),
+            (Error::IndexWrongBitSize(8, 24), io::ErrorKind::InvalidInput),
+            (Error::UnsupportedBitSize(12), io::ErrorKind::InvalidInput),
+            (Error::IndexCorrupt, io::ErrorKind::InvalidData),
+            (
+                Error::CorruptRecordList { offset: 6 },
+                io::ErrorKind::InvalidData,
+            ),
+            (Error::Arithmetic, io::ErrorKind::InvalidData),
+            (
+                Error::CorruptHeader {
+                    reason: "header is too big",
+                },
+                io::ErrorKind::InvalidData,
+            ),
+            (Error::UnsupportedVersion(255), io::ErrorKind::InvalidData),
+            (
+                Error::ShardCountMismatch {
+                    manifest: 4,
+                    given: 2,
+                },
+                io::ErrorKind::InvalidInput,
+            ),
+            (Error::CorruptShardManifest, io::ErrorKind::InvalidData),
+            (
+                Error::MissingChecksum { len: 3 },
+                io::ErrorKind::InvalidData,
+            ),
+            (
+                Error::InvalidDump {
+                    line: 2,
+                    reason: "malformed record",
+                },
+                io::ErrorKind::InvalidData,
+            ),
+            (
+                Error::InvalidHeader {
+                    field: "version",
+                    value: 255,
+                },
+                io::ErrorKind::InvalidData,
+            ),
+            (
+                Error::Primary(PrimaryError::OutOfBounds { pos: 10, len: 5 }),
+                io::ErrorKind::UnexpectedEof,
+            ),
+            (
+                Error::Primary(PrimaryError::from(io_error(io::ErrorKind::NotFound))),
+                io::ErrorKind::NotFound,
+            ),
+            (
+                Error::Primary(PrimaryError::ReadOnly),
+                io::ErrorKind::PermissionDenied,
+            ),
+            (
+                Error::Primary(PrimaryError::MisalignedRead { pos: 3 }),
+                io::ErrorKind::InvalidInput,
+            ),
+            (
+                Error::Primary(PrimaryError::ChecksumMismatch { pos: 3 }),
+                io::ErrorKind::InvalidData,
+            ),
+            (
+                Error::Primary(PrimaryError::Other("some error".into())),
+                io::ErrorKind::Other,
+            ),
+            (Error::ReadOnly, io::ErrorKind::PermissionDenied),
+            (Error::RateLimited, io::ErrorKind::WouldBlock),
+        ];
+        for (error, kind) in cases {
+            let message = error.to_string();
+            let converted = io::Error::from(error);
+            assert_eq!(converted.kind(), kind);
+            // The original error is kept.
+            assert_eq!(converted.to_string(), message);
+            assert!(converted.get_ref().unwrap().is::<Error>());
+        }
+    }
+
+    #[test]
+    fn categories() {
+        assert!(Error::IndexCorrupt.is_corruption());
+        assert!(Error::CorruptRecordList { offset: 6 }.is_corruption());
+        assert!(Error::InvalidHeader {
+            field: "buckets_bits",
+            value: 0
+        }
+        .is_corruption());
+        assert!(Error::CorruptHeader {
+            reason: "header is too short"
+        }
+        .is_corruption());
+        assert!(!Error::UnsupportedVersion(255).is_corruption());
+        assert!(!Error::ReadOnly.is_corruption());
+        assert!(!Error::from(io::Error::from(io::ErrorKind::NotFound)).is_corruption());
+
+        assert!(Error::from(io::Error::from(io::ErrorKind::NotFound)).is_not_found());
+        assert!(
+            Error::Primary(PrimaryError::from(io::Error::from(io::ErrorKind::NotFound)))
+                .is_not_found()
+        );
+        assert!(!Error::IndexCorrupt.is_not_found());
+        assert!(!Error::Primary(PrimaryError::OutOfBounds { pos: 10, len: 5 }).is_not_found());
+    }
+
+    #[test]
+    fn errors_are_send_sync() {
+        // It fails to compile if the bounds aren't met.
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<Error>();
+        assert_send_sync::<PrimaryError>();
+    }
+
+    #[test]
+    fn io_error_context() {
+        let source = || io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+        let error = Error::io(
+            "reading record list",
+            Path::new("/data/x.index"),
+            Some(123456),
+        )(source());
+        assert_eq!(
+            error.to_string(),
+            r#"IO error reading record list at offset 123456 in "/data/x.index": failed to fill whole buffer"#
+        );
+
+        let error = Error::io("flushing index", Path::new("/data/x.index"), None)(source());
+        assert_eq!(
+            error.to_string(),
+            r#"IO error flushing index in "/data/x.index": failed to fill whole buffer"#
+        );
+
+        let error = Error::from(source());
+        assert_eq!(error.to_string(), "IO error: failed to fill whole buffer");
+    }
 }
@@ -0,0 +1,364 @@
+//! Checks whether an index and its primary storage are healthy.
+//!
+//! [`check`] reads the index file directly, it doesn't need to be openable. The found problems
+//! are either fatal, i.e. the database returns wrong results or cannot be opened, or recoverable,
+//! e.g. data that is ignored or superseded.
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::index::{self, SIZE_PREFIX_SIZE};
+use crate::primary::{PrimaryError, PrimaryStorage};
+use crate::recordlist::{RecordList, BUCKET_PREFIX_SIZE};
+
+/// Controls which checks [`check`] runs.
+#[derive(Clone, Copy, Debug)]
+pub struct FsckOptions {
+    /// Whether the records are checked against the primary storage. It looks up every key, hence
+    /// it is the expensive part of the check.
+    pub check_primary: bool,
+}
+
+impl Default for FsckOptions {
+    fn default() -> Self {
+        Self {
+            check_primary: true,
+        }
+    }
+}
+
+/// How bad a problem is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Severity {
+    /// The database cannot be opened or returns wrong results.
+    Fatal,
+    /// The problem doesn't affect the live data, e.g. it's in data that was superseded.
+    Recoverable,
+}
+
+/// The kinds of problems [`check`] finds.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ProblemKind {
+    /// The header cannot be read or contains invalid values.
+    InvalidHeader { reason: String },
+    /// The index has an older version, it is migrated when it's opened. The record lists are not
+    /// checked.
+    OutdatedVersion { version: u8 },
+    /// The record list is truncated, its records are out of bounds or not sorted.
+    MalformedRecordList { reason: String },
+    /// The record list belongs to a bucket that doesn't exist.
+    BucketOutOfRange { bucket: u32 },
+    /// The record list was written with a different number of bits for the buckets.
+    WrongBucketsBits { buckets_bits: u8 },
+    /// The record points past the end of the primary storage.
+    DanglingOffset { file_offset: u64, primary_size: u64 },
+    /// The key in the primary storage doesn't match the record.
+    KeyMismatch { file_offset: u64 },
+    /// The key couldn't be read from the primary storage.
+    PrimaryError { file_offset: u64, reason: String },
+    /// There are bytes at the end of the index that are not a complete record list.
+    TrailingGarbage { len: u64 },
+}
+
+/// A single problem found by [`check`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FsckProblem {
+    pub severity: Severity,
+    /// The offset within the index file where the problem is, if it's a specific one.
+    pub index_offset: Option<u64>,
+    pub kind: ProblemKind,
+}
+
+/// The result of [`check`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FsckReport {
+    /// The version of the index, if the header could be read.
+    pub version: Option<u8>,
+    /// The number of bits used for the buckets, if the header could be read.
+    pub buckets_bits: Option<u8>,
+    /// The number of record lists in the index file, including superseded ones.
+    pub record_lists_checked: u64,
+    /// The number of record lists the buckets point to.
+    pub live_record_lists: u64,
+    /// The number of records in all record lists.
+    pub records_checked: u64,
+    /// The number of records that were checked against the primary storage.
+    pub primary_records_checked: u64,
+    pub problems: Vec<FsckProblem>,
+}
+
+impl FsckReport {
+    /// Returns true if no problems were found.
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Returns true if any of the problems is fatal.
+    pub fn has_fatal(&self) -> bool {
+        self.problems
+            .iter()
+            .any(|problem| problem.severity == Severity::Fatal)
+    }
+
+    fn push(&mut self, severity: Severity, index_offset: Option<u64>, kind: ProblemKind) {
+        self.problems.push(FsckProblem {
+            severity,
+            index_offset,
+            kind,
+        });
+    }
+}
+
+/// A problem with a record list whose severity depends on whether the record list is live.
+struct RecordListProblem {
+    bucket: u32,
+    index_offset: u64,
+    kind: ProblemKind,
+}
+
+/// Checks an index file and, depending on the options, whether it matches the primary storage.
+///
+/// It validates the header, the framing and ordering of every record list and detects trailing
+/// garbage. With [`FsckOptions::check_primary`] the records of the live record lists are looked
+/// up in the primary storage. Problems are part of the report, an error is only returned if the
+/// index file cannot be read.
+///
+/// The check against the primary storage assumes that the buckets were selected by the default
+/// [`crate::hasher::LeadingBytesHasher`].
+pub fn check<T, P>(index_path: T, primary: &P, options: FsckOptions) -> Result<FsckReport, Error>
+where
+    T: AsRef<Path>,
+    P: PrimaryStorage,
+{
+    let index_path = index_path.as_ref();
+    let mut report = FsckReport::default();
+    let mut file = File::open(index_path).map_err(Error::io("checking index", index_path, None))?;
+
+    let (header, header_size) = match index::read_header(&mut file) {
+        Ok(header_and_size) => header_and_size,
+        Err(error @ Error::CorruptHeader { .. }) | Err(error @ Error::UnsupportedVersion(_)) => {
+            report.push(
+                Severity::Fatal,
+                Some(0),
+                ProblemKind::InvalidHeader {
+                    reason: error.to_string(),
+                },
+            );
+            return Ok(report);
+        }
+        Err(error) => return Err(error),
+    };
+    report.version = Some(header.version);
+    report.buckets_bits = Some(header.buckets_bits);
+    if let Err(error) = header.validate() {
+        report.push(
+            Severity::Fatal,
+            Some(0),
+            ProblemKind::InvalidHeader {
+                reason: error.to_string(),
+            },
+        );
+        return Ok(report);
+    }
+    // Record lists before version 3 have a different layout.
+    if header.version < 3 {
+        report.push(
+            Severity::Recoverable,
+            None,
+            ProblemKind::OutdatedVersion {
+                version: header.version,
+            },
+        );
+        return Ok(report);
+    }
+
+    let file_size = file
+        .metadata()
+        .map_err(Error::io("checking index", index_path, None))?
+        .len();
+    let mut reader = BufReader::new(file);
+    let mut pos = u64::try_from(header_size).map_err(|_| Error::Arithmetic)?;
+    // The offset of the latest record list of every bucket, those are the live ones.
+    let mut buckets: HashMap<u32, u64> = HashMap::new();
+    let mut record_list_problems = Vec::new();
+    while pos < file_size {
+        let remaining = file_size - pos;
+        let mut size_buffer = [0; SIZE_PREFIX_SIZE];
+        if remaining < SIZE_PREFIX_SIZE as u64 {
+            report.push(
+                Severity::Recoverable,
+                Some(pos),
+                ProblemKind::TrailingGarbage { len: remaining },
+            );
+            break;
+        }
+        reader.read_exact(&mut size_buffer).map_err(Error::io(
+            "checking index",
+            index_path,
+            Some(pos),
+        ))?;
+        let size = u64::from(u32::from_le_bytes(size_buffer));
+        if size > remaining - SIZE_PREFIX_SIZE as u64 {
+            report.push(
+                Severity::Recoverable,
+                Some(pos),
+                ProblemKind::TrailingGarbage { len: remaining },
+            );
+            break;
+        }
+        let mut data = vec![0u8; usize::try_from(size).map_err(|_| Error::Arithmetic)?];
+        reader
+            .read_exact(&mut data)
+            .map_err(Error::io("checking index", index_path, Some(pos)))?;
+        report.record_lists_checked += 1;
+
+        if data.len() < BUCKET_PREFIX_SIZE {
+            report.push(
+                Severity::Fatal,
+                Some(pos),
+                ProblemKind::MalformedRecordList {
+                    reason: "The bucket prefix is missing.".to_string(),
+                },
+            );
+        } else {
+            let bucket = u32::from_le_bytes(data[..BUCKET_PREFIX_SIZE].try_into().unwrap());
+            if u64::from(bucket) >= 1u64 << header.buckets_bits {
+                report.push(
+                    Severity::Fatal,
+                    Some(pos),
+                    ProblemKind::BucketOutOfRange { bucket },
+                );
+            } else {
+                buckets.insert(bucket, pos);
+                match RecordList::validate(&data) {
+                    Ok(()) => {
+                        let records = RecordList::new(&data);
+                        report.records_checked += records.into_iter().count() as u64;
+                        if records.buckets_bits() != header.buckets_bits {
+                            record_list_problems.push(RecordListProblem {
+                                bucket,
+                                index_offset: pos,
+                                kind: ProblemKind::WrongBucketsBits {
+                                    buckets_bits: records.buckets_bits(),
+                                },
+                            });
+                        }
+                    }
+                    Err(error) => record_list_problems.push(RecordListProblem {
+                        bucket,
+                        index_offset: pos,
+                        kind: ProblemKind::MalformedRecordList {
+                            reason: error.to_string(),
+                        },
+                    }),
+                }
+            }
+        }
+        pos += SIZE_PREFIX_SIZE as u64 + size;
+    }
+    report.live_record_lists = buckets.len() as u64;
+
+    // Problems in superseded record lists don't matter, they are never read.
+    let mut broken_offsets = Vec::new();
+    for problem in record_list_problems {
+        let severity = if buckets.get(&problem.bucket) == Some(&problem.index_offset) {
+            broken_offsets.push(problem.index_offset);
+            Severity::Fatal
+        } else {
+            Severity::Recoverable
+        };
+        report.push(severity, Some(problem.index_offset), problem.kind);
+    }
+
+    if options.check_primary {
+        let mut live: Vec<(u32, u64)> = buckets
+            .into_iter()
+            .filter(|(_bucket, index_offset)| !broken_offsets.contains(index_offset))
+            .collect();
+        live.sort_by_key(|(_bucket, index_offset)| *index_offset);
+        let primary_size = primary.size()?;
+        let mut file = reader.into_inner();
+        for (bucket, index_offset) in live {
+            let mut size_buffer = [0; SIZE_PREFIX_SIZE];
+            file.seek(SeekFrom::Start(index_offset))
+                .and_then(|_| file.read_exact(&mut size_buffer))
+                .map_err(Error::io("checking index", index_path, Some(index_offset)))?;
+            let mut data = vec![0u8; u32::from_le_bytes(size_buffer) as usize];
+            file.read_exact(&mut data).map_err(Error::io(
+                "checking index",
+                index_path,
+                Some(index_offset),
+            ))?;
+            check_records(
+                &mut report,
+                primary,
+                primary_size,
+                header.buckets_bits,
+                bucket,
+                index_offset,
+                &RecordList::new(&data),
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+/// Checks that the records of a record list point to matching keys in the primary storage.
+fn check_records<P: PrimaryStorage>(
+    report: &mut FsckReport,
+    primary: &P,
+    primary_size: Option<u64>,
+    buckets_bits: u8,
+    bucket: u32,
+    index_offset: u64,
+    records: &RecordList,
+) {
+    for record in records {
+        report.primary_records_checked += 1;
+        let file_offset = record.file_offset;
+        if let Some(primary_size) = primary_size {
+            if file_offset >= primary_size {
+                report.push(
+                    Severity::Fatal,
+                    Some(index_offset),
+                    ProblemKind::DanglingOffset {
+                        file_offset,
+                        primary_size,
+                    },
+                );
+                continue;
+            }
+        }
+        let kind = match primary.get_index_key(file_offset) {
+            Ok(index_key) => {
+                let key_bucket = index_key
+                    .get(..4)
+                    .map(|prefix| u32::from_le_bytes(prefix.try_into().unwrap()))
+                    .map(|prefix| prefix & ((1u64 << buckets_bits) - 1) as u32);
+                if key_bucket == Some(bucket)
+                    && index::strip_bucket_prefix(&index_key, buckets_bits).starts_with(record.key)
+                {
+                    continue;
+                }
+                ProblemKind::KeyMismatch { file_offset }
+            }
+            Err(PrimaryError::OutOfBounds { len, .. }) => ProblemKind::DanglingOffset {
+                file_offset,
+                primary_size: len,
+            },
+            Err(error) => ProblemKind::PrimaryError {
+                file_offset,
+                reason: error.to_string(),
+            },
+        };
+        report.push(Severity::Fatal, Some(index_offset), kind);
+    }
+}
@@ -0,0 +1,45 @@
+//! The bodies of the fuzz targets in the `fuzz` directory.
+//!
+//! They are part of this crate, so that the regular tests can run them on seed inputs. It's only
+//! available with the `fuzz` feature enabled. Errors are expected for arbitrary input, only panics
+//! are bugs.
+use std::fs;
+use std::path::Path;
+
+use crate::index::Index;
+use crate::primary::PrimaryStorage;
+use crate::recordlist::RecordList;
+
+/// Parses the data as record list, it is only accessed if it is valid.
+pub fn record_list_new(data: &[u8]) {
+    if RecordList::validate(data).is_err() {
+        return;
+    }
+    let record_list = RecordList::new(data);
+    for record in &record_list {
+        let _ = record_list.get(record.key);
+    }
+}
+
+/// Writes the data to the given path and opens it as index.
+pub fn index_open<P: PrimaryStorage, const N: u8>(path: &Path, data: &[u8], primary: P) {
+    fs::write(path, data).expect("writing the index must succeed");
+    let _ = Index::<_, N>::open(path, primary);
+}
+
+/// Prefixes the payload with its size as unsigned LEB128, like the frames of a CAR file.
+pub fn size_prefixed(payload: &[u8]) -> Vec<u8> {
+    let mut size = payload.len();
+    let mut data = Vec::with_capacity(payload.len() + 10);
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            data.push(byte);
+            break;
+        }
+        data.push(byte | 0x80);
+    }
+    data.extend_from_slice(payload);
+    data
+}
@@ -0,0 +1,90 @@
+//! Selects the bucket a key belongs to.
+//!
+//! The default [`LeadingBytesHasher`] uses the first bytes of the key, which is ideal for keys that
+//! are cryptographic hashes. Keys whose leading bytes are not uniformly distributed can be spread
+//! over the buckets with a [`BucketHasher`] that hashes the whole key, e.g. the
+//! `XxHashBucketHasher` that is available with the `xxhash` feature.
+//!
+//! The hasher isn't stored in the index, an index needs to be opened with the same hasher it was
+//! created with.
+
+/// Determines the bucket of a key, see [`crate::index::Index`].
+pub trait BucketHasher {
+    /// Returns the bucket of the key, it's smaller than `2^bits`.
+    ///
+    /// The key is at least [`crate::index::DEFAULT_MIN_KEY_LENGTH`] bytes long.
+    fn bucket(key: &[u8], bits: u8) -> u32;
+
+    /// Returns the number of leading bytes that are the same for all keys of a bucket.
+    ///
+    /// Those bytes aren't stored in the index. It's only safe to strip bytes that are fully
+    /// determined by the bucket, hence the default is none.
+    fn stripped_prefix_len(_bits: u8) -> usize {
+        0
+    }
+}
+
+/// Returns the bits that are used for the buckets.
+fn mask(bits: u8) -> u32 {
+    ((1u64 << bits) - 1) as u32
+}
+
+/// Interprets the first 4 bytes of the key as little-endian integer and uses the lower bits as
+/// the bucket.
+///
+/// The bytes that are fully covered by the bits are not stored in the index. E.g. with 24 bits
+/// the first 3 bytes are stripped, with 19 bits only 2 bytes are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LeadingBytesHasher;
+
+impl BucketHasher for LeadingBytesHasher {
+    fn bucket(key: &[u8], bits: u8) -> u32 {
+        let prefix = u32::from_le_bytes([key[0], key[1], key[2], key[3]]);
+        prefix & mask(bits)
+    }
+
+    fn stripped_prefix_len(bits: u8) -> usize {
+        usize::from(bits / 8)
+    }
+}
+
+/// Hashes the whole key with XXH3 and uses the lower bits of the hash as the bucket.
+#[cfg(feature = "xxhash")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XxHashBucketHasher;
+
+#[cfg(feature = "xxhash")]
+impl BucketHasher for XxHashBucketHasher {
+    fn bucket(key: &[u8], bits: u8) -> u32 {
+        xxhash::xxh3::xxh3_64(key) as u32 & mask(bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BucketHasher, LeadingBytesHasher};
+
+    #[test]
+    fn leading_bytes_hasher() {
+        let key = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05];
+        assert_eq!(LeadingBytesHasher::bucket(&key, 24), 0x020100);
+        assert_eq!(LeadingBytesHasher::bucket(&key, 8), 0);
+        assert_eq!(LeadingBytesHasher::bucket(&key, 32), 0x03020100);
+        assert_eq!(LeadingBytesHasher::stripped_prefix_len(24), 3);
+        assert_eq!(LeadingBytesHasher::stripped_prefix_len(19), 2);
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn xxhash_bucket_hasher() {
+        use super::XxHashBucketHasher;
+
+        // Keys with the same leading bytes end up in different buckets.
+        let buckets: Vec<u32> = (0..16u8)
+            .map(|byte| XxHashBucketHasher::bucket(&[0, 0, 0, 0, byte], 8))
+            .collect();
+        assert!(buckets.iter().all(|bucket| *bucket < 256));
+        assert!(buckets.iter().any(|bucket| *bucket != buckets[0]));
+        assert_eq!(XxHashBucketHasher::stripped_prefix_len(24), 0);
+    }
+}
@@ -8,30 +8,50 @@
 //!     |       4 bytes      | Variable size |         4 bytes        |  Variable size | … |
 //!     | Size of the header |   [`Header`]  | Size of the Recordlist |   Recordlist   | … |
 //! ```
-use std::cell::RefCell;
 use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::{TryFrom, TryInto};
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard};
+use std::time::Instant;
 
 use log::{debug, warn};
 
 use crate::buckets::Buckets;
+use crate::bufferpool::BufferPool;
 use crate::error::Error;
-use crate::primary::PrimaryStorage;
-use crate::recordlist::{self, RecordList, BUCKET_PREFIX_SIZE};
+use crate::hasher::{BucketHasher, LeadingBytesHasher};
+use crate::metrics::Metrics;
+use crate::primary::{read_exact_at, PrimaryStorage};
+use crate::recordlist::{self, Record, RecordList, BUCKET_PREFIX_SIZE, RECORDLIST_HEADER_SIZE};
+use crate::sharedindex::SharedIndex;
+use crate::syncer::{BackgroundSyncer, SyncPolicy};
 
-pub const INDEX_VERSION: u8 = 2;
+/// Version 3 added the number of bits used for the buckets to every record list.
+/// Version 4 added the minimum key length to the header.
+pub const INDEX_VERSION: u8 = 4;
+/// The oldest version that can still be opened, it is migrated to [`INDEX_VERSION`].
+pub const OLDEST_INDEX_VERSION: u8 = 2;
+/// The maximum size of the header, anything bigger is considered corrupt.
+pub const MAX_HEADER_SIZE: usize = 4096;
 /// Number of bytes used for the size prefix of a record list.
 pub const SIZE_PREFIX_SIZE: usize = 4;
+/// The minimum key length of new indexes and the one of indexes older than version 4. It's also
+/// the smallest one possible, as the first 4 bytes determine the bucket.
+pub const DEFAULT_MIN_KEY_LENGTH: u8 = 4;
+/// The first line of a dump starts with it, see [`Index::dump`].
+pub const DUMP_HEADER: &str = "storethehash-index-dump";
 
 /// Remove the prefix that is used for the bucket.
 ///
 /// The first bits of a key are used to determine the bucket to put the key into. This function
 /// removes those bytes. Only bytes that are fully covered by the bits are removed. E.g. a bit
 /// value of 19 will remove only 2 bytes, whereas 24 bits removes 3 bytes.
-fn strip_bucket_prefix(key: &[u8], bits: u8) -> &[u8] {
+pub(crate) fn strip_bucket_prefix(key: &[u8], bits: u8) -> &[u8] {
     &key[usize::from(bits / 8)..]
 }
 
@@ -39,15 +59,19 @@ fn strip_bucket_prefix(key: &[u8], bits: u8) -> &[u8] {
 ///
 /// The serialized header is:
 /// ```text
-///     |         1 byte        |                1 byte               |
-///     | Version of the header | Number of bits used for the buckets |
+///     |         1 byte        |                1 byte               |        1 byte      |
+///     | Version of the header | Number of bits used for the buckets | Minimum key length |
 /// ```
+///
+/// Headers older than version 4 don't contain the minimum key length.
 #[derive(Debug)]
 pub struct Header {
     /// A version number in case we change the header
     pub version: u8,
     /// The number of bits used to determine the in-memory buckets
     pub buckets_bits: u8,
+    /// Keys that are shorter than this number of bytes are rejected.
+    pub min_key_length: u8,
 }
 
 impl Header {
@@ -55,34 +79,286 @@ impl Header {
         Self {
             version: INDEX_VERSION,
             buckets_bits,
+            min_key_length: DEFAULT_MIN_KEY_LENGTH,
+        }
+    }
+
+    /// Checks that the fields have values this version of the index can deal with.
+    ///
+    /// The version must be between [`OLDEST_INDEX_VERSION`] and [`INDEX_VERSION`], the number
+    /// of bits used for the buckets must be between 1 and 32 and the minimum key length must be
+    /// at least [`DEFAULT_MIN_KEY_LENGTH`].
+    pub fn validate(&self) -> Result<(), Error> {
+        check_version(self.version)?;
+        if self.buckets_bits == 0 || self.buckets_bits > 32 {
+            return Err(Error::InvalidHeader {
+                field: "buckets_bits",
+                value: self.buckets_bits,
+            });
+        }
+        if self.min_key_length < DEFAULT_MIN_KEY_LENGTH {
+            return Err(Error::InvalidHeader {
+                field: "min_key_length",
+                value: self.min_key_length,
+            });
         }
+        Ok(())
     }
 }
 
 impl From<Header> for Vec<u8> {
     fn from(header: Header) -> Self {
-        vec![header.version, header.buckets_bits]
+        if header.version < 4 {
+            vec![header.version, header.buckets_bits]
+        } else {
+            vec![header.version, header.buckets_bits, header.min_key_length]
+        }
     }
 }
 
-impl From<&[u8]> for Header {
-    fn from(bytes: &[u8]) -> Self {
-        Self {
-            version: bytes[0],
-            buckets_bits: bytes[1],
+/// Parses a serialized header, bytes after the known fields are ignored.
+impl TryFrom<&[u8]> for Header {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let too_short = Error::CorruptHeader {
+            reason: "header is too short",
+        };
+        match bytes {
+            [version, buckets_bits, rest @ ..] => {
+                check_version(*version)?;
+                let min_key_length = match (version, rest) {
+                    (2..=3, _) => DEFAULT_MIN_KEY_LENGTH,
+                    (_, [min_key_length, ..]) => *min_key_length,
+                    _ => return Err(too_short),
+                };
+                Ok(Self {
+                    version: *version,
+                    buckets_bits: *buckets_bits,
+                    min_key_length,
+                })
+            }
+            _ => Err(too_short),
         }
     }
 }
 
+/// Returns an error if an index with the given version cannot be opened.
+fn check_version(version: u8) -> Result<(), Error> {
+    if (OLDEST_INDEX_VERSION..=INDEX_VERSION).contains(&version) {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedVersion(version))
+    }
+}
+
+/// Information about a single [`Index::put`] call, which is passed on to the put observer.
 #[derive(Debug)]
-pub struct Index<P: PrimaryStorage, const N: u8> {
-    buckets: RefCell<Buckets<N>>,
-    reader: File,
-    writer: RefCell<BufWriter<File>>,
+pub struct PutEvent<'a> {
+    /// The bucket the key was put into.
+    pub bucket: usize,
+    /// The key that was put.
+    pub key: &'a [u8],
+    /// The file offset in the primary storage.
+    pub file_offset: u64,
+    /// Whether a new record was inserted. It's `false` if the key already existed.
+    pub was_insert: bool,
+    /// The byte size of the records of the bucket before the put.
+    pub record_list_size_before: usize,
+    /// The byte size of the records of the bucket after the put.
+    pub record_list_size_after: usize,
+}
+
+/// A callback that is called at the end of every successful [`Index::put`].
+pub type PutObserver = Arc<dyn Fn(PutEvent) + Send + Sync>;
+
+/// The index maps keys to positions in the primary storage.
+///
+/// `N` is the number of bits used for the buckets, `H` selects the bucket of a key, see
+/// [`BucketHasher`]. `R` is what the index is stored in, a file unless it was opened with
+/// [`Index::from_reader`].
+///
+/// If the primary storage is `Sync`, so is the index. Reads of a file-backed index never use the
+/// cursor of the file, which belongs to the writer. Gets read at the position of a record list,
+/// scans like [`Index::used_buckets`] keep their own position and stop at the size the index had
+/// when they started. Hence reads can run concurrently with each other and with puts, see
+/// [`Index::into_shared`]. Indexes in other storages are read through the writer, while holding
+/// its lock. Changes of the record lists, e.g. puts and deletes, are serialized.
+pub struct Index<
+    P: PrimaryStorage,
+    const N: u8,
+    H: BucketHasher = LeadingBytesHasher,
+    R: Read + Write + Seek = File,
+> {
+    buckets: RwLock<Buckets<N>>,
+    /// A clone of the index file, so that file-backed indexes are read without taking the lock of
+    /// the writer. It's `None` for other storages, see [`Index::from_reader`].
+    reader: Option<File>,
+    writer: Mutex<BufWriter<R>>,
+    /// Held from reading the record list of a bucket until the bucket points to its changed copy,
+    /// so that concurrent changes of the same bucket don't lose each other's records. It's
+    /// separate from the writer, as indexes that aren't files are read through the writer.
+    update_lock: Mutex<()>,
+    put_observer: Option<PutObserver>,
+    /// If set, the puts and the writes are reported to it.
+    metrics: Option<Arc<dyn Metrics>>,
+    /// A warning is logged when a put results in a record list that is bigger than this number of
+    /// bytes.
+    warn_threshold_bytes: Option<usize>,
+    /// The bucket and the position within its record list of the last put, as long as the record
+    /// list wasn't changed since. It's used to validate the hints of [`Index::put_sorted_hint`].
+    last_put_pos: Mutex<Option<(u32, usize)>>,
+    /// Keys that are shorter are rejected, it's stored in the header.
+    min_key_length: usize,
+    /// If set, the written record lists are reported to it, see
+    /// [`IndexBuilder::with_background_sync`].
+    syncer: Option<BackgroundSyncer>,
+    /// The buffers the record lists are read into by [`Index::get`].
+    buffers: BufferPool,
+    /// The path of the index file, it's used for error messages. It's `None` for other storages.
+    path: Option<PathBuf>,
+    hasher: PhantomData<H>,
     pub primary: P,
 }
 
-impl<P: PrimaryStorage, const N: u8> Index<P, N> {
+impl<P, const N: u8, H, R> fmt::Debug for Index<P, N, H, R>
+where
+    P: PrimaryStorage + fmt::Debug,
+    H: BucketHasher,
+    R: Read + Write + Seek + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Index")
+            .field("buckets", &self.buckets)
+            .field("reader", &self.reader)
+            .field("writer", &self.writer)
+            .field("update_lock", &self.update_lock)
+            .field("put_observer", &self.put_observer.is_some())
+            .field("metrics", &self.metrics.is_some())
+            .field("warn_threshold_bytes", &self.warn_threshold_bytes)
+            .field("last_put_pos", &self.last_put_pos)
+            .field("min_key_length", &self.min_key_length)
+            .field("syncer", &self.syncer)
+            .field("buffers", &self.buffers)
+            .field("path", &self.path)
+            .field("primary", &self.primary)
+            .finish()
+    }
+}
+
+/// Opens an [`Index`] with settings that go beyond [`Index::open`] and [`Index::open_read_only`].
+pub struct IndexBuilder<P: PrimaryStorage, const N: u8, H: BucketHasher = LeadingBytesHasher> {
+    path: PathBuf,
+    primary: P,
+    read_only: bool,
+    warn_threshold_bytes: Option<usize>,
+    min_key_length: usize,
+    metrics: Option<Arc<dyn Metrics>>,
+    sync_policy: Option<SyncPolicy>,
+    hasher: PhantomData<H>,
+}
+
+impl<P: PrimaryStorage + fmt::Debug, const N: u8, H: BucketHasher> fmt::Debug
+    for IndexBuilder<P, N, H>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndexBuilder")
+            .field("path", &self.path)
+            .field("primary", &self.primary)
+            .field("read_only", &self.read_only)
+            .field("warn_threshold_bytes", &self.warn_threshold_bytes)
+            .field("min_key_length", &self.min_key_length)
+            .field("metrics", &self.metrics.is_some())
+            .field("sync_policy", &self.sync_policy)
+            .finish()
+    }
+}
+
+impl<P: PrimaryStorage, const N: u8, H: BucketHasher> IndexBuilder<P, N, H> {
+    pub fn new<T>(path: T, primary: P) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            primary,
+            read_only: false,
+            warn_threshold_bytes: None,
+            min_key_length: usize::from(DEFAULT_MIN_KEY_LENGTH),
+            metrics: None,
+            sync_policy: None,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Opens the index read-only, see [`Index::open_read_only`].
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Logs a warning for big record lists, see [`Index::set_warn_threshold`].
+    pub fn with_warn_threshold(mut self, bytes: usize) -> Self {
+        self.warn_threshold_bytes = Some(bytes);
+        self
+    }
+
+    /// Rejects keys that are shorter than the given number of bytes, the default is
+    /// [`DEFAULT_MIN_KEY_LENGTH`].
+    ///
+    /// It's stored in the header when a new index is created. An existing index keeps the
+    /// minimum key length it was created with, for indexes older than version 4 it's the default.
+    /// It panics if the length is smaller than the default or bigger than 255.
+    pub fn with_min_key_length(mut self, min_key_length: usize) -> Self {
+        assert!(
+            (usize::from(DEFAULT_MIN_KEY_LENGTH)..=255).contains(&min_key_length),
+            "Minimum key length must be between {} and 255 bytes",
+            DEFAULT_MIN_KEY_LENGTH
+        );
+        self.min_key_length = min_key_length;
+        self
+    }
+
+    /// Reports the puts and the writes, see [`Index::set_metrics`].
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Syncs the index file from a background thread, so that the record lists a crash can lose
+    /// are bounded by the policy, see [`BackgroundSyncer`].
+    ///
+    /// The thread does a final sync when the index is dropped. It's ignored for a read-only
+    /// index.
+    pub fn with_background_sync(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = Some(policy);
+        self
+    }
+
+    pub fn open(self) -> Result<Index<P, N, H>, Error> {
+        let min_key_length =
+            u8::try_from(self.min_key_length).expect("Minimum key length was checked");
+        let mut index =
+            Index::open_with_mode(&self.path, self.primary, self.read_only, min_key_length)?;
+        index.warn_threshold_bytes = self.warn_threshold_bytes;
+        index.metrics = self.metrics;
+        if let (Some(policy), false) = (self.sync_policy, self.read_only) {
+            let file = index.writer().get_ref().try_clone().map_err(Error::io(
+                "starting background sync",
+                &self.path,
+                None,
+            ))?;
+            index.syncer = Some(BackgroundSyncer::new(file, policy).map_err(Error::io(
+                "starting background sync",
+                &self.path,
+                None,
+            ))?);
+        }
+        Ok(index)
+    }
+}
+
+impl<P: PrimaryStorage, const N: u8, H: BucketHasher> Index<P, N, H> {
     /// Open and index.
     ///
     /// It is created if there is no existing index at that path.
@@ -90,127 +366,423 @@ impl<P: PrimaryStorage, const N: u8> Index<P, N> {
     where
         T: AsRef<Path>,
     {
-        let index_path = path.as_ref();
+        Self::open_with_mode(path.as_ref(), primary, false, DEFAULT_MIN_KEY_LENGTH)
+    }
+
+    /// Open an existing index without write access.
+    ///
+    /// This works even if the file is read-only on disk. Writing to the index returns an error.
+    /// An index that needs to be upgraded to the current version cannot be opened read-only.
+    pub fn open_read_only<T>(path: T, primary: P) -> Result<Self, Error>
+    where
+        T: AsRef<Path>,
+    {
+        Self::open_with_mode(path.as_ref(), primary, true, DEFAULT_MIN_KEY_LENGTH)
+    }
+
+    /// The minimum key length is only used if a new index is created.
+    fn open_with_mode(
+        index_path: &Path,
+        primary: P,
+        read_only: bool,
+        min_key_length: u8,
+    ) -> Result<Self, Error> {
         let mut options = OpenOptions::new();
-        let options = options.read(true).append(true);
+        let options = options.read(true).append(!read_only);
         debug!("Opening index file: {:?}", &index_path);
-        let (index_file, buckets) = match options.open(index_path) {
+        let (index_file, buckets, min_key_length) = match options.open(index_path) {
             // If an existing file is opened, recreate the in-memory [`Buckets']
             Ok(mut file) => {
                 // Read the header to determine whether the index was created with a different bit
                 // size for the buckets
-                let (header, bytes_read) = read_header(&mut file)?;
+                let (mut header, mut bytes_read) = read_index_header(&mut file, index_path)?;
+                header.validate()?;
                 if header.buckets_bits != N {
                     return Err(Error::IndexWrongBitSize(header.buckets_bits, N));
                 }
 
-                debug!("Initalize buckets.");
-                // Fill up the in-memory buckets with the data from the index
-                let mut buckets = Buckets::<N>::new();
-                // TODO vmx 2020-11-30: Find if there's a better way than cloning the file. Perhaps
-                // a BufReader should be used instead of File for this whole module?
-                let mut buffered = BufReader::new(file.try_clone()?);
-                for entry in IndexIter::new(&mut buffered, bytes_read) {
-                    match entry {
-                        Ok((data, pos)) => {
-                            let bucket_prefix = u32::from_le_bytes(
-                                data[..BUCKET_PREFIX_SIZE]
-                                    .try_into()
-                                    .expect("Slice is guaranteed to be exactly 4 bytes"),
-                            );
-                            let bucket =
-                                usize::try_from(bucket_prefix).expect(">=32-bit platform needed");
-                            buckets
-                                .put(bucket, pos)
-                                .expect("Cannot be out of bounds as it was materialized before");
-                        }
-                        // The file is corrupt. Though it's not a problem, just take the data we
-                        // are able to use and move on.
-                        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
-                            //return Err(Error::IndexCorrupt);
-                            warn!("Index file is corrupt.");
-                            file.seek(SeekFrom::End(0))?;
-                            break;
-                        }
-                        Err(error) => return Err(error.into()),
+                // Older indexes are upgraded to the current format first.
+                if header.version == 2 {
+                    if read_only {
+                        return Err(Error::ReadOnly);
                     }
+                    drop(file);
+                    migrate_v2(index_path)?;
+                    file = options.open(index_path).map_err(Error::io(
+                        "opening index",
+                        index_path,
+                        None,
+                    ))?;
+                    let (migrated_header, migrated_bytes_read) =
+                        read_index_header(&mut file, index_path)?;
+                    header = migrated_header;
+                    bytes_read = migrated_bytes_read;
                 }
+                debug!("Index version is {}.", header.version);
 
+                debug!("Initalize buckets.");
+                // Fill up the in-memory buckets with the data from the index
+                let buckets = load_buckets(&mut file, bytes_read)
+                    .map_err(|error| error.with_io_context("loading buckets", index_path, None))?;
                 debug!("Intialize buckets done.");
 
-                (file, buckets)
+                (file, buckets, header.min_key_length)
             }
             // If the file doesn't exist yet create it with the correct header
-            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            Err(error) if error.kind() == io::ErrorKind::NotFound && !read_only => {
                 debug!("Create new index.");
-                let header: Vec<u8> = Header::new(N).into();
-                let header_size: [u8; 4] = u32::try_from(header.len())
-                    .expect("A header cannot be bigger than 2^32.")
-                    .to_le_bytes();
+                let mut file = options.create(true).open(index_path).map_err(Error::io(
+                    "creating index",
+                    index_path,
+                    None,
+                ))?;
+                let header = Header {
+                    min_key_length,
+                    ..Header::new(N)
+                };
+                write_index_header(&mut file, index_path, header)?;
+                (file, Buckets::<N>::new(), min_key_length)
+            }
+            Err(error) => return Err(Error::io("opening index", index_path, None)(error)),
+        };
+
+        let reader = index_file.try_clone()?;
+        let mut index = Self::from_parts(index_file, buckets, min_key_length, primary);
+        index.reader = Some(reader);
+        index.path = Some(index_path.to_path_buf());
+        Ok(index)
+    }
 
-                let mut file = options.create(true).open(index_path)?;
-                file.write_all(&header_size)?;
-                file.write_all(&header)?;
-                file.sync_data()?;
-                (file, Buckets::<N>::new())
+    /// Creates a new index at the given path from a dump that was created by [`Index::dump`].
+    ///
+    /// The dump contains the keys the way they are stored in the index, hence the primary storage
+    /// isn't accessed and the restored index behaves exactly like the dumped one, it also has the
+    /// same minimum key length. The dump needs to be created with the same number of bits for the
+    /// buckets. It fails if there is already a file at that path, if the restore fails the
+    /// partially restored index is left behind.
+    pub fn restore<R, T>(reader: R, index_path: T, primary: P) -> Result<Self, Error>
+    where
+        R: BufRead,
+        T: AsRef<Path>,
+    {
+        let index_path = index_path.as_ref();
+        if index_path.exists() {
+            return Err(Error::io("restoring index", index_path, None)(
+                io::Error::new(io::ErrorKind::AlreadyExists, "Index already exists."),
+            ));
+        }
+
+        let mut lines = reader.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let mut header_fields = header.split(' ');
+        match (header_fields.next(), header_fields.next()) {
+            (Some(DUMP_HEADER), Some(buckets_bits)) => match buckets_bits.parse::<u8>() {
+                Ok(buckets_bits) if buckets_bits == N => {}
+                Ok(buckets_bits) => return Err(Error::IndexWrongBitSize(buckets_bits, N)),
+                Err(_) => {
+                    return Err(Error::InvalidDump {
+                        line: 1,
+                        reason: "invalid number of bits for the buckets",
+                    })
+                }
+            },
+            _ => {
+                return Err(Error::InvalidDump {
+                    line: 1,
+                    reason: "header is missing",
+                })
+            }
+        }
+        // Dumps that were created before the minimum key length was part of the header are from
+        // indexes with the default one.
+        let min_key_length = match header_fields.next().map(str::parse::<u8>) {
+            None => DEFAULT_MIN_KEY_LENGTH,
+            Some(Ok(min_key_length)) if min_key_length >= DEFAULT_MIN_KEY_LENGTH => min_key_length,
+            Some(_) => {
+                return Err(Error::InvalidDump {
+                    line: 1,
+                    reason: "invalid minimum key length",
+                })
             }
-            Err(error) => return Err(error.into()),
         };
+        if header_fields.next().is_some() {
+            return Err(Error::InvalidDump {
+                line: 1,
+                reason: "header has too many fields",
+            });
+        }
 
-        Ok(Self {
-            buckets: RefCell::new(buckets),
-            reader: index_file.try_clone()?,
-            writer: RefCell::new(BufWriter::new(index_file)),
+        let index = IndexBuilder::<P, N, H>::new(index_path, primary)
+            .with_min_key_length(usize::from(min_key_length))
+            .open()?;
+        // The record list of a bucket is written once all of its records were read.
+        let mut current: Option<(u32, Vec<u8>, Vec<u8>)> = None;
+        for (line_number, line) in (2..).zip(lines) {
+            let invalid = |reason| Error::InvalidDump {
+                line: line_number,
+                reason,
+            };
+            let (bucket, key, file_offset) =
+                parse_dump_line(&line?).ok_or_else(|| invalid("malformed record"))?;
+            if bucket >= 1 << N {
+                return Err(invalid("bucket is out of range"));
+            }
+            current = match current {
+                Some((current_bucket, last_key, mut data)) if current_bucket == bucket => {
+                    if key <= last_key {
+                        return Err(invalid("records are not sorted"));
+                    }
+                    recordlist::extend_with_offset_and_key(&mut data, &key, file_offset);
+                    Some((bucket, key, data))
+                }
+                Some((current_bucket, _, _)) if current_bucket > bucket => {
+                    return Err(invalid("records are not sorted"));
+                }
+                previous => {
+                    if let Some((previous_bucket, _, data)) = previous {
+                        index.write_record_list(previous_bucket, &data)?;
+                    }
+                    let data = recordlist::encode_offset_and_key(&key, file_offset);
+                    Some((bucket, key, data))
+                }
+            };
+        }
+        if let Some((bucket, _, data)) = current {
+            index.write_record_list(bucket, &data)?;
+        }
+        index.flush()?;
+        Ok(index)
+    }
+
+    /// Wraps the index, so that it can be shared between threads, see [`SharedIndex`].
+    pub fn into_shared(self) -> SharedIndex<P, N, H> {
+        SharedIndex::new(self)
+    }
+}
+
+impl<P: PrimaryStorage, const N: u8, H: BucketHasher, R: Read + Write + Seek> Index<P, N, H, R> {
+    /// Opens an index that is stored in the given storage instead of a file, e.g. in a
+    /// `Cursor<Vec<u8>>`.
+    ///
+    /// An empty storage is initialized as new index. Otherwise the in-memory buckets are
+    /// recreated from it, like [`Index::open`] does with an existing file. Indexes that need to
+    /// be migrated to the current version cannot be opened this way, they return
+    /// [`Error::UnsupportedVersion`]. Unlike file-backed indexes, every read takes the lock that
+    /// is also used for the writes, hence concurrent gets are serialized.
+    pub fn from_reader(mut reader: R, primary: P) -> Result<Self, Error> {
+        let (buckets, min_key_length) = if reader.seek(SeekFrom::End(0))? == 0 {
+            debug!("Create new index.");
+            let header = Header {
+                min_key_length: DEFAULT_MIN_KEY_LENGTH,
+                ..Header::new(N)
+            };
+            reader.write_all(&header_bytes(header)?)?;
+            reader.flush()?;
+            (Buckets::<N>::new(), DEFAULT_MIN_KEY_LENGTH)
+        } else {
+            reader.seek(SeekFrom::Start(0))?;
+            let (header, bytes_read) = read_header(&mut reader)?;
+            header.validate()?;
+            if header.buckets_bits != N {
+                return Err(Error::IndexWrongBitSize(header.buckets_bits, N));
+            }
+            // Only files can be migrated, see `migrate_v2`.
+            if header.version == 2 {
+                return Err(Error::UnsupportedVersion(header.version));
+            }
+            let buckets = load_buckets(&mut reader, bytes_read)?;
+            (buckets, header.min_key_length)
+        };
+        Ok(Self::from_parts(reader, buckets, min_key_length, primary))
+    }
+
+    /// Creates an index that doesn't have a path or a clone of the file (yet).
+    fn from_parts(writer: R, buckets: Buckets<N>, min_key_length: u8, primary: P) -> Self {
+        Self {
+            buckets: RwLock::new(buckets),
+            reader: None,
+            writer: Mutex::new(BufWriter::new(writer)),
+            put_observer: None,
+            metrics: None,
+            warn_threshold_bytes: None,
+            update_lock: Mutex::new(()),
+            last_put_pos: Mutex::new(None),
+            min_key_length: usize::from(min_key_length),
+            syncer: None,
+            buffers: BufferPool::new(),
+            path: None,
+            hasher: PhantomData,
             primary,
-        })
+        }
+    }
+
+    /// Set a callback that is called at the end of every successful [`Index::put`].
+    ///
+    /// This can be used to e.g. record metrics about the bucket saturation.
+    pub fn set_put_observer(&mut self, observer: Box<dyn Fn(PutEvent) + Send + Sync>) {
+        self.put_observer = Some(Arc::from(observer));
+    }
+
+    /// Report every put, every written record list and the gets of [`Index::get_verified`] to
+    /// the given metrics, see [`Metrics`].
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Log a warning whenever a put results in a record list that is bigger than the given number
+    /// of bytes.
+    ///
+    /// Such big record lists make puts and gets slow, they are a sign that the number of bits used
+    /// for the buckets is too small for the number of keys.
+    pub fn set_warn_threshold(&mut self, bytes: usize) {
+        self.warn_threshold_bytes = Some(bytes);
+    }
+
+    /// Calls the put observer and reports the put that started at the given time to the metrics,
+    /// if there are any.
+    fn notify_put(&self, start: Option<Instant>, event: PutEvent) {
+        if let (Some(metrics), Some(start)) = (&self.metrics, start) {
+            metrics.record_put(start.elapsed(), event.record_list_size_after);
+        }
+        if let Some(observer) = &self.put_observer {
+            observer(event);
+        }
+    }
+
+    /// Returns the minimum length of the keys, see [`IndexBuilder::with_min_key_length`].
+    pub fn min_key_length(&self) -> usize {
+        self.min_key_length
     }
 
     /// Put a key together with a file offset into the index.
     ///
-    /// The key needs to be a cryptographically secure hash and at least 4 bytes long.
-    pub fn put(&self, key: &[u8], file_offset: u64) -> Result<(), Error> {
-        assert!(key.len() >= 4, "Key must be at least 4 bytes long");
+    /// The key needs to be a cryptographically secure hash and at least
+    /// [`Index::min_key_length`] bytes long, else it panics.
+    ///
+    /// Returns the position of the record list that was appended to the index for the bucket of
+    /// the key, see [`Index::bucket_records`]. If the key already exists nothing is appended, then
+    /// it's the position of the existing record list.
+    pub fn put(&self, key: &[u8], file_offset: u64) -> Result<u64, Error> {
+        let (_key_pos, recordlist_pos) = self.put_with_hint(key, file_offset, None)?;
+        Ok(recordlist_pos)
+    }
 
-        // Determine which bucket a key falls into. Use the first few bytes of they key for it and
-        // interpret them as a little-endian integer.
-        let prefix_bytes: [u8; 4] = key[0..4].try_into().unwrap();
-        let prefix = u32::from_le_bytes(prefix_bytes);
-        let leading_bits = (1 << N) - 1;
-        let bucket: u32 = prefix & leading_bits;
+    /// Same as [`Index::put`], but the search for the insertion point can start at a position
+    /// that was returned by the previous call.
+    ///
+    /// It returns the position of the key within the record list of its bucket. When keys are
+    /// inserted in sorted order (sorted by bucket first), passing that position on to the next
+    /// call skips the records that are known to be smaller than the next key. The hint is ignored
+    /// if the record list was changed since, if the key is in a different bucket or if the key
+    /// isn't bigger than the one at the hinted position, so passing a wrong hint only costs
+    /// performance.
+    pub fn put_sorted_hint(
+        &self,
+        key: &[u8],
+        file_offset: u64,
+        hint_pos: Option<usize>,
+    ) -> Result<usize, Error> {
+        let (key_pos, _recordlist_pos) = self.put_with_hint(key, file_offset, hint_pos)?;
+        Ok(key_pos)
+    }
 
-        // Get the index file offset of the record list the key is in.
-        let index_offset = self.buckets.borrow().get(bucket as usize)?;
+    /// Removes the leading bytes that are the same for all keys of a bucket, see
+    /// [`BucketHasher::stripped_prefix_len`].
+    fn strip_bucket_prefix(key: &[u8]) -> &[u8] {
+        &key[H::stripped_prefix_len(N)..]
+    }
+
+    /// Panics if the key is shorter than the minimum key length.
+    fn check_key_length(&self, key: &[u8]) {
+        assert!(
+            key.len() >= self.min_key_length,
+            "Key must be at least {} bytes long",
+            self.min_key_length
+        );
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "Index::put",
+            level = "trace",
+            skip(self, key, hint_pos),
+            fields(bucket = tracing::field::Empty, record_list_size = tracing::field::Empty)
+        )
+    )]
+    /// Returns the position of the key within its record list and the position of the record list
+    /// within the index.
+    fn put_with_hint(
+        &self,
+        key: &[u8],
+        file_offset: u64,
+        hint_pos: Option<usize>,
+    ) -> Result<(usize, u64), Error> {
+        self.check_key_length(key);
+        // Time is only measured if it's reported.
+        let start = self.metrics.as_ref().map(|_| Instant::now());
+        let _update = lock(&self.update_lock);
 
-        // The key doesn't need the prefix that was used to find the right bucket. For simplicty
-        // only full bytes are trimmed off.
-        let index_key = strip_bucket_prefix(&key, N);
+        // Determine which bucket a key falls into.
+        let bucket = H::bucket(key, N);
+        record_span!(bucket = bucket);
 
-        // Reading and seeking needs mutable file accesss.
-        let mut writer = self.writer.borrow_mut();
-        let mut reader = &self.reader;
+        // Get the index file offset of the record list the key is in.
+        let index_offset = self.buckets()[bucket as usize];
+
+        // The key doesn't need the prefix that was used to find the right bucket.
+        let index_key = Self::strip_bucket_prefix(key);
 
         // No records stored in that bucket yet
-        let new_data = if index_offset == 0 {
+        let (new_data, recordlist_size_before, key_pos) = if index_offset == 0 {
             // As it's the first key a single byte is enough as it doesn't need to be distinguised
             // from other keys.
             let trimmed_index_key = &index_key[..1];
-            recordlist::encode_offset_and_key(trimmed_index_key, file_offset)
+            (
+                recordlist::encode_offset_and_key(trimmed_index_key, file_offset),
+                0,
+                0,
+            )
         }
         // Read the record list from disk and insert the new key
         else {
-            let mut recordlist_size_buffer = [0; 4];
-            reader.seek(SeekFrom::Start(index_offset))?;
-            reader.read_exact(&mut recordlist_size_buffer)?;
-            let recordlist_size = usize::try_from(u32::from_le_bytes(recordlist_size_buffer))
-                .expect(">=32-bit platform needed");
+            let data = self.read_record_list(index_offset)?;
+            let records = RecordList::new(&data);
+
+            // A hint is only valid if it was returned by the last put, which then also was the
+            // last change of the record list.
+            let start_pos = match hint_pos {
+                Some(hint_pos)
+                    if *lock(&self.last_put_pos) == Some((bucket, hint_pos))
+                        && hint_pos < records.len()
+                        && records.read_record(hint_pos).key <= index_key =>
+                {
+                    hint_pos
+                }
+                _ => 0,
+            };
 
-            let mut data = vec![0u8; recordlist_size];
-            reader.read_exact(&mut data)?;
+            // With strict deduplication, inserting the same record again is always a no-op,
+            // independent of how the key would be inserted otherwise.
+            #[cfg(feature = "strict_dedup")]
+            if records.get(index_key) == Some(file_offset) {
+                self.notify_put(
+                    start,
+                    PutEvent {
+                        bucket: bucket as usize,
+                        key,
+                        file_offset,
+                        was_insert: false,
+                        record_list_size_before: records.len(),
+                        record_list_size_after: records.len(),
+                    },
+                );
+                return Ok((self.remember_put_pos(bucket, 0), index_offset));
+            }
 
-            let records = RecordList::new(&data);
-            let (pos, prev_record) = records.find_key_position(index_key);
+            let (pos, prev_record) = records.find_key_position_from(index_key, start_pos);
 
-            match prev_record {
+            let (new_data, key_pos) = match prev_record {
                 // The previous key is fully contained in the current key. We need to read the full
                 // key from the main data file in order to retrieve a key that is distinguishable
                 // from the one that should get inserted.
@@ -218,12 +790,23 @@ impl<P: PrimaryStorage, const N: u8> Index<P, N> {
                     let full_prev_key = self.primary.get_index_key(prev_record.file_offset)?;
                     // The index key has already removed the prefix that is used to determine the
                     // bucket. Do the same for the full previous key.
-                    let prev_key = strip_bucket_prefix(&full_prev_key[..], N);
+                    let prev_key = Self::strip_bucket_prefix(&full_prev_key[..]);
                     let key_trim_pos = first_non_common_byte(index_key, prev_key);
 
                     // Only store the new key if it doesn't exist yet.
                     if key_trim_pos >= index_key.len() {
-                        return Ok(());
+                        self.notify_put(
+                            start,
+                            PutEvent {
+                                bucket: bucket as usize,
+                                key,
+                                file_offset,
+                                was_insert: false,
+                                record_list_size_before: records.len(),
+                                record_list_size_after: records.len(),
+                            },
+                        );
+                        return Ok((self.remember_put_pos(bucket, prev_record.pos), index_offset));
                     }
 
                     let trimmed_prev_key = &prev_key[..=key_trim_pos];
@@ -231,18 +814,24 @@ impl<P: PrimaryStorage, const N: u8> Index<P, N> {
 
                     // Replace the existing previous key (which is too short) with a new one and
                     // also insert the new key.
-                    let keys = if trimmed_prev_key < trimmed_index_key {
-                        [
-                            (trimmed_prev_key, prev_record.file_offset),
-                            (trimmed_index_key, file_offset),
-                        ]
+                    let (keys, key_pos) = if trimmed_prev_key < trimmed_index_key {
+                        (
+                            [
+                                (trimmed_prev_key, prev_record.file_offset),
+                                (trimmed_index_key, file_offset),
+                            ],
+                            prev_record.pos + recordlist::record_size(trimmed_prev_key),
+                        )
                     } else {
-                        [
-                            (trimmed_index_key, file_offset),
-                            (trimmed_prev_key, prev_record.file_offset),
-                        ]
+                        (
+                            [
+                                (trimmed_index_key, file_offset),
+                                (trimmed_prev_key, prev_record.file_offset),
+                            ],
+                            prev_record.pos,
+                        )
                     };
-                    records.put_keys(&keys, prev_record.pos..pos)
+                    (records.put_keys(&keys, prev_record.pos..pos), key_pos)
 
                     // There is no need to do anything with the next key as the next key is
                     // already guaranteed to be distinguishable from the new key as it was already
@@ -279,96 +868,816 @@ impl<P: PrimaryStorage, const N: u8> Index<P, N> {
                     let key_trim_pos = cmp::min(min_prefix, index_key.len());
 
                     let trimmed_index_key = &index_key[0..=key_trim_pos];
-                    records.put_keys(&[(trimmed_index_key, file_offset)], pos..pos)
+                    (
+                        records.put_keys(&[(trimmed_index_key, file_offset)], pos..pos),
+                        pos,
+                    )
                 }
-            }
+            };
+            (new_data, records.len(), key_pos)
         };
 
-        let recordlist_pos = writer
-            .seek(SeekFrom::End(0))
-            .expect("It's always possible to seek to the end of the file.");
+        let recordlist_pos = self.write_record_list(bucket, &new_data)?;
+        record_span!(record_list_size = new_data.len());
 
-        // Write new data to disk. The record list is prefixed with bucket they are in. This is
-        // needed in order to reconstruct the in-memory buckets from the index itself.
-        // TODO vmx 2020-11-25: This should be an error and not a panic
-        let new_data_size: [u8; 4] = u32::try_from(new_data.len() + BUCKET_PREFIX_SIZE)
-            .expect("A record list cannot be bigger than 2^32.")
-            .to_le_bytes();
-        writer.write_all(&new_data_size)?;
-        writer.write_all(&bucket.to_le_bytes())?;
-        writer.write_all(&new_data)?;
-        // Fsyncs are expensive
-        //self.file.sync_data()?;
+        if let Some(threshold) = self.warn_threshold_bytes {
+            if new_data.len() > threshold {
+                warn!(
+                    "Record list for bucket {} is {} bytes, consider increasing BUCKETS_BITS",
+                    bucket,
+                    new_data.len()
+                );
+            }
+        }
 
-        // Keep the reference to the stored data in the bucket
-        self.buckets
-            .borrow_mut()
-            .put(bucket as usize, recordlist_pos)?;
+        self.notify_put(
+            start,
+            PutEvent {
+                bucket: bucket as usize,
+                key,
+                file_offset,
+                was_insert: true,
+                record_list_size_before: recordlist_size_before,
+                record_list_size_after: new_data.len(),
+            },
+        );
 
-        Ok(())
+        Ok((self.remember_put_pos(bucket, key_pos), recordlist_pos))
+    }
+
+    /// Stores the position of the last put, so that it can be used as a hint for the next one.
+    fn remember_put_pos(&self, bucket: u32, pos: usize) -> usize {
+        *lock(&self.last_put_pos) = Some((bucket, pos));
+        pos
     }
 
     /// Get the file offset in the primary storage of a key.
     pub fn get(&self, key: &[u8]) -> Result<Option<u64>, Error> {
-        assert!(key.len() >= 4, "Key must be at least 4 bytes long");
+        Ok(self.get_with_probe_count(key)?.0)
+    }
 
-        // Determine which bucket a key falls into. Use the first few bytes of they key for it and
-        // interpret them as a little-endian integer.
-        let prefix_bytes: [u8; 4] = key[0..4].try_into().unwrap();
-        let prefix = u32::from_le_bytes(prefix_bytes);
-        let leading_bits = (1 << N) - 1;
-        let bucket: u32 = prefix & leading_bits;
+    /// Same as [`Index::get`], but it also returns the probe depth, the number of records that
+    /// were scanned in the record list of the bucket.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "Index::get",
+            level = "trace",
+            skip(self, key),
+            fields(
+                bucket = tracing::field::Empty,
+                record_list_size = tracing::field::Empty,
+                probe_count = tracing::field::Empty
+            )
+        )
+    )]
+    pub fn get_with_probe_count(&self, key: &[u8]) -> Result<(Option<u64>, usize), Error> {
+        self.check_key_length(key);
+
+        // Determine which bucket a key falls into.
+        let bucket = H::bucket(key, N);
+        record_span!(bucket = bucket);
 
         // Get the index file offset of the record list the key is in.
-        let index_offset = self.buckets.borrow().get(bucket as usize)?;
-        // The key doesn't need the prefix that was used to find the right bucket. For simplicty
-        // only full bytes are trimmed off.
-        let index_key = strip_bucket_prefix(&key, N);
+        let index_offset = self.buckets()[bucket as usize];
+        // The key doesn't need the prefix that was used to find the right bucket.
+        let index_key = Self::strip_bucket_prefix(key);
 
         // No records stored in that bucket yet
         if index_offset == 0 {
-            Ok(None)
+            Ok((None, 0))
         }
         // Read the record list from disk and get the file offset of that key in the primary
         // storage.
         else {
-            let mut recordlist_size_buffer = [0; 4];
-            let mut reader = &self.reader;
-            reader.seek(SeekFrom::Start(index_offset))?;
-            reader.read_exact(&mut recordlist_size_buffer)?;
-            let recordlist_size = usize::try_from(u32::from_le_bytes(recordlist_size_buffer))
-                .expect(">=32-bit platform needed");
+            let mut data = self.buffers.get();
+            self.read_record_list_into(index_offset, &mut data)?;
+            let records = RecordList::new(&data);
+            let (last_match, match_count, probe_count) =
+                records.get_last_record_with_counts(index_key);
+            record_span!(record_list_size = records.len(), probe_count = probe_count);
+            let file_offset = if match_count > 1 {
+                let (candidates, _probe_count) = records.get_records_with_probe_count(index_key);
+                self.verify_collision(key, &candidates)?
+            } else {
+                last_match.map(|record| record.file_offset)
+            };
+            Ok((file_offset, probe_count))
+        }
+    }
+
+    /// Returns how often each probe depth occurs when getting the given keys.
+    ///
+    /// The keys of the histogram are the probe depths, see [`Index::get_with_probe_count`], the
+    /// values the number of keys with that depth. Deep probes are a sign that the number of bits
+    /// used for the buckets is too small.
+    pub fn get_probe_histogram(&self, keys: &[&[u8]]) -> Result<HashMap<usize, u64>, Error> {
+        let mut histogram = HashMap::new();
+        for key in keys {
+            let (_file_offset, probe_count) = self.get_with_probe_count(key)?;
+            *histogram.entry(probe_count).or_insert(0) += 1;
+        }
+        Ok(histogram)
+    }
+
+    /// Get the file offset of a key and verify it against the key in the primary storage.
+    ///
+    /// Unlike [`Index::get`] it's guaranteed that the returned key-value pair belongs to the given
+    /// key, if it is [`LookupResult::Found`].
+    pub fn get_verified(&self, key: &[u8]) -> Result<LookupResult, Error> {
+        let start = self.metrics.as_ref().map(|_| Instant::now());
+        let result = match self.get(key)? {
+            Some(file_offset) => {
+                let (primary_key, value) = self.primary.get(file_offset)?;
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_primary_read(primary_key.len() + value.len());
+                }
+                if P::index_key(&primary_key)? == key {
+                    LookupResult::Found {
+                        file_offset,
+                        primary_key,
+                        value,
+                    }
+                } else {
+                    LookupResult::PrefixMatch {
+                        file_offset,
+                        primary_key,
+                    }
+                }
+            }
+            None => LookupResult::NotFound,
+        };
+        if let (Some(metrics), Some(start)) = (&self.metrics, start) {
+            metrics.record_get(
+                start.elapsed(),
+                matches!(result, LookupResult::Found { .. }),
+                matches!(result, LookupResult::PrefixMatch { .. }),
+            );
+        }
+        Ok(result)
+    }
 
-            let mut data = vec![0u8; recordlist_size];
-            reader.read_exact(&mut data)?;
+    /// Returns the file offset of the candidate whose full key matches the given one.
+    ///
+    /// If several stored prefixes match a key, the longest one isn't necessarily the right one.
+    /// Hence the full keys are retrieved from the primary storage, starting with the longest
+    /// prefix. If none of them matches, the longest prefix is returned, like it's done when there
+    /// is only a single candidate.
+    fn verify_collision(&self, key: &[u8], candidates: &[Record]) -> Result<Option<u64>, Error> {
+        for candidate in candidates.iter().rev() {
+            if self.primary.get_index_key(candidate.file_offset)? == key {
+                return Ok(Some(candidate.file_offset));
+            }
+        }
+        Ok(candidates.last().map(|record| record.file_offset))
+    }
+
+    /// Remove a key from the index.
+    ///
+    /// As the index only stores prefixes, the record that would be returned by [`Index::get`]
+    /// is removed. It's up to the caller to make sure that it's actually the requested key.
+    ///
+    /// Returns whether a record was removed.
+    pub fn delete(&self, key: &[u8]) -> Result<bool, Error> {
+        self.check_key_length(key);
+        let _update = lock(&self.update_lock);
 
+        // Determine which bucket a key falls into.
+        let bucket = H::bucket(key, N);
+
+        // Get the index file offset of the record list the key is in.
+        let index_offset = self.buckets()[bucket as usize];
+        // No records stored in that bucket
+        if index_offset == 0 {
+            return Ok(false);
+        }
+
+        // The key doesn't need the prefix that was used to find the right bucket.
+        let index_key = Self::strip_bucket_prefix(key);
+
+        let data = self.read_record_list(index_offset)?;
+        let records = RecordList::new(&data);
+        let new_data = match records.get_record(index_key) {
+            Some(record) => records.remove_record(record.pos),
+            None => return Ok(false),
+        };
+
+        // The record list is written even if it is empty. Else the bucket would point to the
+        // previous record list when the index is opened again.
+        self.write_record_list(bucket, &new_data)?;
+        Ok(true)
+    }
+
+    /// Returns the file offsets in the primary storage of all keys within a bucket.
+    ///
+    /// The offsets are sorted by the keys they belong to.
+    pub fn file_offsets_in_bucket(&self, bucket: usize) -> Result<Vec<u64>, Error> {
+        // No records stored in that bucket yet
+        let data = match self.bucket_records(bucket)? {
+            Some((_index_offset, data)) => data,
+            None => return Ok(Vec::new()),
+        };
+        let records = RecordList::new(&data);
+        Ok(records
+            .into_iter()
+            .map(|record| record.file_offset)
+            .collect())
+    }
+
+    /// Returns the bucket of a key.
+    pub(crate) fn key_bucket(&self, key: &[u8]) -> usize {
+        H::bucket(key, N) as usize
+    }
+
+    /// Returns the position within the index and the data of the live record list of a bucket.
+    ///
+    /// It's `None` if nothing was stored in that bucket yet. Use [`RecordList::new`] to access
+    /// the records.
+    pub fn bucket_records(&self, bucket: usize) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        let index_offset = self.buckets().get(bucket)?;
+        if index_offset == 0 {
+            return Ok(None);
+        }
+        let data = self.read_record_list(index_offset)?;
+        Ok(Some((index_offset, data)))
+    }
+
+    /// Iterates over the live record lists of all non-empty buckets, sorted by bucket.
+    ///
+    /// Each item is the bucket, the position of the record list within the index and its data.
+    /// Unlike [`IndexIter`] it skips the record lists that were superseded by newer ones.
+    pub fn live_entries(&self) -> impl Iterator<Item = Result<(usize, u64, Vec<u8>), Error>> + '_ {
+        (0..1 << N).filter_map(move |bucket| match self.bucket_records(bucket) {
+            Ok(Some((index_offset, data))) => Some(Ok((bucket, index_offset, data))),
+            Ok(None) => None,
+            Err(error) => Some(Err(error)),
+        })
+    }
+
+    /// Writes all live records in a portable text format, see [`Index::restore`].
+    ///
+    /// The first line is [`DUMP_HEADER`] followed by the number of bits used for the buckets and
+    /// the minimum key length, separated by spaces. Then there's one line per record with the bucket, the hex encoded key as it's stored in
+    /// the index (without the bytes used for the bucket) and the file offset, separated by
+    /// spaces. The records are sorted by bucket, within a bucket by key. Returns the number of
+    /// records.
+    pub fn dump<W: Write>(&self, mut writer: W) -> Result<u64, Error> {
+        writeln!(writer, "{} {} {}", DUMP_HEADER, N, self.min_key_length())?;
+        let mut count = 0;
+        for entry in self.live_entries() {
+            let (bucket, _index_offset, data) = entry?;
+            for record in &RecordList::new(&data) {
+                write!(writer, "{} ", bucket)?;
+                for byte in record.key {
+                    write!(writer, "{:02x}", byte)?;
+                }
+                writeln!(writer, " {}", record.file_offset)?;
+                count += 1;
+            }
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Replaces the file offsets in the primary storage, e.g. after it was defragmented.
+    ///
+    /// `mapping` contains `(old_offset, new_offset)` tuples. Offsets that are not part of the
+    /// mapping stay as they are. Only the record lists that contain changed offsets are written
+    /// again.
+    pub fn remap_offsets(&self, mapping: &[(u64, u64)]) -> Result<(), Error> {
+        let mapping: HashMap<u64, u64> = mapping.iter().copied().collect();
+        let _update = lock(&self.update_lock);
+        for bucket in 0..1u32 << N {
+            let index_offset = self.buckets()[bucket as usize];
+            if index_offset == 0 {
+                continue;
+            }
+
+            let data = self.read_record_list(index_offset)?;
             let records = RecordList::new(&data);
-            let file_offset = records.get(index_key);
-            Ok(file_offset)
+            let mut changed = false;
+            let mut new_data = Vec::with_capacity(data.len() - RECORDLIST_HEADER_SIZE);
+            for record in records.into_iter() {
+                let file_offset = match mapping.get(&record.file_offset) {
+                    Some(new_offset) => {
+                        changed |= *new_offset != record.file_offset;
+                        *new_offset
+                    }
+                    None => record.file_offset,
+                };
+                new_data.extend(recordlist::encode_offset_and_key(record.key, file_offset));
+            }
+            if changed {
+                self.write_record_list(bucket, &new_data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the record list (including the bucket prefix) at the given index file offset.
+    ///
+    /// Returns [`Error::CorruptRecordList`] if the record list is truncated or malformed.
+    ///
+    /// The cursor of the index file isn't used, so that concurrent gets don't interfere.
+    fn read_record_list(&self, index_offset: u64) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        self.read_record_list_into(index_offset, &mut data)?;
+        Ok(data)
+    }
+
+    /// Same as [`Index::read_record_list`], but the record list is read into the given buffer,
+    /// its previous content is replaced.
+    fn read_record_list_into(&self, index_offset: u64, data: &mut Vec<u8>) -> Result<(), Error> {
+        let io_error = || self.io_error("reading record list", Some(index_offset));
+        let mut recordlist_size_buffer = [0; 4];
+        self.read_exact_at(&mut recordlist_size_buffer, index_offset)
+            .map_err(io_error())?;
+        let recordlist_size = u32::from_le_bytes(recordlist_size_buffer);
+
+        // Check the size before the data is allocated, a corrupt size might be huge.
+        let available = self
+            .storage_len()
+            .map_err(io_error())?
+            .saturating_sub(index_offset + SIZE_PREFIX_SIZE as u64);
+        if u64::from(recordlist_size) > available {
+            return Err(Error::CorruptRecordList {
+                offset: index_offset,
+            });
+        }
+        let recordlist_size = usize::try_from(recordlist_size).map_err(|_| Error::Arithmetic)?;
+
+        data.clear();
+        data.resize(recordlist_size, 0);
+        self.read_exact_at(data, index_offset + SIZE_PREFIX_SIZE as u64)
+            .map_err(io_error())?;
+        if !RecordList::is_well_formed(data) {
+            return Err(Error::CorruptRecordList {
+                offset: index_offset,
+            });
         }
+        Ok(())
+    }
+
+    /// Returns the in-memory buckets.
+    ///
+    /// They are only updated after a record list was fully written, hence a panic of another
+    /// thread never leaves them inconsistent and the poisoning is ignored.
+    fn buckets(&self) -> RwLockReadGuard<'_, Buckets<N>> {
+        self.buckets.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the writer of the index file.
+    ///
+    /// A panic of another thread at most leaves a partial record list at the end of the file,
+    /// which no bucket points to, hence the poisoning is ignored. Its buffer is always flushed
+    /// before the lock is released, hence the storage can also be read through it.
+    fn writer(&self) -> MutexGuard<'_, BufWriter<R>> {
+        lock(&self.writer)
+    }
+
+    /// Calls `f` with a reader of the index that is positioned anywhere.
+    ///
+    /// File-backed indexes are read through the clone of the file with a [`PositionedReader`],
+    /// which ends at the current size of the index. Other ones are read through the writer, whose
+    /// lock is held meanwhile.
+    fn with_reader<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        E: From<io::Error>,
+        F: FnOnce(&mut dyn ReadSeek) -> Result<T, E>,
+    {
+        match &self.reader {
+            Some(file) => {
+                let len = self.storage_len()?;
+                f(&mut PositionedReader::new(file, len))
+            }
+            None => f(self.writer().get_mut()),
+        }
+    }
+
+    /// Reads exactly enough bytes to fill the buffer, starting at the given offset.
+    fn read_exact_at(&self, buffer: &mut [u8], offset: u64) -> Result<(), io::Error> {
+        match &self.reader {
+            Some(file) => read_exact_at(file, buffer, offset),
+            None => self.with_reader(|reader| {
+                reader.seek(SeekFrom::Start(offset))?;
+                reader.read_exact(buffer)
+            }),
+        }
+    }
+
+    /// Returns the size of the index in bytes, without moving the cursor of a file.
+    ///
+    /// The writer flushes every record list before it releases its lock, hence the size never
+    /// includes a record list that is only partially written.
+    fn storage_len(&self) -> Result<u64, io::Error> {
+        let mut writer = self.writer();
+        match &self.reader {
+            Some(file) => Ok(file.metadata()?.len()),
+            None => writer.get_mut().seek(SeekFrom::End(0)),
+        }
+    }
+
+    /// Returns a function that converts an I/O error into one with the given context, see
+    /// [`Error::io`].
+    fn io_error(
+        &self,
+        operation: &'static str,
+        offset: Option<u64>,
+    ) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| Error::Io {
+            operation: Some(operation),
+            path: self.path.clone(),
+            offset,
+            source,
+        }
+    }
+
+    /// Appends the records of a bucket to the index and updates the bucket to point to it.
+    ///
+    /// Returns the position the record list was appended at.
+    fn write_record_list(&self, bucket: u32, records: &[u8]) -> Result<u64, Error> {
+        // Positions within the old record list are no longer valid.
+        *lock(&self.last_put_pos) = None;
+        let new_data_size: [u8; 4] = u32::try_from(records.len() + RECORDLIST_HEADER_SIZE)
+            .map_err(|_| Error::Arithmetic)?
+            .to_le_bytes();
+
+        let mut writer = self.writer();
+        let recordlist_pos = writer
+            .seek(SeekFrom::End(0))
+            .map_err(self.io_error("writing record list", None))?;
+
+        // Write new data to disk. The record list is prefixed with bucket they are in. This is
+        // needed in order to reconstruct the in-memory buckets from the index itself.
+        writer
+            .write_all(&new_data_size)
+            .and_then(|_| writer.write_all(&bucket.to_le_bytes()))
+            .and_then(|_| writer.write_all(&[N]))
+            .and_then(|_| writer.write_all(records))
+            // Flush, so that the data is visible to the reader. The seek above flushes anyway,
+            // hence the buffer only combines the writes of a single record list.
+            .and_then(|_| writer.flush())
+            .map_err(self.io_error("writing record list", Some(recordlist_pos)))?;
+        let bytes_written = SIZE_PREFIX_SIZE + RECORDLIST_HEADER_SIZE + records.len();
+        if let Some(metrics) = &self.metrics {
+            metrics.record_index_write(bytes_written);
+        }
+        if let Some(syncer) = &self.syncer {
+            syncer.record_write(bytes_written as u64);
+        }
+        // Fsyncs are expensive
+        //self.file.sync_data()?;
+
+        // Keep the reference to the stored data in the bucket
+        self.buckets
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(bucket as usize, recordlist_pos)?;
+        Ok(recordlist_pos)
+    }
+
+    /// Flushes all buffered writes of the index and syncs them to disk.
+    ///
+    /// Storages that aren't files are only flushed.
+    pub fn flush(&self) -> Result<(), Error> {
+        let mut writer = self.writer();
+        writer
+            .flush()
+            .and_then(|_| match &self.reader {
+                Some(file) => file.sync_data(),
+                None => Ok(()),
+            })
+            .map_err(self.io_error("flushing index", None))
+    }
+
+    /// Flushes the index and copies it into a new file at the given path.
+    pub fn snapshot(&self, path: &Path) -> Result<(), Error> {
+        self.flush()?;
+        self.with_reader(|reader| {
+            let size = reader.seek(SeekFrom::End(0))?;
+            reader.seek(SeekFrom::Start(0))?;
+            let mut snapshot = File::create(path)?;
+            io::copy(&mut reader.take(size), &mut snapshot)?;
+            snapshot.sync_all()?;
+            Ok(())
+        })
+    }
+
+    /// Returns the size of the index file in bytes.
+    pub fn size(&self) -> Result<u64, Error> {
+        self.storage_len()
+            .map_err(self.io_error("reading index size", None))
+    }
+
+    /// Returns the number of bytes of the index file that are still in use.
+    ///
+    /// A put never changes a record list in place, it appends a new one for that bucket. Only the
+    /// header and the most recent record list of every bucket are in use. Only the size prefixes
+    /// of those are read, not the record lists themselves.
+    pub fn live_size(&self) -> Result<u64, Error> {
+        let read_size_prefix_at = |offset| {
+            let mut size_buffer = [0; SIZE_PREFIX_SIZE];
+            self.read_exact_at(&mut size_buffer, offset)
+                .map_err(self.io_error("reading size prefix", Some(offset)))?;
+            Ok::<_, Error>(u64::from(u32::from_le_bytes(size_buffer)))
+        };
+        let mut live_size = SIZE_PREFIX_SIZE as u64 + read_size_prefix_at(0)?;
+        for index_offset in self.offsets() {
+            // No records stored in that bucket yet
+            if index_offset == 0 {
+                continue;
+            }
+            live_size += SIZE_PREFIX_SIZE as u64 + read_size_prefix_at(index_offset)?;
+        }
+        Ok(live_size)
+    }
+
+    /// Returns how many bytes of the index file are taken by superseded record lists.
+    ///
+    /// It's based on [`Index::size`] and [`Index::live_size`], it can be used to decide whether
+    /// it's worth compacting the index.
+    pub fn garbage_stats(&self) -> Result<GarbageStats, Error> {
+        let total_bytes = self.size()?;
+        let live_bytes = self.live_size()?;
+        let stale_bytes = total_bytes.saturating_sub(live_bytes);
+        let stale_ratio = if total_bytes == 0 {
+            0.0
+        } else {
+            stale_bytes as f64 / total_bytes as f64
+        };
+        Ok(GarbageStats {
+            total_bytes,
+            live_bytes,
+            stale_bytes,
+            stale_ratio,
+        })
+    }
+
+    /// Returns how many bytes re-trimming the keys would save, summed up over the live record
+    /// lists, see [`RecordList::bytes_wasted`].
+    ///
+    /// It reads all live record lists, but not the primary storage.
+    pub fn total_bytes_wasted(&self) -> Result<usize, Error> {
+        let index_offsets: Vec<u64> = self
+            .buckets()
+            .iter()
+            .map(|(_bucket, index_offset)| index_offset)
+            .filter(|index_offset| *index_offset != 0)
+            .collect();
+        let mut bytes_wasted = 0;
+        for index_offset in index_offsets {
+            let data = self.read_record_list(index_offset)?;
+            bytes_wasted += RecordList::new(&data).bytes_wasted();
+        }
+        Ok(bytes_wasted)
+    }
+
+    /// Returns all buckets that were ever written to, see [`IndexBucketIter`].
+    ///
+    /// Only the bucket prefixes of the record lists are read. Unlike the buckets with an offset,
+    /// it also contains the buckets whose keys were all deleted.
+    pub fn used_buckets(&self) -> Result<HashSet<u32>, Error> {
+        self.with_reader(|mut reader| {
+            reader.seek(SeekFrom::Start(0))?;
+            let header_size = read_size_prefix(&mut reader)?;
+            reader.seek(SeekFrom::Current(header_size as i64))?;
+            IndexBucketIter::new(reader).collect::<Result<_, _>>()
+        })
+        .map_err(self.io_error("reading record lists", None))
     }
 
     /// Return a copy of the in-memory index offsets, sorted by the buckets.
     pub fn offsets(&self) -> Vec<u64> {
-        self.buckets.borrow().0.clone()
+        self.buckets().0.clone()
     }
+
+    /// Returns the number of records of all non-empty buckets as `(bucket, record_count)` pairs.
+    ///
+    /// The pairs are sorted by bucket. This reads the record lists of all buckets, hence it's
+    /// meant for capacity planning and not for the hot path.
+    pub fn bucket_load_factor(&self) -> Result<Vec<(usize, usize)>, Error> {
+        let mut loads = Vec::new();
+        for (bucket, index_offset) in self.offsets().into_iter().enumerate() {
+            // No records stored in that bucket yet
+            if index_offset == 0 {
+                continue;
+            }
+            let data = self.read_record_list(index_offset)?;
+            let record_count = RecordList::new(&data).into_iter().count();
+            // A bucket might be empty again after all its keys were deleted.
+            if record_count > 0 {
+                loads.push((bucket, record_count));
+            }
+        }
+        Ok(loads)
+    }
+
+    /// Returns the number of records of every bucket, the vector is indexed by bucket.
+    ///
+    /// Only the current record list of each non-empty bucket is read.
+    pub fn count_by_bucket(&self) -> Result<Vec<u64>, Error> {
+        let mut counts = vec![0; 1 << N];
+        for (bucket, index_offset) in self.offsets().into_iter().enumerate() {
+            // No records stored in that bucket yet
+            if index_offset == 0 {
+                continue;
+            }
+            let data = self.read_record_list(index_offset)?;
+            counts[bucket] = RecordList::new(&data).into_iter().count() as u64;
+        }
+        Ok(counts)
+    }
+
+    /// Returns the number of records of the fullest bucket.
+    pub fn max_load(&self) -> Result<usize, Error> {
+        Ok(max_load(&self.bucket_load_factor()?))
+    }
+
+    /// Returns the average number of records per bucket, the empty buckets included.
+    pub fn avg_load(&self) -> Result<f64, Error> {
+        Ok(avg_load::<N>(&self.bucket_load_factor()?))
+    }
+
+    /// Returns statistics about how the records are distributed over the buckets.
+    pub fn stats(&self) -> Result<IndexStats, Error> {
+        let loads = self.bucket_load_factor()?;
+        Ok(IndexStats {
+            non_empty_buckets: loads.len(),
+            records: loads
+                .iter()
+                .map(|(_bucket, record_count)| record_count)
+                .sum(),
+            max_load: max_load(&loads),
+            avg_load: avg_load::<N>(&loads),
+        })
+    }
+}
+
+impl<P: PrimaryStorage, const N: u8, R: Read + Write + Seek> Index<P, N, LeadingBytesHasher, R> {
+    /// Returns the file offsets of all keys that start with the given prefix.
+    ///
+    /// It's only available with the [`LeadingBytesHasher`], as it's the only one that puts keys
+    /// with the same prefix into the same bucket.
+    ///
+    /// The prefix needs to cover at least the bits used for the buckets, so that all matching
+    /// keys are in a single bucket, else it panics. The offsets are sorted by key. The index only
+    /// stores the shortest prefix that distinguishes a key from its neighbours, if that's shorter
+    /// than the given prefix, the full key is read from the primary storage to check whether it
+    /// matches.
+    pub fn get_prefix(&self, full_key_prefix: &[u8]) -> Result<Vec<u64>, Error> {
+        assert!(
+            full_key_prefix.len() * 8 >= usize::from(N),
+            "Prefix must be at least {} bits long",
+            N
+        );
+        let mut prefix_bytes = [0; 4];
+        let prefix_len = cmp::min(prefix_bytes.len(), full_key_prefix.len());
+        prefix_bytes[..prefix_len].copy_from_slice(&full_key_prefix[..prefix_len]);
+        let bucket = LeadingBytesHasher::bucket(&prefix_bytes, N);
+
+        let data = match self.bucket_records(bucket as usize)? {
+            Some((_index_offset, data)) => data,
+            None => return Ok(Vec::new()),
+        };
+        let prefix = Self::strip_bucket_prefix(full_key_prefix);
+        let mut file_offsets = Vec::new();
+        for record in &RecordList::new(&data) {
+            let matches = if record.key.starts_with(prefix) {
+                true
+            } else if prefix.starts_with(record.key) {
+                self.primary
+                    .get_index_key(record.file_offset)?
+                    .starts_with(full_key_prefix)
+            } else {
+                false
+            };
+            if matches {
+                file_offsets.push(record.file_offset);
+            }
+        }
+        Ok(file_offsets)
+    }
+}
+
+/// Statistics about how the records are distributed over the buckets, see [`Index::stats`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IndexStats {
+    /// The number of buckets that contain at least one record.
+    pub non_empty_buckets: usize,
+    /// The total number of records.
+    pub records: usize,
+    /// The number of records of the fullest bucket.
+    pub max_load: usize,
+    /// The average number of records per bucket, the empty buckets included.
+    pub avg_load: f64,
+}
+
+/// How much of the index file is in use, see [`Index::garbage_stats`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GarbageStats {
+    /// The size of the index file in bytes.
+    pub total_bytes: u64,
+    /// The bytes of the header and the most recent record list of every bucket.
+    pub live_bytes: u64,
+    /// The bytes of the superseded record lists.
+    pub stale_bytes: u64,
+    /// The stale bytes relative to the total bytes, between 0 and 1.
+    pub stale_ratio: f64,
+}
+
+fn max_load(loads: &[(usize, usize)]) -> usize {
+    loads
+        .iter()
+        .map(|(_bucket, record_count)| *record_count)
+        .max()
+        .unwrap_or(0)
+}
+
+fn avg_load<const N: u8>(loads: &[(usize, usize)]) -> f64 {
+    let records: usize = loads
+        .iter()
+        .map(|(_bucket, record_count)| record_count)
+        .sum();
+    records as f64 / (1u64 << N) as f64
+}
+
+/// The result of [`Index::get_verified`].
+#[derive(Debug, PartialEq)]
+pub enum LookupResult {
+    /// No stored key shares a prefix with the requested key.
+    NotFound,
+    /// The key was found in the primary storage.
+    Found {
+        file_offset: u64,
+        primary_key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// A stored prefix matches the requested key, but the full key in the primary storage is a
+    /// different one.
+    PrefixMatch {
+        file_offset: u64,
+        primary_key: Vec<u8>,
+    },
 }
 
 /// An iterator over index entries.
 ///
 /// On each iteration it returns the position of the record within the index together with the raw
 /// record list data.
+///
+/// It can also iterate backwards, starting with the most recently appended record list. As the
+/// size of a record list is stored in front of it, the positions of all remaining record lists
+/// are collected first, when iterating backwards for the first time.
 #[derive(Debug)]
 pub struct IndexIter<R: Read> {
     /// The index data we are iterating over
     index: R,
     /// The current position within the index
     pos: usize,
+    /// The positions of the record lists that weren't returned yet. It's only populated once the
+    /// iteration from the back starts.
+    remaining: Option<VecDeque<u64>>,
 }
 
 impl<R: Read> IndexIter<R> {
     pub fn new(index: R, pos: usize) -> Self {
-        Self { index, pos }
+        Self {
+            index,
+            pos,
+            remaining: None,
+        }
+    }
+}
+
+impl<R: Read + Seek> IndexIter<R> {
+    /// Collects the positions of all record lists from the current position to the end.
+    fn collect_positions(&mut self) -> Result<VecDeque<u64>, io::Error> {
+        let mut positions = VecDeque::new();
+        let mut pos = u64::try_from(self.pos).expect("64-bit platform needed");
+        self.index.seek(SeekFrom::Start(pos))?;
+        loop {
+            match read_size_prefix(&mut self.index) {
+                Ok(size) => {
+                    positions.push_back(pos);
+                    let size = i64::try_from(size).expect("64-bit platform needed");
+                    pos = self.index.seek(SeekFrom::Current(size))?;
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(positions)
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Reads the record list at the given position.
+    fn read_at(&mut self, pos: u64) -> Result<(Vec<u8>, u64), io::Error> {
+        self.index.seek(SeekFrom::Start(pos))?;
+        let size = read_size_prefix(&mut self.index)?;
+        let data = read_record_list_data(&mut self.index, size)?;
+        Ok((data, pos))
     }
 }
 
@@ -376,19 +1685,22 @@ impl<R: Read + Seek> Iterator for IndexIter<R> {
     type Item = Result<(Vec<u8>, u64), io::Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // The iteration from the back has started, hence use the collected positions.
+        if let Some(remaining) = &mut self.remaining {
+            let pos = remaining.pop_front()?;
+            return Some(self.read_at(pos));
+        }
+
         match read_size_prefix(&mut self.index) {
             Ok(size) => {
                 let pos = u64::try_from(self.pos).expect("64-bit platform needed");
                 // Advance the position to the end of records list
                 self.pos += SIZE_PREFIX_SIZE + size;
 
-                let mut data = vec![0u8; size];
-                match self.index.read_exact(&mut data) {
-                    Ok(_) => (),
-                    Err(error) => return Some(Err(error)),
-                };
-
-                Some(Ok((data, pos)))
+                match read_record_list_data(&mut self.index, size) {
+                    Ok(data) => Some(Ok((data, pos))),
+                    Err(error) => Some(Err(error)),
+                }
             }
             // Stop iteration if the end of the file is reached.
             Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => None,
@@ -397,6 +1709,301 @@ impl<R: Read + Seek> Iterator for IndexIter<R> {
     }
 }
 
+impl<R: Read + Seek> DoubleEndedIterator for IndexIter<R> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining.is_none() {
+            match self.collect_positions() {
+                Ok(positions) => self.remaining = Some(positions),
+                Err(error) => {
+                    // Make sure the iteration stops after the error.
+                    self.remaining = Some(VecDeque::new());
+                    return Some(Err(error));
+                }
+            }
+        }
+
+        let pos = self.remaining.as_mut()?.pop_back()?;
+        Some(self.read_at(pos))
+    }
+}
+
+/// An iterator over the buckets of the record lists of an index, in file order.
+///
+/// Unlike [`IndexIter`] only the bucket prefix of a record list is read, the rest of it is
+/// skipped. A bucket is returned for every record list that was written for it, including the
+/// superseded ones. The reader needs to be positioned at the first record list, i.e. after the
+/// header, and shouldn't be buffered, as a seek discards the buffer.
+#[derive(Debug)]
+pub struct IndexBucketIter<R: Read> {
+    /// The index data we are iterating over
+    index: R,
+    /// Whether the end of the index or an error was reached.
+    done: bool,
+}
+
+impl<R: Read> IndexBucketIter<R> {
+    pub fn new(index: R) -> Self {
+        Self { index, done: false }
+    }
+}
+
+impl<R: Read + Seek> IndexBucketIter<R> {
+    /// Reads the bucket prefix of a record list of the given size and skips the rest of it.
+    fn read_bucket(&mut self, size: usize) -> Result<u32, io::Error> {
+        if size < BUCKET_PREFIX_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Record list is smaller than its bucket prefix.",
+            ));
+        }
+        let mut bucket = [0; BUCKET_PREFIX_SIZE];
+        self.index.read_exact(&mut bucket)?;
+        let rest = i64::try_from(size - BUCKET_PREFIX_SIZE).expect("64-bit platform needed");
+        self.index.seek(SeekFrom::Current(rest))?;
+        Ok(u32::from_le_bytes(bucket))
+    }
+}
+
+impl<R: Read + Seek> Iterator for IndexBucketIter<R> {
+    type Item = Result<u32, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = match read_size_prefix(&mut self.index) {
+            Ok(size) => self.read_bucket(size),
+            // Stop iteration if the end of the file is reached.
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                self.done = true;
+                return None;
+            }
+            Err(error) => Err(error),
+        };
+        // Make sure the iteration stops after an error.
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
+/// Reads the data of a record list whose size prefix was already read.
+///
+/// The size isn't trusted, memory is only allocated for the data that is actually read. If the
+/// reader ends before, an `UnexpectedEof` error is returned.
+fn read_record_list_data<R: Read>(reader: &mut R, size: usize) -> Result<Vec<u8>, io::Error> {
+    let mut data = Vec::new();
+    reader
+        .take(u64::try_from(size).expect("64-bit platform needed"))
+        .read_to_end(&mut data)?;
+    if data.len() != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Record list is truncated.",
+        ));
+    }
+    Ok(data)
+}
+
+/// Returns the bucket a record list at the given index file offset belongs to.
+///
+/// A record list that is too short for a bucket prefix or whose bucket doesn't exist returns
+/// [`Error::CorruptRecordList`].
+fn bucket_of_record_list<const N: u8>(data: &[u8], offset: u64) -> Result<usize, Error> {
+    let bucket_prefix = data
+        .get(..BUCKET_PREFIX_SIZE)
+        .map(|prefix| u32::from_le_bytes(prefix.try_into().expect("Slice is 4 bytes")))
+        .ok_or(Error::CorruptRecordList { offset })?;
+    match usize::try_from(bucket_prefix) {
+        Ok(bucket) if bucket < 1 << N => Ok(bucket),
+        _ => Err(Error::CorruptRecordList { offset }),
+    }
+}
+
+/// Recreates the in-memory buckets from the record lists of the index, starting at `pos`.
+///
+/// A truncated record list at the end of the file is ignored, the file is then positioned at its
+/// end. A record list that doesn't belong to a bucket returns [`Error::CorruptRecordList`].
+#[cfg(not(feature = "rayon"))]
+fn load_buckets<R: Read + Seek, const N: u8>(
+    file: &mut R,
+    pos: usize,
+) -> Result<Buckets<N>, Error> {
+    let mut buckets = Buckets::<N>::new();
+    let mut corrupt = false;
+    for entry in IndexIter::new(BufReader::new(&mut *file), pos) {
+        match entry {
+            Ok((data, pos)) => buckets.put(bucket_of_record_list::<N>(&data, pos)?, pos)?,
+            // The file is corrupt. Though it's not a problem, just take the data we are able to
+            // use and move on.
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                //return Err(Error::IndexCorrupt);
+                warn!("Index file is corrupt.");
+                corrupt = true;
+                break;
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+    if corrupt {
+        file.seek(SeekFrom::End(0))?;
+    }
+    Ok(buckets)
+}
+
+/// The number of record lists that are read from the index before they are processed in
+/// parallel.
+#[cfg(feature = "rayon")]
+const LOAD_BUCKETS_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Recreates the in-memory buckets from the record lists of the index, starting at `pos`.
+///
+/// A truncated record list at the end of the file is ignored, the file is then positioned at its
+/// end. A record list that doesn't belong to a bucket returns [`Error::CorruptRecordList`].
+///
+/// The record lists are read sequentially in chunks, each chunk is then processed in parallel.
+/// The index is append-only, hence the most recent record list of a bucket is the one with the
+/// highest position. This makes it possible to update the buckets without locking.
+#[cfg(feature = "rayon")]
+fn load_buckets<R: Read + Seek, const N: u8>(
+    file: &mut R,
+    pos: usize,
+) -> Result<Buckets<N>, Error> {
+    use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let buckets: Vec<AtomicU64> = (0..1usize << N).map(|_| AtomicU64::new(0)).collect();
+    let mut entries = IndexIter::new(BufReader::new(&mut *file), pos);
+    let mut chunk = Vec::with_capacity(LOAD_BUCKETS_CHUNK_SIZE);
+    let mut corrupt = false;
+    loop {
+        chunk.clear();
+        for entry in entries.by_ref().take(LOAD_BUCKETS_CHUNK_SIZE) {
+            match entry {
+                Ok(entry) => chunk.push(entry),
+                // The file is corrupt. Though it's not a problem, just take the data we are able
+                // to use and move on.
+                Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    warn!("Index file is corrupt.");
+                    corrupt = true;
+                    break;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        chunk.par_iter().try_for_each(|(data, pos)| {
+            let bucket = bucket_of_record_list::<N>(data, *pos)?;
+            buckets[bucket].fetch_max(*pos, Ordering::Relaxed);
+            Ok::<_, Error>(())
+        })?;
+
+        if corrupt || chunk.len() < LOAD_BUCKETS_CHUNK_SIZE {
+            break;
+        }
+    }
+    if corrupt {
+        file.seek(SeekFrom::End(0))?;
+    }
+    Ok(Buckets(
+        buckets.into_iter().map(AtomicU64::into_inner).collect(),
+    ))
+}
+
+/// Parses a record of a dump, see [`Index::dump`], into the bucket, the key and the file offset.
+fn parse_dump_line(line: &str) -> Option<(u32, Vec<u8>, u64)> {
+    let mut fields = line.split(' ');
+    let bucket = fields.next()?.parse().ok()?;
+    let key = decode_hex(fields.next()?).filter(|key| !key.is_empty() && key.len() <= 255)?;
+    let file_offset = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((bucket, key, file_offset))
+}
+
+/// Decodes a string of hex encoded bytes.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let pairs = hex.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    pairs
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// A reader of the index, see [`Index::with_reader`].
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// Reads a file with positioned reads, so that the cursor of the file, which is shared with the
+/// writer of the index, isn't moved.
+///
+/// The reader ends at the given length, so that it doesn't see a record list that is appended
+/// meanwhile.
+struct PositionedReader<'a> {
+    file: &'a File,
+    pos: u64,
+    len: u64,
+}
+
+impl<'a> PositionedReader<'a> {
+    fn new(file: &'a File, len: u64) -> Self {
+        Self { file, pos: 0, len }
+    }
+}
+
+impl Read for PositionedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let max_len =
+            usize::try_from(remaining).map_or(buf.len(), |remaining| remaining.min(buf.len()));
+        let bytes_read = read_at(self.file, &mut buf[..max_len], self.pos)?;
+        self.pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for PositionedReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, io::Error> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => i128::from(offset),
+            SeekFrom::End(offset) => i128::from(self.len) + i128::from(offset),
+            SeekFrom::Current(offset) => i128::from(self.pos) + i128::from(offset),
+        };
+        self.pos = u64::try_from(new_pos).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Locks a mutex, a panic of another thread that held the lock is ignored.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Reads bytes into the buffer, starting at the given offset, without using the cursor of the
+/// file.
+#[cfg(unix)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<usize, io::Error> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buffer, offset)
+}
+
+/// Reads bytes into the buffer, starting at the given offset.
+///
+/// On Windows the cursor of the file is moved, though it's not used for the offset.
+#[cfg(windows)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<usize, io::Error> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buffer, offset)
+}
+
 /// Only reads the size prefix of the data and returns it.
 pub fn read_size_prefix<R: Read>(reader: &mut R) -> Result<usize, io::Error> {
     let mut size_buffer = [0; SIZE_PREFIX_SIZE];
@@ -405,21 +2012,122 @@ pub fn read_size_prefix<R: Read>(reader: &mut R) -> Result<usize, io::Error> {
     Ok(size)
 }
 
-/// Returns the headet together with the bytes read.
+/// Returns the header together with its size prefix.
+fn header_bytes(header: Header) -> Result<Vec<u8>, Error> {
+    let header: Vec<u8> = header.into();
+    let header_size: [u8; 4] = u32::try_from(header.len())
+        .map_err(|_| Error::Arithmetic)?
+        .to_le_bytes();
+    Ok([&header_size[..], &header].concat())
+}
+
+/// Writes the header together with its size prefix and syncs it to disk.
+fn write_index_header(file: &mut File, index_path: &Path, header: Header) -> Result<(), Error> {
+    let header = header_bytes(header)?;
+    file.write_all(&header)
+        .and_then(|_| file.sync_data())
+        .map_err(Error::io("writing header", index_path, Some(0)))
+}
+
+/// Reads the header, see [`read_header`], an I/O error contains the path of the index.
+pub(crate) fn read_index_header<R: Read>(
+    file: &mut R,
+    index_path: &Path,
+) -> Result<(Header, usize), Error> {
+    read_header(file).map_err(|error| match error {
+        Error::Io { source, .. } => Error::io("reading header", index_path, Some(0))(source),
+        error => error,
+    })
+}
+
+/// Returns the header together with the bytes read.
 ///
 /// The bytes read include all the bytes that were read by this function. Hence it also includes
-/// the 4-byte size prefix of the header besides the size of the header data itself.
-pub fn read_header(file: &mut File) -> Result<(Header, usize), io::Error> {
+/// the 4-byte size prefix of the header besides the size of the header data itself. A file that
+/// ends within the header, or whose header is bigger than [`MAX_HEADER_SIZE`], is corrupt.
+pub fn read_header<R: Read>(file: &mut R) -> Result<(Header, usize), Error> {
     let mut header_size_buffer = [0; SIZE_PREFIX_SIZE];
-    file.read_exact(&mut header_size_buffer)?;
+    read_header_bytes(file, &mut header_size_buffer)?;
     let header_size =
         usize::try_from(u32::from_le_bytes(header_size_buffer)).expect(">=32-bit platform needed");
+    if header_size > MAX_HEADER_SIZE {
+        return Err(Error::CorruptHeader {
+            reason: "header is too big",
+        });
+    }
     let mut header_bytes = vec![0u8; header_size];
-    file.read_exact(&mut header_bytes)?;
-    Ok((
-        Header::from(&header_bytes[..]),
-        SIZE_PREFIX_SIZE + header_size,
-    ))
+    read_header_bytes(file, &mut header_bytes)?;
+    let header = Header::try_from(&header_bytes[..])?;
+    Ok((header, SIZE_PREFIX_SIZE + header_size))
+}
+
+/// Fills the buffer, a file that ends before that has a corrupt header.
+fn read_header_bytes<R: Read>(file: &mut R, buffer: &mut [u8]) -> Result<(), Error> {
+    file.read_exact(buffer).map_err(|error| {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            Error::CorruptHeader {
+                reason: "file ends within the header",
+            }
+        } else {
+            Error::from(error)
+        }
+    })
+}
+
+/// Upgrades an index with version 2 to the current version.
+///
+/// Version 2 record lists don't contain the number of bits used for the buckets. All record lists
+/// are copied into a new file with that information added, which then replaces the old index.
+fn migrate_v2(index_path: &Path) -> Result<(), Error> {
+    debug!("Migrate index from version 2 to {}.", INDEX_VERSION);
+    let mut old_file = File::open(index_path)?;
+    let (header, bytes_read) = read_header(&mut old_file)?;
+
+    let mut migrated_path = index_path.as_os_str().to_owned();
+    migrated_path.push(".migrate");
+    let mut migrated = BufWriter::new(
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&migrated_path)?,
+    );
+
+    let new_header: Vec<u8> = Header::new(header.buckets_bits).into();
+    let header_size: [u8; 4] = u32::try_from(new_header.len())
+        .map_err(|_| Error::Arithmetic)?
+        .to_le_bytes();
+    migrated.write_all(&header_size)?;
+    migrated.write_all(&new_header)?;
+
+    for entry in IndexIter::new(BufReader::new(old_file), bytes_read) {
+        match entry {
+            Ok((data, pos)) => {
+                if data.len() < BUCKET_PREFIX_SIZE {
+                    return Err(Error::CorruptRecordList { offset: pos });
+                }
+                let size: [u8; 4] = u32::try_from(data.len() + 1)
+                    .map_err(|_| Error::Arithmetic)?
+                    .to_le_bytes();
+                migrated.write_all(&size)?;
+                migrated.write_all(&data[..BUCKET_PREFIX_SIZE])?;
+                migrated.write_all(&[header.buckets_bits])?;
+                migrated.write_all(&data[BUCKET_PREFIX_SIZE..])?;
+            }
+            // A truncated record list at the end is dropped, the same way as it would be ignored
+            // when the index is opened.
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                warn!("Index file is corrupt.");
+                break;
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    let migrated = migrated.into_inner().map_err(|error| error.into_error())?;
+    migrated.sync_data()?;
+    fs::rename(&migrated_path, index_path)?;
+    Ok(())
 }
 
 /// Returns the position of the first character that both given slices have not in common.
@@ -427,7 +2135,7 @@ pub fn read_header(file: &mut File) -> Result<(Header, usize), io::Error> {
 /// It might return an index that is bigger than the input strings. If one is full prefix of the
 /// other, the index will be `shorter_slice.len() + 1`, if both slices are equal it will be
 /// `slice.len() + 1`
-fn first_non_common_byte(aa: &[u8], bb: &[u8]) -> usize {
+pub(crate) fn first_non_common_byte(aa: &[u8], bb: &[u8]) -> usize {
     let smaller_length = cmp::min(aa.len(), bb.len());
 
     let mut index = 0;
@@ -442,7 +2150,57 @@ fn first_non_common_byte(aa: &[u8], bb: &[u8]) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use super::first_non_common_byte;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+
+    use super::{first_non_common_byte, Index};
+    use crate::primary::{PrimaryError, PrimaryStorage};
+
+    /// A primary storage whose positions are the indices of the keys.
+    #[derive(Default)]
+    struct KeysPrimary(RefCell<Vec<Vec<u8>>>);
+
+    impl PrimaryStorage for KeysPrimary {
+        fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+            let key = self.0.borrow()[pos as usize].clone();
+            Ok((key, Vec::new()))
+        }
+
+        fn put(&self, key: &[u8], _value: &[u8]) -> Result<u64, PrimaryError> {
+            let mut keys = self.0.borrow_mut();
+            keys.push(key.to_vec());
+            Ok(keys.len() as u64 - 1)
+        }
+    }
+
+    #[test]
+    fn used_buckets() {
+        const BUCKETS_BITS: u8 = 8;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let index = Index::<_, BUCKETS_BITS>::open(
+            temp_dir.path().join("storethehash.index"),
+            KeysPrimary::default(),
+        )
+        .unwrap();
+        assert!(index.used_buckets().unwrap().is_empty());
+
+        // Some buckets get more than one record list.
+        for ii in 0..100u8 {
+            let key = [ii % 30, ii, 2, 3, 4, 5];
+            let file_offset = index.primary.put(&key, b"").unwrap();
+            index.put(&key, file_offset).unwrap();
+        }
+        index.flush().unwrap();
+
+        let occupied: HashSet<u32> = index
+            .buckets()
+            .iter()
+            .filter(|(_bucket, offset)| *offset != 0)
+            .map(|(bucket, _offset)| bucket as u32)
+            .collect();
+        assert_eq!(occupied.len(), 30);
+        assert_eq!(index.used_buckets().unwrap(), occupied);
+    }
 
     #[test]
     fn test_first_non_common_byte() {
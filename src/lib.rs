@@ -3,9 +3,53 @@
 //!  - Must be bigger than 4 bytes
 #![feature(min_const_generics)]
 
+/// Records values of fields of the current tracing span, see the `tracing` feature.
+///
+/// The fields need to be declared when the span is created. Without the feature it does nothing.
+macro_rules! record_span {
+    ($($field:ident = $value:expr),+ $(,)?) => {
+        #[cfg(feature = "tracing")]
+        {
+            let span = tracing::Span::current();
+            $(span.record(stringify!($field), &$value);)+
+        }
+    };
+}
+
 pub mod buckets;
+pub mod bufferpool;
+pub mod codec;
 pub mod db;
+pub mod dynindex;
 pub mod error;
+pub mod fsck;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod hasher;
 pub mod index;
+pub mod metrics;
 pub mod primary;
+pub mod progress;
+pub mod ratelimit;
+pub mod readahead;
+mod readscheduler;
 pub mod recordlist;
+pub mod sharded;
+pub mod sharedindex;
+pub mod syncer;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+/// The `tracing` crate, so that primary storages can emit spans with the same version.
+#[cfg(feature = "tracing")]
+pub use tracing;
+
+/// Re-exports the most common types, so that a single `use storethehash::prelude::*` is enough.
+pub mod prelude {
+    pub use crate::buckets::Buckets;
+    pub use crate::db::Db;
+    pub use crate::error::Error;
+    pub use crate::index::{Header, Index, IndexBucketIter, IndexBuilder, IndexIter};
+    pub use crate::primary::{PrimaryError, PrimaryStorage};
+    pub use crate::recordlist::{Record, RecordList};
+}
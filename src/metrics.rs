@@ -0,0 +1,173 @@
+//! Hooks for operational metrics, e.g. to export them to a monitoring system.
+//!
+//! A [`Metrics`] implementation can be set with [`crate::db::DbBuilder::with_metrics`] or
+//! [`crate::index::IndexBuilder::with_metrics`]. The index reports its puts and the bytes it
+//! writes, the database reports its gets and the bytes it reads from the primary storage.
+//! [`AtomicMetrics`] is a reference implementation that sums everything up.
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Receives measurements of the operations of a database or an index.
+///
+/// All methods do nothing by default. They are called on the hot paths, hence implementations
+/// need to be cheap, and they must not panic.
+pub trait Metrics: Send + Sync {
+    /// Called after every successful put with its duration and the byte size of the records of
+    /// the bucket the key was put into.
+    fn record_put(&self, _duration: Duration, _record_list_bytes: usize) {}
+
+    /// Called after every successful get with its duration. `hit` is whether a value was found,
+    /// `false_positive` whether the index pointed to an entry with a different key.
+    fn record_get(&self, _duration: Duration, _hit: bool, _false_positive: bool) {}
+
+    /// Called with the number of bytes of every record list that is written to the index.
+    fn record_index_write(&self, _bytes: usize) {}
+
+    /// Called with the number of bytes of the key and the value of every entry a get read from
+    /// the primary storage.
+    fn record_primary_read(&self, _bytes: usize) {}
+
+    /// Returns the measurements so far, if the implementation keeps them.
+    ///
+    /// It's used by [`crate::db::Db::stats`], by default there is none.
+    fn snapshot(&self) -> Option<MetricsSnapshot> {
+        None
+    }
+}
+
+/// Metrics that ignore all measurements.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullMetrics;
+
+impl Metrics for NullMetrics {}
+
+/// The measurements of [`AtomicMetrics`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MetricsSnapshot {
+    /// The number of puts.
+    pub puts: u64,
+    /// The total time spent in puts.
+    pub put_time: Duration,
+    /// The biggest record list a put resulted in, in bytes.
+    pub max_record_list_bytes: u64,
+    /// The number of gets.
+    pub gets: u64,
+    /// The total time spent in gets.
+    pub get_time: Duration,
+    /// The number of gets that found a value.
+    pub hits: u64,
+    /// The number of gets where the index pointed to an entry with a different key.
+    pub false_positives: u64,
+    /// The number of bytes written to the index.
+    pub index_bytes_written: u64,
+    /// The number of bytes read from the primary storage.
+    pub primary_bytes_read: u64,
+}
+
+/// Metrics that sum up all measurements with atomic counters, see [`AtomicMetrics::snapshot`].
+#[derive(Debug, Default)]
+pub struct AtomicMetrics {
+    puts: AtomicU64,
+    put_nanos: AtomicU64,
+    max_record_list_bytes: AtomicU64,
+    gets: AtomicU64,
+    get_nanos: AtomicU64,
+    hits: AtomicU64,
+    false_positives: AtomicU64,
+    index_bytes_written: AtomicU64,
+    primary_bytes_read: AtomicU64,
+}
+
+impl AtomicMetrics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+/// Converts without panicking, values that don't fit are capped.
+fn saturating_u64<T>(value: T) -> u64
+where
+    u64: TryFrom<T>,
+{
+    u64::try_from(value).unwrap_or(u64::MAX)
+}
+
+/// Adds to a counter, it wraps around on overflow instead of panicking.
+fn add(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+impl Metrics for AtomicMetrics {
+    fn record_put(&self, duration: Duration, record_list_bytes: usize) {
+        add(&self.puts, 1);
+        add(&self.put_nanos, saturating_u64(duration.as_nanos()));
+        self.max_record_list_bytes
+            .fetch_max(saturating_u64(record_list_bytes), Ordering::Relaxed);
+    }
+
+    fn record_get(&self, duration: Duration, hit: bool, false_positive: bool) {
+        add(&self.gets, 1);
+        add(&self.get_nanos, saturating_u64(duration.as_nanos()));
+        add(&self.hits, u64::from(hit));
+        add(&self.false_positives, u64::from(false_positive));
+    }
+
+    fn record_index_write(&self, bytes: usize) {
+        add(&self.index_bytes_written, saturating_u64(bytes));
+    }
+
+    fn record_primary_read(&self, bytes: usize) {
+        add(&self.primary_bytes_read, saturating_u64(bytes));
+    }
+
+    fn snapshot(&self) -> Option<MetricsSnapshot> {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Some(MetricsSnapshot {
+            puts: load(&self.puts),
+            put_time: Duration::from_nanos(load(&self.put_nanos)),
+            max_record_list_bytes: load(&self.max_record_list_bytes),
+            gets: load(&self.gets),
+            get_time: Duration::from_nanos(load(&self.get_nanos)),
+            hits: load(&self.hits),
+            false_positives: load(&self.false_positives),
+            index_bytes_written: load(&self.index_bytes_written),
+            primary_bytes_read: load(&self.primary_bytes_read),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{AtomicMetrics, Metrics, MetricsSnapshot, NullMetrics};
+
+    #[test]
+    fn atomic_metrics() {
+        let metrics = AtomicMetrics::new();
+        metrics.record_put(Duration::from_micros(3), 40);
+        metrics.record_put(Duration::from_micros(5), 20);
+        metrics.record_get(Duration::from_micros(2), true, false);
+        metrics.record_get(Duration::from_micros(2), false, true);
+        metrics.record_index_write(49);
+        metrics.record_primary_read(12);
+        assert_eq!(
+            metrics.snapshot().unwrap(),
+            MetricsSnapshot {
+                puts: 2,
+                put_time: Duration::from_micros(8),
+                max_record_list_bytes: 40,
+                gets: 2,
+                get_time: Duration::from_micros(4),
+                hits: 1,
+                false_positives: 1,
+                index_bytes_written: 49,
+                primary_bytes_read: 12,
+            }
+        );
+        assert_eq!(NullMetrics.snapshot(), None);
+    }
+}
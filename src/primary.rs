@@ -3,17 +3,89 @@
 //! The secondary index should work independent of how the primary data is stored. Likely the
 //! primary data is stored in a file alongside the index. But it could also be in memory or on a
 //! remote server.
+use std::fs::File;
+use std::io;
+use std::iter;
+use std::path::{Path, PathBuf};
+
 use thiserror::Error;
 
+use crate::error::io_context;
+
 #[derive(Error, Debug)]
 pub enum PrimaryError {
-    #[error("Out of bounds error.")]
-    OutOfBounds,
-    #[error("IO error.")]
-    Io(#[from] std::io::Error),
-    // Catch-all for errors that could happen within the primary storage.
+    /// The requested position is past the end of the storage (or of the data read from it).
+    #[error("Out of bounds error: position {pos} is past the length {len}.")]
+    OutOfBounds { pos: u64, len: u64 },
+    /// An I/O error together with the operation that failed and where it happened, if known.
+    #[error("IO error{}: {source}", io_context(*.operation, .path, *.offset))]
+    Io {
+        operation: Option<&'static str>,
+        path: Option<PathBuf>,
+        offset: Option<u64>,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Primary storage is read-only.")]
+    ReadOnly,
+    /// The requested position isn't the start of an entry, but e.g. in the middle of one.
+    #[error("Misaligned read: position {pos} is not the start of an entry.")]
+    MisalignedRead { pos: u64 },
+    /// The entry at the position was read, but its data doesn't match the stored checksum.
+    #[error("Checksum mismatch: entry at position {pos} is corrupt.")]
+    ChecksumMismatch { pos: u64 },
+    // Catch-all for errors that could happen within the primary storage. It's `Send + Sync`, so
+    // that errors can be passed between threads.
     #[error(transparent)]
-    Other(Box<dyn std::error::Error>),
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl PrimaryError {
+    /// Returns a function that converts an I/O error into one with the given context.
+    ///
+    /// It's meant to be used with `map_err`, see [`crate::error::Error::io`].
+    pub fn io<'a>(
+        operation: &'static str,
+        path: &'a Path,
+        offset: Option<u64>,
+    ) -> impl FnOnce(io::Error) -> Self + 'a {
+        move |source| Self::Io {
+            operation: Some(operation),
+            path: Some(path.to_path_buf()),
+            offset,
+            source,
+        }
+    }
+
+    /// Adds context to an I/O error that doesn't have any yet, other errors are returned as is.
+    pub fn with_io_context(
+        self,
+        operation: &'static str,
+        path: &Path,
+        offset: Option<u64>,
+    ) -> Self {
+        match self {
+            Self::Io {
+                operation: None,
+                path: None,
+                offset: None,
+                source,
+            } => Self::io(operation, path, offset)(source),
+            error => error,
+        }
+    }
+}
+
+/// An I/O error without any context.
+impl From<io::Error> for PrimaryError {
+    fn from(source: io::Error) -> Self {
+        Self::Io {
+            operation: None,
+            path: None,
+            offset: None,
+            source,
+        }
+    }
 }
 
 pub trait PrimaryStorage {
@@ -23,6 +95,101 @@ pub trait PrimaryStorage {
     /// Saves a key-value pair and returns the position it was stored at.
     fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError>;
 
+    /// Flushes all buffered writes and syncs them to disk.
+    ///
+    /// By default it does nothing, which is correct for storages that don't buffer any writes.
+    fn flush(&self) -> Result<(), PrimaryError> {
+        Ok(())
+    }
+
+    /// Copies all data into a new file at the given path and returns its size in bytes.
+    ///
+    /// The copy can be opened as primary storage again. By default snapshots are not supported.
+    fn snapshot(&self, _path: &Path) -> Result<u64, PrimaryError> {
+        Err(PrimaryError::Other(
+            "Snapshots are not supported by this primary storage.".into(),
+        ))
+    }
+
+    /// Returns the size of the stored data in bytes.
+    ///
+    /// By default `None` is returned, for storages that cannot tell.
+    fn size(&self) -> Result<Option<u64>, PrimaryError> {
+        Ok(None)
+    }
+
+    /// Returns the position of the first entry.
+    ///
+    /// It's only different from 0 for storages that have a header.
+    fn first_pos(&self) -> u64 {
+        0
+    }
+
+    /// Returns the position right after the entry at the given position.
+    ///
+    /// Starting at [`PrimaryStorage::first_pos`], it can be used to go through all stored entries.
+    /// By default it's not supported.
+    fn next_pos(&self, _pos: u64) -> Result<u64, PrimaryError> {
+        Err(PrimaryError::Other(
+            "Iterating is not supported by this primary storage.".into(),
+        ))
+    }
+
+    /// Returns the values of all stored entries in the order they were stored, without their keys.
+    ///
+    /// Every stored entry is returned, also the ones the index doesn't point to, e.g. because
+    /// they were deleted. By default it goes through the entries with [`PrimaryStorage::next_pos`] and
+    /// [`PrimaryStorage::get`], storages can implement it without decoding the keys. The iteration
+    /// stops after the first error.
+    fn iter_values(&self) -> Box<dyn Iterator<Item = Result<Vec<u8>, PrimaryError>> + '_> {
+        let size = match self.size() {
+            Ok(Some(size)) => size,
+            Ok(None) => {
+                return Box::new(iter::once(Err(PrimaryError::Other(
+                    "Size of the primary storage is unknown.".into(),
+                ))))
+            }
+            Err(error) => return Box::new(iter::once(Err(error))),
+        };
+        let mut pos = self.first_pos();
+        Box::new(iter::from_fn(move || {
+            if pos >= size {
+                return None;
+            }
+            let value = self.get(pos).and_then(|(_key, value)| {
+                pos = self.next_pos(pos)?;
+                Ok(value)
+            });
+            if value.is_err() {
+                pos = size;
+            }
+            Some(value)
+        }))
+    }
+
+    /// Hints that all entries from the given position on are about to be read in ascending order.
+    ///
+    /// Scans like [`crate::db::Db::verify`] call it before they start and
+    /// [`PrimaryStorage::advise_normal`] once they are done. Storages that are backed by a file
+    /// can use [`crate::readahead::advise_sequential`]. By default it does nothing.
+    fn advise_sequential(&self, _pos: u64) -> Result<(), PrimaryError> {
+        Ok(())
+    }
+
+    /// Reverts [`PrimaryStorage::advise_sequential`], so that lookups don't read ahead.
+    ///
+    /// By default it does nothing.
+    fn advise_normal(&self) -> Result<(), PrimaryError> {
+        Ok(())
+    }
+
+    /// Returns whether the entry at the given position is intact.
+    ///
+    /// By default every entry is assumed to be intact, for storages that cannot tell.
+    fn verify_at(&self, _pos: u64) -> Result<bool, PrimaryError> {
+        Ok(true)
+    }
+
     /// Creates a key that can be used for the index.
     ///
     /// The index needs a key which is at least 4 bytes long and contains random bytes (the more
@@ -42,3 +209,39 @@ pub trait PrimaryStorage {
         Self::index_key(&key)
     }
 }
+
+/// Reads exactly enough bytes to fill the buffer, starting at the given offset, without using
+/// the cursor of the file.
+///
+/// Storages that are backed by a file use it, so that concurrent reads don't interfere with each
+/// other or with the writes.
+#[cfg(unix)]
+pub fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<(), io::Error> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buffer, offset)
+}
+
+/// Reads exactly enough bytes to fill the buffer, starting at the given offset.
+///
+/// On Windows the cursor of the file is moved, though it's not used for the offset.
+#[cfg(windows)]
+pub fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> Result<(), io::Error> {
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            }
+            Ok(bytes_read) => {
+                buffer = &mut buffer[bytes_read..];
+                offset += bytes_read as u64;
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
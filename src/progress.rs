@@ -0,0 +1,247 @@
+//! Report the progress of long-running bulk imports and maintenance operations.
+//!
+//! All progress is reported to a [`ProgressSink`]. One can be set with
+//! [`crate::db::DbBuilder::with_progress_sink`], it is then called with the totals so far every
+//! [`crate::db::DbBuilder::progress_interval`] successful [`crate::db::Db::put`]s, so that it
+//! doesn't slow down the writes.
+//!
+//! Operations that go through a whole database, e.g. [`crate::db::Db::verify_with_progress`],
+//! take a sink as parameter and report their phase to it. They throttle the reports with a
+//! [`ProgressReporter`].
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The number of keys after which a database calls its progress sink by default, see
+/// [`crate::db::DbBuilder::progress_interval`].
+pub const DEFAULT_PROGRESS_INTERVAL: u64 = 100_000;
+
+/// Receives the progress of the writes to a database and of long-running operations.
+///
+/// Both methods do nothing by default, a sink only implements the ones it's interested in.
+pub trait ProgressSink: Send + Sync {
+    /// Called with the number of keys that were inserted and the number of bytes (keys and
+    /// values) that were written since the database was opened.
+    fn report(&self, _keys_inserted: u64, _bytes_written: u64) {}
+
+    /// Called with the name of an operation, the number of items that are done and the total
+    /// number of items, if it is known upfront.
+    ///
+    /// The unit of the items depends on the operation, e.g. buckets or entries.
+    fn report_phase(&self, _phase: &str, _done: u64, _total: Option<u64>) {}
+}
+
+/// Returns the message that the printing sinks use for the progress of an operation.
+fn phase_message(phase: &str, done: u64, total: Option<u64>) -> String {
+    match total {
+        Some(total) => format!("{}: {}/{}", phase, done, total),
+        None => format!("{}: {}", phase, done),
+    }
+}
+
+/// A progress sink that ignores all reports.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullProgressSink;
+
+impl ProgressSink for NullProgressSink {}
+
+/// A progress sink that prints every report to stderr.
+///
+/// With the default interval of the database that's every [`DEFAULT_PROGRESS_INTERVAL`] keys.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StderrProgressSink;
+
+impl ProgressSink for StderrProgressSink {
+    fn report(&self, keys_inserted: u64, bytes_written: u64) {
+        eprintln!(
+            "{} keys inserted, {} bytes written",
+            keys_inserted, bytes_written
+        );
+    }
+
+    fn report_phase(&self, phase: &str, done: u64, total: Option<u64>) {
+        eprintln!("{}", phase_message(phase, done, total));
+    }
+}
+
+/// A progress sink that prints every report to stdout, it's used by the CLI.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdoutProgressSink;
+
+impl ProgressSink for StdoutProgressSink {
+    fn report(&self, keys_inserted: u64, bytes_written: u64) {
+        println!(
+            "{} keys inserted, {} bytes written",
+            keys_inserted, bytes_written
+        );
+    }
+
+    fn report_phase(&self, phase: &str, done: u64, total: Option<u64>) {
+        println!("{}", phase_message(phase, done, total));
+    }
+}
+
+/// A progress sink that prints the writes to stderr at most once per interval.
+///
+/// The first report happens once the interval passed after the sink was created. It only sees the
+/// reports of the database, hence it's best combined with a small
+/// [`crate::db::DbBuilder::progress_interval`]. The progress of operations is already throttled by
+/// a [`ProgressReporter`], it's always printed.
+#[derive(Debug)]
+pub struct PeriodicProgressSink {
+    interval: Duration,
+    /// The time of the last report.
+    last_report: Mutex<Instant>,
+}
+
+impl PeriodicProgressSink {
+    pub fn new(interval_secs: u64) -> Self {
+        Self {
+            interval: Duration::from_secs(interval_secs),
+            last_report: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Returns whether the interval passed since the last report, if so the next interval starts.
+    fn is_due(&self, now: Instant) -> bool {
+        // A poisoned lock only means that a previous report panicked, the time is still valid.
+        let mut last_report = match self.last_report.lock() {
+            Ok(last_report) => last_report,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if now.duration_since(*last_report) >= self.interval {
+            *last_report = now;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl ProgressSink for PeriodicProgressSink {
+    fn report(&self, keys_inserted: u64, bytes_written: u64) {
+        if self.is_due(Instant::now()) {
+            eprintln!(
+                "{} keys inserted, {} bytes written",
+                keys_inserted, bytes_written
+            );
+        }
+    }
+
+    fn report_phase(&self, phase: &str, done: u64, total: Option<u64>) {
+        eprintln!("{}", phase_message(phase, done, total));
+    }
+}
+
+/// The minimum time between two reports of a [`ProgressReporter`].
+pub const PROGRESS_REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Passes the progress of an operation on to [`ProgressSink::report_phase`], at most once every
+/// [`PROGRESS_REPORT_INTERVAL`].
+///
+/// The final state is always reported by [`ProgressReporter::finish`].
+pub struct ProgressReporter<'a> {
+    progress: Option<&'a dyn ProgressSink>,
+    phase: &'a str,
+    total: Option<u64>,
+    /// The time of the last report.
+    last_report: Instant,
+}
+
+impl<'a> ProgressReporter<'a> {
+    /// Creates a reporter for a single operation, without a sink nothing is reported.
+    pub fn new(progress: Option<&'a dyn ProgressSink>, phase: &'a str, total: Option<u64>) -> Self {
+        Self {
+            progress,
+            phase,
+            total,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Reports the number of items that are done, if the interval passed since the last report.
+    pub fn update(&mut self, done: u64) {
+        if self.progress.is_some() {
+            self.update_at(done, Instant::now());
+        }
+    }
+
+    fn update_at(&mut self, done: u64, now: Instant) {
+        if now.duration_since(self.last_report) >= PROGRESS_REPORT_INTERVAL {
+            self.last_report = now;
+            self.report(done);
+        }
+    }
+
+    /// Reports the number of items once the operation is done.
+    pub fn finish(self, done: u64) {
+        self.report(done);
+    }
+
+    fn report(&self, done: u64) {
+        if let Some(progress) = self.progress {
+            progress.report_phase(self.phase, done, self.total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use super::{PeriodicProgressSink, ProgressReporter, ProgressSink, PROGRESS_REPORT_INTERVAL};
+
+    #[derive(Default)]
+    struct RecordingProgress(Mutex<Vec<(String, u64, Option<u64>)>>);
+
+    impl ProgressSink for RecordingProgress {
+        fn report_phase(&self, phase: &str, done: u64, total: Option<u64>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((phase.to_string(), done, total));
+        }
+    }
+
+    #[test]
+    fn progress_reporter_is_throttled() {
+        let progress = RecordingProgress::default();
+        let mut reporter = ProgressReporter::new(Some(&progress), "test", Some(10));
+        let start = reporter.last_report;
+        reporter.update_at(1, start + PROGRESS_REPORT_INTERVAL / 2);
+        reporter.update_at(2, start + PROGRESS_REPORT_INTERVAL);
+        reporter.update_at(3, start + PROGRESS_REPORT_INTERVAL * 3 / 2);
+        reporter.update_at(4, start + PROGRESS_REPORT_INTERVAL * 2);
+        reporter.finish(10);
+        assert_eq!(
+            *progress.0.lock().unwrap(),
+            vec![
+                ("test".to_string(), 2, Some(10)),
+                ("test".to_string(), 4, Some(10)),
+                ("test".to_string(), 10, Some(10)),
+            ]
+        );
+
+        // Without a sink nothing happens.
+        let mut reporter = ProgressReporter::new(None, "test", None);
+        reporter.update(1);
+        reporter.finish(1);
+    }
+
+    #[test]
+    fn periodic_is_due() {
+        let sink = PeriodicProgressSink::new(10);
+        let start = *sink.last_report.lock().unwrap();
+        assert!(!sink.is_due(start + Duration::from_secs(9)));
+        assert!(sink.is_due(start + Duration::from_secs(10)));
+        // The next interval starts with the last report.
+        assert!(!sink.is_due(start + Duration::from_secs(15)));
+        assert!(sink.is_due(start + Duration::from_secs(21)));
+
+        // Without an interval every call reports.
+        let sink = PeriodicProgressSink::new(0);
+        assert!(sink.is_due(Instant::now()));
+        assert!(sink.is_due(Instant::now()));
+    }
+}
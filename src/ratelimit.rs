@@ -0,0 +1,124 @@
+//! Limit the rate at which data is written to a database.
+//!
+//! A [`RateLimiter`] can be set with [`crate::db::DbBuilder::with_rate_limiter`]. It is called
+//! before anything is written on every [`crate::db::Db::put`].
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+/// Decides whether and when a write may happen.
+pub trait RateLimiter: Send + Sync {
+    /// Acquires the permission to write the given number of bytes.
+    ///
+    /// It may block until the bytes are available. If they can never be acquired, an error
+    /// (usually [`Error::RateLimited`]) is returned and nothing is written.
+    fn acquire(&self, bytes: usize) -> Result<(), Error>;
+}
+
+/// A rate limiter based on the token bucket algorithm.
+///
+/// The bucket holds up to `capacity` bytes and is refilled with `bytes_per_second`. It starts
+/// full, hence bursts of up to `capacity` bytes are written without delay. A write of more than
+/// `capacity` bytes can never be satisfied and returns [`Error::RateLimited`].
+#[derive(Debug)]
+pub struct TokenBucketRateLimiter {
+    capacity: u64,
+    bytes_per_second: u64,
+    state: Mutex<TokenBucketState>,
+}
+
+#[derive(Debug)]
+struct TokenBucketState {
+    /// The number of bytes that can currently be written.
+    tokens: f64,
+    /// The time when the tokens were last refilled.
+    last_refill: Instant,
+}
+
+impl TokenBucketRateLimiter {
+    pub fn new(capacity: u64, bytes_per_second: u64) -> Self {
+        Self {
+            capacity,
+            bytes_per_second,
+            state: Mutex::new(TokenBucketState {
+                tokens: capacity as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Adds the tokens that were refilled since the last call.
+    fn refill(&self, state: &mut TokenBucketState) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens =
+            (state.tokens + elapsed * self.bytes_per_second as f64).min(self.capacity as f64);
+        state.last_refill = now;
+    }
+}
+
+impl RateLimiter for TokenBucketRateLimiter {
+    fn acquire(&self, bytes: usize) -> Result<(), Error> {
+        let bytes = bytes as f64;
+        if bytes > self.capacity as f64 {
+            return Err(Error::RateLimited);
+        }
+
+        // The lock is held while waiting, so that concurrent writers are served in order.
+        let mut state = self.state.lock().map_err(|_| Error::RateLimited)?;
+        self.refill(&mut state);
+        if state.tokens < bytes {
+            if self.bytes_per_second == 0 {
+                return Err(Error::RateLimited);
+            }
+            let missing = bytes - state.tokens;
+            thread::sleep(Duration::from_secs_f64(
+                missing / self.bytes_per_second as f64,
+            ));
+            self.refill(&mut state);
+        }
+        // Rounding might leave it a tiny bit short, that's fine.
+        state.tokens = (state.tokens - bytes).max(0.0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{RateLimiter, TokenBucketRateLimiter};
+    use crate::error::Error;
+
+    #[test]
+    fn token_bucket_burst() {
+        let limiter = TokenBucketRateLimiter::new(100, 1);
+        let start = Instant::now();
+        limiter.acquire(60).unwrap();
+        limiter.acquire(40).unwrap();
+        // The bucket starts full, hence there's no waiting for the first 100 bytes.
+        assert!(start.elapsed() < Duration::from_millis(500));
+        // It can never hold more than its capacity.
+        assert!(matches!(limiter.acquire(101), Err(Error::RateLimited)));
+    }
+
+    #[test]
+    fn token_bucket_waits_for_refill() {
+        let limiter = TokenBucketRateLimiter::new(100, 1000);
+        limiter.acquire(100).unwrap();
+        let start = Instant::now();
+        // 50 bytes at 1000 bytes per second take 50ms to refill.
+        limiter.acquire(50).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn token_bucket_no_refill() {
+        let limiter = TokenBucketRateLimiter::new(10, 0);
+        limiter.acquire(10).unwrap();
+        assert!(matches!(limiter.acquire(1), Err(Error::RateLimited)));
+    }
+}
@@ -0,0 +1,115 @@
+//! Hints to the operating system that a file is about to be read sequentially.
+//!
+//! Scans like [`crate::db::Db::verify`] or rebuilding an index read the primary storage from the
+//! beginning to the end. With a cold page cache they are much faster if the operating system
+//! reads ahead aggressively, which it only does once it detected the sequential access itself.
+//! [`advise_sequential`] tells it right away. Primary storages that are backed by a file use it
+//! to implement [`crate::primary::PrimaryStorage::advise_sequential`], so that all scans share it.
+//!
+//! The hints are only given with the `libc` feature on Linux and Android, where
+//! `posix_fadvise()` is used. Everywhere else the functions do nothing, a hint is never needed for
+//! correctness.
+use std::fs::File;
+use std::io;
+
+/// The number of bytes that [`advise_sequential`] asks the operating system to read right away.
+///
+/// Asking for more, e.g. the whole file, would evict other data from the page cache before it's
+/// needed.
+pub const WILLNEED_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Returns whether the hints have an effect on this platform, see the
+/// [module documentation](self).
+pub const fn is_supported() -> bool {
+    cfg!(all(
+        feature = "libc",
+        any(target_os = "linux", target_os = "android")
+    ))
+}
+
+/// Hints that the given range of the file is about to be read sequentially.
+///
+/// A `len` of 0 means until the end of the file. The first [`WILLNEED_SIZE`] bytes of the range
+/// are also requested to be read in the background. Hints for ranges past the end of the file
+/// are fine, they are ignored.
+pub fn advise_sequential(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    imp::advise_sequential(file, offset, len)
+}
+
+/// Reverts [`advise_sequential`] for the given range, once the sequential scan is done.
+///
+/// Otherwise the random reads of lookups would also read ahead a lot, which wastes I/O.
+pub fn advise_normal(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    imp::advise_normal(file, offset, len)
+}
+
+#[cfg(all(feature = "libc", any(target_os = "linux", target_os = "android")))]
+mod imp {
+    use std::convert::TryFrom;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    use super::WILLNEED_SIZE;
+
+    fn fadvise(file: &File, offset: u64, len: u64, advice: libc::c_int) -> io::Result<()> {
+        let to_off_t = |value| {
+            libc::off_t::try_from(value)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "range too big for off_t"))
+        };
+        let (offset, len) = (to_off_t(offset)?, to_off_t(len)?);
+        let result = unsafe { libc::posix_fadvise(file.as_raw_fd(), offset, len, advice) };
+        // It returns the error instead of setting `errno`.
+        match result {
+            0 => Ok(()),
+            error => Err(io::Error::from_raw_os_error(error)),
+        }
+    }
+
+    pub fn advise_sequential(file: &File, offset: u64, len: u64) -> io::Result<()> {
+        fadvise(file, offset, len, libc::POSIX_FADV_SEQUENTIAL)?;
+        let willneed_len = if len == 0 {
+            WILLNEED_SIZE
+        } else {
+            len.min(WILLNEED_SIZE)
+        };
+        fadvise(file, offset, willneed_len, libc::POSIX_FADV_WILLNEED)
+    }
+
+    pub fn advise_normal(file: &File, offset: u64, len: u64) -> io::Result<()> {
+        fadvise(file, offset, len, libc::POSIX_FADV_NORMAL)
+    }
+}
+
+#[cfg(not(all(feature = "libc", any(target_os = "linux", target_os = "android"))))]
+mod imp {
+    use std::fs::File;
+    use std::io;
+
+    pub fn advise_sequential(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn advise_normal(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{advise_normal, advise_sequential, WILLNEED_SIZE};
+
+    #[test]
+    fn hints_succeed() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[1; 4096]).unwrap();
+
+        advise_sequential(&file, 0, 0).unwrap();
+        advise_sequential(&file, 100, 10).unwrap();
+        // Past the end of the file.
+        advise_sequential(&file, 10 * WILLNEED_SIZE, WILLNEED_SIZE).unwrap();
+        advise_normal(&file, 0, 0).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Reads from the primary storage in the order of the positions.
+//!
+//! Reading many entries in random order is slow, on spinning disks because of the seeks and on
+//! SSDs because the readahead of the operating system is wasted. Where many positions are known
+//! before the primary storage is read, e.g. for [`crate::db::Db::get_many`], they are collected in
+//! a [`ReadScheduler`], which reads them in ascending order and returns the results in the order
+//! they were requested.
+
+/// Collects positions and reads them in ascending order, see the [module documentation](self).
+#[derive(Debug, Default)]
+pub(crate) struct ReadScheduler {
+    positions: Vec<u64>,
+}
+
+impl ReadScheduler {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            positions: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Adds a position to read.
+    pub fn push(&mut self, pos: u64) {
+        self.positions.push(pos);
+    }
+
+    /// Calls `read` for every position in ascending order of the positions.
+    ///
+    /// The results are in the order the positions were added. Equal positions are read in the
+    /// order they were added and once per time they were added.
+    pub fn run<R, F>(self, mut read: F) -> Vec<R>
+    where
+        F: FnMut(u64) -> R,
+    {
+        let mut order: Vec<usize> = (0..self.positions.len()).collect();
+        order.sort_by_key(|index| self.positions[*index]);
+        let mut results: Vec<Option<R>> = (0..self.positions.len()).map(|_| None).collect();
+        for index in order {
+            results[index] = Some(read(self.positions[index]));
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every position was read"))
+            .collect()
+    }
+}
+
+impl From<Vec<u64>> for ReadScheduler {
+    fn from(positions: Vec<u64>) -> Self {
+        Self { positions }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReadScheduler;
+
+    #[test]
+    fn reads_are_sorted() {
+        let mut scheduler = ReadScheduler::with_capacity(4);
+        scheduler.push(30);
+        scheduler.push(10);
+        scheduler.push(20);
+        scheduler.push(10);
+
+        let mut read_order = Vec::new();
+        let results = scheduler.run(|pos| {
+            read_order.push(pos);
+            pos * 2
+        });
+        assert_eq!(read_order, [10, 10, 20, 30]);
+        assert_eq!(results, [60, 20, 40, 20]);
+
+        assert!(ReadScheduler::default().run(|pos| pos).is_empty());
+        assert_eq!(ReadScheduler::from(vec![2, 1]).run(|pos| pos * 2), [4, 2]);
+    }
+}
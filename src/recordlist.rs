@@ -1,10 +1,21 @@
 ///! Implement a data structure that supports storing and retrieving file offsets by key.
+use std::cmp::{self, Ordering};
 use std::convert::TryInto;
+use std::fmt;
 use std::io::{self, Read};
 use std::ops::Range;
 
+use thiserror::Error;
+
+use crate::index::{first_non_common_byte, strip_bucket_prefix};
+use crate::primary::{PrimaryError, PrimaryStorage};
+
 /// In how many bytes the bucket prefixes are stored.
 pub const BUCKET_PREFIX_SIZE: usize = 4;
+/// In how many bytes the number of bits used for the buckets is stored.
+pub const BUCKETS_BITS_SIZE: usize = 1;
+/// The size of the data that precedes the records.
+pub const RECORDLIST_HEADER_SIZE: usize = BUCKET_PREFIX_SIZE + BUCKETS_BITS_SIZE;
 
 // Byte size of the file offset
 const FILE_OFFSET_BYTES: usize = 8;
@@ -23,18 +34,53 @@ pub struct Record<'a> {
     pub file_offset: u64,
 }
 
+/// Formats the record as the hex encoded key and the file offset, e.g. `0a0b0c -> 1234`.
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.key {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, " -> {}", self.file_offset)
+    }
+}
+
+/// The difference between two record lists, see [`RecordList::diff`].
+#[derive(Debug, Default, PartialEq)]
+pub struct RecordListDiff<'a> {
+    /// Records that are only in the new record list.
+    pub added: Vec<Record<'a>>,
+    /// Records that are only in the old record list.
+    pub removed: Vec<Record<'a>>,
+    /// Records that have the same key, but a different file offset, as `(old, new)` pairs.
+    pub updated: Vec<(Record<'a>, Record<'a>)>,
+}
+
+/// Why a record list is invalid, see [`RecordList::validate`].
+#[derive(Debug, Error, PartialEq)]
+pub enum RecordListError {
+    #[error("Record list is truncated or its records are out of bounds.")]
+    Malformed,
+    #[error("Record at position `{pos}` is not sorted by key.")]
+    Unsorted { pos: usize },
+}
+
 /// The main object that contains several [`Record`]s. Records can be stored and retrieved.
 ///
 /// The underlying data is a continuous range of bytes. The format is:
 ///
 /// ```text
-///     |                  Once                  |      Repeated     |
-///     |                                        |                   |
-///     |                 4 bytes                | Variable size | … |
-///     | Bit value used to determine the bucket |     Record    | … |
+///     |                  Once                  |               Once              |      Repeated     |
+///     |                                        |                                 |                   |
+///     |                 4 bytes                |              1 byte             | Variable size | … |
+///     | Bit value used to determine the bucket | Number of bits used for buckets |     Record    | … |
 /// ```
+///
+/// The number of bits used for the buckets is stored, so that a record list can be validated
+/// without knowing the header of the index it is part of.
 #[derive(Debug)]
 pub struct RecordList<'a> {
+    /// The number of bits that were used to determine the buckets.
+    buckets_bits: u8,
     /// The bytes containing the records.
     data: &'a [u8],
 }
@@ -44,16 +90,74 @@ impl<'a> RecordList<'a> {
         // The record list itself doesn't care about the bits that were used to associate it with a
         // bucket, hence we just skip those.
         Self {
-            data: &data[BUCKET_PREFIX_SIZE..],
+            buckets_bits: data[BUCKET_PREFIX_SIZE],
+            data: &data[RECORDLIST_HEADER_SIZE..],
+        }
+    }
+
+    /// Returns whether the data (including the bucket prefix) is a structurally valid record list.
+    ///
+    /// It checks that there is a header and that all records are within the bounds of the data.
+    /// Only well-formed record lists can be read without panicking.
+    pub fn is_well_formed(data: &[u8]) -> bool {
+        if data.len() < RECORDLIST_HEADER_SIZE {
+            return false;
+        }
+        let records = &data[RECORDLIST_HEADER_SIZE..];
+        let mut pos = 0;
+        while pos < records.len() {
+            let size_offset = pos + FILE_OFFSET_BYTES;
+            match records.get(size_offset) {
+                Some(size) => pos = size_offset + KEY_SIZE_BYTE + usize::from(*size),
+                None => return false,
+            }
+        }
+        pos == records.len()
+    }
+
+    /// Checks that the data (including the bucket prefix) is a valid record list.
+    ///
+    /// Besides the checks of [`RecordList::is_well_formed`] it makes sure that the records are
+    /// sorted by their keys. Keys that are equal are tolerated, they are what
+    /// [`RecordList::get_records`] is for.
+    pub fn validate(data: &[u8]) -> Result<(), RecordListError> {
+        if !Self::is_well_formed(data) {
+            return Err(RecordListError::Malformed);
         }
+        let records = RecordList::new(data);
+        let mut prev_key: Option<&[u8]> = None;
+        for record in &records {
+            if matches!(prev_key, Some(prev_key) if prev_key > record.key) {
+                return Err(RecordListError::Unsorted { pos: record.pos });
+            }
+            prev_key = Some(record.key);
+        }
+        Ok(())
+    }
+
+    /// The number of bits that were used to determine the buckets of the index.
+    pub fn buckets_bits(&self) -> u8 {
+        self.buckets_bits
     }
 
     /// Finds the position where a key would be added.
     ///
     /// Returns the position together with the previous record.
     pub fn find_key_position(&self, key: &[u8]) -> (usize, Option<Record>) {
+        self.find_key_position_from(key, 0)
+    }
+
+    /// Same as [`RecordList::find_key_position`], but it starts at the given position.
+    ///
+    /// The position must point to the first byte of a record whose key isn't bigger than the
+    /// given key, all records before it are skipped.
+    pub fn find_key_position_from(&self, key: &[u8], start: usize) -> (usize, Option<Record<'_>>) {
         let mut prev_record = None;
-        for record in self {
+        let records = RecordListIter {
+            records: self,
+            pos: start,
+        };
+        for record in records {
             // Location where the key gets inserted is found
             if record.key > key {
                 return (record.pos, prev_record);
@@ -96,8 +200,16 @@ impl<'a> RecordList<'a> {
     /// As the index is only storing prefixes and not the actual keys, the returned offset might
     /// match, it's not guaranteed. Once the key is retieved from the primary storage it needs to
     /// be checked if it actually matches.
-    #[allow(clippy::suspicious_else_formatting)]
     pub fn get(&self, key: &[u8]) -> Option<u64> {
+        self.get_record(key).map(|record| record.file_offset)
+    }
+
+    /// Get the record that matches that key.
+    ///
+    /// The same restrictions as for [`RecordList::get`] apply, the record might not match the
+    /// actual key.
+    #[allow(clippy::suspicious_else_formatting)]
+    pub fn get_record(&self, key: &[u8]) -> Option<Record> {
         // Several prefixes can match a `key`, we are only interested in the last one that
         // matches, hence keep a match around until we can be sure it's the last one.
         let mut might_match = None;
@@ -112,7 +224,65 @@ impl<'a> RecordList<'a> {
                 break;
             }
         }
-        might_match.map(|record| record.file_offset)
+        might_match
+    }
+
+    /// Get all records whose stored key is a prefix of the given key.
+    ///
+    /// The records are sorted by their stored key, hence the last one is the one
+    /// [`RecordList::get_record`] would return. Usually there is at most one record, more only
+    /// show up if the stored keys are not distinguishable from each other.
+    pub fn get_records(&self, key: &[u8]) -> Vec<Record> {
+        self.get_records_with_probe_count(key).0
+    }
+
+    /// Same as [`RecordList::get_records`], but it also returns the number of records that were
+    /// scanned.
+    pub fn get_records_with_probe_count(&self, key: &[u8]) -> (Vec<Record<'_>>, usize) {
+        let mut matches = Vec::new();
+        let mut probe_count = 0;
+        for record in self {
+            probe_count += 1;
+            if key.starts_with(record.key) {
+                matches.push(record);
+            }
+            // No keys from here on can possibly match.
+            else if record.key > key {
+                break;
+            }
+        }
+        (matches, probe_count)
+    }
+
+    /// Same as [`RecordList::get_records_with_probe_count`], but only the last matching record is
+    /// returned, together with the number of matching records and the probe count.
+    ///
+    /// Unlike the former it doesn't allocate, the other matches are only needed on collisions.
+    pub fn get_last_record_with_counts(&self, key: &[u8]) -> (Option<Record<'_>>, usize, usize) {
+        let mut last_match = None;
+        let mut match_count = 0;
+        let mut probe_count = 0;
+        for record in self {
+            probe_count += 1;
+            if key.starts_with(record.key) {
+                last_match = Some(record);
+                match_count += 1;
+            }
+            // No keys from here on can possibly match.
+            else if record.key > key {
+                break;
+            }
+        }
+        (last_match, match_count, probe_count)
+    }
+
+    /// Removes the record at the given position and returns the new data.
+    ///
+    /// The given position must point to the first byte where the record starts.
+    pub fn remove_record(&self, pos: usize) -> Vec<u8> {
+        let record = self.read_record(pos);
+        let end = pos + FILE_OFFSET_BYTES + KEY_SIZE_BYTE + record.key.len();
+        self.put_keys(&[], pos..end)
     }
 
     /// Reads a record from a slice at the givem position.
@@ -131,6 +301,25 @@ impl<'a> RecordList<'a> {
         }
     }
 
+    /// Returns the record at the given index (0-indexed).
+    ///
+    /// The records are scanned from the start, hence it's O(n).
+    pub fn nth_record(&self, n: usize) -> Option<Record> {
+        self.into_iter().nth(n)
+    }
+
+    /// Returns the first record, the one with the lowest key.
+    pub fn first(&self) -> Option<Record> {
+        self.nth_record(0)
+    }
+
+    /// Returns the last record, the one with the highest key.
+    ///
+    /// All records are scanned, hence it's O(n).
+    pub fn last(&self) -> Option<Record> {
+        self.into_iter().last()
+    }
+
     /// The length of the record list.
     pub fn len(&self) -> usize {
         self.data.len()
@@ -140,6 +329,113 @@ impl<'a> RecordList<'a> {
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
+
+    /// Returns which records were added, removed or updated between two record lists.
+    ///
+    /// Records are compared by their stored key. Note that the stored keys are only prefixes, if
+    /// a key was extended in order to be distinguishable from a newly inserted one, it shows up
+    /// as removed (the old prefix) and added (the new prefix).
+    pub fn diff(old: &'a RecordList<'a>, new: &'a RecordList<'a>) -> RecordListDiff<'a> {
+        let mut diff = RecordListDiff::default();
+        let mut old_records = old.into_iter().peekable();
+        let mut new_records = new.into_iter().peekable();
+
+        // Both record lists are sorted by key, hence they can be merged in a single pass.
+        loop {
+            let ordering = match (old_records.peek(), new_records.peek()) {
+                (Some(old_record), Some(new_record)) => old_record.key.cmp(new_record.key),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match ordering {
+                Ordering::Less => diff
+                    .removed
+                    .push(old_records.next().expect("Record was peeked at")),
+                Ordering::Greater => diff
+                    .added
+                    .push(new_records.next().expect("Record was peeked at")),
+                Ordering::Equal => {
+                    let old_record = old_records.next().expect("Record was peeked at");
+                    let new_record = new_records.next().expect("Record was peeked at");
+                    if old_record.file_offset != new_record.file_offset {
+                        diff.updated.push((old_record, new_record));
+                    }
+                }
+            }
+        }
+
+        diff
+    }
+
+    /// Re-trims all keys to the shortest prefixes that distinguish them from their neighbors and
+    /// returns the new data.
+    ///
+    /// Keys only ever get longer when keys are inserted, after deletions they might be longer
+    /// than needed. The full keys are read from the primary storage. Like [`crate::fsck`] it
+    /// assumes that the index uses the [`crate::hasher::LeadingBytesHasher`].
+    pub fn compact<P: PrimaryStorage>(&self, primary: &P) -> Result<Vec<u8>, PrimaryError> {
+        let mut records = Vec::new();
+        for record in self {
+            let full_key = primary.get_index_key(record.file_offset)?;
+            let key = strip_bucket_prefix(&full_key, self.buckets_bits).to_vec();
+            records.push((key, record.file_offset));
+        }
+
+        let mut result = Vec::with_capacity(self.data.len());
+        for (ii, (key, file_offset)) in records.iter().enumerate() {
+            let prev_key = ii.checked_sub(1).map(|prev| &records[prev].0[..]);
+            let next_key = records.get(ii + 1).map(|(next_key, _)| &next_key[..]);
+            let min_prefix = min_prefix_len(key, prev_key, next_key);
+            extend_with_offset_and_key(&mut result, &key[..min_prefix], *file_offset);
+        }
+        Ok(result)
+    }
+
+    /// Returns the byte size of the records if all keys were re-trimmed, see
+    /// [`RecordList::compact`].
+    ///
+    /// Unlike compacting, it doesn't need the primary storage. Neighboring keys are
+    /// distinguishable, hence their stored prefixes differ at the same byte as their full keys.
+    /// A key whose neighbor's stored key is a prefix of it isn't distinguishable, it's kept as it
+    /// is.
+    pub fn bytes_optimal(&self) -> usize {
+        let keys: Vec<&[u8]> = self.into_iter().map(|record| record.key).collect();
+        keys.iter()
+            .enumerate()
+            .map(|(ii, key)| {
+                let prev_key = ii.checked_sub(1).map(|prev| keys[prev]);
+                let next_key = keys.get(ii + 1).copied();
+                if matches!(prev_key, Some(prev_key) if key.starts_with(prev_key)) {
+                    record_size(key)
+                } else {
+                    record_size(&key[..min_prefix_len(key, prev_key, next_key)])
+                }
+            })
+            .sum()
+    }
+
+    /// Returns how many bytes re-trimming the keys would save, the difference between
+    /// [`RecordList::len`] and [`RecordList::bytes_optimal`].
+    ///
+    /// Keys only get longer than needed when their neighbors are deleted. It tells whether
+    /// compacting the record list pays off.
+    pub fn bytes_wasted(&self) -> usize {
+        self.len() - self.bytes_optimal()
+    }
+}
+
+/// Returns the length of the shortest prefix of a key that distinguishes it from its neighbors.
+///
+/// Keys that aren't distinguishable from their neighbors are kept as they are.
+fn min_prefix_len(key: &[u8], prev_key: Option<&[u8]>, next_key: Option<&[u8]>) -> usize {
+    let non_common_byte_pos = |neighbor: Option<&[u8]>| {
+        neighbor.map_or(0, |neighbor| first_non_common_byte(key, neighbor))
+    };
+    cmp::min(
+        cmp::max(non_common_byte_pos(prev_key), non_common_byte_pos(next_key)) + 1,
+        key.len(),
+    )
 }
 
 impl<'a> IntoIterator for &'a RecordList<'a> {
@@ -186,7 +482,7 @@ impl<'a> Iterator for RecordListIter<'a> {
 ///     |         8 bytes        |      1 byte     | Variable size < 256 bytes |
 ///     | Pointer to actual data | Size of the key |            Key            |
 /// ```
-fn extend_with_offset_and_key(vec: &mut Vec<u8>, key: &[u8], offset: u64) {
+pub(crate) fn extend_with_offset_and_key(vec: &mut Vec<u8>, key: &[u8], offset: u64) {
     let size: u8 = key
         .len()
         .try_into()
@@ -196,9 +492,14 @@ fn extend_with_offset_and_key(vec: &mut Vec<u8>, key: &[u8], offset: u64) {
     vec.extend_from_slice(key);
 }
 
+/// Returns the number of bytes a record with the given key needs.
+pub fn record_size(key: &[u8]) -> usize {
+    FILE_OFFSET_BYTES + KEY_SIZE_BYTE + key.len()
+}
+
 /// Encodes a key and and offset into a single record
 pub fn encode_offset_and_key(key: &[u8], offset: u64) -> Vec<u8> {
-    let mut encoded = Vec::with_capacity(FILE_OFFSET_BYTES + KEY_SIZE_BYTE + key.len());
+    let mut encoded = Vec::with_capacity(record_size(key));
     extend_with_offset_and_key(&mut encoded, key, offset);
     encoded
 }
@@ -213,10 +514,27 @@ pub fn read_bucket_prefix<R: Read>(reader: &mut R) -> Result<u32, io::Error> {
 
 #[cfg(test)]
 mod tests {
-    use super::{encode_offset_and_key, Record, RecordList, FILE_OFFSET_BYTES, KEY_SIZE_BYTE};
+    use super::{
+        encode_offset_and_key, Record, RecordList, RecordListDiff, RecordListError,
+        FILE_OFFSET_BYTES, KEY_SIZE_BYTE, RECORDLIST_HEADER_SIZE,
+    };
 
     use std::str;
 
+    use quickcheck::quickcheck;
+
+    use crate::primary::{PrimaryError, PrimaryStorage};
+    use crate::testing::{self, ArbitraryRecordList};
+
+    // Returns the encoded record list (including the bucket prefix) of the given keys.
+    fn encode_record_list(keys: &[(&str, u64)]) -> Vec<u8> {
+        let keys: Vec<(&[u8], u64)> = keys
+            .iter()
+            .map(|(key, file_offset)| (key.as_bytes(), *file_offset))
+            .collect();
+        testing::encode_record_list(&keys)
+    }
+
     #[test]
     fn test_encode_offset_and_key() {
         let key = b"abcdefg";
@@ -252,8 +570,9 @@ mod tests {
             data.extend_from_slice(&encoded);
         }
 
-        // The record list have the bits that were used to determine the bucket as prefix
-        let prefixed_data = &[&[0, 0, 0, 0], &data[..]].concat();
+        // The record list have the bits that were used to determine the bucket and the number of
+        // bits used for the buckets as prefix
+        let prefixed_data = &[&[0, 0, 0, 0, 24], &data[..]].concat();
         // Verify that it can be correctly iterated over those encoded records
         let records = RecordList::new(&prefixed_data);
         let mut records_iter = records.into_iter();
@@ -271,8 +590,9 @@ mod tests {
             let encoded = encode_offset_and_key(key.as_bytes(), ii as u64);
             data.extend_from_slice(&encoded);
         }
-        // The record list have the bits that were used to determine the bucket as prefix
-        let prefixed_data = &[&[0, 0, 0, 0], &data[..]].concat();
+        // The record list have the bits that were used to determine the bucket and the number of
+        // bits used for the buckets as prefix
+        let prefixed_data = &[&[0, 0, 0, 0, 24], &data[..]].concat();
         let records = RecordList::new(&prefixed_data);
 
         // First key
@@ -324,8 +644,9 @@ mod tests {
     fn assert_add_key(records: &RecordList, key: &[u8]) {
         let (pos, _prev_record) = records.find_key_position(key);
         let new_data = records.put_keys(&[(key, 773)], pos..pos);
-        // The record list have the bits that were used to determine the bucket as prefix
-        let prefixed_new_data = &[&[0, 0, 0, 0], &new_data[..]].concat();
+        // The record list have the bits that were used to determine the bucket and the number of
+        // bits used for the buckets as prefix
+        let prefixed_new_data = &[&[0, 0, 0, 0, 24], &new_data[..]].concat();
         let new_records = RecordList::new(&prefixed_new_data);
         let (inserted_pos, inserted_record) = new_records.find_key_position(key);
         assert_eq!(
@@ -344,8 +665,9 @@ mod tests {
             let encoded = encode_offset_and_key(key.as_bytes(), ii as u64);
             data.extend_from_slice(&encoded);
         }
-        // The record list have the bits that were used to determine the bucket as prefix
-        let prefixed_data = &[&[0, 0, 0, 0], &data[..]].concat();
+        // The record list have the bits that were used to determine the bucket and the number of
+        // bits used for the buckets as prefix
+        let prefixed_data = &[&[0, 0, 0, 0, 24], &data[..]].concat();
         let records = RecordList::new(&prefixed_data);
 
         // First key
@@ -384,8 +706,9 @@ mod tests {
 
         let keys = [(new_prev_key, prev_record.file_offset), (key, 770)];
         let new_data = records.put_keys(&keys, prev_record.pos..pos);
-        // The record list have the bits that were used to determine the bucket as prefix
-        let prefixed_new_data = &[&[0, 0, 0, 0], &new_data[..]].concat();
+        // The record list have the bits that were used to determine the bucket and the number of
+        // bits used for the buckets as prefix
+        let prefixed_new_data = &[&[0, 0, 0, 0, 24], &new_data[..]].concat();
         let new_records = RecordList::new(&prefixed_new_data);
 
         // Find the newly added prev_key
@@ -416,8 +739,9 @@ mod tests {
             let encoded = encode_offset_and_key(key.as_bytes(), ii as u64);
             data.extend_from_slice(&encoded);
         }
-        // The record list have the bits that were used to determine the bucket as prefix
-        let prefixed_data = &[&[0, 0, 0, 0], &data[..]].concat();
+        // The record list have the bits that were used to determine the bucket and the number of
+        // bits used for the buckets as prefix
+        let prefixed_data = &[&[0, 0, 0, 0, 24], &data[..]].concat();
         let records = RecordList::new(&prefixed_data);
 
         // Between two keys with same prefix, but first one being shorter
@@ -445,16 +769,13 @@ mod tests {
 
     #[test]
     fn record_list_get_key() {
-        // Create data
-        let keys: Vec<&str> = vec!["a", "ac", "b", "de", "dn", "nky", "xrlfg"];
-        let mut data = Vec::new();
-        for (ii, key) in keys.iter().enumerate() {
-            let encoded = encode_offset_and_key(key.as_bytes(), ii as u64);
-            data.extend_from_slice(&encoded);
-        }
-        // The record list have the bits that were used to determine the bucket as prefix
-        let prefixed_data = &[&[0, 0, 0, 0], &data[..]].concat();
-        let records = RecordList::new(&prefixed_data);
+        let keys: Vec<(&str, u64)> = ["a", "ac", "b", "de", "dn", "nky", "xrlfg"]
+            .iter()
+            .enumerate()
+            .map(|(ii, key)| (*key, ii as u64))
+            .collect();
+        let data = encode_record_list(&keys);
+        let records = RecordList::new(&data);
 
         // First key
         let file_offset = records.get(b"a").unwrap();
@@ -492,4 +813,314 @@ mod tests {
         let file_offset = records.get(b"dg");
         assert_eq!(file_offset, None);
     }
+
+    #[test]
+    fn record_list_build() {
+        let data = testing::build_record_list(100, 8);
+        let records = RecordList::new(&data);
+        let keys: Vec<&[u8]> = records.into_iter().map(|record| record.key).collect();
+        assert_eq!(keys.len(), 100);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn record_list_get_every_record() {
+        fn prop(data: ArbitraryRecordList) -> bool {
+            let records = data.record_list();
+            data.records()
+                .iter()
+                .all(|record| records.get(&record.key) == Some(record.file_offset))
+        }
+        quickcheck(prop as fn(ArbitraryRecordList) -> bool);
+    }
+
+    #[test]
+    fn record_list_remove_every_record() {
+        fn prop(data: ArbitraryRecordList) -> bool {
+            let records = data.record_list();
+            records.into_iter().all(|record| {
+                let removed = records.remove_record(record.pos);
+                let prefixed = [&data.0[..RECORDLIST_HEADER_SIZE], &removed[..]].concat();
+                let after = RecordList::new(&prefixed);
+                after.get(record.key).is_none()
+                    && after.into_iter().count() == records.into_iter().count() - 1
+            })
+        }
+        quickcheck(prop as fn(ArbitraryRecordList) -> bool);
+    }
+
+    #[test]
+    fn record_list_nth_record() {
+        fn prop(data: ArbitraryRecordList) -> bool {
+            let records = data.record_list();
+            let all: Vec<Record> = records.into_iter().collect();
+            (0..=all.len()).all(|n| records.nth_record(n).as_ref() == all.get(n))
+                && records.first().as_ref() == all.first()
+                && records.last().as_ref() == all.last()
+        }
+        quickcheck(prop as fn(ArbitraryRecordList) -> bool);
+
+        let data = encode_record_list(&[("a", 0), ("b", 1), ("c", 2)]);
+        let records = RecordList::new(&data);
+        assert_eq!(records.nth_record(1).unwrap().key, b"b");
+        assert_eq!(records.first().unwrap().key, b"a");
+        assert_eq!(records.last().unwrap().key, b"c");
+        assert_eq!(records.nth_record(3), None);
+
+        let data = encode_record_list(&[]);
+        let records = RecordList::new(&data);
+        assert_eq!(records.first(), None);
+        assert_eq!(records.last(), None);
+    }
+
+    #[test]
+    fn record_list_is_well_formed() {
+        fn prop(data: ArbitraryRecordList) -> bool {
+            RecordList::is_well_formed(&data.0)
+        }
+        quickcheck(prop as fn(ArbitraryRecordList) -> bool);
+
+        let data = encode_record_list(&[("a", 0), ("bc", 1)]);
+        assert!(RecordList::is_well_formed(&data));
+        // Truncated within the last key.
+        assert!(!RecordList::is_well_formed(&data[..data.len() - 1]));
+        // Truncated within the file offset of the last record.
+        assert!(!RecordList::is_well_formed(&data[..data.len() - 4]));
+        // No header.
+        assert!(!RecordList::is_well_formed(&data[..3]));
+        assert!(RecordList::is_well_formed(&encode_record_list(&[])));
+
+        // The key size points beyond the end of the data.
+        let mut data = encode_record_list(&[("a", 0)]);
+        let key_size_pos = data.len() - 2;
+        data[key_size_pos] = 0xff;
+        assert!(!RecordList::is_well_formed(&data));
+    }
+
+    #[test]
+    fn record_list_validate() {
+        fn prop(data: ArbitraryRecordList) -> bool {
+            RecordList::validate(&data.0).is_ok()
+        }
+        quickcheck(prop as fn(ArbitraryRecordList) -> bool);
+
+        let data = encode_record_list(&[("a", 0), ("ab", 1), ("ab", 2), ("b", 3)]);
+        assert_eq!(RecordList::validate(&data), Ok(()));
+        assert_eq!(
+            RecordList::validate(&data[..data.len() - 1]),
+            Err(RecordListError::Malformed)
+        );
+
+        let data = encode_record_list(&[("a", 0), ("c", 1), ("b", 2)]);
+        let pos_b = 2 * (FILE_OFFSET_BYTES + KEY_SIZE_BYTE + 1);
+        assert_eq!(
+            RecordList::validate(&data),
+            Err(RecordListError::Unsorted { pos: pos_b })
+        );
+    }
+
+    #[test]
+    fn record_list_get_records() {
+        let data = encode_record_list(&[("a", 0), ("ac", 1), ("acd", 2), ("b", 3)]);
+        let records = RecordList::new(&data);
+
+        let file_offsets = |key: &[u8]| -> Vec<u64> {
+            records
+                .get_records(key)
+                .iter()
+                .map(|record| record.file_offset)
+                .collect()
+        };
+        assert_eq!(file_offsets(b"acdc"), vec![0, 1, 2]);
+        assert_eq!(file_offsets(b"acx"), vec![0, 1]);
+        assert_eq!(file_offsets(b"bb"), vec![3]);
+        assert_eq!(file_offsets(b"c"), vec![]);
+        // The last record is the same one `get_record()` returns.
+        assert_eq!(
+            records.get_records(b"acdc").pop(),
+            records.get_record(b"acdc")
+        );
+
+        // Only the last match is returned, together with the counts.
+        let (last_match, match_count, probe_count) = records.get_last_record_with_counts(b"acx");
+        assert_eq!(last_match, records.get_record(b"acx"));
+        assert_eq!(match_count, 2);
+        assert_eq!(probe_count, records.get_records_with_probe_count(b"acx").1);
+        assert_eq!(records.get_last_record_with_counts(b"c"), (None, 0, 4));
+    }
+
+    #[test]
+    fn record_list_diff_added() {
+        let old_data = encode_record_list(&[("a", 0), ("d", 1)]);
+        let new_data = encode_record_list(&[("a", 0), ("b", 2), ("d", 1), ("x", 3)]);
+        let old = RecordList::new(&old_data);
+        let new = RecordList::new(&new_data);
+
+        let diff = RecordList::diff(&old, &new);
+        let added: Vec<(&[u8], u64)> = diff
+            .added
+            .iter()
+            .map(|record| (record.key, record.file_offset))
+            .collect();
+        assert_eq!(added, [(&b"b"[..], 2), (&b"x"[..], 3)]);
+        assert!(diff.removed.is_empty());
+        assert!(diff.updated.is_empty());
+    }
+
+    #[test]
+    fn record_list_diff_removed() {
+        let old_data = encode_record_list(&[("a", 0), ("b", 2), ("d", 1)]);
+        let new_data = encode_record_list(&[("d", 1)]);
+        let old = RecordList::new(&old_data);
+        let new = RecordList::new(&new_data);
+
+        let diff = RecordList::diff(&old, &new);
+        let removed: Vec<(&[u8], u64)> = diff
+            .removed
+            .iter()
+            .map(|record| (record.key, record.file_offset))
+            .collect();
+        assert_eq!(removed, [(&b"a"[..], 0), (&b"b"[..], 2)]);
+        assert!(diff.added.is_empty());
+        assert!(diff.updated.is_empty());
+    }
+
+    #[test]
+    fn record_list_diff_mixed() {
+        let old_data = encode_record_list(&[("a", 0), ("b", 2), ("d", 1), ("x", 3)]);
+        let new_data = encode_record_list(&[("aa", 0), ("ab", 4), ("b", 5), ("x", 3)]);
+        let old = RecordList::new(&old_data);
+        let new = RecordList::new(&new_data);
+
+        let diff = RecordList::diff(&old, &new);
+        let added: Vec<&[u8]> = diff.added.iter().map(|record| record.key).collect();
+        assert_eq!(added, [&b"aa"[..], &b"ab"[..]]);
+        let removed: Vec<&[u8]> = diff.removed.iter().map(|record| record.key).collect();
+        assert_eq!(removed, [&b"a"[..], &b"d"[..]]);
+        let updated: Vec<(&[u8], u64, u64)> = diff
+            .updated
+            .iter()
+            .map(|(old, new)| (old.key, old.file_offset, new.file_offset))
+            .collect();
+        assert_eq!(updated, [(&b"b"[..], 2, 5)]);
+    }
+
+    #[test]
+    fn record_list_diff_equal() {
+        let data = encode_record_list(&[("a", 0), ("b", 2)]);
+        let old = RecordList::new(&data);
+        let new = RecordList::new(&data);
+        assert_eq!(RecordList::diff(&old, &new), RecordListDiff::default());
+    }
+
+    #[test]
+    fn record_list_buckets_bits() {
+        let data = encode_record_list(&[("a", 0)]);
+        let records = RecordList::new(&data);
+        assert_eq!(records.buckets_bits(), 24);
+    }
+
+    #[test]
+    fn record_list_remove_record() {
+        let data = encode_record_list(&[("a", 0), ("b", 1), ("cde", 2)]);
+        let records = RecordList::new(&data);
+
+        let record = records.get_record(b"bxyz").unwrap();
+        assert_eq!(record.key, b"b");
+        let new_data = records.remove_record(record.pos);
+        let prefixed_new_data = &[&[0, 0, 0, 0, 24], &new_data[..]].concat();
+        let new_records = RecordList::new(prefixed_new_data);
+        let keys: Vec<&[u8]> = new_records.into_iter().map(|record| record.key).collect();
+        assert_eq!(keys, [&b"a"[..], &b"cde"[..]]);
+        assert_eq!(new_records.get(b"bxyz"), None);
+
+        // Removing the only record results in an empty record list.
+        let data = encode_record_list(&[("a", 0)]);
+        let records = RecordList::new(&data);
+        assert!(records.remove_record(0).is_empty());
+    }
+
+    /// A primary storage whose positions are the indices of the keys.
+    struct KeysPrimary(Vec<Vec<u8>>);
+
+    impl PrimaryStorage for KeysPrimary {
+        fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+            let key = self.0[pos as usize].clone();
+            Ok((key, Vec::new()))
+        }
+
+        fn put(&self, _key: &[u8], _value: &[u8]) -> Result<u64, PrimaryError> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn record_list_compact() {
+        // The first 3 bytes are used for the bucket with 24 bits.
+        let keys = ["apple", "apricot", "banana", "cherry"];
+        let primary = KeysPrimary(
+            keys.iter()
+                .map(|key| [&b"xyz"[..], key.as_bytes()].concat())
+                .collect(),
+        );
+        // The keys are longer than needed, e.g. because other keys were deleted.
+        let data = encode_record_list(&[("appl", 0), ("apr", 1), ("ban", 2), ("ch", 3)]);
+        let records = RecordList::new(&data);
+
+        let compacted = records.compact(&primary).unwrap();
+        assert!(compacted.len() <= records.len());
+        let prefixed_compacted = &[&[0, 0, 0, 0, 24], &compacted[..]].concat();
+        let compacted_records = RecordList::new(prefixed_compacted);
+        let compacted_keys: Vec<&[u8]> = compacted_records
+            .into_iter()
+            .map(|record| record.key)
+            .collect();
+        assert_eq!(
+            compacted_keys,
+            [&b"app"[..], &b"apr"[..], &b"b"[..], &b"c"[..]]
+        );
+        for (ii, key) in keys.iter().enumerate() {
+            assert_eq!(compacted_records.get(key.as_bytes()), Some(ii as u64));
+        }
+
+        // Compacting again doesn't change anything.
+        assert_eq!(compacted_records.compact(&primary).unwrap(), compacted);
+    }
+
+    #[test]
+    fn record_list_bytes_wasted() {
+        // "appl" only needs 3 bytes to be distinguishable from "apr", "ban" and "ch" need a
+        // single one.
+        let data = encode_record_list(&[("appl", 0), ("apr", 1), ("ban", 2), ("ch", 3)]);
+        let records = RecordList::new(&data);
+        let record_overhead = FILE_OFFSET_BYTES + KEY_SIZE_BYTE;
+        assert_eq!(records.bytes_optimal(), 4 * record_overhead + 3 + 3 + 1 + 1);
+        assert_eq!(records.bytes_wasted(), 1 + 2 + 1);
+
+        // Compacted keys don't waste anything.
+        let compacted = encode_record_list(&[("app", 0), ("apr", 1), ("b", 2), ("c", 3)]);
+        assert_eq!(RecordList::new(&compacted).bytes_wasted(), 0);
+
+        // A single key needs a single byte.
+        let single = encode_record_list(&[("apple", 0)]);
+        assert_eq!(RecordList::new(&single).bytes_wasted(), 4);
+
+        // Keys that aren't distinguishable are kept.
+        let indistinguishable = encode_record_list(&[("ap", 0), ("apple", 1)]);
+        assert_eq!(RecordList::new(&indistinguishable).bytes_wasted(), 0);
+
+        let empty = encode_record_list(&[]);
+        assert_eq!(RecordList::new(&empty).bytes_optimal(), 0);
+        assert_eq!(RecordList::new(&empty).bytes_wasted(), 0);
+    }
+
+    #[test]
+    fn record_display() {
+        let record = Record {
+            pos: 5,
+            key: &[0x0a, 0x0b, 0xfc],
+            file_offset: 1234,
+        };
+        assert_eq!(record.to_string(), "0a0bfc -> 1234");
+    }
 }
@@ -0,0 +1,267 @@
+//! A database that spreads its keys over several independent databases.
+//!
+//! A single [`Db`] serializes all puts on one index file. A [`ShardedDb`] owns several of them,
+//! the shards, every one with its own index file. A key always belongs to the same shard, it's
+//! selected by a hash of its index key, see [`ShardedDb::shard_of`]. Hence a get only consults a
+//! single shard and [`ShardedDb::put_batch`] can write to all shards in parallel.
+//!
+//! The number of shards is recorded in a manifest file next to the index files. As it determines
+//! where the keys are, opening with a different number of shards returns
+//! [`Error::ShardCountMismatch`].
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::db::{DanglingReference, Db, DbStats, InsertPosition};
+use crate::error::Error;
+use crate::primary::PrimaryStorage;
+
+/// The name of the manifest file within the directory of a [`ShardedDb`].
+pub const MANIFEST_FILE_NAME: &str = "shards";
+
+/// The offset basis of the 64-bit FNV-1a hash.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+/// The prime of the 64-bit FNV-1a hash.
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// A database whose keys are spread over several [`Db`]s.
+pub struct ShardedDb<P: PrimaryStorage, const N: u8> {
+    shards: Vec<Db<P, N>>,
+}
+
+impl<P: PrimaryStorage + fmt::Debug, const N: u8> fmt::Debug for ShardedDb<P, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedDb")
+            .field("shards", &self.shards)
+            .finish()
+    }
+}
+
+/// Statistics about all shards, see [`ShardedDb::stats`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ShardedDbStats {
+    /// The statistics of every shard, in the order of the shards.
+    pub shards: Vec<DbStats>,
+    /// The total number of records.
+    pub records: usize,
+    /// The total size of the index files in bytes.
+    pub index_size: u64,
+    /// The total number of bytes of the index files that are still in use.
+    pub live_index_size: u64,
+}
+
+/// The result of [`ShardedDb::verify`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShardedVerifyReport {
+    /// The number of records in all indexes that were checked.
+    pub records_checked: u64,
+    /// The records that point past the end of the primary storage, together with their shard.
+    pub dangling: Vec<(usize, DanglingReference)>,
+}
+
+impl<P: PrimaryStorage, const N: u8> ShardedDb<P, N> {
+    /// Opens a database with one shard per primary storage.
+    ///
+    /// The index files and the manifest are stored within the given directory, which is created
+    /// if needed. Every shard stores its data in its own primary storage. A single primary storage
+    /// can be shared by passing handles that refer to the same storage, those need to be safe to
+    /// use from several threads for [`ShardedDb::put_batch`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if no primary storage is given.
+    pub fn open<T>(dir: T, primaries: Vec<P>) -> Result<Self, Error>
+    where
+        T: AsRef<Path>,
+    {
+        assert!(!primaries.is_empty(), "at least one shard is needed");
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(Error::io("creating shard directory", dir, None))?;
+        check_manifest(&dir.join(MANIFEST_FILE_NAME), primaries.len())?;
+        let shards = primaries
+            .into_iter()
+            .enumerate()
+            .map(|(shard, primary)| Db::open(primary, index_path(dir, shard)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { shards })
+    }
+
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns a single shard, e.g. to access its primary storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the shard doesn't exist.
+    pub fn shard(&self, shard: usize) -> &Db<P, N> {
+        &self.shards[shard]
+    }
+
+    /// Returns the shard the given key belongs to.
+    ///
+    /// It's the 64-bit FNV-1a hash of the index key modulo the number of shards. The whole index
+    /// key is hashed, so that the keys of a shard are still spread over all its buckets.
+    pub fn shard_of(&self, key: &[u8]) -> Result<usize, Error> {
+        let index_key = P::index_key(key)?;
+        let hash = index_key.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+        });
+        Ok((hash % self.shards.len() as u64) as usize)
+    }
+
+    /// Returns the value of the given key, only the shard of the key is consulted.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.shards[self.shard_of(key)?].get(key)
+    }
+
+    /// Stores a key-value pair in the shard of the key, see [`Db::put`].
+    ///
+    /// The returned positions are those within that shard.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<InsertPosition, Error> {
+        self.shards[self.shard_of(key)?].put(key, value)
+    }
+
+    /// Stores several key-value pairs, the shards are written to in parallel.
+    ///
+    /// The entries are split by shard and every shard that got any is written to from its own
+    /// thread, within a shard the entries keep their order. The positions are returned in the
+    /// order of the entries. If a shard fails, the other shards still store their entries and the
+    /// error of the first failing shard is returned.
+    pub fn put_batch(&mut self, entries: &[(&[u8], &[u8])]) -> Result<Vec<InsertPosition>, Error>
+    where
+        P: Send,
+    {
+        let mut routed = vec![Vec::new(); self.shards.len()];
+        for (ii, (key, value)) in entries.iter().enumerate() {
+            routed[self.shard_of(key)?].push((ii, *key, *value));
+        }
+
+        let results: Vec<Result<Vec<_>, Error>> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .shards
+                .iter_mut()
+                .zip(routed)
+                .filter(|(_db, shard_entries)| !shard_entries.is_empty())
+                .map(|(db, shard_entries)| {
+                    scope.spawn(move || {
+                        shard_entries
+                            .into_iter()
+                            .map(|(ii, key, value)| Ok((ii, db.put(key, value)?)))
+                            .collect()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|error| panic::resume_unwind(error))
+                })
+                .collect()
+        });
+
+        let mut positions = vec![None; entries.len()];
+        for result in results {
+            for (ii, position) in result? {
+                positions[ii] = Some(position);
+            }
+        }
+        Ok(positions
+            .into_iter()
+            .map(|position| position.expect("every entry was routed to a shard"))
+            .collect())
+    }
+
+    /// Returns statistics about every shard, together with their totals.
+    ///
+    /// This reads all indexes, hence it can be slow.
+    pub fn stats(&self) -> Result<ShardedDbStats, Error> {
+        let shards = self
+            .shards
+            .iter()
+            .map(Db::stats)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ShardedDbStats {
+            records: shards.iter().map(|stats| stats.index.records).sum(),
+            index_size: shards.iter().map(|stats| stats.index_size).sum(),
+            live_index_size: shards.iter().map(|stats| stats.live_index_size).sum(),
+            shards,
+        })
+    }
+
+    /// Checks the records of all shards, see [`Db::verify`].
+    pub fn verify(&self) -> Result<ShardedVerifyReport, Error> {
+        let mut report = ShardedVerifyReport::default();
+        for (shard, db) in self.shards.iter().enumerate() {
+            let shard_report = db.verify()?;
+            report.records_checked += shard_report.records_checked;
+            report.dangling.extend(
+                shard_report
+                    .dangling
+                    .into_iter()
+                    .map(|dangling| (shard, dangling)),
+            );
+        }
+        Ok(report)
+    }
+
+    /// Flushes all shards, see [`Db::flush`].
+    pub fn flush(&self) -> Result<(), Error> {
+        for db in &self.shards {
+            db.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes all data to disk and closes the database.
+    pub fn close(self) -> Result<(), Error> {
+        self.flush()
+    }
+}
+
+/// Returns the path of the index file of a shard.
+fn index_path(dir: &Path, shard: usize) -> PathBuf {
+    dir.join(format!("index.{}", shard))
+}
+
+/// Creates the manifest with the number of shards, or checks it if it already exists.
+///
+/// The manifest contains the number of shards as decimal number, followed by a newline.
+fn check_manifest(path: &Path, shard_count: usize) -> Result<(), Error> {
+    match fs::read_to_string(path) {
+        Ok(manifest) => {
+            let manifest_count = manifest
+                .strip_suffix('\n')
+                .and_then(|count| count.parse::<usize>().ok())
+                .filter(|count| *count > 0)
+                .ok_or(Error::CorruptShardManifest)?;
+            if manifest_count != shard_count {
+                return Err(Error::ShardCountMismatch {
+                    manifest: manifest_count,
+                    given: shard_count,
+                });
+            }
+            Ok(())
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map_err(Error::io("creating shard manifest", path, None))?;
+            file.write_all(format!("{}\n", shard_count).as_bytes())
+                .and_then(|_| file.sync_all())
+                .map_err(Error::io("writing shard manifest", path, Some(0)))
+        }
+        Err(error) => Err(Error::io("reading shard manifest", path, None)(error)),
+    }
+}
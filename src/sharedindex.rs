@@ -0,0 +1,106 @@
+//! An index that can be shared between threads, see [`Index::into_shared`].
+//!
+//! [`SharedIndex`] wraps the index into a read-write lock. Gets only need the read lock, hence
+//! they run concurrently, puts take the write lock. [`SharedIndexReader`] is a handle that can
+//! only get, e.g. to hand it out to the threads of a pool that only serve lookups.
+//!
+//! The index can only be shared if its primary storage is `Send + Sync`. The primary storage is
+//! read concurrently as well, when the index needs to resolve keys that share a prefix.
+
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+
+use crate::error::Error;
+use crate::hasher::{BucketHasher, LeadingBytesHasher};
+use crate::index::Index;
+use crate::primary::PrimaryStorage;
+
+/// An index that can be cloned cheaply and shared between threads, all clones refer to the same
+/// index.
+///
+/// The lock is never poisoned, a panicking put at most leaves a partial record list at the end of
+/// the index file, which isn't referenced.
+pub struct SharedIndex<P: PrimaryStorage, const N: u8, H: BucketHasher = LeadingBytesHasher> {
+    index: Arc<RwLock<Index<P, N, H>>>,
+}
+
+/// A handle to a [`SharedIndex`] that can only get, see [`SharedIndex::clone_reader`].
+pub struct SharedIndexReader<P: PrimaryStorage, const N: u8, H: BucketHasher = LeadingBytesHasher> {
+    index: Arc<RwLock<Index<P, N, H>>>,
+}
+
+impl<P: PrimaryStorage, const N: u8, H: BucketHasher> Clone for SharedIndex<P, N, H> {
+    fn clone(&self) -> Self {
+        Self {
+            index: Arc::clone(&self.index),
+        }
+    }
+}
+
+impl<P: PrimaryStorage, const N: u8, H: BucketHasher> Clone for SharedIndexReader<P, N, H> {
+    fn clone(&self) -> Self {
+        Self {
+            index: Arc::clone(&self.index),
+        }
+    }
+}
+
+/// Takes the read lock of an index.
+fn read<P: PrimaryStorage, const N: u8, H: BucketHasher>(
+    index: &RwLock<Index<P, N, H>>,
+) -> RwLockReadGuard<'_, Index<P, N, H>> {
+    index.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<P: PrimaryStorage, const N: u8, H: BucketHasher> SharedIndex<P, N, H> {
+    pub(crate) fn new(index: Index<P, N, H>) -> Self {
+        Self {
+            index: Arc::new(RwLock::new(index)),
+        }
+    }
+
+    /// Returns the position of the key in the primary storage, see [`Index::get`].
+    ///
+    /// Only the read lock is taken.
+    pub fn get(&self, key: &[u8]) -> Result<Option<u64>, Error> {
+        read(&self.index).get(key)
+    }
+
+    /// Returns whether the index contains a record for the key, see [`Index::get`].
+    ///
+    /// Only the read lock is taken.
+    pub fn contains(&self, key: &[u8]) -> Result<bool, Error> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Puts a key into the index, see [`Index::put`].
+    ///
+    /// The write lock is taken, hence it waits for all running gets and blocks new ones until
+    /// it's done.
+    pub fn put(&self, key: &[u8], file_offset: u64) -> Result<u64, Error> {
+        self.index
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(key, file_offset)
+    }
+
+    /// Flushes the index, see [`Index::flush`].
+    ///
+    /// Only the read lock is taken, gets continue while the data is synced.
+    pub fn flush(&self) -> Result<(), Error> {
+        read(&self.index).flush()
+    }
+
+    /// Returns a handle that can only get.
+    pub fn clone_reader(&self) -> SharedIndexReader<P, N, H> {
+        SharedIndexReader {
+            index: Arc::clone(&self.index),
+        }
+    }
+}
+
+impl<P: PrimaryStorage, const N: u8, H: BucketHasher> SharedIndexReader<P, N, H> {
+    /// Returns the position of the key in the primary storage, see [`SharedIndex::get`].
+    pub fn get(&self, key: &[u8]) -> Result<Option<u64>, Error> {
+        read(&self.index).get(key)
+    }
+}
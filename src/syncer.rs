@@ -0,0 +1,312 @@
+//! Syncs a file to disk in the background, so that the data a crash can lose is bounded.
+//!
+//! Syncing after every write is slow, never syncing puts everything since the last flush at risk.
+//! A [`BackgroundSyncer`] owns a thread that syncs a file once more than
+//! [`SyncPolicy::max_dirty_bytes`] were written, or once the oldest unsynced write is
+//! [`SyncPolicy::max_interval`] old, whatever comes first. The writers report how many bytes they
+//! wrote with [`BackgroundSyncer::record_write`].
+//!
+//! It's enabled with [`crate::index::IndexBuilder::with_background_sync`] for an index, primary
+//! storages may support it as well.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+/// When a [`BackgroundSyncer`] syncs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncPolicy {
+    /// A sync is done once at least that many bytes were written since the last one.
+    pub max_dirty_bytes: u64,
+    /// A sync is done once the oldest write since the last one is that old.
+    pub max_interval: Duration,
+}
+
+impl Default for SyncPolicy {
+    /// Syncs every 16 MiB or every second.
+    fn default() -> Self {
+        Self {
+            max_dirty_bytes: 16 * 1024 * 1024,
+            max_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Something that can be synced to disk, it's implemented for [`File`].
+pub trait SyncTarget: Send + 'static {
+    /// Syncs the written data to disk, see [`File::sync_data`].
+    fn sync_data(&self) -> Result<(), io::Error>;
+}
+
+impl SyncTarget for File {
+    fn sync_data(&self) -> Result<(), io::Error> {
+        File::sync_data(self)
+    }
+}
+
+/// The state that is shared between the writers and the sync thread.
+#[derive(Debug, Default)]
+struct State {
+    /// The number of bytes written since the last sync.
+    dirty_bytes: u64,
+    /// When the oldest write since the last sync happened.
+    dirty_since: Option<Instant>,
+    /// Set when the syncer is closed, the thread then does a final sync and exits.
+    shutdown: bool,
+    /// The error of the most recent failed sync, it's returned by [`BackgroundSyncer::close`].
+    error: Option<io::Error>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Wakes up the thread when a threshold might have been reached or on shutdown.
+    wakeup: Condvar,
+}
+
+impl Shared {
+    /// The state is consistent at all times, hence the poisoning is ignored.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A thread that syncs a file according to a [`SyncPolicy`].
+///
+/// When it's closed or dropped, the thread does a final sync and is joined.
+pub struct BackgroundSyncer {
+    shared: Arc<Shared>,
+    policy: SyncPolicy,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl fmt::Debug for BackgroundSyncer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackgroundSyncer")
+            .field("state", &self.shared.state)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl BackgroundSyncer {
+    /// Starts the thread that syncs the given target.
+    pub fn new<T: SyncTarget>(target: T, policy: SyncPolicy) -> Result<Self, io::Error> {
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("storethehash-sync".to_string())
+                .spawn(move || run(target, policy, &shared))?
+        };
+        Ok(Self {
+            shared,
+            policy,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the policy the syncer was started with.
+    pub fn policy(&self) -> SyncPolicy {
+        self.policy
+    }
+
+    /// Records that the given number of bytes were written to the target.
+    ///
+    /// It's cheap, the sync itself always happens on the thread.
+    pub fn record_write(&self, bytes: u64) {
+        let mut state = self.shared.lock();
+        state.dirty_bytes = state.dirty_bytes.saturating_add(bytes);
+        // The thread needs to know when the interval starts and when the bytes are exceeded.
+        if state.dirty_since.is_none() {
+            state.dirty_since = Some(Instant::now());
+            self.shared.wakeup.notify_one();
+        } else if state.dirty_bytes >= self.policy.max_dirty_bytes {
+            self.shared.wakeup.notify_one();
+        }
+    }
+
+    /// Does a final sync and joins the thread.
+    ///
+    /// Returns the error of the final sync, or of an earlier one that failed.
+    pub fn close(mut self) -> Result<(), io::Error> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<(), io::Error> {
+        if let Some(thread) = self.thread.take() {
+            self.shared.lock().shutdown = true;
+            self.shared.wakeup.notify_one();
+            if thread.join().is_err() {
+                return Err(io::Error::other("Background sync thread panicked."));
+            }
+        }
+        match self.shared.lock().error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for BackgroundSyncer {
+    fn drop(&mut self) {
+        if let Err(error) = self.shutdown() {
+            warn!("Background sync failed: {}", error);
+        }
+    }
+}
+
+/// The loop of the sync thread, it returns after the final sync.
+fn run<T: SyncTarget>(target: T, policy: SyncPolicy, shared: &Shared) {
+    let mut state = shared.lock();
+    loop {
+        let shutdown = state.shutdown;
+        let due = state.dirty_bytes >= policy.max_dirty_bytes
+            || matches!(state.dirty_since, Some(since) if since.elapsed() >= policy.max_interval);
+        if shutdown || due {
+            state.dirty_bytes = 0;
+            state.dirty_since = None;
+            // The writers can continue while the data is synced.
+            drop(state);
+            let result = target.sync_data();
+            state = shared.lock();
+            if let Err(error) = result {
+                warn!("Background sync failed: {}", error);
+                state.error = Some(error);
+            }
+            if shutdown {
+                return;
+            }
+            continue;
+        }
+
+        state = match state.dirty_since {
+            Some(since) => {
+                let timeout = policy
+                    .max_interval
+                    .checked_sub(since.elapsed())
+                    .unwrap_or_default();
+                shared
+                    .wakeup
+                    .wait_timeout(state, timeout)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            }
+            // Nothing to sync, wait for the next write.
+            None => shared
+                .wakeup
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{BackgroundSyncer, SyncPolicy, SyncTarget};
+
+    /// Counts the syncs, they fail if `fail` is set.
+    #[derive(Clone, Default)]
+    struct RecordingTarget {
+        syncs: Arc<AtomicUsize>,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl RecordingTarget {
+        fn syncs(&self) -> usize {
+            self.syncs.load(Ordering::SeqCst)
+        }
+
+        /// Waits until there were the given number of syncs, it panics after a few seconds.
+        fn wait_for_syncs(&self, syncs: usize) {
+            let start = Instant::now();
+            while self.syncs() < syncs {
+                assert!(start.elapsed() < Duration::from_secs(5), "no sync happened");
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    impl SyncTarget for RecordingTarget {
+        fn sync_data(&self) -> Result<(), io::Error> {
+            self.syncs.fetch_add(1, Ordering::SeqCst);
+            if self.fail.load(Ordering::SeqCst) {
+                Err(io::Error::new(io::ErrorKind::Other, "disk is gone"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn dirty_bytes_trigger_sync() {
+        let target = RecordingTarget::default();
+        let policy = SyncPolicy {
+            max_dirty_bytes: 100,
+            max_interval: Duration::from_secs(3600),
+        };
+        let syncer = BackgroundSyncer::new(target.clone(), policy).unwrap();
+
+        syncer.record_write(60);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(target.syncs(), 0);
+        syncer.record_write(60);
+        target.wait_for_syncs(1);
+
+        // The count starts again after a sync.
+        syncer.record_write(60);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(target.syncs(), 1);
+        syncer.close().unwrap();
+        assert_eq!(target.syncs(), 2);
+    }
+
+    #[test]
+    fn interval_triggers_sync() {
+        let target = RecordingTarget::default();
+        let policy = SyncPolicy {
+            max_dirty_bytes: u64::MAX,
+            max_interval: Duration::from_millis(20),
+        };
+        let syncer = BackgroundSyncer::new(target.clone(), policy).unwrap();
+
+        syncer.record_write(1);
+        target.wait_for_syncs(1);
+        // Without writes there is nothing to sync.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(target.syncs(), 1);
+        syncer.record_write(1);
+        target.wait_for_syncs(2);
+        drop(syncer);
+        assert_eq!(target.syncs(), 3);
+    }
+
+    #[test]
+    fn close_always_syncs() {
+        let target = RecordingTarget::default();
+        let syncer = BackgroundSyncer::new(target.clone(), SyncPolicy::default()).unwrap();
+        syncer.close().unwrap();
+        assert_eq!(target.syncs(), 1);
+
+        let syncer = BackgroundSyncer::new(target.clone(), SyncPolicy::default()).unwrap();
+        drop(syncer);
+        assert_eq!(target.syncs(), 2);
+
+        // A failed sync is returned.
+        target.fail.store(true, Ordering::SeqCst);
+        let syncer = BackgroundSyncer::new(target.clone(), SyncPolicy::default()).unwrap();
+        syncer.record_write(1);
+        assert_eq!(syncer.close().unwrap_err().kind(), io::ErrorKind::Other);
+    }
+}
@@ -0,0 +1,141 @@
+//! Helpers for creating test fixtures.
+//!
+//! It's only available for the tests of this crate or with the `testing` feature enabled.
+use std::convert::TryFrom;
+use std::path::Path;
+
+use quickcheck::{Arbitrary, Gen};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::error::Error;
+use crate::index::Index;
+use crate::primary::PrimaryStorage;
+use crate::recordlist::{self, Record, RecordList};
+
+/// The seed of the random number generator, so that the fixtures are reproducible.
+const SEED: u64 = 42;
+
+/// Returns a key with the given number of random bytes.
+pub fn random_key(len: usize, rng: &mut impl Rng) -> Vec<u8> {
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+/// Encodes the given keys as a record list (including the bucket prefix).
+///
+/// The keys need to be sorted and distinguishable from each other.
+pub fn encode_record_list(keys: &[(&[u8], u64)]) -> Vec<u8> {
+    // The record list has the bits that were used to determine the bucket and the number of bits
+    // used for the buckets as prefix.
+    let mut data = vec![0, 0, 0, 0, 24];
+    for (key, file_offset) in keys {
+        data.extend_from_slice(&recordlist::encode_offset_and_key(key, *file_offset));
+    }
+    data
+}
+
+/// Returns a record list (including the bucket prefix) with `n` random keys.
+///
+/// The file offsets are the positions of the keys in sorted order. There might be fewer than `n`
+/// keys if the same random key was generated more than once.
+pub fn build_record_list(n: usize, key_len: usize) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut keys: Vec<Vec<u8>> = (0..n).map(|_| random_key(key_len, &mut rng)).collect();
+    keys.sort();
+    keys.dedup();
+    let records: Vec<(&[u8], u64)> = keys
+        .iter()
+        .enumerate()
+        .map(|(ii, key)| (&key[..], u64::try_from(ii).expect("64-bit platform needed")))
+        .collect();
+    encode_record_list(&records)
+}
+
+/// Returns an index at `temp_dir` that contains `n` random keys with a size of 32 bytes.
+///
+/// The keys are also stored in a new primary storage, the value is the position of the key.
+pub fn build_index_with_n_keys<P, const N: u8>(
+    n: usize,
+    temp_dir: &Path,
+) -> Result<Index<P, N>, Error>
+where
+    P: PrimaryStorage + Default,
+{
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let index = Index::<_, N>::open(temp_dir.join("storethehash.index"), P::default())?;
+    for ii in 0..n {
+        let key = random_key(32, &mut rng);
+        let pos = index.primary.put(&key, &ii.to_le_bytes())?;
+        index.put(&P::index_key(&key)?, pos)?;
+    }
+    Ok(index)
+}
+
+/// An owned [`Record`] that can be generated by quickcheck.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArbitraryRecord {
+    pub key: Vec<u8>,
+    pub file_offset: u64,
+}
+
+impl Arbitrary for ArbitraryRecord {
+    fn arbitrary(g: &mut Gen) -> Self {
+        // A key is at least one byte long and its size is stored in a single byte.
+        let len = usize::from(u8::arbitrary(g) % 32) + 1;
+        Self {
+            key: (0..len).map(|_| u8::arbitrary(g)).collect(),
+            file_offset: u64::arbitrary(g),
+        }
+    }
+}
+
+impl<'a> From<Record<'a>> for ArbitraryRecord {
+    fn from(record: Record<'a>) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            file_offset: record.file_offset,
+        }
+    }
+}
+
+/// The data of a valid [`RecordList`] that can be generated by quickcheck.
+///
+/// The records are sorted and distinguishable from each other.
+#[derive(Clone, Debug)]
+pub struct ArbitraryRecordList(pub Vec<u8>);
+
+impl ArbitraryRecordList {
+    /// Returns the record list that is backed by this data.
+    pub fn record_list(&self) -> RecordList<'_> {
+        RecordList::new(&self.0)
+    }
+
+    /// Returns the records of the record list.
+    pub fn records(&self) -> Vec<ArbitraryRecord> {
+        self.record_list()
+            .into_iter()
+            .map(ArbitraryRecord::from)
+            .collect()
+    }
+}
+
+impl Arbitrary for ArbitraryRecordList {
+    fn arbitrary(g: &mut Gen) -> Self {
+        // Keys of the same size are always distinguishable from each other.
+        let key_len = usize::from(u8::arbitrary(g) % 32) + 1;
+        let mut records: Vec<ArbitraryRecord> = Vec::<ArbitraryRecord>::arbitrary(g)
+            .into_iter()
+            .map(|mut record| {
+                record.key.resize(key_len, 0);
+                record
+            })
+            .collect();
+        records.sort_by(|a, b| a.key.cmp(&b.key));
+        records.dedup_by(|a, b| a.key == b.key);
+        let keys: Vec<(&[u8], u64)> = records
+            .iter()
+            .map(|record| (&record.key[..], record.file_offset))
+            .collect();
+        Self(encode_record_list(&keys))
+    }
+}
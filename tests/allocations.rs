@@ -0,0 +1,76 @@
+//! Counts the heap allocations of lookups, so that the hot path doesn't start allocating again.
+//!
+//! A counting global allocator is used. The count is per thread, as the tests run in parallel.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use storethehash::index::Index;
+use storethehash_primary_inmemory::InMemory;
+
+const BUCKETS_BITS: u8 = 8;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // The thread local might already be gone when a thread shuts down.
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations the given function did on the current thread.
+fn count_allocations<F: FnOnce()>(function: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    function();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// Returns a key whose leading bytes differ for every entry.
+fn key(ii: u32) -> Vec<u8> {
+    [&ii.to_le_bytes()[..], &[0xaa; 28]].concat()
+}
+
+#[test]
+fn index_get_does_not_allocate() {
+    const GETS: u32 = 1000;
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    for ii in 0..100 {
+        index.put(&key(ii), u64::from(ii)).unwrap();
+    }
+    let keys: Vec<_> = (0..GETS).map(|ii| key(ii % 100)).collect();
+    // The first gets may allocate the buffers that are reused later.
+    for key in &keys[..10] {
+        index.get(key).unwrap();
+    }
+
+    let allocations = count_allocations(|| {
+        for (ii, key) in keys.iter().enumerate() {
+            assert_eq!(index.get(key).unwrap(), Some(u64::from(ii as u32 % 100)));
+        }
+    });
+    println!(
+        "allocations per get: {}",
+        allocations as f64 / f64::from(GETS)
+    );
+    assert_eq!(allocations, 0);
+}
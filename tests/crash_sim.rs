@@ -0,0 +1,136 @@
+//! Crash simulation: a child process inserts keys and gets killed at a random point, afterwards
+//! the database must still be usable and contain all the keys the child reported as inserted.
+//!
+//! The child is this test binary itself, running the ignored `crash_sim_child` test.
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use storethehash::codec::{KeyCodec, Sha256Codec};
+use storethehash::db::Db;
+use storethehash::fsck::{self, FsckOptions, FsckProblem, ProblemKind, Severity};
+use storethehash_primary_cid::CidPrimary;
+
+const BUCKETS_BITS: u8 = 8;
+/// The number of keys the child inserts if it isn't killed before.
+const NUM_KEYS: usize = 2000;
+/// How often the crash is simulated.
+const NUM_RUNS: u64 = 100;
+/// The environment variable that contains the directory the child writes the database to.
+const DIR_ENV_VAR: &str = "STH_CRASH_SIM_DIR";
+/// The child prints this prefix followed by the number of the key, once the put returned.
+const INSERTED_PREFIX: &str = "inserted ";
+
+fn value(num: usize) -> Vec<u8> {
+    format!("value {}", num).into_bytes()
+}
+
+/// A CIDv1 with the raw codec and the SHA2-256 digest of the value.
+fn cid(value: &[u8]) -> Vec<u8> {
+    let digest = Sha256Codec::encode(value).unwrap();
+    [&[0x01, 0x55, 0x12, 0x20][..], &digest[..]].concat()
+}
+
+fn open_db(dir: &Path) -> Db<CidPrimary, BUCKETS_BITS> {
+    let primary = CidPrimary::open(dir.join("storethehash.data")).unwrap();
+    Db::open(primary, dir.join("storethehash.index")).unwrap()
+}
+
+/// Runs in the child process, it inserts the keys until it's killed.
+#[test]
+#[ignore]
+fn crash_sim_child() {
+    let dir = env::var(DIR_ENV_VAR).expect("only runs as child of the crash simulation");
+    let db = open_db(Path::new(&dir));
+    let stdout = std::io::stdout();
+    for num in 0..NUM_KEYS {
+        let value = value(num);
+        db.put(&cid(&value), &value).unwrap();
+        // The put flushed the data to the operating system, hence it survives the process being
+        // killed.
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}{}", INSERTED_PREFIX, num).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+/// Returns the number of keys the child reported as inserted before it was killed.
+fn run_child(dir: &Path, kill_after: usize) -> usize {
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args([
+            "--ignored",
+            "--exact",
+            "crash_sim_child",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(DIR_ENV_VAR, dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut inserted = 0;
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    for line in stdout.lines() {
+        if let Some(num) = line.unwrap().strip_prefix(INSERTED_PREFIX) {
+            inserted = num.parse::<usize>().unwrap() + 1;
+            if inserted >= kill_after {
+                // Sends a `SIGKILL` on Unix.
+                child.kill().unwrap();
+                break;
+            }
+        }
+    }
+    child.wait().unwrap();
+    inserted
+}
+
+/// A record list that was only partially written is the only acceptable problem.
+fn is_partial_write(problem: &FsckProblem) -> bool {
+    problem.severity == Severity::Recoverable
+        && matches!(problem.kind, ProblemKind::TrailingGarbage { .. })
+}
+
+#[test]
+fn crash_sim() {
+    for run in 0..NUM_RUNS {
+        let mut rng = StdRng::seed_from_u64(run);
+        let kill_after = rng.gen_range(1..=NUM_KEYS);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let inserted = run_child(temp_dir.path(), kill_after);
+        assert!(inserted >= kill_after, "run {}", run);
+
+        let primary = CidPrimary::open(temp_dir.path().join("storethehash.data")).unwrap();
+        let report = fsck::check(
+            temp_dir.path().join("storethehash.index"),
+            &primary,
+            FsckOptions::default(),
+        )
+        .unwrap();
+        assert!(
+            report.problems.iter().all(is_partial_write),
+            "run {}: {:?}",
+            run,
+            report
+        );
+        drop(primary);
+
+        let db = open_db(temp_dir.path());
+        for num in 0..inserted {
+            let value = value(num);
+            assert_eq!(
+                db.get(&cid(&value)).unwrap(),
+                Some(value),
+                "run {}: key {} of {} is missing",
+                run,
+                num,
+                inserted
+            );
+        }
+        // The child might have inserted more keys before it was actually killed.
+        assert!(db.count().unwrap() >= inserted, "run {}", run);
+    }
+}
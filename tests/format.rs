@@ -0,0 +1,309 @@
+//! Checks the files that are written against the on-disk format that is described in the README.
+//!
+//! The format is parsed by the functions in here, independently of the implementation, so that an
+//! accidental change of the format makes these tests fail. The fixtures in `tests/fixtures/format`
+//! are a database with a [`CidPrimary`], they are regenerated by running the tests with the
+//! `STH_REGENERATE_FIXTURES` environment variable set.
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use storethehash::codec::{KeyCodec, Sha256Codec};
+use storethehash::db::Db;
+use storethehash::index::{self, Index, IndexIter};
+use storethehash::recordlist::RecordList;
+use storethehash::testing::random_key;
+use storethehash_primary_cid::CidPrimary;
+
+const BUCKETS_BITS: u8 = 8;
+/// The CIDv1 prefix for raw data hashed with SHA2-256.
+const CID_V1_PREFIX: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+const FIXTURE_ENTRIES: usize = 40;
+/// The entry of the fixture that is deleted again.
+const FIXTURE_DELETED: usize = 7;
+
+/// The index as described by the format.
+#[derive(Debug)]
+struct SpecIndex {
+    version: u8,
+    buckets_bits: u8,
+    min_key_length: u8,
+    record_lists: Vec<SpecRecordList>,
+}
+
+#[derive(Debug, PartialEq)]
+struct SpecRecordList {
+    /// The position of the record list within the index, it points to its size prefix.
+    pos: u64,
+    bucket: u32,
+    /// The keys and file offsets.
+    records: Vec<(Vec<u8>, u64)>,
+}
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/format")
+}
+
+fn cid(data: &[u8]) -> Vec<u8> {
+    [&CID_V1_PREFIX[..], &Sha256Codec::encode(data).unwrap()].concat()
+}
+
+fn fixture_data(ii: usize) -> Vec<u8> {
+    format!("format {}", ii).into_bytes()
+}
+
+/// Writes the database the fixtures are made of.
+fn build_fixture(dir: &Path) {
+    let db_path = dir.join("storethehash.db");
+    let primary = CidPrimary::open(&db_path).unwrap();
+    let db = Db::<_, BUCKETS_BITS>::open(primary, dir.join("storethehash.db.index")).unwrap();
+    for ii in 0..FIXTURE_ENTRIES {
+        let data = fixture_data(ii);
+        db.put(&cid(&data), &data).unwrap();
+    }
+    assert!(db.delete(&cid(&fixture_data(FIXTURE_DELETED))).unwrap());
+    db.close().unwrap();
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> &'a [u8] {
+    assert!(
+        *pos + len <= data.len(),
+        "data ends at {} within a field",
+        pos
+    );
+    let slice = &data[*pos..*pos + len];
+    *pos += len;
+    slice
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> u32 {
+    u32::from_le_bytes(take(data, pos, 4).try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], pos: &mut usize) -> u64 {
+    u64::from_le_bytes(take(data, pos, 8).try_into().unwrap())
+}
+
+/// Reads an unsigned LEB128 varint.
+fn read_varint(data: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(data, pos, 1)[0];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+    panic!("varint at {} is too long", pos)
+}
+
+/// Parses an index file, it panics if it violates the format.
+fn parse_index(data: &[u8]) -> SpecIndex {
+    let mut pos = 0;
+    let header_size = read_u32(data, &mut pos);
+    assert_eq!(header_size, 3, "a version 4 header has 3 bytes");
+    let header = take(data, &mut pos, 3);
+    let (version, buckets_bits, min_key_length) = (header[0], header[1], header[2]);
+    assert_eq!(version, 4);
+    assert!(buckets_bits <= 32);
+    assert!(min_key_length >= 4);
+
+    let mut record_lists = Vec::new();
+    while pos < data.len() {
+        let list_pos = pos as u64;
+        let size = read_u32(data, &mut pos) as usize;
+        assert!(size >= 5, "record list at {} is too small", list_pos);
+        let list = take(data, &mut pos, size);
+        let mut list_cursor = 0;
+        let bucket = read_u32(list, &mut list_cursor);
+        assert!(u64::from(bucket) < 1 << buckets_bits);
+        assert_eq!(take(list, &mut list_cursor, 1)[0], buckets_bits);
+        let mut records = Vec::new();
+        while list_cursor < list.len() {
+            let file_offset = read_u64(list, &mut list_cursor);
+            let key_size = take(list, &mut list_cursor, 1)[0];
+            assert!(key_size > 0, "empty key in record list at {}", list_pos);
+            let key = take(list, &mut list_cursor, usize::from(key_size)).to_vec();
+            records.push((key, file_offset));
+        }
+        for pair in records.windows(2) {
+            let (key, next_key) = (&pair[0].0, &pair[1].0);
+            assert!(
+                key < next_key,
+                "keys of record list at {} are unsorted",
+                list_pos
+            );
+            assert!(
+                !next_key.starts_with(key),
+                "keys of record list at {} are not distinguishable",
+                list_pos
+            );
+        }
+        record_lists.push(SpecRecordList {
+            pos: list_pos,
+            bucket,
+            records,
+        });
+    }
+    SpecIndex {
+        version,
+        buckets_bits,
+        min_key_length,
+        record_lists,
+    }
+}
+
+/// Parses a primary storage with CIDv1s and returns the digests by the position of the entry.
+fn parse_cid_primary(data: &[u8]) -> HashMap<u64, Vec<u8>> {
+    let mut entries = HashMap::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let entry_pos = pos as u64;
+        let size = read_varint(data, &mut pos) as usize;
+        let entry = take(data, &mut pos, size);
+        let mut entry_cursor = 0;
+        assert_eq!(read_varint(entry, &mut entry_cursor), 1, "CID version");
+        let _codec = read_varint(entry, &mut entry_cursor);
+        let _hash_code = read_varint(entry, &mut entry_cursor);
+        let digest_size = read_varint(entry, &mut entry_cursor) as usize;
+        let digest = take(entry, &mut entry_cursor, digest_size).to_vec();
+        entries.insert(entry_pos, digest);
+    }
+    entries
+}
+
+/// Checks that the index and the primary storage of a database follow the format and returns
+/// the live record lists by bucket.
+fn check_database(index_path: &Path, primary_path: &Path) -> HashMap<u32, SpecRecordList> {
+    let spec = parse_index(&fs::read(index_path).unwrap());
+    let digests = parse_cid_primary(&fs::read(primary_path).unwrap());
+    let mask = (1u64 << spec.buckets_bits) - 1;
+    let stripped_bytes = usize::from(spec.buckets_bits / 8);
+
+    // Only the last record list of a bucket is live.
+    let mut live = HashMap::new();
+    for record_list in spec.record_lists {
+        live.insert(record_list.bucket, record_list);
+    }
+    for record_list in live.values() {
+        for (key, file_offset) in &record_list.records {
+            let digest = &digests[file_offset];
+            let bucket = u64::from(u32::from_le_bytes(digest[..4].try_into().unwrap())) & mask;
+            assert_eq!(bucket, u64::from(record_list.bucket));
+            assert!(digest[stripped_bytes..].starts_with(key));
+        }
+    }
+    live
+}
+
+#[test]
+fn writer_matches_fixtures() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    build_fixture(temp_dir.path());
+    if env::var_os("STH_REGENERATE_FIXTURES").is_some() {
+        fs::create_dir_all(fixtures_dir()).unwrap();
+        for file in &["storethehash.db", "storethehash.db.index"] {
+            fs::copy(temp_dir.path().join(file), fixtures_dir().join(file)).unwrap();
+        }
+    }
+    for file in &["storethehash.db", "storethehash.db.index"] {
+        assert_eq!(
+            fs::read(temp_dir.path().join(file)).unwrap(),
+            fs::read(fixtures_dir().join(file)).unwrap(),
+            "{} differs from the fixture",
+            file
+        );
+    }
+}
+
+#[test]
+fn read_fixtures() {
+    let index_path = fixtures_dir().join("storethehash.db.index");
+    let primary_path = fixtures_dir().join("storethehash.db");
+    let live = check_database(&index_path, &primary_path);
+
+    let spec = parse_index(&fs::read(&index_path).unwrap());
+    assert_eq!(spec.version, index::INDEX_VERSION);
+    assert_eq!(spec.buckets_bits, BUCKETS_BITS);
+    assert_eq!(spec.min_key_length, index::DEFAULT_MIN_KEY_LENGTH);
+    // Some buckets contain more than one key, hence there are superseded record lists.
+    assert!(spec.record_lists.len() > live.len());
+
+    // The fixtures are copied, so that they are never changed by opening them.
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    fs::copy(&primary_path, &db_path).unwrap();
+    fs::copy(&index_path, temp_dir.path().join("storethehash.db.index")).unwrap();
+    let db = Db::<_, BUCKETS_BITS>::open_read_only(
+        CidPrimary::open_read_only(&db_path).unwrap(),
+        temp_dir.path().join("storethehash.db.index"),
+    )
+    .unwrap();
+    for ii in 0..FIXTURE_ENTRIES {
+        let data = fixture_data(ii);
+        let expected = if ii == FIXTURE_DELETED {
+            None
+        } else {
+            Some(data.clone())
+        };
+        assert_eq!(db.get(&cid(&data)).unwrap(), expected);
+    }
+
+    // The record iteration returns the same record lists as the format describes.
+    let mut index_file = fs::File::open(&index_path).unwrap();
+    let (_header, header_size) = index::read_header(&mut index_file).unwrap();
+    let iterated: Vec<SpecRecordList> = IndexIter::new(index_file, header_size)
+        .map(|entry| {
+            let (data, pos) = entry.unwrap();
+            SpecRecordList {
+                pos,
+                bucket: u32::from_le_bytes(data[..4].try_into().unwrap()),
+                records: RecordList::new(&data)
+                    .into_iter()
+                    .map(|record| (record.key.to_vec(), record.file_offset))
+                    .collect(),
+            }
+        })
+        .collect();
+    assert_eq!(iterated, spec.record_lists);
+}
+
+#[test]
+fn writer_follows_format() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("storethehash.db");
+    let index_path = temp_dir.path().join("storethehash.db.index");
+    let db = Db::<_, BUCKETS_BITS>::open(CidPrimary::open(&db_path).unwrap(), &index_path).unwrap();
+    // With 8 bits for the buckets, the keys need to be trimmed to more than a single byte.
+    let mut rng = StdRng::seed_from_u64(42);
+    let num_entries = 2000;
+    for _ in 0..num_entries {
+        let cid = [&CID_V1_PREFIX[..], &random_key(32, &mut rng)].concat();
+        db.put(&cid, b"data").unwrap();
+    }
+    db.close().unwrap();
+
+    let live = check_database(&index_path, &db_path);
+    let records: usize = live
+        .values()
+        .map(|record_list| record_list.records.len())
+        .sum();
+    assert_eq!(records, num_entries);
+    assert!(live
+        .values()
+        .flat_map(|record_list| &record_list.records)
+        .any(|(key, _)| key.len() > 1));
+
+    // The live record lists are the ones the index uses.
+    let index =
+        Index::<_, BUCKETS_BITS>::open_read_only(&index_path, CidPrimary::open(&db_path).unwrap())
+            .unwrap();
+    for entry in index.live_entries() {
+        let (bucket, pos, _data) = entry.unwrap();
+        assert_eq!(live[&(bucket as u32)].pos, pos);
+    }
+}
@@ -0,0 +1,85 @@
+//! Runs the fuzz targets on seed inputs, so that they don't break without noticing.
+use std::convert::TryFrom;
+
+use storethehash::fuzz;
+use storethehash::index::Header;
+use storethehash::primary::PrimaryStorage;
+use storethehash::testing;
+use storethehash_primary_cid::CidPrimary;
+use storethehash_primary_inmemory::InMemory;
+
+const BUCKETS_BITS: u8 = 8;
+
+/// The body of the `cid_primary_get` fuzz target.
+fn cid_primary_get(data: &[u8]) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("storethehash.data");
+    std::fs::write(&path, fuzz::size_prefixed(data)).unwrap();
+    let primary = CidPrimary::open_read_only(&path).unwrap();
+    let _ = primary.get(0);
+}
+
+/// Returns a valid index header including its size prefix.
+fn index_header() -> Vec<u8> {
+    let header: Vec<u8> = Header::new(BUCKETS_BITS).into();
+    let size = u32::try_from(header.len()).unwrap().to_le_bytes();
+    [&size[..], &header[..]].concat()
+}
+
+#[test]
+fn fuzz_recordlist_new() {
+    let keys: Vec<(&[u8], u64)> = vec![(b"key1", 1), (b"key2", 2)];
+    let valid = testing::encode_record_list(&keys);
+    let seeds: Vec<Vec<u8>> = vec![
+        Vec::new(),
+        vec![0xff; 3],
+        valid.clone(),
+        valid[..valid.len() - 1].to_vec(),
+        [&valid[..], &[0x10, 0x00]].concat(),
+    ];
+    for seed in &seeds {
+        fuzz::record_list_new(seed);
+    }
+}
+
+#[test]
+fn fuzz_index_open() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("storethehash.index");
+    let header = index_header();
+    let seeds: Vec<Vec<u8>> = vec![
+        Vec::new(),
+        vec![0xff; 8],
+        header.clone(),
+        header[..header.len() - 1].to_vec(),
+        // A record list that is cut off.
+        [&header[..], &[0x20, 0, 0, 0, 0x01]].concat(),
+        // A record list that is too short for a bucket prefix.
+        [&header[..], &[2, 0, 0, 0, 0x01, 0x02]].concat(),
+        // A record list for a bucket that doesn't exist.
+        [
+            &header[..],
+            &[5, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, BUCKETS_BITS],
+        ]
+        .concat(),
+        // A record list whose size is far bigger than the file.
+        [&header[..], &[0xff, 0xff, 0xff, 0xff, 0x01]].concat(),
+    ];
+    for seed in &seeds {
+        fuzz::index_open::<_, BUCKETS_BITS>(&path, seed, InMemory::new(&[]));
+    }
+}
+
+#[test]
+fn fuzz_cid_primary_get() {
+    let seeds: Vec<Vec<u8>> = vec![
+        Vec::new(),
+        vec![0x12, 0x20],
+        [&[0x01, 0x55, 0x12, 0x20][..], &[0xaa; 32], b"data"].concat(),
+        vec![0x01, 0x55, 0x12, 0xff, 0xff, 0xff, 0xff, 0x0f],
+        vec![0xff; 12],
+    ];
+    for seed in &seeds {
+        cid_primary_get(seed);
+    }
+}
@@ -1,19 +1,47 @@
-use std::convert::TryInto;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
 use std::fs::{self, File};
-use std::path::Path;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use storethehash::index::{self, Header, Index, IndexIter, INDEX_VERSION};
-use storethehash::recordlist::RecordList;
-use storethehash_primary_inmemory::InMemory;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use storethehash::codec::{KeyCodec, Sha256Codec};
+use storethehash::db::{
+    DanglingReference, Db, DbBuilder, ImportReport, InsertPosition, RepairPrimaryReport,
+    VerifyReport,
+};
+use storethehash::dynindex::DynIndex;
+use storethehash::error::{ChecksumError, Error};
+use storethehash::fsck::{self, FsckOptions, FsckReport, ProblemKind, Severity};
+use storethehash::hasher::{LeadingBytesHasher, XxHashBucketHasher};
+use storethehash::index::{
+    self, Header, Index, IndexBuilder, IndexIter, IndexStats, LookupResult, DEFAULT_MIN_KEY_LENGTH,
+    INDEX_VERSION, MAX_HEADER_SIZE, OLDEST_INDEX_VERSION,
+};
+use storethehash::metrics::{AtomicMetrics, Metrics};
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+use storethehash::progress::{ProgressSink, DEFAULT_PROGRESS_INTERVAL};
+use storethehash::ratelimit::{RateLimiter, TokenBucketRateLimiter};
+use storethehash::recordlist::{self, RecordList};
+use storethehash::syncer::SyncPolicy;
+use storethehash::testing::{build_index_with_n_keys, random_key};
+use storethehash_primary_cid::CidPrimary;
+use storethehash_primary_inmemory::{InMemory, PrimaryEvent, RecordingInMemory};
 
 fn assert_header(index_path: &Path, buckets_bits: u8) {
     let index_data = fs::read(&index_path).unwrap();
     let header_size_bytes: [u8; 4] = index_data[0..4].try_into().unwrap();
     let header_size = u32::from_le_bytes(header_size_bytes);
 
-    assert_eq!(header_size, 2);
-    let header_data = &index_data[index_data.len() - header_size as usize..];
-    let header = Header::from(header_data);
+    assert_eq!(header_size, 3);
+    let header_data = &index_data[4..4 + header_size as usize];
+    let header = Header::try_from(header_data).unwrap();
     assert_eq!(header.version, INDEX_VERSION);
     assert_eq!(header.buckets_bits, buckets_bits);
 }
@@ -230,6 +258,184 @@ fn index_get() {
     assert_eq!(shorter_than_prefixes, None);
 }
 
+/// Creates an index with `N` bits and checks that it's detected and usable.
+fn assert_open_detect<const N: u8>() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![9, 8, 7, 6, 5, 4, 3, 2, 1, 0];
+    let primary_storage =
+        || InMemory::new(&[(key1.clone(), vec![0x10]), (key2.clone(), vec![0x20])]);
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index = Index::<_, N>::open(&index_path, primary_storage()).unwrap();
+    index.put(&key1, 0).unwrap();
+    index.flush().unwrap();
+    drop(index);
+
+    assert_eq!(DynIndex::detect_buckets_bits(&index_path).unwrap(), N);
+    let index = DynIndex::open_detect(&index_path, primary_storage()).unwrap();
+    assert_eq!(index.buckets_bits(), N);
+    assert_eq!(index.get(&key1).unwrap(), Some(0));
+    assert_eq!(index.get(&key2).unwrap(), None);
+    index.put(&key2, 1).unwrap();
+    assert_eq!(index.get(&key1).unwrap(), Some(0));
+    assert_eq!(index.get(&key2).unwrap(), Some(1));
+}
+
+#[test]
+fn index_open_detect() {
+    assert_open_detect::<8>();
+    assert_open_detect::<16>();
+    assert_open_detect::<24>();
+}
+
+#[test]
+#[ignore = "the buckets of a 32-bit index need 32 GiB of memory"]
+fn index_open_detect_32_bits() {
+    assert_open_detect::<32>();
+}
+
+#[test]
+fn index_open_detect_header() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    // A header of 3 bytes with the version, the bits for the buckets and the minimum key length.
+    let header =
+        |buckets_bits| [&3u32.to_le_bytes()[..], &[INDEX_VERSION, buckets_bits, 4]].concat();
+
+    fs::write(&index_path, header(32)).unwrap();
+    assert_eq!(DynIndex::detect_buckets_bits(&index_path).unwrap(), 32);
+
+    fs::write(&index_path, header(12)).unwrap();
+    assert!(matches!(
+        DynIndex::open_detect(&index_path, InMemory::new(&[])),
+        Err(Error::UnsupportedBitSize(12))
+    ));
+
+    // Unlike `Index::open`, no new index is created.
+    let missing_path = temp_dir.path().join("missing.index");
+    assert!(matches!(
+        DynIndex::open_detect(&missing_path, InMemory::new(&[])),
+        Err(Error::Io { .. })
+    ));
+    assert!(!missing_path.exists());
+}
+
+#[test]
+fn index_get_corrupt_record_list() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![2, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), &index_path).unwrap();
+    let record_list_offset = fs::metadata(&index_path).unwrap().len();
+    db.put(&key1, &[0x10]).unwrap();
+
+    // Overwrite the size of the record list with a value that is bigger than the file.
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(&index_path)
+        .unwrap();
+    file.seek(SeekFrom::Start(record_list_offset)).unwrap();
+    file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+    assert!(matches!(
+        db.get(&key1),
+        Err(Error::CorruptRecordList { offset }) if offset == record_list_offset
+    ));
+    assert!(matches!(
+        db.put(&key1, &[0x11]),
+        Err(Error::CorruptRecordList { .. })
+    ));
+
+    // Shrink the size, so that the record is truncated.
+    file.seek(SeekFrom::Start(record_list_offset)).unwrap();
+    file.write_all(&7u32.to_le_bytes()).unwrap();
+    assert!(matches!(
+        db.get(&key1),
+        Err(Error::CorruptRecordList { offset }) if offset == record_list_offset
+    ));
+
+    // The database is still usable for other buckets.
+    db.put(&key2, &[0x20]).unwrap();
+    assert_eq!(db.get(&key2).unwrap(), Some(vec![0x20]));
+}
+
+#[test]
+fn index_open_corrupt() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    let header = fs::read(&index_path).unwrap();
+    let record_list_offset = u64::try_from(header.len()).unwrap();
+
+    // A record list that is too short for a bucket prefix.
+    fs::write(
+        &index_path,
+        [&header[..], &[2, 0, 0, 0, 0x01, 0x02]].concat(),
+    )
+    .unwrap();
+    assert!(matches!(
+        Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])),
+        Err(Error::CorruptRecordList { offset }) if offset == record_list_offset
+    ));
+
+    // A record list for a bucket that doesn't exist.
+    let out_of_range = [
+        &5u32.to_le_bytes()[..],
+        &u32::MAX.to_le_bytes(),
+        &[BUCKETS_BITS],
+    ]
+    .concat();
+    fs::write(&index_path, [&header[..], &out_of_range].concat()).unwrap();
+    assert!(matches!(
+        Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])),
+        Err(Error::CorruptRecordList { offset }) if offset == record_list_offset
+    ));
+
+    // A record list whose size is far bigger than the file is a truncated one, it's ignored.
+    fs::write(
+        &index_path,
+        [&header[..], &u32::MAX.to_le_bytes(), &[0x01]].concat(),
+    )
+    .unwrap();
+    Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+}
+
+#[test]
+fn index_io_error_context() {
+    let key = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    let record_list_offset = fs::metadata(&index_path).unwrap().len();
+    index.put(&key, 0).unwrap();
+
+    // Cut the index right before the record list.
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&index_path)
+        .unwrap()
+        .set_len(record_list_offset)
+        .unwrap();
+    let error = index.get(&key).unwrap_err();
+    assert!(matches!(
+        &error,
+        Error::Io { path: Some(path), offset: Some(offset), .. }
+            if path == &index_path && *offset == record_list_offset
+    ));
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "IO error reading record list at offset {} in {:?}: failed to fill whole buffer",
+            record_list_offset, index_path
+        )
+    );
+}
+
 #[test]
 fn index_header() {
     const BUCKETS_BITS: u8 = 24;
@@ -248,3 +454,1993 @@ fn index_header() {
         assert_header(&index_path, BUCKETS_BITS);
     }
 }
+
+#[test]
+fn index_invalid_header() {
+    const BUCKETS_BITS: u8 = 24;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+
+    assert!(Header::new(BUCKETS_BITS).validate().is_ok());
+    assert!(Header::new(1).validate().is_ok());
+    assert!(Header::new(32).validate().is_ok());
+
+    let write_header = |version: u8, buckets_bits: u8| {
+        let mut file = File::create(&index_path).unwrap();
+        file.write_all(&3u32.to_le_bytes()).unwrap();
+        file.write_all(&[version, buckets_bits, DEFAULT_MIN_KEY_LENGTH])
+            .unwrap();
+    };
+
+    for (version, buckets_bits, field, value) in &[
+        (INDEX_VERSION, 0, "buckets_bits", 0),
+        (INDEX_VERSION, 33, "buckets_bits", 33),
+        (INDEX_VERSION, 255, "buckets_bits", 255),
+    ] {
+        let header = Header {
+            version: *version,
+            buckets_bits: *buckets_bits,
+            min_key_length: DEFAULT_MIN_KEY_LENGTH,
+        };
+        assert!(matches!(
+            header.validate(),
+            Err(Error::InvalidHeader { field: f, value: v }) if f == *field && v == *value
+        ));
+
+        write_header(*version, *buckets_bits);
+        let result = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[]));
+        assert!(matches!(
+            result,
+            Err(Error::InvalidHeader { field: f, value: v }) if f == *field && v == *value
+        ));
+    }
+
+    for (version, buckets_bits) in &[
+        (INDEX_VERSION + 1, BUCKETS_BITS),
+        (255, BUCKETS_BITS),
+        (0, BUCKETS_BITS),
+        (OLDEST_INDEX_VERSION - 1, BUCKETS_BITS),
+        // The version is checked first.
+        (255, 0),
+    ] {
+        let header = Header {
+            version: *version,
+            buckets_bits: *buckets_bits,
+            min_key_length: DEFAULT_MIN_KEY_LENGTH,
+        };
+        assert!(matches!(
+            header.validate(),
+            Err(Error::UnsupportedVersion(v)) if v == *version
+        ));
+
+        write_header(*version, *buckets_bits);
+        let result = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[]));
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedVersion(v)) if v == *version
+        ));
+    }
+}
+
+#[test]
+fn index_corrupt_header() {
+    const BUCKETS_BITS: u8 = 24;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+
+    assert!(matches!(
+        Header::try_from(&[][..]),
+        Err(Error::CorruptHeader { .. })
+    ));
+    assert!(matches!(
+        Header::try_from(&[INDEX_VERSION][..]),
+        Err(Error::CorruptHeader { .. })
+    ));
+    // Since version 4 the minimum key length is required.
+    assert!(matches!(
+        Header::try_from(&[INDEX_VERSION, BUCKETS_BITS][..]),
+        Err(Error::CorruptHeader { .. })
+    ));
+    // Additional bytes are ignored.
+    let header = Header::try_from(&[INDEX_VERSION, BUCKETS_BITS, 8, 0xff][..]).unwrap();
+    assert_eq!(header.buckets_bits, BUCKETS_BITS);
+    assert_eq!(header.min_key_length, 8);
+    // Older versions don't have it.
+    let header = Header::try_from(&[3, BUCKETS_BITS][..]).unwrap();
+    assert_eq!(header.min_key_length, DEFAULT_MIN_KEY_LENGTH);
+
+    let too_big = u32::try_from(MAX_HEADER_SIZE + 1).unwrap();
+    for data in [
+        // Empty file.
+        Vec::new(),
+        // Truncated size prefix.
+        vec![2, 0],
+        // The size prefix says 2 bytes, but the file ends.
+        [&2u32.to_le_bytes()[..], &[INDEX_VERSION]].concat(),
+        // A 1-byte header.
+        [&1u32.to_le_bytes()[..], &[INDEX_VERSION]].concat(),
+        // Absurd size prefixes.
+        [&too_big.to_le_bytes()[..], &[INDEX_VERSION, BUCKETS_BITS]].concat(),
+        [&u32::MAX.to_le_bytes()[..], &[INDEX_VERSION, BUCKETS_BITS]].concat(),
+    ] {
+        fs::write(&index_path, &data).unwrap();
+        let mut file = File::open(&index_path).unwrap();
+        assert!(
+            matches!(
+                index::read_header(&mut file),
+                Err(Error::CorruptHeader { .. })
+            ),
+            "{:?}",
+            data
+        );
+        let result = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[]));
+        assert!(
+            matches!(result, Err(Error::CorruptHeader { .. })),
+            "{:?}",
+            data
+        );
+    }
+}
+
+#[test]
+fn index_put_observer() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    const BUCKETS_BITS: u8 = 24;
+    let primary_storage = InMemory::new(&[(key1.clone(), vec![0x10]), (key2.clone(), vec![0x20])]);
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let mut index = Index::<_, BUCKETS_BITS>::open(&index_path, primary_storage).unwrap();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let events = Arc::new(Mutex::new(Vec::new()));
+    {
+        let calls = calls.clone();
+        let events = events.clone();
+        index.set_put_observer(Box::new(move |event| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            events.lock().unwrap().push((
+                event.bucket,
+                event.was_insert,
+                event.record_list_size_before,
+                event.record_list_size_after,
+            ));
+        }));
+    }
+
+    index.put(&key1, 0).unwrap();
+    index.put(&key2, 1).unwrap();
+    // Inserting an existing key again doesn't change the record list.
+    index.put(&key1, 0).unwrap();
+
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    let bucket = 0x030201;
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            (bucket, true, 0, 10),
+            (bucket, true, 10, 26),
+            (bucket, false, 26, 26)
+        ]
+    );
+}
+
+#[test]
+fn index_load() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+
+    let empty_dir = temp_dir.path().join("empty");
+    fs::create_dir(&empty_dir).unwrap();
+    let index = build_index_with_n_keys::<InMemory, BUCKETS_BITS>(0, &empty_dir).unwrap();
+    assert_eq!(index.bucket_load_factor().unwrap(), vec![]);
+    assert_eq!(index.max_load().unwrap(), 0);
+
+    // The keys are uniformly distributed.
+    let index = build_index_with_n_keys::<InMemory, BUCKETS_BITS>(1000, temp_dir.path()).unwrap();
+
+    let loads = index.bucket_load_factor().unwrap();
+    assert!(loads.len() <= 256);
+    assert!(loads.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(loads.iter().map(|(_, count)| count).sum::<usize>(), 1000);
+
+    let max_load = index.max_load().unwrap();
+    let avg_load = index.avg_load().unwrap();
+    assert!((avg_load - 1000.0 / 256.0).abs() < f64::EPSILON);
+    assert!(max_load < 20, "max load is {}", max_load);
+
+    let stats = index.stats().unwrap();
+    assert_eq!(
+        stats,
+        IndexStats {
+            non_empty_buckets: loads.len(),
+            records: 1000,
+            max_load,
+            avg_load,
+        }
+    );
+}
+
+#[test]
+fn index_from_reader() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    let key3 = vec![2, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    let primary = || {
+        InMemory::new(&[
+            (key1.clone(), vec![0x10]),
+            (key2.clone(), vec![0x20]),
+            (key3.clone(), vec![0x30]),
+        ])
+    };
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let file_index = Index::<_, BUCKETS_BITS>::open(&index_path, primary()).unwrap();
+    let mut data = Vec::new();
+    {
+        let index = Index::<_, BUCKETS_BITS, LeadingBytesHasher, _>::from_reader(
+            Cursor::new(&mut data),
+            primary(),
+        )
+        .unwrap();
+        for (pos, key) in [&key1, &key2, &key3].iter().enumerate() {
+            index.put(key, pos as u64).unwrap();
+            file_index.put(key, pos as u64).unwrap();
+        }
+        assert_eq!(index.get(&key1).unwrap(), Some(0));
+        assert_eq!(index.get(&key2).unwrap(), Some(1));
+        assert_eq!(index.get(&key3).unwrap(), Some(2));
+        assert_eq!(index.size().unwrap(), file_index.size().unwrap());
+        assert_eq!(index.live_size().unwrap(), file_index.live_size().unwrap());
+        assert_eq!(
+            index.used_buckets().unwrap(),
+            file_index.used_buckets().unwrap()
+        );
+        index.flush().unwrap();
+        let snapshot_path = temp_dir.path().join("snapshot.index");
+        index.snapshot(&snapshot_path).unwrap();
+        assert_eq!(
+            fs::read(&snapshot_path).unwrap(),
+            fs::read(&index_path).unwrap()
+        );
+    }
+    // The storage contains exactly the same bytes as the file.
+    assert_eq!(data, fs::read(&index_path).unwrap());
+
+    // The buckets are recreated when it's opened again.
+    let index = Index::<_, BUCKETS_BITS, LeadingBytesHasher, _>::from_reader(
+        Cursor::new(&mut data),
+        primary(),
+    )
+    .unwrap();
+    assert_eq!(index.get(&key1).unwrap(), Some(0));
+    assert_eq!(index.get(&key3).unwrap(), Some(2));
+    assert_eq!(index.offsets(), file_index.offsets());
+    drop(index);
+
+    assert!(matches!(
+        Index::<_, 16, LeadingBytesHasher, _>::from_reader(Cursor::new(&mut data), primary()),
+        Err(Error::IndexWrongBitSize(BUCKETS_BITS, 16))
+    ));
+    // A version 2 index needs to be migrated, which only works with files.
+    assert!(matches!(
+        Index::<_, 24, LeadingBytesHasher, _>::from_reader(
+            Cursor::new(vec![2, 0, 0, 0, 2, 24]),
+            primary()
+        ),
+        Err(Error::UnsupportedVersion(2))
+    ));
+}
+
+#[test]
+fn db_for_each_in_bucket() {
+    // With 8 bits the first byte of a key is its bucket.
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    let key3 = vec![2, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), &index_path).unwrap();
+    db.put(&key1, &[0x10]).unwrap();
+    db.put(&key2, &[0x20]).unwrap();
+    db.put(&key3, &[0x30]).unwrap();
+
+    let mut entries = Vec::new();
+    db.for_each_in_bucket(1, |key, value| {
+        entries.push((key, value));
+        Ok(())
+    })
+    .unwrap();
+    // The entries are sorted by their keys.
+    assert_eq!(entries, vec![(key2, vec![0x20]), (key1, vec![0x10])]);
+
+    let mut entries = Vec::new();
+    db.for_each_in_bucket(2, |key, value| {
+        entries.push((key, value));
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(entries, vec![(key3, vec![0x30])]);
+
+    // An empty bucket.
+    db.for_each_in_bucket(3, |_key, _value| panic!("bucket must be empty"))
+        .unwrap();
+
+    // The iteration stops at the first error.
+    let mut calls = 0;
+    let result = db.for_each_in_bucket(1, |_key, _value| {
+        calls += 1;
+        Err(Error::IndexCorrupt)
+    });
+    assert!(matches!(result, Err(Error::IndexCorrupt)));
+    assert_eq!(calls, 1);
+
+    // A bucket that doesn't exist.
+    let result = db.for_each_in_bucket(1 << BUCKETS_BITS, |_key, _value| Ok(()));
+    assert!(matches!(result, Err(Error::BucketsOutOfBounds)));
+}
+
+#[test]
+fn db_iter_by_bucket() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), &index_path).unwrap();
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut keys: Vec<Vec<u8>> = (0..100).map(|_| random_key(32, &mut rng)).collect();
+    for (ii, key) in keys.iter().enumerate() {
+        db.put(key, &[ii as u8]).unwrap();
+    }
+
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = db.iter_by_bucket().map(Result::unwrap).collect();
+    assert_eq!(entries.len(), 100);
+    // With 8 bits the first byte of a key is its bucket, within a bucket the keys are sorted.
+    assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    keys.sort();
+    let iter_keys: Vec<Vec<u8>> = entries.iter().map(|(key, _value)| key.clone()).collect();
+    assert_eq!(iter_keys, keys);
+
+    // A second run returns the entries in the same order.
+    let second_run: Vec<(Vec<u8>, Vec<u8>)> = db.iter_by_bucket().map(Result::unwrap).collect();
+    assert_eq!(second_run, entries);
+}
+
+#[test]
+fn db_snapshot() {
+    // CIDv1 with the raw codec and a SHA2-256 multihash.
+    let cid = |digest_byte: u8| [&[0x01, 0x55, 0x12, 0x20][..], &[digest_byte; 32][..]].concat();
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary = CidPrimary::open(temp_dir.path().join("storethehash.db")).unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(primary, &index_path).unwrap();
+    db.put(&cid(1), b"value 1").unwrap();
+    db.put(&cid(2), b"value 2").unwrap();
+
+    let snapshot = db.snapshot(&temp_dir.path().join("snapshot")).unwrap();
+    assert_eq!(
+        snapshot.primary_size,
+        fs::metadata(&snapshot.primary_path).unwrap().len()
+    );
+
+    // Changes after the snapshot was taken are not part of it.
+    db.put(&cid(3), b"value 3").unwrap();
+    let snapshot_db: Db<_, BUCKETS_BITS> = snapshot
+        .open(CidPrimary::open(&snapshot.primary_path).unwrap())
+        .unwrap();
+    assert_eq!(db.count().unwrap(), 3);
+    assert_eq!(snapshot_db.count().unwrap(), 2);
+    assert_eq!(snapshot_db.get(&cid(2)).unwrap(), Some(b"value 2".to_vec()));
+    assert_eq!(snapshot_db.get(&cid(3)).unwrap(), None);
+
+    // The snapshot is read-only.
+    assert!(matches!(
+        snapshot_db.put(&cid(4), b"value 4"),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(snapshot_db.delete(&cid(1)), Err(Error::ReadOnly)));
+}
+
+#[test]
+fn db_repair_primary() {
+    // A CIDv1 with the raw codec and the SHA2-256 digest of the given data.
+    let cid = |data: &[u8]| {
+        let digest = Sha256Codec::encode(data).unwrap();
+        [&[0x01, 0x55, 0x12, 0x20][..], &digest[..]].concat()
+    };
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary_path = temp_dir.path().join("storethehash.db");
+    let primary = CidPrimary::open(&primary_path).unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(primary, &index_path).unwrap();
+
+    db.put(&cid(b"value 1"), b"value 1").unwrap();
+    let corrupt_offset = fs::metadata(&primary_path).unwrap().len();
+    // The digest of the CID doesn't match the stored data.
+    let corrupt_cid = cid(b"value 2");
+    db.put(&corrupt_cid, b"corrupt").unwrap();
+    db.put(&cid(b"value 3"), b"value 3").unwrap();
+    assert_eq!(db.count().unwrap(), 3);
+
+    let report = db.repair_primary().unwrap();
+    assert_eq!(
+        report,
+        RepairPrimaryReport {
+            blocks_checked: 3,
+            blocks_corrupt: 1,
+            corrupt_offsets: vec![corrupt_offset],
+        }
+    );
+    assert_eq!(db.get(&corrupt_cid).unwrap(), None);
+    assert_eq!(db.get(&cid(b"value 1")).unwrap(), Some(b"value 1".to_vec()));
+    assert_eq!(db.get(&cid(b"value 3")).unwrap(), Some(b"value 3".to_vec()));
+    assert_eq!(db.count().unwrap(), 2);
+}
+
+/// Creates a database with a CID primary storage and returns the paths of the primary storage and
+/// the index.
+fn build_fsck_fixture(dir: &Path, name: &str, values: &[&[u8]]) -> (PathBuf, PathBuf) {
+    const BUCKETS_BITS: u8 = 8;
+    let primary_path = dir.join(format!("{}.data", name));
+    let index_path = dir.join(format!("{}.index", name));
+    let primary = CidPrimary::open(&primary_path).unwrap();
+    let db = Db::<_, BUCKETS_BITS>::open(primary, &index_path).unwrap();
+    for value in values {
+        let digest = Sha256Codec::encode(value).unwrap();
+        let cid = [&[0x01, 0x55, 0x12, 0x20][..], &digest[..]].concat();
+        db.put(&cid, value).unwrap();
+    }
+    db.close().unwrap();
+    (primary_path, index_path)
+}
+
+#[test]
+fn fsck_healthy() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let values: &[&[u8]] = &[b"value 1", b"value 2", b"value 3", b"value 4"];
+    let (primary_path, index_path) = build_fsck_fixture(temp_dir.path(), "healthy", values);
+    let primary = CidPrimary::open(&primary_path).unwrap();
+
+    let report = fsck::check(&index_path, &primary, FsckOptions::default()).unwrap();
+    assert!(report.is_healthy(), "{:?}", report);
+    assert_eq!(report.version, Some(INDEX_VERSION));
+    assert_eq!(report.buckets_bits, Some(8));
+    assert_eq!(report.records_checked, report.primary_records_checked);
+    assert_eq!(report.primary_records_checked, 4);
+
+    let report = fsck::check(
+        &index_path,
+        &primary,
+        FsckOptions {
+            check_primary: false,
+        },
+    )
+    .unwrap();
+    assert!(report.is_healthy());
+    assert_eq!(report.primary_records_checked, 0);
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["problems"], serde_json::json!([]));
+    assert_eq!(json["buckets_bits"], 8);
+}
+
+#[test]
+fn fsck_truncated_index() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let values: &[&[u8]] = &[b"value 1", b"value 2"];
+    let (primary_path, index_path) = build_fsck_fixture(temp_dir.path(), "truncated", values);
+    let primary = CidPrimary::open(&primary_path).unwrap();
+    let index_size = fs::metadata(&index_path).unwrap().len();
+
+    // Cut the last record list, it is ignored when the index is opened.
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(&index_path)
+        .unwrap();
+    file.set_len(index_size - 3).unwrap();
+    let report = fsck::check(&index_path, &primary, FsckOptions::default()).unwrap();
+    assert!(!report.is_healthy());
+    assert!(!report.has_fatal());
+    assert_eq!(report.problems.len(), 1);
+    assert_eq!(report.problems[0].severity, Severity::Recoverable);
+    assert!(matches!(
+        report.problems[0].kind,
+        ProblemKind::TrailingGarbage { .. }
+    ));
+
+    // Cut within the header.
+    file.set_len(5).unwrap();
+    let report = fsck::check(&index_path, &primary, FsckOptions::default()).unwrap();
+    assert!(report.has_fatal());
+    assert!(matches!(
+        report.problems[0].kind,
+        ProblemKind::InvalidHeader { .. }
+    ));
+    assert_eq!(report.version, None);
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["problems"][0]["severity"], "Fatal");
+}
+
+#[test]
+fn fsck_mismatched_pair() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let values: &[&[u8]] = &[b"value 1", b"value 2", b"value 3"];
+    let (_primary_path, index_path) = build_fsck_fixture(temp_dir.path(), "first", values);
+    // Same sizes, but different keys.
+    let other_values: &[&[u8]] = &[b"other 1", b"other 2", b"other 3"];
+    let (other_primary_path, _other_index_path) =
+        build_fsck_fixture(temp_dir.path(), "second", other_values);
+    let other_primary = CidPrimary::open(&other_primary_path).unwrap();
+
+    let report = fsck::check(&index_path, &other_primary, FsckOptions::default()).unwrap();
+    assert_eq!(report.problems.len(), 3);
+    assert!(report.problems.iter().all(|problem| {
+        problem.severity == Severity::Fatal
+            && matches!(problem.kind, ProblemKind::KeyMismatch { .. })
+    }));
+
+    // The index points past the end of an empty primary storage.
+    let empty_primary = CidPrimary::open(temp_dir.path().join("empty.data")).unwrap();
+    let report = fsck::check(&index_path, &empty_primary, FsckOptions::default()).unwrap();
+    assert_eq!(report.problems.len(), 3);
+    assert!(report.problems.iter().all(|problem| matches!(
+        problem.kind,
+        ProblemKind::DanglingOffset {
+            primary_size: 0,
+            ..
+        }
+    )));
+
+    // Without the primary checks the index itself is fine.
+    let report = fsck::check(
+        &index_path,
+        &empty_primary,
+        FsckOptions {
+            check_primary: false,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        report,
+        FsckReport {
+            version: Some(INDEX_VERSION),
+            buckets_bits: Some(8),
+            record_lists_checked: report.record_lists_checked,
+            live_record_lists: report.live_record_lists,
+            records_checked: 3,
+            primary_records_checked: 0,
+            problems: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn db_snapshot_unsupported() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), &index_path).unwrap();
+    let result = db.snapshot(&temp_dir.path().join("snapshot"));
+    assert!(matches!(result, Err(Error::Primary(_))));
+}
+
+#[test]
+fn db_stats() {
+    // With 8 bits the first byte of a key is its bucket.
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), &index_path).unwrap();
+    let header_size = db.stats().unwrap().index_size;
+    db.put(&key1, &[0x10]).unwrap();
+    let first_put_size = db.stats().unwrap().index_size;
+    db.put(&key2, &[0x20]).unwrap();
+
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.index.records, 2);
+    assert_eq!(stats.index.non_empty_buckets, 1);
+    assert_eq!(stats.index_size, fs::metadata(&index_path).unwrap().len());
+    // The record list of the first put was superseded by the one of the second put.
+    assert_eq!(
+        stats.live_index_size,
+        header_size + stats.index_size - first_put_size
+    );
+    assert_eq!(
+        stats.garbage.stale_bytes,
+        stats.index_size - stats.live_index_size
+    );
+    // The in-memory primary storage doesn't know its size.
+    assert_eq!(stats.primary_size, None);
+}
+
+#[test]
+fn db_count_by_bucket() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = Db::<_, BUCKETS_BITS>::open(
+        InMemory::new(&[]),
+        temp_dir.path().join("storethehash.index"),
+    )
+    .unwrap();
+    assert_eq!(db.count_by_bucket().unwrap(), vec![0; 1 << BUCKETS_BITS]);
+    assert_eq!(db.total_count().unwrap(), 0);
+
+    let mut rng = StdRng::seed_from_u64(3);
+    let keys: Vec<Vec<u8>> = (0..500).map(|_| random_key(32, &mut rng)).collect();
+    for key in &keys {
+        db.put(key, b"value").unwrap();
+    }
+
+    let counts = db.count_by_bucket().unwrap();
+    assert_eq!(counts.len(), 1 << BUCKETS_BITS);
+    // With 8 bits the first byte of a key is its bucket.
+    for (bucket, count) in counts.iter().enumerate() {
+        let expected = keys.iter().filter(|key| key[0] as usize == bucket).count();
+        assert_eq!(*count, expected as u64);
+    }
+    assert_eq!(db.total_count().unwrap(), keys.len() as u64);
+    assert_eq!(db.total_count().unwrap(), db.count().unwrap() as u64);
+}
+
+#[test]
+fn db_rate_limiter() {
+    // Counts the acquired bytes.
+    #[derive(Clone, Default)]
+    struct CountingLimiter(Arc<AtomicUsize>);
+    impl RateLimiter for CountingLimiter {
+        fn acquire(&self, bytes: usize) -> Result<(), Error> {
+            self.0.fetch_add(bytes, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+
+    let limiter = CountingLimiter::default();
+    let db = DbBuilder::<_, BUCKETS_BITS>::new(InMemory::new(&[]), &index_path)
+        .with_rate_limiter(limiter.clone())
+        .open()
+        .unwrap();
+    db.put(&key1, &[0x10, 0x11]).unwrap();
+    assert_eq!(limiter.0.load(Ordering::SeqCst), key1.len() + 2);
+
+    // A limiter without any capacity rejects all writes, nothing is written.
+    let index_path = temp_dir.path().join("storethehash-limited.index");
+    let db = DbBuilder::<_, BUCKETS_BITS>::new(InMemory::new(&[]), &index_path)
+        .with_rate_limiter(TokenBucketRateLimiter::new(0, 0))
+        .open()
+        .unwrap();
+    assert!(matches!(db.put(&key2, &[0x20]), Err(Error::RateLimited)));
+    assert_eq!(db.get(&key2).unwrap(), None);
+    assert_eq!(db.count().unwrap(), 0);
+}
+
+#[test]
+fn db_metrics() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![9, 8, 7, 6, 5, 4, 3, 2, 1, 0];
+    // It's in the same bucket as the first key and has the same first byte after the bucket
+    // prefix, hence the index points to the first key.
+    let key3 = vec![1, 2, 3, 4, 5, 6, 8, 8, 8, 8];
+    let value = b"value";
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let metrics = Arc::new(AtomicMetrics::new());
+    let db = DbBuilder::<_, 24>::new(
+        InMemory::new(&[]),
+        temp_dir.path().join("storethehash.index"),
+    )
+    .with_metrics(metrics.clone())
+    .open()
+    .unwrap();
+    db.put(&key1, value).unwrap();
+    db.put(&key2, value).unwrap();
+    // The key already exists, the index isn't written.
+    db.put(&key1, value).unwrap();
+    assert_eq!(db.get(&key1).unwrap(), Some(value.to_vec()));
+    assert_eq!(db.get(&key3).unwrap(), None);
+    assert_eq!(db.get(&[7, 7, 7, 7, 7]).unwrap(), None);
+
+    let snapshot = metrics.snapshot().unwrap();
+    assert_eq!(snapshot.puts, 3);
+    // A record with an 8 byte offset, the key size and a single byte key.
+    assert_eq!(snapshot.max_record_list_bytes, 10);
+    // Two record lists with their size prefix, bucket prefix and bits for the buckets.
+    assert_eq!(snapshot.index_bytes_written, 2 * (4 + 5 + 10));
+    assert_eq!(snapshot.gets, 3);
+    assert_eq!(snapshot.hits, 1);
+    assert_eq!(snapshot.false_positives, 1);
+    // The entry of the first key is read by both, the hit and the false positive.
+    assert_eq!(
+        snapshot.primary_bytes_read,
+        2 * (key1.len() + value.len()) as u64
+    );
+    assert_eq!(db.stats().unwrap().metrics, Some(snapshot));
+
+    // The batched gets are reported the same way.
+    db.get_many(&[&key1, &key3, &[7, 7, 7, 7, 7]]);
+    let snapshot = metrics.snapshot().unwrap();
+    assert_eq!(snapshot.gets, 6);
+    assert_eq!(snapshot.hits, 2);
+    assert_eq!(snapshot.false_positives, 2);
+}
+
+#[test]
+fn db_progress_sink() {
+    #[derive(Clone, Default)]
+    struct RecordingSink(Arc<Mutex<Vec<(u64, u64)>>>);
+    impl ProgressSink for RecordingSink {
+        fn report(&self, keys_inserted: u64, bytes_written: u64) {
+            self.0.lock().unwrap().push((keys_inserted, bytes_written));
+        }
+    }
+
+    const NUM_KEYS: u64 = 200_000;
+    const BUCKETS_BITS: u8 = 16;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let sink = RecordingSink::default();
+    let db = DbBuilder::<_, BUCKETS_BITS>::new(
+        InMemory::new(&[]),
+        temp_dir.path().join("storethehash.index"),
+    )
+    .with_progress_sink(sink.clone())
+    .open()
+    .unwrap();
+
+    let mut rng = StdRng::seed_from_u64(42);
+    for _ in 0..NUM_KEYS {
+        db.put(&random_key(32, &mut rng), b"value").unwrap();
+    }
+    // The sink is called every `DEFAULT_PROGRESS_INTERVAL` keys.
+    assert_eq!(
+        *sink.0.lock().unwrap(),
+        vec![
+            (
+                DEFAULT_PROGRESS_INTERVAL,
+                DEFAULT_PROGRESS_INTERVAL * (32 + 5)
+            ),
+            (NUM_KEYS, NUM_KEYS * (32 + 5)),
+        ]
+    );
+
+    // A custom interval, 0 is treated like 1.
+    let sink = RecordingSink::default();
+    let db = DbBuilder::<_, BUCKETS_BITS>::new(
+        InMemory::new(&[]),
+        temp_dir.path().join("storethehash2.index"),
+    )
+    .with_progress_sink(sink.clone())
+    .progress_interval(0)
+    .open()
+    .unwrap();
+    db.put(b"key1", b"value").unwrap();
+    db.put(b"key2", b"value").unwrap();
+    assert_eq!(*sink.0.lock().unwrap(), vec![(1, 4 + 5), (2, 2 * (4 + 5))]);
+}
+
+/// A primary storage that uses the default implementations, e.g. of
+/// [`PrimaryStorage::iter_values`].
+struct DefaultsPrimary(CidPrimary);
+
+impl PrimaryStorage for DefaultsPrimary {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        self.0.get(pos)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        self.0.put(key, value)
+    }
+
+    fn size(&self) -> Result<Option<u64>, PrimaryError> {
+        self.0.size()
+    }
+
+    fn next_pos(&self, pos: u64) -> Result<u64, PrimaryError> {
+        self.0.next_pos(pos)
+    }
+}
+
+#[test]
+fn db_iter_values_only() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary_path = temp_dir.path().join("storethehash.db");
+    let db = Db::<_, 8>::open(
+        CidPrimary::open(&primary_path).unwrap(),
+        temp_dir.path().join("storethehash.db.index"),
+    )
+    .unwrap();
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..50u8)
+        .map(|ii| {
+            let data = vec![ii; usize::from(ii)];
+            let cid = [
+                &[0x01, 0x55, 0x12, 0x20][..],
+                &Sha256Codec::encode(&data).unwrap(),
+            ]
+            .concat();
+            (cid, data)
+        })
+        .collect();
+    for (cid, data) in &entries {
+        db.put(cid, data).unwrap();
+    }
+    // The value of a deleted key is still in the primary storage.
+    assert!(db.delete(&entries[3].0).unwrap());
+
+    let expected: Vec<Vec<u8>> = entries.into_iter().map(|(_cid, data)| data).collect();
+    let values: Vec<Vec<u8>> = db.iter_values_only().map(Result::unwrap).collect();
+    assert_eq!(values, expected);
+    assert_eq!(db.iter_by_bucket().count(), expected.len() - 1);
+
+    // The default implementation returns the same values.
+    let primary = DefaultsPrimary(CidPrimary::open_read_only(&primary_path).unwrap());
+    let values: Vec<Vec<u8>> = primary.iter_values().map(Result::unwrap).collect();
+    assert_eq!(values, expected);
+    // Without knowing the size it's an error.
+    let values: Vec<_> = InMemory::new(&[]).iter_values().collect();
+    assert!(matches!(values[..], [Err(PrimaryError::Other(_))]));
+}
+
+#[test]
+fn db_export_import() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+    let keys: Vec<Vec<u8>> = (0..100).map(|_| random_key(32, &mut rng)).collect();
+    let db = Db::<_, BUCKETS_BITS>::open(
+        InMemory::new(&[]),
+        temp_dir.path().join("storethehash.index"),
+    )
+    .unwrap();
+    for (ii, key) in keys.iter().enumerate() {
+        db.put(key, format!("value {}", ii).as_bytes()).unwrap();
+    }
+    // Only the live entries are exported, the first value of a key is kept and deleted keys are
+    // gone.
+    db.put(&keys[1], b"other value").unwrap();
+    assert!(db.delete(&keys[2]).unwrap());
+
+    let mut export = Vec::new();
+    assert_eq!(db.export(&mut export).unwrap(), 99);
+
+    let imported =
+        Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), temp_dir.path().join("imported.index"))
+            .unwrap();
+    assert_eq!(
+        imported.import(&export[..], false).unwrap(),
+        ImportReport {
+            imported: 99,
+            skipped: 0
+        }
+    );
+    for key in &keys {
+        assert_eq!(imported.get(key).unwrap(), db.get(key).unwrap());
+    }
+    assert_eq!(imported.get(&keys[1]).unwrap(), Some(b"value 1".to_vec()));
+    assert_eq!(imported.get(&keys[2]).unwrap(), None);
+    let mut reexport = Vec::new();
+    imported.export(&mut reexport).unwrap();
+    assert_eq!(reexport, export);
+
+    // Restarting an import doesn't store anything twice.
+    assert_eq!(
+        imported.import(&export[..], true).unwrap(),
+        ImportReport {
+            imported: 0,
+            skipped: 99
+        }
+    );
+    assert!(matches!(
+        imported.primary().get(99),
+        Err(PrimaryError::OutOfBounds { len: 99, .. })
+    ));
+
+    // An export that ends within an entry is an error.
+    let truncated =
+        Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), temp_dir.path().join("truncated.index"))
+            .unwrap();
+    let error = truncated
+        .import(&export[..export.len() - 1], false)
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        Error::Io { ref source, .. } if source.kind() == std::io::ErrorKind::UnexpectedEof
+    ));
+    assert_eq!(truncated.count().unwrap(), 98);
+}
+
+#[test]
+fn db_put_insert_position() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), &index_path).unwrap();
+    // The index only contains the header so far, the first record list is appended after it.
+    let header_size = fs::metadata(&index_path).unwrap().len();
+
+    let key1 = [1, 2, 3, 4, 5, 6];
+    let position1 = db.put(&key1, b"value1").unwrap();
+    assert_eq!(
+        position1,
+        InsertPosition {
+            primary_offset: 0,
+            index_bucket: 1,
+            index_record_list_offset: header_size,
+        }
+    );
+
+    // A key of the same bucket appends a new record list.
+    let key2 = [1, 9, 9, 9, 9, 9];
+    let position2 = db.put(&key2, b"value2").unwrap();
+    assert_eq!(position2.primary_offset, 1);
+    assert_eq!(position2.index_bucket, 1);
+    assert!(position2.index_record_list_offset > position1.index_record_list_offset);
+
+    // Putting an existing key doesn't change the index.
+    let position3 = db.put(&key1, b"other value").unwrap();
+    assert_eq!(position3.primary_offset, 2);
+    assert_eq!(
+        position3.index_record_list_offset,
+        position2.index_record_list_offset
+    );
+    assert_eq!(
+        db.put_get_offset(&[2, 2, 3, 4, 5, 6], b"value3").unwrap(),
+        3
+    );
+    db.flush().unwrap();
+
+    // The positions are the ones the index points to.
+    let index = Index::<_, BUCKETS_BITS>::open_read_only(&index_path, InMemory::new(&[])).unwrap();
+    let (record_list_offset, _data) = index.bucket_records(1).unwrap().unwrap();
+    assert_eq!(record_list_offset, position2.index_record_list_offset);
+}
+
+#[test]
+fn db_progress() {
+    #[derive(Default)]
+    struct RecordingProgress(Mutex<Vec<(String, u64, Option<u64>)>>);
+    impl ProgressSink for RecordingProgress {
+        fn report_phase(&self, phase: &str, done: u64, total: Option<u64>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((phase.to_string(), done, total));
+        }
+    }
+
+    /// Checks that the reports are increasing and returns the last one.
+    fn last_report(progress: RecordingProgress) -> (String, u64, Option<u64>) {
+        let reports = progress.0.into_inner().unwrap();
+        assert!(reports.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        reports.last().unwrap().clone()
+    }
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+    let db = Db::<_, BUCKETS_BITS>::open(
+        InMemory::new(&[]),
+        temp_dir.path().join("storethehash.index"),
+    )
+    .unwrap();
+    for _ in 0..100 {
+        db.put(&random_key(32, &mut rng), b"value").unwrap();
+    }
+
+    let progress = RecordingProgress::default();
+    db.verify_with_progress(Some(&progress)).unwrap();
+    assert_eq!(
+        last_report(progress),
+        (
+            "verify".to_string(),
+            1 << BUCKETS_BITS,
+            Some(1 << BUCKETS_BITS)
+        )
+    );
+
+    let progress = RecordingProgress::default();
+    let mut export = Vec::new();
+    db.export_with_progress(&mut export, Some(&progress))
+        .unwrap();
+    assert_eq!(last_report(progress), ("export".to_string(), 100, None));
+}
+
+#[test]
+fn db_sync_to() {
+    // With 8 bits the first byte of a key is its bucket.
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    let key3 = vec![2, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key4 = vec![3, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let source =
+        Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), temp_dir.path().join("source.index"))
+            .unwrap();
+    let mut dest =
+        Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), temp_dir.path().join("dest.index"))
+            .unwrap();
+
+    source.put(&key1, &[0x10]).unwrap();
+    source.put(&key2, &[0x20]).unwrap();
+    source.put(&key3, &[0x30]).unwrap();
+    // Two buckets differ from the empty destination.
+    assert_eq!(source.sync_to(&mut dest).unwrap(), 2);
+    assert_eq!(dest.get(&key1).unwrap(), Some(vec![0x10]));
+    assert_eq!(dest.get(&key2).unwrap(), Some(vec![0x20]));
+    assert_eq!(dest.get(&key3).unwrap(), Some(vec![0x30]));
+
+    // Deleted, added and updated entries.
+    source.delete(&key1).unwrap();
+    source.put(&key4, &[0x40]).unwrap();
+    source.delete(&key3).unwrap();
+    source.put(&key3, &[0x31]).unwrap();
+    source.sync_to(&mut dest).unwrap();
+    assert_eq!(dest.get(&key1).unwrap(), None);
+    assert_eq!(dest.get(&key2).unwrap(), Some(vec![0x20]));
+    assert_eq!(dest.get(&key3).unwrap(), Some(vec![0x31]));
+    assert_eq!(dest.get(&key4).unwrap(), Some(vec![0x40]));
+    assert_eq!(dest.count().unwrap(), source.count().unwrap());
+}
+
+#[test]
+fn db_verify() {
+    // With 8 bits the first byte of a key is its bucket.
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![2, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key3 = vec![3, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), &index_path).unwrap();
+    db.put(&key1, &[0x10]).unwrap();
+    db.put(&key2, &[0x20]).unwrap();
+    db.put(&key3, &[0x30]).unwrap();
+    assert_eq!(
+        db.verify().unwrap(),
+        VerifyReport {
+            records_checked: 3,
+            dangling: Vec::new(),
+        }
+    );
+    db.close().unwrap();
+
+    // The primary storage lost all but the first entry.
+    let primary = InMemory::new(&[(key1.clone(), vec![0x10])]);
+    let db = Db::<_, BUCKETS_BITS>::open(primary, &index_path).unwrap();
+    assert_eq!(
+        db.verify().unwrap(),
+        VerifyReport {
+            records_checked: 3,
+            dangling: vec![
+                DanglingReference {
+                    bucket: 2,
+                    file_offset: 1,
+                    past_eof: 0,
+                },
+                DanglingReference {
+                    bucket: 3,
+                    file_offset: 2,
+                    past_eof: 1,
+                },
+            ],
+        }
+    );
+}
+
+/// Returns the positions of the primary storage reads since the given number of events.
+fn primary_reads(primary: &RecordingInMemory, since: usize) -> Vec<u64> {
+    primary.events()[since..]
+        .iter()
+        .filter_map(|event| match event {
+            PrimaryEvent::Get { pos, .. } => Some(*pos),
+            PrimaryEvent::Put { .. } => None,
+        })
+        .collect()
+}
+
+fn assert_ascending(positions: &[u64]) {
+    assert!(
+        positions.windows(2).all(|pair| pair[0] <= pair[1]),
+        "positions are not ascending: {:?}",
+        positions
+    );
+}
+
+#[test]
+fn db_sorted_primary_reads() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let keys: Vec<Vec<u8>> = (0..200).map(|_| random_key(32, &mut rng)).collect();
+    let missing = random_key(32, &mut rng);
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, 24>::open(RecordingInMemory::default(), &index_path).unwrap();
+    for (ii, key) in keys.iter().enumerate() {
+        db.put(key, &[ii as u8]).unwrap();
+    }
+
+    // The keys are in a different order than they are stored.
+    let mut requested: Vec<&[u8]> = keys.iter().rev().map(Vec::as_slice).collect();
+    requested.insert(100, &missing);
+    let since = db.primary().events().len();
+    let results = db.get_many(&requested);
+    let reads = primary_reads(db.primary(), since);
+    assert_eq!(reads.len(), keys.len());
+    assert_ascending(&reads);
+    // The results are still in the order of the keys.
+    assert_eq!(results.len(), requested.len());
+    assert_eq!(results[100].as_ref().unwrap(), &None);
+    for (key, result) in requested.iter().zip(&results) {
+        if let Some(ii) = keys.iter().position(|stored| stored == key) {
+            assert_eq!(result.as_ref().unwrap(), &Some(vec![ii as u8]));
+        }
+    }
+
+    let since = db.primary().events().len();
+    assert_eq!(db.verify().unwrap().records_checked, keys.len() as u64);
+    let reads = primary_reads(db.primary(), since);
+    assert_eq!(reads.len(), keys.len());
+    assert_ascending(&reads);
+}
+
+#[test]
+fn db_checked_crc32() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![2, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key3 = vec![3, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key4 = vec![4, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let value = b"value";
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Db::<_, 8>::open(InMemory::new(&[]), &index_path).unwrap();
+    db.put_checked_crc32(&key1, value).unwrap();
+    db.put_checked_crc32(&key2, b"").unwrap();
+    assert_eq!(
+        db.get_checked_crc32(&key1).unwrap(),
+        Some(Ok(value.to_vec()))
+    );
+    assert_eq!(db.get_checked_crc32(&key2).unwrap(), Some(Ok(Vec::new())));
+    assert_eq!(db.get_checked_crc32(&key3).unwrap(), None);
+    // The raw value contains the checksum, it's the CRC32 check value.
+    db.put_checked_crc32(&key3, b"123456789").unwrap();
+    assert_eq!(
+        db.get(&key3).unwrap(),
+        Some([&b"123456789"[..], &0xcbf4_3926u32.to_le_bytes()].concat())
+    );
+
+    // A corrupt value is detected.
+    let corrupt = [&b"valuf"[..], &crc32fast::hash(value).to_le_bytes()].concat();
+    db.put(&key4, &corrupt).unwrap();
+    assert_eq!(
+        db.get_checked_crc32(&key4).unwrap(),
+        Some(Err(ChecksumError {
+            expected: crc32fast::hash(value),
+            found: crc32fast::hash(b"valuf"),
+        }))
+    );
+
+    // A value without a checksum.
+    let key5 = vec![5, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    db.put(&key5, b"abc").unwrap();
+    assert!(matches!(
+        db.get_checked_crc32(&key5),
+        Err(Error::MissingChecksum { len: 3 })
+    ));
+}
+
+/// A [`CidPrimary`] that logs the readahead hints it gets, `true` for a sequential one.
+struct AdvisedPrimary {
+    inner: CidPrimary,
+    advice: RefCell<Vec<bool>>,
+}
+
+impl PrimaryStorage for AdvisedPrimary {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        self.inner.get(pos)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        self.inner.put(key, value)
+    }
+
+    fn size(&self) -> Result<Option<u64>, PrimaryError> {
+        self.inner.size()
+    }
+
+    fn next_pos(&self, pos: u64) -> Result<u64, PrimaryError> {
+        self.inner.next_pos(pos)
+    }
+
+    fn verify_at(&self, pos: u64) -> Result<bool, PrimaryError> {
+        self.inner.verify_at(pos)
+    }
+
+    fn advise_sequential(&self, pos: u64) -> Result<(), PrimaryError> {
+        self.advice.borrow_mut().push(true);
+        self.inner.advise_sequential(pos)
+    }
+
+    fn advise_normal(&self) -> Result<(), PrimaryError> {
+        self.advice.borrow_mut().push(false);
+        self.inner.advise_normal()
+    }
+}
+
+#[test]
+fn db_readahead_hints() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary_path = temp_dir.path().join("storethehash.db");
+    let index_path = temp_dir.path().join("storethehash.db.index");
+    let open = |readahead_hints| {
+        let primary = AdvisedPrimary {
+            inner: CidPrimary::open(&primary_path).unwrap(),
+            advice: RefCell::new(Vec::new()),
+        };
+        DbBuilder::<_, 8>::new(primary, &index_path)
+            .readahead_hints(readahead_hints)
+            .open()
+            .unwrap()
+    };
+
+    let db = open(true);
+    let mut cid = Vec::new();
+    for ii in 0..100u8 {
+        let data = [ii; 100];
+        cid = [
+            &[0x01, 0x55, 0x12, 0x20][..],
+            &Sha256Codec::encode(&data).unwrap(),
+        ]
+        .concat();
+        db.put(&cid, &data).unwrap();
+    }
+    db.flush().unwrap();
+    // Every scan gives a sequential hint before it starts and reverts it at the end.
+    assert_eq!(db.verify().unwrap().records_checked, 100);
+    assert_eq!(db.export(Vec::new()).unwrap(), 100);
+    assert_eq!(db.repair_primary().unwrap().blocks_corrupt, 0);
+    assert_eq!(
+        *db.primary().advice.borrow(),
+        [true, false, true, false, true, false]
+    );
+    // Lookups don't give any hints.
+    assert!(db.get(&cid).unwrap().is_some());
+    assert_eq!(db.primary().advice.borrow().len(), 6);
+    drop(db);
+
+    let db = open(false);
+    assert_eq!(db.verify().unwrap().records_checked, 100);
+    assert_eq!(db.export(Vec::new()).unwrap(), 100);
+    assert_eq!(db.repair_primary().unwrap().blocks_corrupt, 0);
+    assert!(db.primary().advice.borrow().is_empty());
+}
+
+#[test]
+fn db_delete() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    const BUCKETS_BITS: u8 = 24;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    {
+        let db = Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), &index_path).unwrap();
+        db.put(&key1, &[0x10]).unwrap();
+        db.put(&key2, &[0x20]).unwrap();
+
+        assert!(db.delete(&key1).unwrap());
+        assert_eq!(db.get(&key1).unwrap(), None);
+        assert_eq!(db.get(&key2).unwrap(), Some(vec![0x20]));
+        // The key doesn't exist anymore.
+        assert!(!db.delete(&key1).unwrap());
+
+        // Deleting the last key of a bucket.
+        assert!(db.delete(&key2).unwrap());
+        assert_eq!(db.get(&key2).unwrap(), None);
+    }
+
+    // The keys are still deleted after the index was opened again.
+    let primary_storage = InMemory::new(&[(key1.clone(), vec![0x10]), (key2.clone(), vec![0x20])]);
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, primary_storage).unwrap();
+    assert_eq!(index.get(&key1).unwrap(), None);
+    assert_eq!(index.get(&key2).unwrap(), None);
+}
+
+#[test]
+fn index_migrate_v2() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    const BUCKETS_BITS: u8 = 24;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+
+    // A version 2 index with a single record list that contains both keys.
+    let mut v2_index = vec![2, 0, 0, 0, 2, BUCKETS_BITS];
+    let mut recordlist = vec![0x01, 0x02, 0x03, 0x00];
+    recordlist.extend_from_slice(&recordlist::encode_offset_and_key(&[4, 5, 6, 7], 1));
+    recordlist.extend_from_slice(&recordlist::encode_offset_and_key(&[4, 5, 6, 9], 0));
+    v2_index.extend_from_slice(&(recordlist.len() as u32).to_le_bytes());
+    v2_index.extend_from_slice(&recordlist);
+    fs::write(&index_path, &v2_index).unwrap();
+
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    assert_eq!(index.get(&key1).unwrap(), Some(0));
+    assert_eq!(index.get(&key2).unwrap(), Some(1));
+    assert_header(&index_path, BUCKETS_BITS);
+
+    let mut file = File::open(&index_path).unwrap();
+    let (_header, bytes_read) = index::read_header(&mut file).unwrap();
+    let (data, _pos) = IndexIter::new(&mut file, bytes_read)
+        .next()
+        .unwrap()
+        .unwrap();
+    let recordlist = RecordList::new(&data);
+    assert_eq!(recordlist.buckets_bits(), BUCKETS_BITS);
+    assert_eq!(recordlist.into_iter().count(), 2);
+}
+
+#[cfg(feature = "strict_dedup")]
+#[test]
+fn index_put_strict_dedup() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let primary_storage = InMemory::new(&[(key1.clone(), vec![0x10]), (key2.clone(), vec![0x20])]);
+    let mut index = Index::<_, BUCKETS_BITS>::open(&index_path, primary_storage).unwrap();
+    let inserts = Arc::new(AtomicUsize::new(0));
+    let observer_inserts = inserts.clone();
+    index.set_put_observer(Box::new(move |event| {
+        if event.was_insert {
+            observer_inserts.fetch_add(1, Ordering::SeqCst);
+        }
+    }));
+
+    index.put(&key1, 0).unwrap();
+    index.put(&key2, 1).unwrap();
+    let index_size = fs::metadata(&index_path).unwrap().len();
+
+    // Inserting the same records again doesn't touch the index.
+    index.put(&key1, 0).unwrap();
+    index.put(&key2, 1).unwrap();
+    assert_eq!(fs::metadata(&index_path).unwrap().len(), index_size);
+    assert_eq!(inserts.load(Ordering::SeqCst), 2);
+    assert_eq!(index.get(&key1).unwrap(), Some(0));
+    assert_eq!(index.get(&key2).unwrap(), Some(1));
+}
+
+#[test]
+fn index_get_verified() {
+    let key1 = vec![1, 2, 3, 4, 5, 6, 9, 9, 9, 9];
+    // Shares the prefix with `key1` that is stored in the index.
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    // Is in a different bucket.
+    let key3 = vec![2, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let primary_storage = InMemory::new(&[(key1.clone(), vec![0x10])]);
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, primary_storage).unwrap();
+    index.put(&key1, 0).unwrap();
+
+    assert_eq!(
+        index.get_verified(&key1).unwrap(),
+        LookupResult::Found {
+            file_offset: 0,
+            primary_key: key1.clone(),
+            value: vec![0x10],
+        }
+    );
+    assert_eq!(
+        index.get_verified(&key2).unwrap(),
+        LookupResult::PrefixMatch {
+            file_offset: 0,
+            primary_key: key1,
+        }
+    );
+    assert_eq!(index.get_verified(&key3).unwrap(), LookupResult::NotFound);
+}
+
+#[test]
+fn index_get_verify_collision() {
+    // Both keys share the first 3 bytes.
+    let key1 = vec![1, 2, 3, 5, 5, 6, 7, 8];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    let key3 = vec![1, 2, 3, 9, 9, 9, 9, 9];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+
+    // A record list where the stored keys are not distinguishable from each other, `[2]` is a
+    // prefix of `[2, 3]`.
+    let header: Vec<u8> = Header::new(BUCKETS_BITS).into();
+    let mut index_data = (header.len() as u32).to_le_bytes().to_vec();
+    index_data.extend_from_slice(&header);
+    let mut recordlist = vec![0x01, 0x00, 0x00, 0x00, BUCKETS_BITS];
+    recordlist.extend_from_slice(&recordlist::encode_offset_and_key(&[2], 0));
+    recordlist.extend_from_slice(&recordlist::encode_offset_and_key(&[2, 3], 1));
+    index_data.extend_from_slice(&(recordlist.len() as u32).to_le_bytes());
+    index_data.extend_from_slice(&recordlist);
+    fs::write(&index_path, &index_data).unwrap();
+
+    let primary_storage = InMemory::new(&[(key1.clone(), vec![0x10]), (key2.clone(), vec![0x20])]);
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, primary_storage).unwrap();
+    // The longest matching prefix `[2, 3]` points to `key2`, hence the other candidate is used.
+    assert_eq!(index.get(&key1).unwrap(), Some(0));
+    assert_eq!(index.get(&key2).unwrap(), Some(1));
+    // If no candidate matches, the longest prefix is returned.
+    assert_eq!(index.get(&key3).unwrap(), Some(1));
+}
+
+#[test]
+fn index_get_probe_histogram() {
+    // All keys are in the same bucket.
+    let key1 = vec![1, 1, 3, 4, 5, 6, 7, 8];
+    let key2 = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    let key3 = vec![1, 3, 3, 4, 5, 6, 7, 8];
+    // Is in the same bucket, but not stored.
+    let missing = vec![1, 9, 3, 4, 5, 6, 7, 8];
+    // Is in an empty bucket.
+    let empty_bucket = vec![2, 2, 3, 4, 5, 6, 7, 8];
+
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let primary_storage = InMemory::new(&[
+        (key1.clone(), vec![0x10]),
+        (key2.clone(), vec![0x20]),
+        (key3.clone(), vec![0x30]),
+    ]);
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, primary_storage).unwrap();
+    for (pos, key) in [&key1, &key2, &key3].iter().enumerate() {
+        index.put(key, pos as u64).unwrap();
+    }
+
+    // The scan stops at the first record that is bigger than the key.
+    assert_eq!(index.get_with_probe_count(&key1).unwrap(), (Some(0), 2));
+    assert_eq!(index.get_with_probe_count(&key2).unwrap(), (Some(1), 3));
+    assert_eq!(index.get_with_probe_count(&key3).unwrap(), (Some(2), 3));
+    assert_eq!(index.get_with_probe_count(&missing).unwrap(), (None, 3));
+    assert_eq!(
+        index.get_with_probe_count(&empty_bucket).unwrap(),
+        (None, 0)
+    );
+
+    let keys: Vec<&[u8]> = vec![&key1, &key2, &key3, &missing, &empty_bucket];
+    let histogram = index.get_probe_histogram(&keys).unwrap();
+    let expected: HashMap<usize, u64> = vec![(0, 1), (2, 1), (3, 3)].into_iter().collect();
+    assert_eq!(histogram, expected);
+}
+
+#[test]
+fn index_put_sorted_hint() {
+    const BUCKETS_BITS: u8 = 8;
+    let mut rng = StdRng::seed_from_u64(7);
+    // The keys are all in bucket 1, some of them share a prefix.
+    let mut keys: Vec<Vec<u8>> = (0..200)
+        .map(|ii| {
+            let mut key = random_key(32, &mut rng);
+            key[0] = 1;
+            if ii % 10 == 0 {
+                key[1] = 0xaa;
+            }
+            key
+        })
+        .collect();
+    keys.sort();
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = keys.iter().map(|key| (key.clone(), vec![])).collect();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let expected_path = temp_dir.path().join("expected.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&expected_path, InMemory::new(&entries)).unwrap();
+    for (pos, key) in keys.iter().enumerate() {
+        index.put(key, pos as u64).unwrap();
+    }
+    drop(index);
+
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&entries)).unwrap();
+    let mut hint = None;
+    for (pos, key) in keys.iter().enumerate() {
+        hint = Some(index.put_sorted_hint(key, pos as u64, hint).unwrap());
+    }
+    // Inserting an existing key again doesn't change anything.
+    index.put_sorted_hint(&keys[199], 199, hint).unwrap();
+    drop(index);
+    assert_eq!(
+        fs::read(&index_path).unwrap(),
+        fs::read(&expected_path).unwrap()
+    );
+
+    // Hints that are wrong are ignored.
+    let index_path = temp_dir.path().join("wrong_hints.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&entries)).unwrap();
+    let mut hint = None;
+    for (pos, key) in keys.iter().enumerate().rev() {
+        hint = Some(
+            index
+                .put_sorted_hint(key, pos as u64, hint.map(|hint| hint + 3))
+                .unwrap(),
+        );
+    }
+    for (pos, key) in keys.iter().enumerate() {
+        assert_eq!(index.get(key).unwrap(), Some(pos as u64));
+    }
+}
+
+#[test]
+fn index_iter_rev() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let _index = build_index_with_n_keys::<InMemory, BUCKETS_BITS>(5, temp_dir.path()).unwrap();
+
+    let mut file = File::open(&index_path).unwrap();
+    let (_header, bytes_read) = index::read_header(&mut file).unwrap();
+    let forward: Vec<(Vec<u8>, u64)> = IndexIter::new(&mut file, bytes_read)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(forward.len(), 5);
+
+    file.seek(SeekFrom::Start(bytes_read as u64)).unwrap();
+    let mut backward: Vec<(Vec<u8>, u64)> = IndexIter::new(&mut file, bytes_read)
+        .rev()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    backward.reverse();
+    assert_eq!(backward, forward);
+
+    // Iterating from both ends meets in the middle.
+    file.seek(SeekFrom::Start(bytes_read as u64)).unwrap();
+    let mut iter = IndexIter::new(&mut file, bytes_read);
+    assert_eq!(iter.next_back().unwrap().unwrap(), forward[4]);
+    assert_eq!(iter.next().unwrap().unwrap(), forward[0]);
+    assert_eq!(iter.next_back().unwrap().unwrap(), forward[3]);
+    assert_eq!(iter.count(), 2);
+}
+
+#[test]
+fn index_live_entries() {
+    const BUCKETS_BITS: u8 = 8;
+    let key1 = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    let key2 = vec![1, 9, 3, 4, 5, 6, 7, 8];
+    let key3 = vec![3, 2, 3, 4, 5, 6, 7, 8];
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let primary_storage = InMemory::new(&[
+        (key1.clone(), vec![0x10]),
+        (key2.clone(), vec![0x20]),
+        (key3.clone(), vec![0x30]),
+    ]);
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, primary_storage).unwrap();
+    // Bucket 1 is written twice, the first record list is superseded.
+    index.put(&key1, 0).unwrap();
+    index.put(&key3, 2).unwrap();
+    index.put(&key2, 1).unwrap();
+    index.flush().unwrap();
+
+    let live: Vec<(usize, u64, Vec<u8>)> = index.live_entries().collect::<Result<_, _>>().unwrap();
+    let buckets: Vec<usize> = live.iter().map(|(bucket, _, _)| *bucket).collect();
+    assert_eq!(buckets, [1, 3]);
+    let offsets: Vec<u64> = RecordList::new(&live[0].2)
+        .into_iter()
+        .map(|record| record.file_offset)
+        .collect();
+    assert_eq!(offsets, [0, 1]);
+
+    // The live record list of bucket 1 is the last one in the file.
+    let mut file = File::open(&index_path).unwrap();
+    let (_header, header_size) = index::read_header(&mut file).unwrap();
+    let positions: Vec<u64> = IndexIter::new(file, header_size)
+        .map(|entry| entry.unwrap().1)
+        .collect();
+    assert_eq!(positions.len(), 3);
+    assert_eq!(live[0].1, positions[2]);
+    assert_eq!(
+        index.bucket_records(1).unwrap(),
+        Some((live[0].1, live[0].2.clone()))
+    );
+    assert_eq!(index.bucket_records(2).unwrap(), None);
+}
+
+#[test]
+fn index_min_key_length() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let key = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    {
+        let index = IndexBuilder::<_, BUCKETS_BITS>::new(&index_path, InMemory::new(&[]))
+            .with_min_key_length(8)
+            .open()
+            .unwrap();
+        assert_eq!(index.min_key_length(), 8);
+        index.put(&key, 0).unwrap();
+        index.flush().unwrap();
+    }
+
+    let mut file = File::open(&index_path).unwrap();
+    let (header, _header_size) = index::read_header(&mut file).unwrap();
+    assert_eq!(header.version, INDEX_VERSION);
+    assert_eq!(header.min_key_length, 8);
+
+    // The stored minimum key length is used.
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    assert_eq!(index.min_key_length(), 8);
+    assert_eq!(index.get(&key).unwrap(), Some(0));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        index.get(&key[..7]).unwrap();
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+#[should_panic(expected = "Key must be at least 8 bytes long")]
+fn index_min_key_length_rejects_short_key() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index = IndexBuilder::<_, BUCKETS_BITS>::new(&index_path, InMemory::new(&[]))
+        .with_min_key_length(8)
+        .open()
+        .unwrap();
+    index.put(&[1, 2, 3, 4, 5, 6, 7], 0).unwrap();
+}
+
+#[test]
+fn index_total_bytes_wasted() {
+    const BUCKETS_BITS: u8 = 8;
+    // All keys are in the same bucket, the first two share their first 3 bytes after it.
+    let keys = [
+        vec![1, 0x10, 0x20, 0x30, 4, 5, 6, 7],
+        vec![1, 0x10, 0x20, 0x31, 4, 5, 6, 7],
+        vec![1, 0x50, 2, 3, 4, 5, 6, 7],
+    ];
+    let entries: Vec<_> = keys.iter().map(|key| (key.clone(), vec![])).collect();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index = Index::<_, BUCKETS_BITS>::open(
+        temp_dir.path().join("storethehash.index"),
+        InMemory::new(&entries),
+    )
+    .unwrap();
+    for (ii, key) in keys.iter().enumerate() {
+        index.put(key, ii as u64).unwrap();
+    }
+    assert_eq!(index.total_bytes_wasted().unwrap(), 0);
+
+    // The first key still has a 3 byte prefix, a single byte would distinguish it from the
+    // remaining one.
+    assert!(index.delete(&keys[1]).unwrap());
+    assert_eq!(index.total_bytes_wasted().unwrap(), 2);
+}
+
+#[test]
+fn index_background_sync() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let policy = SyncPolicy {
+        max_dirty_bytes: 64,
+        max_interval: Duration::from_millis(10),
+    };
+    let index = IndexBuilder::<_, BUCKETS_BITS>::new(&index_path, InMemory::new(&[]))
+        .with_background_sync(policy)
+        .open()
+        .unwrap();
+    for ii in 0..100u8 {
+        index
+            .put(&[ii, 1, 2, 3, 4, 5, 6, 7], u64::from(ii))
+            .unwrap();
+    }
+    // Dropping the index joins the sync thread.
+    drop(index);
+
+    let reopened = IndexBuilder::<_, BUCKETS_BITS>::new(&index_path, InMemory::new(&[]))
+        .read_only(true)
+        .with_background_sync(policy)
+        .open()
+        .unwrap();
+    for ii in 0..100u8 {
+        assert_eq!(
+            reopened.get(&[ii, 1, 2, 3, 4, 5, 6, 7]).unwrap(),
+            Some(u64::from(ii))
+        );
+    }
+}
+
+#[test]
+fn index_bucket_hasher() {
+    const BUCKETS_BITS: u8 = 8;
+    // The keys only differ in their last byte, hence they share the leading bytes.
+    let keys: Vec<Vec<u8>> = (0..64u8).map(|byte| vec![1, 2, 3, 4, 5, byte]).collect();
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = keys.iter().map(|key| (key.clone(), vec![])).collect();
+    let temp_dir = tempfile::tempdir().unwrap();
+
+    let leading = Index::<_, BUCKETS_BITS>::open(
+        temp_dir.path().join("leading.index"),
+        InMemory::new(&entries),
+    )
+    .unwrap();
+    let hashed = IndexBuilder::<_, BUCKETS_BITS, XxHashBucketHasher>::new(
+        temp_dir.path().join("hashed.index"),
+        InMemory::new(&entries),
+    )
+    .open()
+    .unwrap();
+    for (pos, key) in keys.iter().enumerate() {
+        leading.put(key, pos as u64).unwrap();
+        hashed.put(key, pos as u64).unwrap();
+    }
+    for (pos, key) in keys.iter().enumerate() {
+        assert_eq!(hashed.get(key).unwrap(), Some(pos as u64));
+    }
+    assert_eq!(hashed.get(&[1, 2, 3, 4, 5, 0xff]).unwrap(), None);
+
+    // All keys end up in the same bucket with the leading bytes, but not when the key is hashed.
+    assert_eq!(leading.stats().unwrap().non_empty_buckets, 1);
+    assert!(hashed.stats().unwrap().non_empty_buckets > 1);
+
+    // The buckets are restored with the same hasher when the index is opened again.
+    drop(hashed);
+    let reopened = IndexBuilder::<_, BUCKETS_BITS, XxHashBucketHasher>::new(
+        temp_dir.path().join("hashed.index"),
+        InMemory::new(&entries),
+    )
+    .open()
+    .unwrap();
+    assert_eq!(reopened.get(&keys[42]).unwrap(), Some(42));
+    assert!(reopened.delete(&keys[42]).unwrap());
+    assert_eq!(reopened.get(&keys[42]).unwrap(), None);
+}
+
+#[test]
+fn index_get_prefix() {
+    const BUCKETS_BITS: u8 = 8;
+    // With 8 bits the first byte of a key is its bucket.
+    let key1 = vec![1, 0x7f, 0x01, 4, 5, 6, 7, 8];
+    let key2 = vec![1, 0x7f, 0x02, 4, 5, 6, 7, 8];
+    let key3 = vec![1, 0x80, 0x03, 4, 5, 6, 7, 8];
+    let key4 = vec![2, 0x7f, 0x01, 4, 5, 6, 7, 8];
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary_storage = InMemory::new(&[
+        (key1.clone(), vec![0x10]),
+        (key2.clone(), vec![0x20]),
+        (key3.clone(), vec![0x30]),
+        (key4.clone(), vec![0x40]),
+    ]);
+    let index =
+        Index::<_, BUCKETS_BITS>::open(temp_dir.path().join("storethehash.index"), primary_storage)
+            .unwrap();
+    for (pos, key) in [&key1, &key2, &key3, &key4].iter().enumerate() {
+        index.put(key, pos as u64).unwrap();
+    }
+
+    assert_eq!(index.get_prefix(&[1]).unwrap(), [0, 1, 2]);
+    assert_eq!(index.get_prefix(&[1, 0x7f]).unwrap(), [0, 1]);
+    assert_eq!(index.get_prefix(&[1, 0x7f, 0x02]).unwrap(), [1]);
+    assert_eq!(index.get_prefix(&key1).unwrap(), [0]);
+    assert_eq!(index.get_prefix(&[2, 0x7f]).unwrap(), [3]);
+    assert_eq!(index.get_prefix(&[3]).unwrap(), Vec::<u64>::new());
+    // Only `0x80` is stored for the third key, the rest is checked against the primary storage.
+    assert_eq!(index.get_prefix(&[1, 0x80, 0x03, 4]).unwrap(), [2]);
+    assert_eq!(
+        index.get_prefix(&[1, 0x80, 0x09]).unwrap(),
+        Vec::<u64>::new()
+    );
+}
+
+#[test]
+fn index_dump_restore() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+    // With 8 bits for the buckets, several keys end up in the same bucket.
+    let keys: Vec<Vec<u8>> = (0..1000).map(|_| random_key(32, &mut rng)).collect();
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = keys.iter().map(|key| (key.clone(), vec![])).collect();
+    let index = Index::<_, BUCKETS_BITS>::open(
+        temp_dir.path().join("storethehash.index"),
+        InMemory::new(&entries),
+    )
+    .unwrap();
+    for (pos, key) in keys.iter().enumerate() {
+        index.put(key, pos as u64).unwrap();
+    }
+    // Superseded record lists are not part of the dump.
+    index.delete(&keys[0]).unwrap();
+
+    let mut dump = Vec::new();
+    assert_eq!(index.dump(&mut dump).unwrap(), 999);
+    assert!(dump.starts_with(format!("{} 8 4\n", index::DUMP_HEADER).as_bytes()));
+
+    // The primary storage isn't needed for restoring nor for the lookups.
+    let restored_path = temp_dir.path().join("restored.index");
+    let restored =
+        Index::<_, BUCKETS_BITS>::restore(&dump[..], &restored_path, InMemory::new(&[])).unwrap();
+    for key in keys.iter().chain(iter::once(&random_key(32, &mut rng))) {
+        assert_eq!(restored.get(key).unwrap(), index.get(key).unwrap());
+    }
+    assert_eq!(restored.stats().unwrap(), index.stats().unwrap());
+    assert!(restored.size().unwrap() < index.size().unwrap());
+    let mut restored_dump = Vec::new();
+    restored.dump(&mut restored_dump).unwrap();
+    assert_eq!(restored_dump, dump);
+    drop(restored);
+    // The restored index can be opened again.
+    let reopened = Index::<_, BUCKETS_BITS>::open(&restored_path, InMemory::new(&entries)).unwrap();
+    assert_eq!(reopened.get(&keys[1]).unwrap(), Some(1));
+
+    // An existing index is never overwritten.
+    assert!(
+        Index::<_, BUCKETS_BITS>::restore(&dump[..], &restored_path, InMemory::new(&[]))
+            .unwrap_err()
+            .to_string()
+            .contains("Index already exists.")
+    );
+    assert!(matches!(
+        Index::<_, 16>::restore(
+            &dump[..],
+            temp_dir.path().join("16.index"),
+            InMemory::new(&[])
+        ),
+        Err(Error::IndexWrongBitSize(8, 16))
+    ));
+    let invalid = format!("{} 8\n1 0a0b 5\n1 0a 6\n", index::DUMP_HEADER);
+    assert!(matches!(
+        Index::<_, BUCKETS_BITS>::restore(
+            invalid.as_bytes(),
+            temp_dir.path().join("unsorted.index"),
+            InMemory::new(&[])
+        ),
+        Err(Error::InvalidDump { line: 3, .. })
+    ));
+    let invalid = format!("{} 8\n256 0a0b 5\n", index::DUMP_HEADER);
+    assert!(matches!(
+        Index::<_, BUCKETS_BITS>::restore(
+            invalid.as_bytes(),
+            temp_dir.path().join("out-of-range.index"),
+            InMemory::new(&[])
+        ),
+        Err(Error::InvalidDump { line: 2, .. })
+    ));
+    assert!(matches!(
+        Index::<_, BUCKETS_BITS>::restore(
+            &b"not a dump\n"[..],
+            temp_dir.path().join("no-header.index"),
+            InMemory::new(&[])
+        ),
+        Err(Error::InvalidDump { line: 1, .. })
+    ));
+    let invalid = format!("{} 8 3\n", index::DUMP_HEADER);
+    assert!(matches!(
+        Index::<_, BUCKETS_BITS>::restore(
+            invalid.as_bytes(),
+            temp_dir.path().join("min-key-length.index"),
+            InMemory::new(&[])
+        ),
+        Err(Error::InvalidDump { line: 1, .. })
+    ));
+}
+
+#[test]
+fn index_dump_restore_min_key_length() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+    let keys: Vec<Vec<u8>> = (0..100).map(|_| random_key(32, &mut rng)).collect();
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = keys.iter().map(|key| (key.clone(), vec![])).collect();
+    let index = IndexBuilder::<_, BUCKETS_BITS>::new(
+        temp_dir.path().join("storethehash.index"),
+        InMemory::new(&entries),
+    )
+    .with_min_key_length(16)
+    .open()
+    .unwrap();
+    for (pos, key) in keys.iter().enumerate() {
+        index.put(key, pos as u64).unwrap();
+    }
+
+    let mut dump = Vec::new();
+    index.dump(&mut dump).unwrap();
+    assert!(dump.starts_with(format!("{} 8 16\n", index::DUMP_HEADER).as_bytes()));
+
+    let restored_path = temp_dir.path().join("restored.index");
+    let restored =
+        Index::<_, BUCKETS_BITS>::restore(&dump[..], &restored_path, InMemory::new(&[])).unwrap();
+    assert_eq!(restored.min_key_length(), 16);
+    drop(restored);
+    // The minimum key length is stored in the header of the restored index.
+    let reopened = Index::<_, BUCKETS_BITS>::open(&restored_path, InMemory::new(&entries)).unwrap();
+    assert_eq!(reopened.min_key_length(), 16);
+    assert_eq!(reopened.get(&keys[1]).unwrap(), Some(1));
+
+    // A dump without a minimum key length uses the default one.
+    let old_dump = format!("{} 8\n", index::DUMP_HEADER);
+    let restored = Index::<_, BUCKETS_BITS>::restore(
+        old_dump.as_bytes(),
+        temp_dir.path().join("old.index"),
+        InMemory::new(&[]),
+    )
+    .unwrap();
+    assert_eq!(
+        restored.min_key_length(),
+        usize::from(DEFAULT_MIN_KEY_LENGTH)
+    );
+}
+
+#[test]
+fn index_garbage_stats() {
+    const BUCKETS_BITS: u8 = 8;
+    let temp_dir = tempfile::tempdir().unwrap();
+
+    // Every key is in its own bucket, hence no record list is superseded.
+    let index_path = temp_dir.path().join("distinct.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    for byte in 0..100u8 {
+        index.put(&[byte, 2, 3, 4, 5, 6, 7, 8], 0).unwrap();
+    }
+    index.flush().unwrap();
+    let stats = index.garbage_stats().unwrap();
+    assert_eq!(stats.total_bytes, fs::metadata(&index_path).unwrap().len());
+    assert_eq!(stats.live_bytes, stats.total_bytes);
+    assert_eq!(stats.stale_bytes, 0);
+    assert_eq!(stats.stale_ratio, 0.0);
+
+    // All keys are in the same bucket, every put supersedes the previous record list.
+    let index_path = temp_dir.path().join("overwrites.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    let keys: Vec<Vec<u8>> = (0..20u8)
+        .map(|byte| vec![1, byte, 3, 4, 5, 6, 7, 8])
+        .collect();
+    for (pos, key) in keys.iter().enumerate() {
+        index.primary.put(key, b"value").unwrap();
+        index.put(key, pos as u64).unwrap();
+    }
+    index.flush().unwrap();
+
+    let mut file = File::open(&index_path).unwrap();
+    let (_header, header_size) = index::read_header(&mut file).unwrap();
+    let sizes: Vec<u64> = IndexIter::new(file, header_size)
+        .map(|entry| 4 + entry.unwrap().0.len() as u64)
+        .collect();
+    assert_eq!(sizes.len(), keys.len());
+    let stale_bytes: u64 = sizes[..sizes.len() - 1].iter().sum();
+
+    let stats = index.garbage_stats().unwrap();
+    assert_eq!(stats.stale_bytes, stale_bytes);
+    assert_eq!(
+        stats.live_bytes,
+        header_size as u64 + sizes[sizes.len() - 1]
+    );
+    assert_eq!(stats.total_bytes, stats.live_bytes + stats.stale_bytes);
+    assert_eq!(
+        stats.stale_ratio,
+        stale_bytes as f64 / stats.total_bytes as f64
+    );
+    assert!(stats.stale_ratio > 0.8);
+}
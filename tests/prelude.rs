@@ -0,0 +1,86 @@
+//! Makes sure that the prelude is enough to use the whole API, hence nothing else of this crate is
+//! imported.
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, Seek, SeekFrom};
+
+use storethehash::prelude::*;
+use storethehash_primary_inmemory::InMemory;
+
+const BUCKETS_BITS: u8 = 8;
+
+#[test]
+fn prelude_db() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db =
+        Db::<_, BUCKETS_BITS>::open(InMemory::new(&[]), temp_dir.path().join("db.index")).unwrap();
+    let key = [1, 2, 3, 4, 5, 6, 7, 8];
+    db.put(&key, b"value").unwrap();
+    assert_eq!(db.get(&key).unwrap(), Some(b"value".to_vec()));
+    assert!(db.delete(&key).unwrap());
+    assert_eq!(db.get(&key).unwrap(), None);
+    db.close().unwrap();
+
+    let read_only =
+        Db::<_, BUCKETS_BITS>::open_read_only(InMemory::new(&[]), temp_dir.path().join("db.index"))
+            .unwrap();
+    assert!(matches!(
+        read_only.put(&key, b"value"),
+        Err(Error::ReadOnly)
+    ));
+}
+
+#[test]
+fn prelude_index() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, InMemory::new(&[])).unwrap();
+    let keys: Vec<[u8; 8]> = (0..4).map(|ii| [ii, 2, 3, 4, 5, 6, 7, ii]).collect();
+    for key in &keys {
+        let pos = index.primary.put(key, b"value").unwrap();
+        index.put(key, pos).unwrap();
+    }
+    assert_eq!(index.get(&keys[2]).unwrap(), Some(2));
+    index.flush().unwrap();
+
+    // The index is a header followed by record lists.
+    let header = Header::new(BUCKETS_BITS);
+    assert!(header.validate().is_ok());
+    let header_bytes: Vec<u8> = header.into();
+    let parsed = Header::try_from(&header_bytes[..]).unwrap();
+    assert_eq!(parsed.buckets_bits, BUCKETS_BITS);
+
+    // The iteration starts at the current position of the reader, right after the header.
+    let header_size = 4 + header_bytes.len();
+    let mut file = BufReader::new(File::open(&index_path).unwrap());
+    file.seek(SeekFrom::Start(header_size as u64)).unwrap();
+    let mut records = Vec::new();
+    for entry in IndexIter::new(file, header_size) {
+        let (data, _pos) = entry.unwrap();
+        let record_list = RecordList::new(&data);
+        let last: Option<Record> = record_list.into_iter().last();
+        records.push(last.unwrap().file_offset);
+    }
+    assert_eq!(records, vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn prelude_buckets_and_primary() {
+    let mut buckets = Buckets::<BUCKETS_BITS>::new();
+    buckets.put(3, 42).unwrap();
+    assert_eq!(buckets.get(3).unwrap(), 42);
+    assert_eq!(buckets[3], 42);
+    assert!(matches!(buckets.get(256), Err(Error::BucketsOutOfBounds)));
+
+    let primary = InMemory::new(&[]);
+    let pos = primary.put(b"key", b"value").unwrap();
+    assert_eq!(
+        primary.get(pos).unwrap(),
+        (b"key".to_vec(), b"value".to_vec())
+    );
+    let error: Error = primary.get(pos + 1).unwrap_err().into();
+    assert!(matches!(
+        error,
+        Error::Primary(PrimaryError::OutOfBounds { pos: 1, len: 1 })
+    ));
+}
@@ -0,0 +1,112 @@
+use storethehash::codec::{KeyCodec, Sha256Codec};
+use storethehash::error::Error;
+use storethehash::sharded::{ShardedDb, MANIFEST_FILE_NAME};
+use storethehash_primary_cid::CidPrimary;
+
+const BUCKETS_BITS: u8 = 8;
+const SHARDS: usize = 4;
+
+/// Returns a CIDv1 with the raw codec and the SHA2-256 multihash of the given number.
+fn cid(ii: u32) -> Vec<u8> {
+    let digest = Sha256Codec::encode(&ii.to_le_bytes()).unwrap();
+    [&[0x01, 0x55, 0x12, 0x20][..], &digest].concat()
+}
+
+fn open(
+    temp_dir: &tempfile::TempDir,
+    shards: usize,
+) -> Result<ShardedDb<CidPrimary, BUCKETS_BITS>, Error> {
+    let primaries = (0..shards)
+        .map(|shard| CidPrimary::open(temp_dir.path().join(format!("primary.{}", shard))).unwrap())
+        .collect();
+    ShardedDb::open(temp_dir.path().join("index"), primaries)
+}
+
+#[test]
+fn routing_is_stable_across_reopen() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = open(&temp_dir, SHARDS).unwrap();
+    let mut routes = Vec::new();
+    for ii in 0..100 {
+        db.put(&cid(ii), &ii.to_le_bytes()).unwrap();
+        routes.push(db.shard_of(&cid(ii)).unwrap());
+    }
+    // All shards are used.
+    for shard in 0..SHARDS {
+        assert!(routes.contains(&shard));
+    }
+    db.close().unwrap();
+
+    let db = open(&temp_dir, SHARDS).unwrap();
+    for ii in 0..100 {
+        let shard = db.shard_of(&cid(ii)).unwrap();
+        assert_eq!(shard, routes[ii as usize]);
+        assert_eq!(db.get(&cid(ii)).unwrap(), Some(ii.to_le_bytes().to_vec()));
+        // Only the owning shard contains the key.
+        for other in (0..SHARDS).filter(|other| *other != shard) {
+            assert_eq!(db.shard(other).get(&cid(ii)).unwrap(), None);
+        }
+    }
+    drop(db);
+
+    // A different number of shards would route the keys differently.
+    assert!(matches!(
+        open(&temp_dir, SHARDS + 1),
+        Err(Error::ShardCountMismatch {
+            manifest: SHARDS,
+            given: 5
+        })
+    ));
+    std::fs::write(
+        temp_dir.path().join("index").join(MANIFEST_FILE_NAME),
+        "four\n",
+    )
+    .unwrap();
+    assert!(matches!(
+        open(&temp_dir, SHARDS),
+        Err(Error::CorruptShardManifest)
+    ));
+}
+
+#[test]
+fn parallel_put_batch() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut db = open(&temp_dir, SHARDS).unwrap();
+    let keys: Vec<_> = (0..1000).map(cid).collect();
+    let values: Vec<_> = (0..1000u32).map(|ii| ii.to_be_bytes()).collect();
+    let entries: Vec<(&[u8], &[u8])> = keys
+        .iter()
+        .zip(&values)
+        .map(|(key, value)| (&key[..], &value[..]))
+        .collect();
+
+    let positions = db.put_batch(&entries).unwrap();
+    assert_eq!(positions.len(), entries.len());
+    db.flush().unwrap();
+
+    for ((key, value), position) in entries.iter().zip(&positions) {
+        assert_eq!(db.get(key).unwrap(), Some(value.to_vec()));
+        // The positions are the ones within the owning shard.
+        let shard = db.shard(db.shard_of(key).unwrap());
+        assert_eq!(
+            shard.get_offset(key).unwrap(),
+            Some(position.primary_offset)
+        );
+    }
+
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.records, 1000);
+    assert_eq!(stats.shards.len(), SHARDS);
+    assert!(stats.shards.iter().all(|shard| shard.index.records > 0));
+    assert_eq!(
+        stats.index_size,
+        stats
+            .shards
+            .iter()
+            .map(|shard| shard.index_size)
+            .sum::<u64>()
+    );
+    let report = db.verify().unwrap();
+    assert_eq!(report.records_checked, 1000);
+    assert!(report.dangling.is_empty());
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::{Arc, Barrier, RwLock};
+use std::thread;
+
+use storethehash::db::Db;
+use storethehash::index::Index;
+use storethehash::primary::{PrimaryError, PrimaryStorage};
+use storethehash::sharedindex::{SharedIndex, SharedIndexReader};
+
+const BUCKETS_BITS: u8 = 8;
+
+/// An in-memory primary storage that can be shared between threads.
+#[derive(Debug, Default)]
+struct SyncPrimary(RwLock<Vec<(Vec<u8>, Vec<u8>)>>);
+
+impl PrimaryStorage for SyncPrimary {
+    fn get(&self, pos: u64) -> Result<(Vec<u8>, Vec<u8>), PrimaryError> {
+        let data = self.0.read().unwrap();
+        data.get(usize::try_from(pos).unwrap())
+            .cloned()
+            .ok_or(PrimaryError::OutOfBounds {
+                pos,
+                len: data.len() as u64,
+            })
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<u64, PrimaryError> {
+        let mut data = self.0.write().unwrap();
+        data.push((key.to_vec(), value.to_vec()));
+        Ok(data.len() as u64 - 1)
+    }
+}
+
+/// Returns a key whose leading bytes differ for every entry.
+fn key(ii: u32) -> Vec<u8> {
+    [&ii.to_le_bytes()[..], &[0xaa; 28]].concat()
+}
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn shared_index_is_send_sync() {
+    assert_send_sync::<SharedIndex<SyncPrimary, BUCKETS_BITS>>();
+    assert_send_sync::<SharedIndexReader<SyncPrimary, BUCKETS_BITS>>();
+    assert_send_sync::<Db<SyncPrimary, BUCKETS_BITS>>();
+}
+
+#[test]
+fn concurrent_readers_and_writer() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index = Index::<_, BUCKETS_BITS>::open(&index_path, SyncPrimary::default()).unwrap();
+    // Some keys exist from the start.
+    for ii in 0..500 {
+        let pos = index.primary.put(&key(ii), b"value").unwrap();
+        index.put(&key(ii), pos).unwrap();
+    }
+    let shared = index.into_shared();
+    let barrier = Arc::new(Barrier::new(9));
+
+    let writer = {
+        let shared = shared.clone();
+        let barrier = Arc::clone(&barrier);
+        thread::spawn(move || {
+            barrier.wait();
+            // The positions within the primary storage aren't needed for the test.
+            for ii in 500..1000 {
+                shared.put(&key(ii), u64::from(ii)).unwrap();
+            }
+            shared.flush().unwrap();
+        })
+    };
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let reader = shared.clone_reader();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                for round in 0..5 {
+                    for ii in 0..500 {
+                        assert_eq!(
+                            reader.get(&key(ii)).unwrap(),
+                            Some(u64::from(ii)),
+                            "round {}",
+                            round
+                        );
+                    }
+                    // Keys of the writer are either not there yet or complete.
+                    for ii in 500..1000 {
+                        let file_offset = reader.get(&key(ii)).unwrap();
+                        assert!(file_offset.is_none() || file_offset == Some(u64::from(ii)));
+                    }
+                }
+            })
+        })
+        .collect();
+
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    for ii in 0..1000 {
+        assert!(shared.contains(&key(ii)).unwrap());
+        assert_eq!(shared.get(&key(ii)).unwrap(), Some(u64::from(ii)));
+    }
+    assert!(!shared.contains(&key(1000)).unwrap());
+}
+
+#[test]
+fn concurrent_db_puts_into_the_same_buckets() {
+    const NUM_THREADS: u32 = 8;
+    const KEYS_PER_THREAD: u32 = 500;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let db = Arc::new(Db::<_, BUCKETS_BITS>::open(SyncPrimary::default(), &index_path).unwrap());
+    let barrier = Arc::new(Barrier::new(NUM_THREADS as usize));
+
+    // All threads write into the same two buckets.
+    let same_bucket_key =
+        |ii: u32| [&[(ii % 2) as u8][..], &ii.to_le_bytes(), &[0xaa; 27]].concat();
+    let threads: Vec<_> = (0..NUM_THREADS)
+        .map(|thread| {
+            let db = Arc::clone(&db);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                (0..KEYS_PER_THREAD)
+                    .map(|ii| {
+                        let key = same_bucket_key(thread * KEYS_PER_THREAD + ii);
+                        db.put(&key, &key).unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let positions: Vec<_> = threads
+        .into_iter()
+        .flat_map(|thread| thread.join().unwrap())
+        .collect();
+
+    // No put lost the records of another one.
+    for ii in 0..NUM_THREADS * KEYS_PER_THREAD {
+        let key = same_bucket_key(ii);
+        assert_eq!(db.get(&key).unwrap(), Some(key));
+    }
+    // Every put appended its own record list.
+    let offsets: HashSet<_> = positions
+        .iter()
+        .map(|position| position.index_record_list_offset)
+        .collect();
+    assert_eq!(offsets.len(), positions.len());
+    // The last record list that was appended for a bucket is its live one.
+    db.flush().unwrap();
+    let index =
+        Index::<_, BUCKETS_BITS>::open_read_only(&index_path, SyncPrimary::default()).unwrap();
+    let mut last_offsets = HashMap::new();
+    for position in &positions {
+        let offset = last_offsets.entry(position.index_bucket).or_insert(0);
+        *offset = position.index_record_list_offset.max(*offset);
+    }
+    assert_eq!(last_offsets.len(), 2);
+    for (bucket, offset) in last_offsets {
+        let (live_offset, _data) = index.bucket_records(bucket).unwrap().unwrap();
+        assert_eq!(live_offset, offset);
+    }
+}
+
+#[test]
+fn concurrent_puts_and_scans() {
+    const NUM_KEYS: u32 = 2000;
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let index =
+        Arc::new(Index::<_, BUCKETS_BITS>::open(&index_path, SyncPrimary::default()).unwrap());
+    let barrier = Arc::new(Barrier::new(3));
+
+    let writer = {
+        let index = Arc::clone(&index);
+        let barrier = Arc::clone(&barrier);
+        thread::spawn(move || {
+            barrier.wait();
+            for ii in 0..NUM_KEYS {
+                let pos = index.primary.put(&key(ii), b"value").unwrap();
+                index.put(&key(ii), pos).unwrap();
+            }
+        })
+    };
+    // The scans read the index while the writer appends to it, they must not move its cursor.
+    let scanners: Vec<_> = (0..2)
+        .map(|scanner| {
+            let index = Arc::clone(&index);
+            let barrier = Arc::clone(&barrier);
+            let snapshot_path = temp_dir.path().join(format!("snapshot{}", scanner));
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..50 {
+                    index.used_buckets().unwrap();
+                    index.live_size().unwrap();
+                    index.snapshot(&snapshot_path).unwrap();
+                }
+            })
+        })
+        .collect();
+
+    writer.join().unwrap();
+    for scanner in scanners {
+        scanner.join().unwrap();
+    }
+    for ii in 0..NUM_KEYS {
+        assert_eq!(index.get(&key(ii)).unwrap(), Some(u64::from(ii)));
+    }
+    assert_eq!(index.used_buckets().unwrap().len(), 1 << BUCKETS_BITS);
+}
@@ -0,0 +1,104 @@
+//! Checks the spans that are emitted with the `tracing` feature.
+#![cfg(feature = "tracing")]
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use storethehash::codec::{KeyCodec, Sha256Codec};
+use storethehash::db::Db;
+use storethehash::tracing::field::{Field, Visit};
+use storethehash::tracing::span::{Attributes, Id, Record};
+use storethehash::tracing::{self, Subscriber};
+use storethehash_primary_cid::CidPrimary;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, Registry};
+
+/// The name of a span and the values of its fields, formatted with `Debug`.
+type SpanFields = (&'static str, HashMap<&'static str, String>);
+
+struct FieldVisitor<'a>(&'a mut HashMap<&'static str, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+/// Collects the fields of all spans once they are closed.
+#[derive(Clone, Default)]
+struct SpanCollector(Arc<Mutex<Vec<SpanFields>>>);
+
+impl<S> Layer<S> for SpanCollector
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let mut fields = HashMap::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        span.extensions_mut().insert((span.name(), fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let mut extensions = span.extensions_mut();
+        let (_name, fields) = extensions.get_mut::<SpanFields>().unwrap();
+        values.record(&mut FieldVisitor(fields));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).unwrap();
+        let fields = span.extensions_mut().remove::<SpanFields>().unwrap();
+        self.0.lock().unwrap().push(fields);
+    }
+}
+
+/// Returns the fields of the only closed span with the given name.
+fn span_fields<'a>(spans: &'a [SpanFields], name: &str) -> &'a HashMap<&'static str, String> {
+    let mut matching = spans.iter().filter(|(span_name, _)| *span_name == name);
+    let (_name, fields) = matching.next().unwrap();
+    assert!(matching.next().is_none(), "more than one `{}` span", name);
+    fields
+}
+
+#[test]
+fn tracing_spans() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let primary = CidPrimary::open(temp_dir.path().join("storethehash.db")).unwrap();
+    let db = Db::<_, 8>::open(primary, temp_dir.path().join("storethehash.db.index")).unwrap();
+    // A CIDv1 with the raw codec and a SHA2-256 multihash.
+    let digest = Sha256Codec::encode(b"data").unwrap();
+    let cid = [&[0x01, 0x55, 0x12, 0x20][..], &digest].concat();
+
+    let collector = SpanCollector::default();
+    let subscriber = Registry::default().with(collector.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        db.put(&cid, b"data").unwrap();
+        assert_eq!(db.get(&cid).unwrap(), Some(b"data".to_vec()));
+    });
+    let spans = collector.0.lock().unwrap();
+    // The digest is the index key, its first byte is the bucket.
+    let bucket = format!("{}", digest[0]);
+
+    let db_put = span_fields(&spans, "Db::put");
+    assert_eq!(db_put["file_offset"], "0");
+    assert_eq!(db_put["bytes_written"], "40");
+    let index_put = span_fields(&spans, "Index::put");
+    assert_eq!(index_put["bucket"], bucket);
+    assert!(index_put.contains_key("record_list_size"));
+    let primary_put = span_fields(&spans, "CidPrimary::put");
+    assert_eq!(primary_put["pos"], "0");
+    assert_eq!(primary_put["bytes_written"], "40");
+
+    let db_get = span_fields(&spans, "Db::get");
+    assert_eq!(db_get["file_offset"], "0");
+    assert_eq!(db_get["bytes_read"], "40");
+    let index_get = span_fields(&spans, "Index::get");
+    assert_eq!(index_get["bucket"], bucket);
+    assert_eq!(index_get["record_list_size"], index_put["record_list_size"]);
+    assert_eq!(index_get["probe_count"], "1");
+    let primary_get = span_fields(&spans, "CidPrimary::get");
+    assert_eq!(primary_get["pos"], "0");
+    assert_eq!(primary_get["bytes_read"], "40");
+}
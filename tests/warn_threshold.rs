@@ -0,0 +1,71 @@
+//! The warning about big record lists is checked in its own test binary, as it needs to install
+//! a global logger.
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use storethehash::index::{Index, IndexBuilder};
+use storethehash::primary::PrimaryStorage;
+use storethehash_primary_inmemory::InMemory;
+
+const BUCKETS_BITS: u8 = 8;
+
+/// Collects the log output so that it can be inspected.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn warnings(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .filter(|line| line.contains("consider increasing BUCKETS_BITS"))
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[test]
+fn warn_threshold() {
+    let captured = Captured::default();
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Warn)
+        .target(env_logger::Target::Pipe(Box::new(captured.clone())))
+        .init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let index_path = temp_dir.path().join("storethehash.index");
+    let mut index = IndexBuilder::<_, BUCKETS_BITS>::new(&index_path, InMemory::new(&[]))
+        .with_warn_threshold(50)
+        .open()
+        .unwrap();
+
+    // All keys go into bucket 1, four of them fit into the threshold, five do not.
+    let put = |index: &Index<InMemory, BUCKETS_BITS>, key: &[u8]| {
+        let pos = index.primary.put(key, b"value").unwrap();
+        index.put(key, pos).unwrap();
+    };
+    for ii in 0..4u8 {
+        put(&index, &[1, 2, 3, ii, 5, 6, 7, 8]);
+    }
+    assert!(captured.warnings().is_empty());
+    put(&index, &[1, 2, 3, 4, 5, 6, 7, 8]);
+    let warnings = captured.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("Record list for bucket 1 is"));
+
+    // The threshold can also be changed on an opened index.
+    index.set_warn_threshold(1000);
+    put(&index, &[1, 2, 3, 5, 5, 6, 7, 8]);
+    assert_eq!(captured.warnings().len(), 1);
+}